@@ -2,7 +2,7 @@ use anyhow::{anyhow, Result};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use crossbeam_utils::sync::WaitGroup;
 use druid::piet::{Piet, TextLayout};
-use druid::{piet::PietTextLayout, FontWeight, Key, Vec2};
+use druid::{piet::PietTextLayout, FontStyle, FontWeight, Key, Vec2};
 use druid::{
     piet::{PietText, Text, TextAttribute, TextLayoutBuilder},
     Color, Command, Data, EventCtx, ExtEventSink, Target, UpdateCtx, WidgetId,
@@ -10,11 +10,13 @@ use druid::{
 };
 use druid::{Env, FontFamily, PaintCtx, Point};
 use language::{new_highlight_config, new_parser, LapceLanguage};
+use lapce_proxy::buffer::PositionEncoding;
 use lapce_proxy::dispatch::NewBufferResponse;
 use lsp_types::SemanticTokensServerCapabilities;
 use lsp_types::{CallHierarchyOptions, SemanticTokensLegend};
 use lsp_types::{
-    CodeActionResponse, Position, Range, TextDocumentContentChangeEvent,
+    CodeActionResponse, CodeLens, DocumentHighlight, DocumentHighlightKind,
+    Position, Range, TextDocumentContentChangeEvent,
 };
 use lsp_types::{Location, SemanticTokens};
 use parking_lot::Mutex;
@@ -51,10 +53,13 @@ use xi_rope::{
 };
 use xi_unicode::EmojiExt;
 
+use crate::code_lens::{EditorCodeLens, CODE_LENS_SEPARATOR};
 use crate::config::{Config, LapceTheme};
 use crate::data::EditorKind;
 use crate::editor::EditorLocationNew;
 use crate::find::FindProgress;
+use crate::inlay_hint::{EditorInlayHint, InlayHint};
+use crate::inline_completion::InlineCompletion;
 use crate::theme::OldLapceTheme;
 use crate::{
     command::LapceUICommand,
@@ -70,6 +75,7 @@ use crate::{
 };
 
 const FIND_BATCH_SIZE: usize = 500000;
+const MAX_WORD_HIGHLIGHTS: usize = 1000;
 
 #[derive(Debug, Clone)]
 pub struct InvalLines {
@@ -122,11 +128,20 @@ pub struct HighlightTextLayout {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Style {
     pub fg_color: Option<String>,
+    /// The semantic token modifiers of the text, drawn the way the theme
+    /// says.
+    pub modifiers: Vec<String>,
 }
 
 pub enum UpdateEvent {
     Buffer(BufferUpdate),
-    SemanticTokens(BufferUpdate, Vec<(usize, usize, String)>),
+    /// Tokens for the whole document, or only for the range of offsets
+    /// given.
+    SemanticTokens(
+        BufferUpdate,
+        Option<(usize, usize)>,
+        Vec<(usize, usize, String, Vec<String>)>,
+    ),
 }
 
 pub struct BufferUpdate {
@@ -186,6 +201,14 @@ struct Revision {
     edit: Contents,
 }
 
+/// An occurrence of the symbol under the cursor.
+#[derive(Clone, Debug)]
+pub struct EditorDocumentHighlight {
+    pub start: usize,
+    pub end: usize,
+    pub kind: DocumentHighlightKind,
+}
+
 #[derive(Clone)]
 pub struct BufferNew {
     pub id: BufferId,
@@ -198,6 +221,8 @@ pub struct BufferNew {
     pub max_len: usize,
     pub max_len_line: usize,
     pub num_lines: usize,
+    /// How the language server counts the characters of positions.
+    pub position_encoding: PositionEncoding,
     pub rev: u64,
     pub dirty: bool,
     pub loaded: bool,
@@ -208,6 +233,9 @@ pub struct BufferNew {
 
     pub find: Rc<RefCell<Find>>,
     pub find_progress: Rc<RefCell<FindProgress>>,
+    /// How often each word is used, with the rev it was counted at, for
+    /// word completion in the other buffers.
+    pub word_counts: Rc<RefCell<Option<(u64, Arc<HashMap<String, usize>>)>>>,
 
     revs: Vec<Revision>,
     cur_undo: usize,
@@ -226,6 +254,15 @@ pub struct BufferNew {
 
     pub code_actions: im::HashMap<usize, CodeActionResponse>,
     pub syntax_tree: Option<Arc<Tree>>,
+    pub inlay_hints: Arc<Vec<EditorInlayHint>>,
+    /// The suggestion drawn as ghost text after the cursor.
+    pub inline_completion: Option<Arc<InlineCompletion>>,
+    pub code_lens: Arc<Vec<EditorCodeLens>>,
+    pub document_highlights: Arc<Vec<EditorDocumentHighlight>>,
+    /// The calls made from or to the item picked in the hierarchy panel.
+    pub call_sites: Arc<Vec<(usize, usize)>>,
+    /// The lines with code lenses drawn above them, in order.
+    code_lens_lines: Arc<Vec<usize>>,
 }
 
 impl BufferNew {
@@ -241,10 +278,12 @@ impl BufferNew {
             line_styles: Rc::new(RefCell::new(Vec::new())),
             find: Rc::new(RefCell::new(Find::new(0))),
             find_progress: Rc::new(RefCell::new(FindProgress::Ready)),
+            word_counts: Rc::new(RefCell::new(None)),
             semantic_tokens: false,
             max_len: 0,
             max_len_line: 0,
             num_lines: 0,
+            position_encoding: PositionEncoding::default(),
             rev: 0,
             start_to_load: Rc::new(RefCell::new(false)),
             loaded: false,
@@ -276,6 +315,12 @@ impl BufferNew {
 
             code_actions: im::HashMap::new(),
             syntax_tree: None,
+            inlay_hints: Arc::new(Vec::new()),
+            inline_completion: None,
+            code_lens: Arc::new(Vec::new()),
+            code_lens_lines: Arc::new(Vec::new()),
+            document_highlights: Arc::new(Vec::new()),
+            call_sites: Arc::new(Vec::new()),
         };
         *buffer.line_styles.borrow_mut() = vec![None; buffer.num_lines()];
         buffer
@@ -319,6 +364,13 @@ impl BufferNew {
         }
 
         self.code_actions.clear();
+        self.inlay_hints = Arc::new(Vec::new());
+        self.inline_completion = None;
+        self.code_lens = Arc::new(Vec::new());
+        self.code_lens_lines = Arc::new(Vec::new());
+        self.document_highlights = Arc::new(Vec::new());
+        self.call_sites = Arc::new(Vec::new());
+        *self.word_counts.borrow_mut() = None;
         let (max_len, max_len_line) = self.get_max_line_len();
         self.max_len = max_len;
         self.max_len_line = max_len_line;
@@ -625,6 +677,7 @@ impl BufferNew {
         bounds: [f64; 2],
         config: &Config,
     ) -> PietTextLayout {
+        let original_content = line_content;
         let (line_content, cursor_index) = if line_content.contains('\t') {
             let cursor_index = cursor_index.map(|index| {
                 line_content
@@ -640,6 +693,59 @@ impl BufferNew {
         } else {
             (line_content.to_string(), cursor_index)
         };
+
+        // Inlay hints and the ghost text of the inline completion are spliced
+        // into the laid out text only, so everything after them on the line
+        // is shifted by their labels.
+        let line_start = self.offset_of_line(line);
+        let mut hints: Vec<(usize, usize, String, bool)> = self
+            .line_inlay_hints(line)
+            .iter()
+            .map(|(_, hint)| {
+                let index = (hint.offset - line_start).min(original_content.len());
+                let tabs = original_content[..index].matches('\t').count();
+                (index, index + tabs * 3, hint.hint.label(), false)
+            })
+            .collect();
+        if let Some((col, ghost)) = self.line_inline_completion(line) {
+            let index = col.min(original_content.len());
+            let tabs = original_content[..index].matches('\t').count();
+            let i = hints.partition_point(|(i, _, _, _)| *i < index);
+            hints.insert(i, (index, index + tabs * 3, ghost, true));
+        }
+        let mut hint_ranges = Vec::new();
+        let (line_content, cursor_index) = if hints.len() > 0 {
+            let mut content = String::new();
+            let mut last = 0;
+            let mut cursor_shift = 0;
+            for (_, index, label, is_ghost) in hints.iter() {
+                let index = (*index).min(line_content.len());
+                content.push_str(&line_content[last..index]);
+                if let Some(cursor_index) = cursor_index {
+                    if line_content[..index].chars().count() <= cursor_index {
+                        cursor_shift += label.chars().count();
+                    }
+                }
+                hint_ranges
+                    .push((content.len()..content.len() + label.len(), *is_ghost));
+                content.push_str(label);
+                last = index;
+            }
+            content.push_str(&line_content[last..]);
+            (content, cursor_index.map(|index| index + cursor_shift))
+        } else {
+            (line_content, cursor_index)
+        };
+        let shift_by_hints = |pos: usize, inclusive: bool| {
+            pos + hints
+                .iter()
+                .filter(|(index, _, _, _)| {
+                    *index < pos || (inclusive && *index == pos)
+                })
+                .map(|(_, _, label, _)| label.len())
+                .sum::<usize>()
+        };
+
         let styles = self.get_line_styles(line);
         let mut layout_builder = ctx
             .text()
@@ -663,16 +769,57 @@ impl BufferNew {
         }
 
         for (start, end, style) in styles.iter() {
+            let range = shift_by_hints(*start, true)..shift_by_hints(*end, false);
             if let Some(fg_color) = style.fg_color.as_ref() {
                 if let Some(fg_color) =
                     config.get_color(&("style.".to_string() + fg_color))
                 {
                     layout_builder = layout_builder.range_attribute(
-                        start..end,
+                        range.clone(),
                         TextAttribute::TextColor(fg_color.clone()),
                     );
                 }
             }
+            for modifier in style.modifiers.iter() {
+                if let Some(modifier_style) = config.get_modifier_style(modifier) {
+                    if modifier_style.bold {
+                        layout_builder = layout_builder.range_attribute(
+                            range.clone(),
+                            TextAttribute::Weight(FontWeight::BOLD),
+                        );
+                    }
+                    if modifier_style.italic {
+                        layout_builder = layout_builder.range_attribute(
+                            range.clone(),
+                            TextAttribute::Style(FontStyle::Italic),
+                        );
+                    }
+                    if modifier_style.underline {
+                        layout_builder = layout_builder.range_attribute(
+                            range.clone(),
+                            TextAttribute::Underline(true),
+                        );
+                    }
+                    if modifier_style.strikethrough {
+                        layout_builder = layout_builder.range_attribute(
+                            range.clone(),
+                            TextAttribute::Strikethrough(true),
+                        );
+                    }
+                }
+            }
+        }
+
+        for (range, is_ghost) in hint_ranges {
+            let color = if is_ghost {
+                LapceTheme::EDITOR_DIM
+            } else {
+                LapceTheme::INLAY_HINT_FOREGROUND
+            };
+            layout_builder = layout_builder.range_attribute(
+                range,
+                TextAttribute::TextColor(config.get_color_unchecked(color).clone()),
+            );
         }
         layout_builder.build_with_bounds(bounds)
     }
@@ -690,15 +837,25 @@ impl BufferNew {
     }
 
     pub fn offset_to_position(&self, offset: usize) -> Position {
-        let (line, col) = self.offset_to_line_col(offset);
+        let offset = offset.min(self.len());
+        let line = self.line_of_offset(offset);
+        let line_start = self.offset_of_line(line);
+        let content = self.slice_to_cow(line_start..offset);
         Position {
             line: line as u32,
-            character: col as u32,
+            character: self.position_encoding.len(&content) as u32,
         }
     }
 
     pub fn offset_of_position(&self, pos: &Position) -> usize {
-        self.offset_of_line_col(pos.line as usize, pos.character as usize)
+        let line = (pos.line as usize).min(self.last_line());
+        let line_start = self.offset_of_line(line);
+        let content = self.slice_to_cow(line_start..self.offset_of_line(line + 1));
+        let content = content.trim_end_matches(&['\n', '\r'][..]);
+        line_start
+            + self
+                .position_encoding
+                .byte_offset(content, pos.character as usize)
     }
 
     pub fn offset_of_mouse(
@@ -709,7 +866,8 @@ impl BufferNew {
         config: &Config,
     ) -> usize {
         let line_height = config.editor.line_height as f64;
-        let line = (pos.y / line_height).floor() as usize;
+        let (line, _) =
+            self.line_of_visual_line((pos.y / line_height).floor() as usize);
         let last_line = self.last_line();
         let (line, col) = if line > last_line {
             (last_line, 0)
@@ -717,12 +875,12 @@ impl BufferNew {
             let line_end = self.line_end_col(line, mode != Mode::Normal);
             let width = config.editor_text_width(text, "W");
 
-            let col = (if mode == Mode::Insert {
+            let visual_col = if mode == Mode::Insert {
                 (pos.x / width).round() as usize
             } else {
                 (pos.x / width).floor() as usize
-            })
-            .min(line_end);
+            };
+            let col = self.col_of_visual_col(line, visual_col).min(line_end);
             (line, col)
         };
         self.offset_of_line_col(line, col)
@@ -1184,6 +1342,423 @@ impl BufferNew {
         WordCursor::new(&self.rope, offset).next_code_boundary()
     }
 
+    pub fn update_inlay_hints(&mut self, rev: u64, hints: Vec<InlayHint>) {
+        if rev != self.rev {
+            return;
+        }
+        let mut hints: Vec<EditorInlayHint> = hints
+            .into_iter()
+            .map(|hint| EditorInlayHint {
+                offset: self.offset_of_position(&hint.position),
+                hint,
+            })
+            .collect();
+        hints.sort_by_key(|h| h.offset);
+        self.inlay_hints = Arc::new(hints);
+    }
+
+    /// Swaps in the resolved version of the hint at `offset`, so the
+    /// tooltip can be shown without asking the server again.
+    pub fn update_inlay_hint(&mut self, rev: u64, offset: usize, hint: InlayHint) {
+        if rev != self.rev {
+            return;
+        }
+        let label = hint.label();
+        if let Some(h) = Arc::make_mut(&mut self.inlay_hints)
+            .iter_mut()
+            .find(|h| h.offset == offset && h.hint.label() == label)
+        {
+            h.hint = hint;
+        }
+    }
+
+    pub fn clear_inlay_hints(&mut self) {
+        if self.inlay_hints.len() > 0 {
+            self.inlay_hints = Arc::new(Vec::new());
+        }
+    }
+
+    fn inlay_hints_apply_delta(&mut self, delta: &RopeDelta) {
+        if self.inlay_hints.len() == 0 {
+            return;
+        }
+        let mut transformer = Transformer::new(delta);
+        for hint in Arc::make_mut(&mut self.inlay_hints).iter_mut() {
+            hint.offset = transformer.transform(hint.offset, false);
+        }
+    }
+
+    /// The inlay hints drawn on `line`, each with the column it sits at,
+    /// in column order.
+    pub fn line_inlay_hints(&self, line: usize) -> Vec<(usize, &EditorInlayHint)> {
+        if self.inlay_hints.len() == 0 {
+            return Vec::new();
+        }
+        let start_offset = self.offset_of_line(line);
+        let end_offset = self.offset_of_line(line + 1);
+        let start = self
+            .inlay_hints
+            .partition_point(|h| h.offset < start_offset);
+        let end = self.inlay_hints.partition_point(|h| h.offset < end_offset);
+        self.inlay_hints[start..end]
+            .iter()
+            .map(|h| (self.offset_to_line_col(h.offset).1, h))
+            .collect()
+    }
+
+    /// Shows `completion` as ghost text, unless the buffer changed since
+    /// it was asked for at `rev`.
+    pub fn set_inline_completion(
+        &mut self,
+        rev: u64,
+        completion: Option<InlineCompletion>,
+    ) {
+        if rev != self.rev {
+            return;
+        }
+        self.inline_completion = completion.map(Arc::new);
+    }
+
+    pub fn clear_inline_completion(&mut self) {
+        if self.inline_completion.is_some() {
+            self.inline_completion = None;
+        }
+    }
+
+    /// Typing the start of the ghost text keeps the rest of it, any other
+    /// change drops it.
+    fn inline_completion_apply_delta(&mut self, delta: &RopeDelta) {
+        let completion = match self.inline_completion.take() {
+            Some(completion) => completion,
+            None => return,
+        };
+        let (iv, new_len) = delta.summary();
+        if iv.start == completion.offset && iv.end == completion.offset {
+            let typed = self.rope.slice_to_cow(iv.start..iv.start + new_len);
+            self.inline_completion = completion.after_typing(&typed).map(Arc::new);
+        }
+    }
+
+    /// The column the ghost text sits at on `line` and the part of it drawn
+    /// there, when it's on `line`.
+    pub fn line_inline_completion(&self, line: usize) -> Option<(usize, String)> {
+        let completion = self.inline_completion.as_ref()?;
+        let (ghost_line, col) = self.offset_to_line_col(completion.offset);
+        if ghost_line != line {
+            return None;
+        }
+        Some((col, completion.first_line()))
+    }
+
+    /// What is drawn on `line` without being in it, in column order: the
+    /// ghost text, with no hint, and the inlay hints, each with the column
+    /// it sits at and its width. At the same column the ghost text comes
+    /// first, right after the caret.
+    pub fn line_virtual_texts(
+        &self,
+        line: usize,
+    ) -> Vec<(usize, usize, Option<&EditorInlayHint>)> {
+        let mut texts: Vec<(usize, usize, Option<&EditorInlayHint>)> = self
+            .line_inlay_hints(line)
+            .into_iter()
+            .map(|(col, hint)| (col, str_col(&hint.hint.label()), Some(hint)))
+            .collect();
+        if let Some((col, ghost)) = self.line_inline_completion(line) {
+            let i = texts.partition_point(|(c, _, _)| *c < col);
+            texts.insert(i, (col, str_col(&ghost), None));
+        }
+        texts
+    }
+
+    /// The column `col` on `line` is drawn at once the inlay hints and
+    /// ghost text in front of it are taken into account. Those sitting
+    /// right at `col` are only counted when `caret` is false, because a
+    /// caret there goes before them while the character there goes after
+    /// them.
+    pub fn visual_col(&self, line: usize, col: usize, caret: bool) -> usize {
+        col + self
+            .line_virtual_texts(line)
+            .iter()
+            .filter(|(c, _, _)| *c < col || (!caret && *c == col))
+            .map(|(_, width, _)| width)
+            .sum::<usize>()
+    }
+
+    /// The reverse of `visual_col`. A visual column inside a hint or the
+    /// ghost text maps to the column it sits at.
+    pub fn col_of_visual_col(&self, line: usize, visual_col: usize) -> usize {
+        let mut shift = 0;
+        for (col, width, _) in self.line_virtual_texts(line) {
+            if visual_col < col + shift {
+                break;
+            }
+            if visual_col < col + shift + width {
+                return col;
+            }
+            shift += width;
+        }
+        visual_col - shift
+    }
+
+    /// The hint drawn over the visual column `visual_col` on `line`.
+    pub fn inlay_hint_at_visual_col(
+        &self,
+        line: usize,
+        visual_col: usize,
+    ) -> Option<&EditorInlayHint> {
+        let mut shift = 0;
+        for (col, width, hint) in self.line_virtual_texts(line) {
+            if visual_col >= col + shift && visual_col < col + shift + width {
+                return hint;
+            }
+            shift += width;
+        }
+        None
+    }
+
+    pub fn update_code_lens(&mut self, rev: u64, lenses: Vec<CodeLens>) {
+        if rev != self.rev {
+            return;
+        }
+        let mut lenses: Vec<EditorCodeLens> = lenses
+            .into_iter()
+            .map(|lens| EditorCodeLens {
+                offset: self.offset_of_position(&lens.range.start),
+                lens,
+            })
+            .collect();
+        lenses.sort_by_key(|l| l.offset);
+        self.code_lens = Arc::new(lenses);
+        self.update_code_lens_lines();
+    }
+
+    /// Fills in the command of a lens that came back from `codeLens/resolve`.
+    pub fn resolve_code_lens(&mut self, rev: u64, lens: CodeLens) {
+        if rev != self.rev {
+            return;
+        }
+        if let Some(l) = Arc::make_mut(&mut self.code_lens)
+            .iter_mut()
+            .find(|l| l.lens.command.is_none() && l.lens.range == lens.range)
+        {
+            l.lens = lens;
+        }
+    }
+
+    pub fn clear_code_lens(&mut self) {
+        if self.code_lens.len() > 0 {
+            self.code_lens = Arc::new(Vec::new());
+            self.code_lens_lines = Arc::new(Vec::new());
+        }
+    }
+
+    fn code_lens_apply_delta(&mut self, delta: &RopeDelta) {
+        if self.code_lens.len() == 0 {
+            return;
+        }
+        let mut transformer = Transformer::new(delta);
+        for lens in Arc::make_mut(&mut self.code_lens).iter_mut() {
+            lens.offset = transformer.transform(lens.offset, false);
+        }
+        self.update_code_lens_lines();
+    }
+
+    fn update_code_lens_lines(&mut self) {
+        let mut lines: Vec<usize> = self
+            .code_lens
+            .iter()
+            .map(|l| self.line_of_offset(l.offset))
+            .collect();
+        lines.dedup();
+        self.code_lens_lines = Arc::new(lines);
+    }
+
+    /// The code lenses drawn above `line`.
+    pub fn line_code_lens(&self, line: usize) -> &[EditorCodeLens] {
+        if self.code_lens.len() == 0 {
+            return &[];
+        }
+        let start_offset = self.offset_of_line(line);
+        let end_offset = self.offset_of_line(line + 1);
+        let start = self.code_lens.partition_point(|l| l.offset < start_offset);
+        let end = self.code_lens.partition_point(|l| l.offset < end_offset);
+        &self.code_lens[start..end]
+    }
+
+    /// The column the code lenses above `line` start at, which lines them up
+    /// with the line's indentation.
+    pub fn code_lens_col(&self, line: usize) -> usize {
+        let offset = self.first_non_blank_character_on_line(line);
+        let col = self.offset_to_line_col(offset).1;
+        self.visual_col(line, col, false)
+    }
+
+    /// The code lens drawn at `visual_col` on the lens row above `line`.
+    pub fn code_lens_at_visual_col(
+        &self,
+        line: usize,
+        visual_col: usize,
+    ) -> Option<&EditorCodeLens> {
+        let mut col = self.code_lens_col(line);
+        for lens in self.line_code_lens(line) {
+            let width = str_col(&lens.title());
+            if visual_col >= col && visual_col < col + width {
+                return Some(lens);
+            }
+            col += width + CODE_LENS_SEPARATOR.len();
+        }
+        None
+    }
+
+    /// The row `line` is drawn at, counting the rows taken by code lenses.
+    pub fn visual_line(&self, line: usize) -> usize {
+        line + self.code_lens_lines.partition_point(|l| *l <= line)
+    }
+
+    /// The number of rows the buffer takes up, code lenses included.
+    pub fn visual_num_lines(&self) -> usize {
+        self.num_lines + self.code_lens_lines.len()
+    }
+
+    /// The reverse of `visual_line`. The flag is set when the row is the
+    /// code lens row above the returned line.
+    pub fn line_of_visual_line(&self, visual_line: usize) -> (usize, bool) {
+        let shift = self
+            .code_lens_lines
+            .iter()
+            .enumerate()
+            .take_while(|(i, l)| **l + *i < visual_line)
+            .count();
+        let line = visual_line - shift;
+        let is_lens = self
+            .code_lens_lines
+            .get(shift)
+            .map(|l| *l == line)
+            .unwrap_or(false);
+        (line, is_lens)
+    }
+
+    pub fn update_document_highlights(
+        &mut self,
+        rev: u64,
+        highlights: Vec<DocumentHighlight>,
+    ) {
+        if rev != self.rev {
+            return;
+        }
+        let mut highlights: Vec<EditorDocumentHighlight> = highlights
+            .iter()
+            .map(|h| EditorDocumentHighlight {
+                start: self.offset_of_position(&h.range.start),
+                end: self.offset_of_position(&h.range.end),
+                kind: h.kind.unwrap_or(DocumentHighlightKind::Text),
+            })
+            .collect();
+        highlights.sort_by_key(|h| h.start);
+        self.document_highlights = Arc::new(highlights);
+    }
+
+    /// Highlights the whole word matches of the identifier at `offset`, for
+    /// when there's no language server to ask.
+    pub fn update_word_highlights(&mut self, rev: u64, offset: usize) {
+        if rev != self.rev {
+            return;
+        }
+        let (start, end) = match self.identifier_at(offset) {
+            Some(range) => range,
+            None => {
+                self.clear_document_highlights();
+                return;
+            }
+        };
+        let word = self.slice_to_cow(start..end).to_string();
+        let is_ident = |c: Option<char>| {
+            c.map(|c| c.is_alphanumeric() || c == '_').unwrap_or(false)
+        };
+        // An identifier never spans lines, so the document is searched a
+        // line at a time rather than copied out whole.
+        let mut highlights = Vec::new();
+        let mut line_start = 0;
+        'lines: for line in self.rope.lines_raw(0..self.len()) {
+            for (i, _) in line.match_indices(word.as_str()) {
+                if is_ident(line[..i].chars().next_back())
+                    || is_ident(line[i + word.len()..].chars().next())
+                {
+                    continue;
+                }
+                highlights.push(EditorDocumentHighlight {
+                    start: line_start + i,
+                    end: line_start + i + word.len(),
+                    kind: DocumentHighlightKind::Text,
+                });
+                if highlights.len() == MAX_WORD_HIGHLIGHTS {
+                    break 'lines;
+                }
+            }
+            line_start += line.len();
+        }
+        self.document_highlights = Arc::new(highlights);
+    }
+
+    pub fn clear_document_highlights(&mut self) {
+        if self.document_highlights.len() > 0 {
+            self.document_highlights = Arc::new(Vec::new());
+        }
+    }
+
+    pub fn set_call_sites(&mut self, ranges: &[Range]) {
+        let mut call_sites: Vec<(usize, usize)> = ranges
+            .iter()
+            .map(|r| {
+                (
+                    self.offset_of_position(&r.start),
+                    self.offset_of_position(&r.end),
+                )
+            })
+            .collect();
+        call_sites.sort();
+        self.call_sites = Arc::new(call_sites);
+    }
+
+    pub fn clear_call_sites(&mut self) {
+        if self.call_sites.len() > 0 {
+            self.call_sites = Arc::new(Vec::new());
+        }
+    }
+
+    /// The range of the identifier the cursor at `offset` is on, which can
+    /// also be right after it.
+    pub fn identifier_at(&self, offset: usize) -> Option<(usize, usize)> {
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        let offset = match self.char_at_offset(offset) {
+            Some(c) if is_ident(c) => offset,
+            _ if offset > 0 => {
+                let prev = self.prev_grapheme_offset(offset, 1, 0);
+                match self.char_at_offset(prev) {
+                    Some(c) if is_ident(c) => prev,
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        let (start, end) = self.select_word(offset);
+        if start < end && self.slice_to_cow(start..end).chars().all(|c| is_ident(c))
+        {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+
+    pub fn document_highlight_at(
+        &self,
+        offset: usize,
+    ) -> Option<&EditorDocumentHighlight> {
+        self.document_highlights
+            .iter()
+            .find(|h| h.start <= offset && offset <= h.end)
+    }
+
     pub fn update_syntax_tree(&mut self, rev: u64, tree: Tree) {
         if rev != self.rev {
             return;
@@ -1347,6 +1922,11 @@ impl BufferNew {
         self.deletes_from_union = new_deletes_from_union;
         self.code_actions.clear();
         self.syntax_tree = None;
+        self.inlay_hints_apply_delta(delta);
+        self.inline_completion_apply_delta(delta);
+        self.code_lens_apply_delta(delta);
+        self.clear_document_highlights();
+        self.clear_call_sites();
 
         let logical_start_line = self.rope.line_of_offset(iv.start);
         let new_logical_end_line = self.rope.line_of_offset(iv.start + newlen) + 1;
@@ -1369,6 +1949,10 @@ impl BufferNew {
         self.last_edit_type = EditType::Other;
     }
 
+    pub fn last_edit_type(&self) -> EditType {
+        self.last_edit_type
+    }
+
     pub fn edit_multiple(
         &mut self,
         ctx: &mut EventCtx,
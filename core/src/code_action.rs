@@ -1,4 +1,4 @@
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 
 use druid::{
     BoxConstraints, Color, Command, Data, Env, Event, EventCtx, FontDescriptor,
@@ -6,17 +6,17 @@ use druid::{
     RenderContext, Size, Target, TextLayout, UpdateCtx, Widget,
 };
 use lsp_types::{
-    CodeActionDisabled, CodeActionOrCommand, DocumentChangeOperation,
-    DocumentChanges, OneOf, TextEdit, Url, WorkspaceEdit,
+    CodeActionDisabled, CodeActionKind, CodeActionOrCommand,
+    DocumentChangeOperation, DocumentChanges, OneOf, TextDocumentEdit, TextEdit,
+    Url,
 };
 
 use crate::{
-    buffer::EditType,
     command::{LapceCommand, LapceUICommand, LAPCE_UI_COMMAND},
     config::LapceTheme,
     data::{EditorContent, LapceMainSplitData, LapceTabData},
     keypress::{KeyPressData, KeyPressFocus},
-    movement::{Movement, Selection},
+    movement::Movement,
     proxy::LapceProxy,
     state::Mode,
     theme::OldLapceTheme,
@@ -102,6 +102,8 @@ impl CodeActionData {
         }
     }
 
+    /// Hands the chosen action to the tab, which resolves it when needed,
+    /// applies its edit and runs its command.
     pub fn select(&mut self, ctx: &mut EventCtx) {
         let editor = self.main_split.active_editor();
         match &editor.content {
@@ -113,48 +115,14 @@ impl CodeActionData {
                 let code_actions =
                     buffer.code_actions.get(&prev_offset).unwrap_or(&empty_vec);
 
-                let action = &code_actions[self.main_split.current_code_actions];
-                match action {
-                    CodeActionOrCommand::Command(cmd) => {}
-                    CodeActionOrCommand::CodeAction(action) => {
-                        if let Some(edit) = action.edit.as_ref() {
-                            if let Some(edits) = workspce_edits(edit) {
-                                if let Some(edits) =
-                                    edits.get(&Url::from_file_path(&path).unwrap())
-                                {
-                                    let path = path.clone();
-                                    let buffer = self
-                                        .main_split
-                                        .open_files
-                                        .get_mut(&path)
-                                        .unwrap();
-                                    let edits: Vec<(Selection, String)> = edits
-                                        .iter()
-                                        .map(|edit| {
-                                            let selection = Selection::region(
-                                                buffer.offset_of_position(
-                                                    &edit.range.start,
-                                                ),
-                                                buffer.offset_of_position(
-                                                    &edit.range.end,
-                                                ),
-                                            );
-                                            (selection, edit.new_text.clone())
-                                        })
-                                        .collect();
-                                    self.main_split.edit(
-                                        ctx,
-                                        &path,
-                                        edits
-                                            .iter()
-                                            .map(|(s, c)| (s, c.as_ref()))
-                                            .collect(),
-                                        EditType::Other,
-                                    );
-                                }
-                            }
-                        }
-                    }
+                if let Some(action) =
+                    code_actions.get(self.main_split.current_code_actions)
+                {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::RunCodeAction(buffer.id, action.clone()),
+                        Target::Widget(*self.main_split.tab_id),
+                    ));
                 }
             }
             EditorContent::None => {}
@@ -358,44 +326,236 @@ impl Widget<LapceTabData> for CodeAction {
     }
 }
 
-fn workspce_edits(edit: &WorkspaceEdit) -> Option<HashMap<Url, Vec<TextEdit>>> {
-    if let Some(changes) = edit.changes.as_ref() {
-        return Some(changes.clone());
+/// Whether the action is a quickfix, one that fixes a diagnostic.
+pub fn is_fix(action: &CodeActionOrCommand) -> bool {
+    match action {
+        CodeActionOrCommand::CodeAction(action) => action
+            .kind
+            .as_ref()
+            .map(|kind| kind.as_str().starts_with(CodeActionKind::QUICKFIX.as_str()))
+            .unwrap_or(false),
+        CodeActionOrCommand::Command(_) => false,
+    }
+}
+
+/// The text edits of a fix that does nothing but edit the document `uri`.
+/// None for one that also edits another file, creates, renames or deletes
+/// one, runs a command, or has to be resolved first.
+pub fn fix_edits(fix: &lsp_types::CodeAction, uri: &Url) -> Option<Vec<TextEdit>> {
+    if fix.command.is_some() || fix.data.is_some() {
+        return None;
+    }
+    let edit = fix.edit.as_ref()?;
+    if let Some(changes) = edit.document_changes.as_ref() {
+        let edits: Vec<&TextDocumentEdit> = match changes {
+            DocumentChanges::Edits(edits) => edits.iter().collect(),
+            DocumentChanges::Operations(ops) => ops
+                .iter()
+                .map(|op| match op {
+                    DocumentChangeOperation::Edit(e) => Some(e),
+                    DocumentChangeOperation::Op(_) => None,
+                })
+                .collect::<Option<_>>()?,
+        };
+        let mut text_edits = Vec::new();
+        for e in edits {
+            if &e.text_document.uri != uri {
+                return None;
+            }
+            text_edits.extend(e.edits.iter().map(|e| match e {
+                OneOf::Left(e) => e.clone(),
+                OneOf::Right(e) => e.text_edit.clone(),
+            }));
+        }
+        return Some(text_edits);
+    }
+    let changes = edit.changes.as_ref()?;
+    if changes.keys().any(|changed| changed != uri) {
+        return None;
     }
+    changes.get(uri).cloned()
+}
 
-    let changes = edit.document_changes.as_ref()?;
-    let edits = match changes {
-        DocumentChanges::Edits(edits) => edits
-            .iter()
-            .map(|e| {
-                (
-                    e.text_document.uri.clone(),
-                    e.edits
-                        .iter()
-                        .map(|e| match e {
-                            OneOf::Left(e) => e.clone(),
-                            OneOf::Right(e) => e.text_edit.clone(),
-                        })
-                        .collect(),
-                )
-            })
-            .collect::<HashMap<Url, Vec<TextEdit>>>(),
-        DocumentChanges::Operations(ops) => ops
-            .iter()
-            .filter_map(|o| match o {
-                DocumentChangeOperation::Op(op) => None,
-                DocumentChangeOperation::Edit(e) => Some((
-                    e.text_document.uri.clone(),
-                    e.edits
-                        .iter()
-                        .map(|e| match e {
-                            OneOf::Left(e) => e.clone(),
-                            OneOf::Right(e) => e.text_edit.clone(),
-                        })
-                        .collect(),
-                )),
-            })
-            .collect::<HashMap<Url, Vec<TextEdit>>>(),
+/// Puts the edits of several fixes, as `(start, end, text)`, together in
+/// the order of the document. An edit the same as one already taken is
+/// shared, since fixes for two diagnostics often add the same thing; a fix
+/// with any other edit overlapping one already taken is dropped. Returns
+/// the edits and how many fixes made it in.
+pub fn merge_fixes(
+    fixes: Vec<Vec<(usize, usize, String)>>,
+) -> (Vec<(usize, usize, String)>, usize) {
+    let overlaps = |a: &(usize, usize, String), b: &(usize, usize, String)| {
+        if a.0 == a.1 && b.0 == b.1 {
+            // two insertions at the same place can't be ordered
+            a.0 == b.0
+        } else {
+            a.0 < b.1 && b.0 < a.1
+        }
     };
-    Some(edits)
+
+    let mut merged: Vec<(usize, usize, String)> = Vec::new();
+    let mut applied = 0;
+    for fix in fixes {
+        let new: Vec<_> = fix
+            .into_iter()
+            .filter(|edit| !merged.contains(edit))
+            .collect();
+        let conflicts = new.iter().enumerate().any(|(i, edit)| {
+            merged.iter().any(|taken| overlaps(edit, taken))
+                || new[i + 1..].iter().any(|other| overlaps(edit, other))
+        });
+        if conflicts {
+            continue;
+        }
+        merged.extend(new);
+        applied += 1;
+    }
+    merged.sort_by_key(|(start, end, _)| (*start, *end));
+    (merged, applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use lsp_types::{
+        OptionalVersionedTextDocumentIdentifier, Position, Range, ResourceOp,
+        WorkspaceEdit,
+    };
+
+    use super::*;
+
+    fn edit(start: usize, end: usize, text: &str) -> (usize, usize, String) {
+        (start, end, text.to_string())
+    }
+
+    #[test]
+    fn test_merge_fixes_shared_edit() {
+        // both fixes add the same import
+        let (edits, applied) = merge_fixes(vec![
+            vec![edit(0, 0, "use a;\n"), edit(10, 12, "x")],
+            vec![edit(0, 0, "use a;\n"), edit(20, 22, "y")],
+        ]);
+        assert_eq!(applied, 2);
+        assert_eq!(
+            edits,
+            vec![edit(0, 0, "use a;\n"), edit(10, 12, "x"), edit(20, 22, "y")]
+        );
+    }
+
+    #[test]
+    fn test_merge_fixes_overlap() {
+        // the second fix overlaps the first with one of its edits, so none
+        // of its edits are taken
+        let (edits, applied) = merge_fixes(vec![
+            vec![edit(10, 15, "a")],
+            vec![edit(0, 2, "b"), edit(12, 20, "c")],
+            vec![edit(30, 31, "d")],
+        ]);
+        assert_eq!(applied, 2);
+        assert_eq!(edits, vec![edit(10, 15, "a"), edit(30, 31, "d")]);
+
+        // a fix overlapping itself is dropped too
+        let (edits, applied) =
+            merge_fixes(vec![vec![edit(0, 5, "a"), edit(3, 8, "b")]]);
+        assert_eq!(applied, 0);
+        assert_eq!(edits, vec![]);
+    }
+
+    #[test]
+    fn test_merge_fixes_inserts_at_same_offset() {
+        // two different insertions at one place can't be ordered
+        let (edits, applied) =
+            merge_fixes(vec![vec![edit(5, 5, "a")], vec![edit(5, 5, "b")]]);
+        assert_eq!(applied, 1);
+        assert_eq!(edits, vec![edit(5, 5, "a")]);
+
+        // an insertion at the edge of a replacement doesn't overlap it
+        let (edits, applied) = merge_fixes(vec![
+            vec![edit(5, 5, "a")],
+            vec![edit(5, 8, "b")],
+            vec![edit(8, 8, "c")],
+        ]);
+        assert_eq!(applied, 3);
+        assert_eq!(
+            edits,
+            vec![edit(5, 5, "a"), edit(5, 8, "b"), edit(8, 8, "c")]
+        );
+    }
+
+    fn text_edit() -> TextEdit {
+        TextEdit {
+            range: Range::new(Position::new(0, 0), Position::new(0, 1)),
+            new_text: "x".to_string(),
+        }
+    }
+
+    fn fix(edit: WorkspaceEdit) -> lsp_types::CodeAction {
+        lsp_types::CodeAction {
+            title: "fix".to_string(),
+            edit: Some(edit),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fix_edits() {
+        let uri = Url::parse("file:///a.rs").unwrap();
+        let other = Url::parse("file:///b.rs").unwrap();
+
+        let mut changes = HashMap::new();
+        changes.insert(uri.clone(), vec![text_edit()]);
+        let local = fix(WorkspaceEdit::new(changes.clone()));
+        assert_eq!(fix_edits(&local, &uri), Some(vec![text_edit()]));
+
+        changes.insert(other.clone(), vec![text_edit()]);
+        assert_eq!(fix_edits(&fix(WorkspaceEdit::new(changes)), &uri), None);
+
+        let document_edit = |uri: &Url| TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: None,
+            },
+            edits: vec![OneOf::Left(text_edit())],
+        };
+        let edits = fix(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![document_edit(
+                &uri,
+            )])),
+            ..Default::default()
+        });
+        assert_eq!(fix_edits(&edits, &uri), Some(vec![text_edit()]));
+
+        let with_rename = fix(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Edit(document_edit(&uri)),
+                DocumentChangeOperation::Op(
+                    serde_json::from_value::<ResourceOp>(serde_json::json!({
+                        "kind": "rename",
+                        "oldUri": uri,
+                        "newUri": other,
+                    }))
+                    .unwrap(),
+                ),
+            ])),
+            ..Default::default()
+        });
+        assert_eq!(fix_edits(&with_rename, &uri), None);
+
+        let mut with_command = local.clone();
+        with_command.command = Some(lsp_types::Command::new(
+            "run".to_string(),
+            "run".to_string(),
+            None,
+        ));
+        assert_eq!(fix_edits(&with_command, &uri), None);
+
+        let mut to_resolve = local.clone();
+        to_resolve.data = Some(serde_json::json!({ "id": 1 }));
+        assert_eq!(fix_edits(&to_resolve, &uri), None);
+
+        let mut without_edit = local;
+        without_edit.edit = None;
+        assert_eq!(fix_edits(&without_edit, &uri), None);
+    }
 }
@@ -0,0 +1,139 @@
+use std::path::PathBuf;
+
+use druid::{ExtEventSink, Target};
+use lsp_types::{CodeLens, Location};
+use serde::Deserialize;
+
+use crate::{
+    buffer::BufferId,
+    command::{LapceUICommand, LAPCE_UI_COMMAND},
+    proxy::LapceProxy,
+};
+
+/// Drawn between the lenses that share a line.
+pub const CODE_LENS_SEPARATOR: &str = " | ";
+
+/// A code lens anchored to a buffer offset. It moves along with edits until
+/// fresh lenses arrive for the new revision.
+#[derive(Clone, Debug)]
+pub struct EditorCodeLens {
+    pub offset: usize,
+    pub lens: CodeLens,
+}
+
+impl EditorCodeLens {
+    pub fn title(&self) -> String {
+        self.lens
+            .command
+            .as_ref()
+            .map(|c| c.title.replace('\n', " "))
+            .unwrap_or_else(|| "...".to_string())
+    }
+}
+
+/// Asks the server for the command of a lens that came without one.
+pub fn resolve_code_lens(
+    proxy: &LapceProxy,
+    buffer_id: BufferId,
+    path: PathBuf,
+    rev: u64,
+    lens: &CodeLens,
+    event_sink: ExtEventSink,
+) {
+    proxy.resolve_code_lens(
+        buffer_id,
+        lens,
+        Box::new(move |result| {
+            if let Ok(res) = result {
+                if let Ok(lens) = serde_json::from_value::<CodeLens>(res) {
+                    event_sink.submit_command(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::ResolveCodeLens(path, rev, lens),
+                        Target::Auto,
+                    );
+                }
+            }
+        }),
+    );
+}
+
+/// What clicking a lens does, for the rust-analyzer commands that are meant
+/// to be run by the client rather than sent back to the server.
+pub enum ClientCommand {
+    RunInTerminal(String),
+    ShowReferences(Vec<Location>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Runnable {
+    kind: String,
+    args: CargoRunnable,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CargoRunnable {
+    workspace_root: Option<PathBuf>,
+    #[serde(default)]
+    cargo_args: Vec<String>,
+    #[serde(default)]
+    cargo_extra_args: Vec<String>,
+    #[serde(default)]
+    executable_args: Vec<String>,
+}
+
+impl ClientCommand {
+    pub fn from_command(
+        command: &lsp_types::Command,
+        workspace: Option<&PathBuf>,
+    ) -> Option<ClientCommand> {
+        let arguments = command.arguments.as_ref()?;
+        match command.command.as_str() {
+            "rust-analyzer.runSingle" | "rust-analyzer.debugSingle" => {
+                let runnable: Runnable =
+                    serde_json::from_value(arguments.get(0)?.clone()).ok()?;
+                if runnable.kind != "cargo" {
+                    return None;
+                }
+                let args = runnable.args;
+                let mut cmd = vec!["cargo".to_string()];
+                cmd.extend(args.cargo_args.iter().map(|a| shell_quote(a)));
+                cmd.extend(args.cargo_extra_args.iter().map(|a| shell_quote(a)));
+                if args.executable_args.len() > 0 {
+                    cmd.push("--".to_string());
+                    cmd.extend(args.executable_args.iter().map(|a| shell_quote(a)));
+                }
+                let cmd = cmd.join(" ");
+                let cmd = match args.workspace_root {
+                    Some(root) if Some(&root) != workspace => {
+                        format!(
+                            "cd {} && {}",
+                            shell_quote(&root.to_string_lossy()),
+                            cmd
+                        )
+                    }
+                    _ => cmd,
+                };
+                Some(ClientCommand::RunInTerminal(cmd))
+            }
+            "rust-analyzer.showReferences" => {
+                let locations: Vec<Location> =
+                    serde_json::from_value(arguments.get(2)?.clone()).ok()?;
+                Some(ClientCommand::ShowReferences(locations))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    if s.len() > 0
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=+,@".contains(c))
+    {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
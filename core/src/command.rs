@@ -4,10 +4,15 @@ use alacritty_terminal::ansi::CursorShape;
 use anyhow::Result;
 use druid::{Point, Rect, Selector, Size, WidgetId};
 use indexmap::IndexMap;
+use lapce_proxy::buffer::PositionEncoding;
+use lapce_proxy::inline_completion::InlineCompletionItem;
+use lapce_proxy::lsp::{LspLogResponse, LspStatus};
 use lapce_proxy::terminal::TermId;
 use lsp_types::{
-    CodeActionResponse, CompletionItem, CompletionResponse, Location, Position,
-    PublishDiagnosticsParams, Range, TextEdit,
+    CallHierarchyItem, CodeAction, CodeActionOrCommand, CodeActionResponse,
+    CodeLens, CompletionItem, CompletionResponse, DocumentHighlight, Location,
+    Position, PrepareRenameResponse, PublishDiagnosticsParams, Range,
+    ShowMessageRequestParams, SignatureHelp, TextEdit, WorkspaceEdit,
 };
 use serde_json::Value;
 use strum::{self, EnumMessage, IntoEnumIterator};
@@ -19,8 +24,10 @@ use xi_rope::spans::Spans;
 use crate::{
     buffer::BufferId,
     buffer::{InvalLines, Style},
-    data::EditorKind,
+    data::{EditorKind, WorkspaceEditRest},
     editor::{EditorLocation, EditorLocationNew, HighlightTextLayout},
+    hierarchy::{HierarchyKind, HierarchyNode},
+    inlay_hint::InlayHint,
     movement::{LinePosition, Movement},
     palette::{NewPaletteItem, PaletteType},
     split::SplitMoveDirection,
@@ -130,6 +137,9 @@ pub enum LapceWorkbenchCommand {
     #[strum(serialize = "palette.symbol")]
     PaletteSymbol,
 
+    #[strum(serialize = "palette.workspace_symbol")]
+    PaletteWorkspaceSymbol,
+
     #[strum(serialize = "palette.command")]
     PaletteCommand,
 
@@ -139,6 +149,25 @@ pub enum LapceWorkbenchCommand {
     #[strum(serialize = "toggle_terminal")]
     ToggleTerminal,
 
+    #[strum(serialize = "toggle_problem")]
+    ToggleProblem,
+
+    #[strum(serialize = "toggle_lsp_log")]
+    #[strum(message = "Toggle Language Server Log")]
+    ToggleLspLog,
+
+    #[strum(serialize = "toggle_inlay_hints")]
+    #[strum(message = "Toggle Inlay Hints")]
+    ToggleInlayHints,
+
+    #[strum(serialize = "toggle_code_lens")]
+    #[strum(message = "Toggle Code Lens")]
+    ToggleCodeLens,
+
+    #[strum(serialize = "toggle_inline_completion")]
+    #[strum(message = "Toggle Inline Completion")]
+    ToggleInlineCompletion,
+
     #[strum(serialize = "toggle_maximized_panel")]
     ToggleMaximizedPanel,
 
@@ -161,6 +190,20 @@ pub enum LapceCommand {
     SourceControlCancel,
     #[strum(serialize = "code_actions.cancel")]
     CodeActionsCancel,
+    #[strum(serialize = "rename.cancel")]
+    RenameCancel,
+    #[strum(serialize = "rename.confirm")]
+    RenameConfirm,
+    #[strum(serialize = "problem.cancel")]
+    ProblemCancel,
+    #[strum(serialize = "hierarchy.cancel")]
+    HierarchyCancel,
+    #[strum(serialize = "lsp_log.cancel")]
+    LspLogCancel,
+    #[strum(serialize = "signature.next")]
+    SignatureNext,
+    #[strum(serialize = "signature.previous")]
+    SignaturePrevious,
     #[strum(serialize = "palette.cancel")]
     PaletteCancel,
     #[strum(serialize = "delete_backward")]
@@ -281,6 +324,29 @@ pub enum LapceCommand {
     CenterOfWindow,
     #[strum(serialize = "goto_definition")]
     GotoDefinition,
+    #[strum(serialize = "goto_type_definition")]
+    #[strum(message = "Go to Type Definition")]
+    GotoTypeDefinition,
+    #[strum(serialize = "goto_implementation")]
+    #[strum(message = "Go to Implementation")]
+    GotoImplementation,
+    #[strum(serialize = "goto_declaration")]
+    #[strum(message = "Go to Declaration")]
+    GotoDeclaration,
+    #[strum(serialize = "peek_definition")]
+    #[strum(message = "Peek Definition")]
+    PeekDefinition,
+    #[strum(serialize = "peek.close")]
+    PeekClose,
+    #[strum(serialize = "show_call_hierarchy")]
+    #[strum(message = "Show Call Hierarchy")]
+    ShowCallHierarchy,
+    #[strum(serialize = "show_type_hierarchy")]
+    #[strum(message = "Show Type Hierarchy")]
+    ShowTypeHierarchy,
+    #[strum(serialize = "show_signature_help")]
+    #[strum(message = "Show Signature Help")]
+    ShowSignatureHelp,
     #[strum(serialize = "jump_location_backward")]
     JumpLocationBackward,
     #[strum(serialize = "jump_location_forward")]
@@ -291,10 +357,31 @@ pub enum LapceCommand {
     PreviousError,
     #[strum(serialize = "document_formatting")]
     DocumentFormatting,
+    #[strum(serialize = "format_selection")]
+    #[strum(message = "Format Selection")]
+    FormatSelection,
+    #[strum(serialize = "restart_language_server")]
+    #[strum(message = "Restart Language Server")]
+    RestartLanguageServer,
     #[strum(serialize = "save")]
     Save,
     #[strum(serialize = "show_code_actions")]
     ShowCodeActions,
+    #[strum(serialize = "fix_all_in_file")]
+    #[strum(message = "Fix All in File")]
+    FixAllInFile,
+    #[strum(serialize = "rename_symbol")]
+    #[strum(message = "Rename Symbol")]
+    RenameSymbol,
+    #[strum(serialize = "select_next_highlight")]
+    #[strum(message = "Select Next Occurrence")]
+    SelectNextHighlight,
+    #[strum(serialize = "select_previous_highlight")]
+    #[strum(message = "Select Previous Occurrence")]
+    SelectPreviousHighlight,
+    #[strum(serialize = "select_all_highlights")]
+    #[strum(message = "Select All Occurrences")]
+    SelectAllHighlights,
     #[strum(serialize = "match_pairs")]
     MatchPairs,
     #[strum(serialize = "next_unmatched_right_bracket")]
@@ -303,6 +390,12 @@ pub enum LapceCommand {
     JumpToNextSnippetPlaceholder,
     #[strum(serialize = "jump_to_prev_snippet_placeholder")]
     JumpToPrevSnippetPlaceholder,
+    #[strum(serialize = "inline_completion.accept")]
+    #[strum(message = "Accept Inline Completion")]
+    InlineCompletionAccept,
+    #[strum(serialize = "inline_completion.accept_word")]
+    #[strum(message = "Accept Next Word of Inline Completion")]
+    InlineCompletionAcceptWord,
     #[strum(serialize = "previous_unmatched_left_bracket")]
     PreviousUnmatchedLeftBracket,
     #[strum(serialize = "next_unmatched_right_curly_bracket")]
@@ -386,15 +479,47 @@ pub enum LapceUICommand {
     CancelCompletion(usize),
     ResolveCompletion(BufferId, u64, usize, CompletionItem),
     UpdateCompletion(usize, String, CompletionResponse),
+    UpdateCompletionResolved(usize, String, CompletionItem),
+    UpdateLocalCompletion(usize, Vec<CompletionItem>),
     UpdateCodeActions(PathBuf, u64, usize, CodeActionResponse),
+    UpdateInlayHints(PathBuf, u64, Vec<InlayHint>),
+    UpdateInlayHint(PathBuf, u64, usize, InlayHint),
+    UpdateInlineCompletion(PathBuf, u64, usize, Vec<InlineCompletionItem>),
+    UpdateCodeLens(PathBuf, u64, Vec<CodeLens>),
+    ResolveCodeLens(PathBuf, u64, CodeLens),
+    RunInTerminal(String),
+    ShowPeek(WidgetId, usize, EditorLocationNew),
+    ClosePeek,
+    ShowHierarchy(HierarchyKind, BufferId, Vec<CallHierarchyItem>),
+    UpdateHierarchyChildren(u64, usize, Vec<HierarchyNode>),
+    ShowCallSites(PathBuf, Vec<Range>),
+    UpdateSignature(usize, usize, Option<SignatureHelp>),
+    UpdateDocumentHighlights(PathBuf, u64, usize, Option<Vec<DocumentHighlight>>),
     CancelPalette,
     ShowCodeActions,
     CancelCodeActions,
+    RunCodeAction(BufferId, CodeActionOrCommand),
+    ApplyCodeAction(BufferId, CodeAction),
+    PrepareRename(PathBuf, u64, usize, PrepareRenameResponse),
+    ShowRename,
+    CancelRename,
+    ApplyWorkspaceEdit(WorkspaceEdit),
+    ContinueWorkspaceEdit(Arc<WorkspaceEditRest>),
+    ApplyServerEdit(String, Value, WorkspaceEdit),
+    ShowMessageRequest(String, Value, ShowMessageRequestParams),
+    UpdateLspStatus(String, LspStatus),
+    ShowError(String),
+    UpdateLspLog(u64, LspLogResponse),
+    UpdatePositionEncoding(PathBuf, PositionEncoding),
     Focus,
     FocusSourceControl,
+    FocusProblem,
+    FocusHierarchy,
+    FocusLspLog,
     FocusEditor,
     RunPalette(Option<PaletteType>),
     RunPaletteReferences(Vec<EditorLocationNew>),
+    RunPaletteMessageRequest(String, Value, ShowMessageRequestParams),
     UpdatePaletteItems(String, Vec<NewPaletteItem>),
     FilterPaletteItems(String, String, Vec<NewPaletteItem>),
     UpdateWindowOrigin,
@@ -416,8 +541,17 @@ pub enum LapceUICommand {
     ApplyEdits(usize, u64, Vec<TextEdit>),
     ApplyEditsAndSave(usize, u64, Result<Value>),
     DocumentFormatAndSave(PathBuf, u64, Result<Value>),
+    DocumentFormat(PathBuf, u64, Result<Value>),
+    FixAllInFile(PathBuf, u64, Result<Value>),
+    OnTypeFormat(PathBuf, u64, Result<Value>),
     BufferSave(PathBuf, u64),
-    UpdateSemanticTokens(BufferId, PathBuf, u64, Vec<(usize, usize, String)>),
+    UpdateSemanticTokens(
+        BufferId,
+        PathBuf,
+        u64,
+        Option<(usize, usize)>,
+        Vec<(usize, usize, String, Vec<String>)>,
+    ),
     UpdateHighlights(BufferId, u64, Vec<(usize, usize, Highlight)>),
     UpdateTerminalTitle(TermId, String),
     UpdateStyle {
@@ -1,9 +1,17 @@
-use std::{cmp::Ordering, collections::HashMap, fmt::Display, sync::Arc};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    sync::Arc,
+};
 
 use anyhow::Error;
 use bit_vec::BitVec;
 use druid::{
-    piet::{Svg, Text, TextAttribute, TextLayoutBuilder},
+    piet::{
+        PietText, PietTextLayout, Svg, Text, TextAttribute,
+        TextLayout as TextLayoutTrait, TextLayoutBuilder,
+    },
     scroll_component::ScrollComponent,
     theme,
     widget::SvgData,
@@ -21,11 +29,12 @@ use std::str::FromStr;
 use crate::{
     buffer::BufferId,
     command::{LapceUICommand, LAPCE_UI_COMMAND},
-    config::LapceTheme,
+    config::{Config, LapceTheme},
     data::LapceTabData,
     movement::Movement,
     proxy::LapceProxy,
     scroll::{LapceIdentityWrapper, LapceScrollNew},
+    signature::documentation_text,
     svg::completion_svg,
     theme::OldLapceTheme,
 };
@@ -53,11 +62,21 @@ impl Snippet {
             } else if let Some((ele, end)) = Self::extract_placeholder(s, pos) {
                 elements.push(ele);
                 pos = end;
+            } else if let Some((ele, end)) = Self::extract_choice(s, pos) {
+                elements.push(ele);
+                pos = end;
+            } else if let Some((ele, end)) = Self::extract_variable(s, pos) {
+                elements.push(ele);
+                pos = end;
             } else if let Some((ele, end)) =
                 Self::extract_text(s, pos, escs.clone(), loose_escs.clone())
             {
                 elements.push(ele);
                 pos = end;
+            } else if s[pos..].starts_with('$') {
+                // a `$` that starts nothing is just a dollar sign
+                elements.push(SnippetElement::Text("$".to_string()));
+                pos += 1;
             } else {
                 break;
             }
@@ -78,7 +97,11 @@ impl Snippet {
             }
         }
 
-        None
+        let re = Regex::new(r#"^\$\{(\d+)/"#).unwrap();
+        let caps = re.captures(&s[pos..])?;
+        let tab = caps.get(1)?.as_str().parse::<usize>().ok()?;
+        let (transform, end) = Self::extract_transform(s, pos + caps.get(0)?.end())?;
+        Some((SnippetElement::Transform(tab, transform), end))
     }
 
     fn extract_placeholder(s: &str, pos: usize) -> Option<(SnippetElement, usize)> {
@@ -105,6 +128,100 @@ impl Snippet {
         Some((SnippetElement::PlaceHolder(tab, els), pos + 1))
     }
 
+    fn extract_choice(s: &str, pos: usize) -> Option<(SnippetElement, usize)> {
+        let re = Regex::new(r#"^\$\{(\d+)\|((?:[^|\\]|\\.)*)\|\}"#).unwrap();
+        let caps = re.captures(&s[pos..])?;
+        let tab = caps.get(1)?.as_str().parse::<usize>().ok()?;
+
+        let mut choices = vec![String::new()];
+        let mut chars = caps.get(2)?.as_str().chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some(c) = chars.next() {
+                        choices.last_mut().unwrap().push(c);
+                    }
+                }
+                ',' => choices.push(String::new()),
+                c => choices.last_mut().unwrap().push(c),
+            }
+        }
+        Some((
+            SnippetElement::Choice(tab, choices),
+            pos + caps.get(0)?.end(),
+        ))
+    }
+
+    fn extract_variable(s: &str, pos: usize) -> Option<(SnippetElement, usize)> {
+        for re in &[
+            Regex::new(r#"^\$([A-Za-z_][A-Za-z0-9_]*)"#).unwrap(),
+            Regex::new(r#"^\$\{([A-Za-z_][A-Za-z0-9_]*)\}"#).unwrap(),
+        ] {
+            if let Some(caps) = re.captures(&s[pos..]) {
+                let name = caps.get(1)?.as_str().to_string();
+                let end = pos + caps.get(0)?.end();
+                return Some((SnippetElement::Variable(name, Vec::new()), end));
+            }
+        }
+
+        let re = Regex::new(r#"^\$\{([A-Za-z_][A-Za-z0-9_]*)([:/])"#).unwrap();
+        let caps = re.captures(&s[pos..])?;
+        let name = caps.get(1)?.as_str().to_string();
+        let start = pos + caps.get(0)?.end();
+        if caps.get(2)?.as_str() == "/" {
+            let (transform, end) = Self::extract_transform(s, start)?;
+            return Some((SnippetElement::VariableTransform(name, transform), end));
+        }
+        let (els, end) =
+            Self::extract_elements(s, start, vec!["$", "}", "\\"], vec![]);
+        if !s[end..].starts_with('}') {
+            return None;
+        }
+        Some((SnippetElement::Variable(name, els), end + 1))
+    }
+
+    /// The `regex/format/flags}` part of a transformation.
+    fn extract_transform(s: &str, pos: usize) -> Option<(SnippetTransform, usize)> {
+        let (regex, pos) = Self::until_slash(s, pos, false)?;
+        let (format, pos) = Self::until_slash(s, pos, true)?;
+        let flags_end = pos + s[pos..].find('}')?;
+        let flags = &s[pos..flags_end];
+        if !flags.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        Some((
+            SnippetTransform {
+                regex: regex.replace("\\/", "/"),
+                format,
+                flags: flags.to_string(),
+            },
+            flags_end + 1,
+        ))
+    }
+
+    /// The text up to the next unescaped `/` and the position after it. A
+    /// `/` in a `${..}` of a format, like `${1:/upcase}`, doesn't count.
+    fn until_slash(s: &str, pos: usize, format: bool) -> Option<(String, usize)> {
+        let mut depth = 0;
+        let mut escaped = false;
+        for (i, c) in s[pos..].char_indices() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match c {
+                '\\' => escaped = true,
+                '{' if format && s[..pos + i].ends_with('$') => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                '/' if depth == 0 => {
+                    return Some((s[pos..pos + i].to_string(), pos + i + 1));
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
     fn extract_text(
         s: &str,
         pos: usize,
@@ -116,25 +233,23 @@ impl Snippet {
         let mut end = pos;
 
         while s.len() > 0 {
-            if s.len() >= 2 {
-                let esc = &s[..2];
-                let mut new_escs = escs.clone();
-                new_escs.extend_from_slice(&loose_escs);
-                let new_escs: Vec<String> =
-                    new_escs.iter().map(|e| format!("\\{}", e)).collect();
-                if new_escs.contains(&esc.to_string()) {
-                    ele = ele + &s[1..2].to_string();
-                    end += 2;
-                    s = &s[2..];
-                    continue;
-                }
+            if let Some(esc) = escs
+                .iter()
+                .chain(loose_escs.iter())
+                .find(|e| s.starts_with(&format!("\\{}", e)))
+            {
+                ele = ele + esc;
+                end += 1 + esc.len();
+                s = &s[1 + esc.len()..];
+                continue;
             }
-            if escs.contains(&&s[0..1]) {
+            if escs.iter().any(|e| s.starts_with(e)) {
                 break;
             }
-            ele = ele + &s[0..1].to_string();
-            end += 1;
-            s = &s[1..];
+            let len = s.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+            ele = ele + &s[0..len];
+            end += len;
+            s = &s[len..];
         }
         if ele.len() == 0 {
             return None;
@@ -142,39 +257,173 @@ impl Snippet {
         Some((SnippetElement::Text(ele), end))
     }
 
+    /// Replaces the variables with their values. `resolve` gives `None` for
+    /// the variables it doesn't know, which are inserted by name, as other
+    /// editors do.
+    pub fn resolve_variables(&mut self, resolve: &dyn Fn(&str) -> Option<String>) {
+        Self::resolve_elements(&mut self.elements, resolve);
+    }
+
+    fn resolve_elements(
+        elements: &mut Vec<SnippetElement>,
+        resolve: &dyn Fn(&str) -> Option<String>,
+    ) {
+        let mut resolved = Vec::new();
+        for el in elements.drain(..) {
+            match el {
+                SnippetElement::Variable(name, mut default) => {
+                    match resolve(&name) {
+                        Some(value) if value != "" => {
+                            resolved.push(SnippetElement::Text(value))
+                        }
+                        _ if !default.is_empty() => {
+                            Self::resolve_elements(&mut default, resolve);
+                            resolved.extend(default);
+                        }
+                        Some(_) => (),
+                        None => resolved.push(SnippetElement::Text(name)),
+                    }
+                }
+                SnippetElement::VariableTransform(name, transform) => {
+                    let value = resolve(&name).unwrap_or_default();
+                    resolved.push(SnippetElement::Text(transform.apply(&value)));
+                }
+                SnippetElement::PlaceHolder(tab, mut els) => {
+                    Self::resolve_elements(&mut els, resolve);
+                    resolved.push(SnippetElement::PlaceHolder(tab, els));
+                }
+                el => resolved.push(el),
+            }
+        }
+        *elements = resolved;
+    }
+
+    /// The text of each tab as inserted, from its first placeholder or
+    /// choice. Mirrors of the tab start out with it.
+    fn tab_values(&self) -> HashMap<usize, String> {
+        fn collect(
+            elements: &[SnippetElement],
+            values: &mut HashMap<usize, String>,
+        ) {
+            for el in elements {
+                match el {
+                    SnippetElement::PlaceHolder(tab, els) => {
+                        if !values.contains_key(tab) {
+                            let text = els.iter().map(|e| e.text()).join("");
+                            values.insert(*tab, text);
+                        }
+                        collect(els, values);
+                    }
+                    SnippetElement::Choice(tab, choices) => {
+                        if !values.contains_key(tab) {
+                            values.insert(
+                                *tab,
+                                choices.first().cloned().unwrap_or_default(),
+                            );
+                        }
+                    }
+                    SnippetElement::Variable(_, els) => collect(els, values),
+                    _ => (),
+                }
+            }
+        }
+        let mut values = HashMap::new();
+        collect(&self.elements, &mut values);
+        values
+    }
+
     pub fn text(&self) -> String {
-        self.elements.iter().map(|e| e.text()).join("")
+        let values = self.tab_values();
+        self.elements.iter().map(|e| e.render(&values)).join("")
     }
 
     pub fn tabs(&self, pos: usize) -> Vec<(usize, (usize, usize))> {
-        Self::elements_tabs(&self.elements, pos)
+        Self::elements_tabs(&self.elements, pos, &self.tab_values())
     }
 
     pub fn elements_tabs(
         elements: &[SnippetElement],
         start: usize,
+        values: &HashMap<usize, String>,
     ) -> Vec<(usize, (usize, usize))> {
         let mut tabs = Vec::new();
         let mut pos = start;
         for el in elements {
+            let end = pos + el.render(values).len();
             match el {
-                SnippetElement::Text(t) => {
-                    pos += t.len();
-                }
                 SnippetElement::PlaceHolder(tab, els) => {
-                    let placeholder_tabs = Self::elements_tabs(els, pos);
-                    let end = pos + els.iter().map(|e| e.len()).sum::<usize>();
                     tabs.push((*tab, (pos, end)));
-                    tabs.extend_from_slice(&placeholder_tabs);
-                    pos = end;
+                    tabs.extend(Self::elements_tabs(els, pos, values));
                 }
-                SnippetElement::Tabstop(tab) => {
-                    tabs.push((*tab, (pos, pos)));
+                SnippetElement::Variable(_, els) => {
+                    tabs.extend(Self::elements_tabs(els, pos, values));
                 }
+                SnippetElement::Tabstop(tab) | SnippetElement::Choice(tab, _) => {
+                    tabs.push((*tab, (pos, end)));
+                }
+                SnippetElement::Text(_)
+                | SnippetElement::Transform(_, _)
+                | SnippetElement::VariableTransform(_, _) => {}
             }
+            pos = end;
         }
         tabs
     }
+
+    /// The mirrors that show a tab through a transformation, with where
+    /// they are when the snippet is inserted at `pos`.
+    pub fn transforms(
+        &self,
+        pos: usize,
+    ) -> Vec<(usize, (usize, usize), SnippetTransform)> {
+        fn collect(
+            elements: &[SnippetElement],
+            start: usize,
+            values: &HashMap<usize, String>,
+            transforms: &mut Vec<(usize, (usize, usize), SnippetTransform)>,
+        ) {
+            let mut pos = start;
+            for el in elements {
+                let end = pos + el.render(values).len();
+                match el {
+                    SnippetElement::Transform(tab, transform) => {
+                        transforms.push((*tab, (pos, end), transform.clone()));
+                    }
+                    SnippetElement::PlaceHolder(_, els)
+                    | SnippetElement::Variable(_, els) => {
+                        collect(els, pos, values, transforms);
+                    }
+                    _ => (),
+                }
+                pos = end;
+            }
+        }
+        let mut transforms = Vec::new();
+        collect(&self.elements, pos, &self.tab_values(), &mut transforms);
+        transforms
+    }
+
+    /// The options of the choice tabs.
+    pub fn choices(&self) -> HashMap<usize, Vec<String>> {
+        fn collect(
+            elements: &[SnippetElement],
+            choices: &mut HashMap<usize, Vec<String>>,
+        ) {
+            for el in elements {
+                match el {
+                    SnippetElement::Choice(tab, options) => {
+                        choices.entry(*tab).or_insert_with(|| options.clone());
+                    }
+                    SnippetElement::PlaceHolder(_, els)
+                    | SnippetElement::Variable(_, els) => collect(els, choices),
+                    _ => (),
+                }
+            }
+        }
+        let mut choices = HashMap::new();
+        collect(&self.elements, &mut choices);
+        choices
+    }
 }
 
 impl FromStr for Snippet {
@@ -198,26 +447,41 @@ pub enum SnippetElement {
     Text(String),
     PlaceHolder(usize, Vec<SnippetElement>),
     Tabstop(usize),
+    Choice(usize, Vec<String>),
+    /// A tab mirrored through a transformation, `${1/regex/format/flags}`.
+    Transform(usize, SnippetTransform),
+    /// A variable with its default, which is empty when there's none.
+    Variable(String, Vec<SnippetElement>),
+    VariableTransform(String, SnippetTransform),
 }
 
 impl SnippetElement {
     pub fn len(&self) -> usize {
-        match &self {
-            SnippetElement::Text(text) => text.len(),
-            SnippetElement::PlaceHolder(_, elements) => {
-                elements.iter().map(|e| e.len()).sum()
-            }
-            SnippetElement::Tabstop(_) => 0,
-        }
+        self.text().len()
     }
 
+    /// The text of the element on its own, where mirrors are empty.
     pub fn text(&self) -> String {
+        self.render(&HashMap::new())
+    }
+
+    fn render(&self, values: &HashMap<usize, String>) -> String {
         match &self {
             SnippetElement::Text(t) => t.to_string(),
-            SnippetElement::PlaceHolder(_, elements) => {
-                elements.iter().map(|e| e.text()).join("")
+            SnippetElement::PlaceHolder(_, elements)
+            | SnippetElement::Variable(_, elements) => {
+                elements.iter().map(|e| e.render(values)).join("")
+            }
+            SnippetElement::Tabstop(tab) => {
+                values.get(tab).cloned().unwrap_or_default()
+            }
+            SnippetElement::Choice(_, choices) => {
+                choices.first().cloned().unwrap_or_default()
+            }
+            SnippetElement::Transform(tab, transform) => {
+                transform.apply(values.get(tab).map(|v| v.as_str()).unwrap_or(""))
             }
-            SnippetElement::Tabstop(_) => "".to_string(),
+            SnippetElement::VariableTransform(_, _) => "".to_string(),
         }
     }
 }
@@ -231,10 +495,252 @@ impl Display for SnippetElement {
                 write!(f, "${{{}:{}}}", tab, elements)
             }
             SnippetElement::Tabstop(tab) => write!(f, "${}", tab),
+            SnippetElement::Choice(tab, choices) => {
+                let choices = choices
+                    .iter()
+                    .map(|c| {
+                        c.replace('\\', "\\\\")
+                            .replace(',', "\\,")
+                            .replace('|', "\\|")
+                    })
+                    .join(",");
+                write!(f, "${{{}|{}|}}", tab, choices)
+            }
+            SnippetElement::Transform(tab, transform) => {
+                write!(f, "${{{}/{}}}", tab, transform)
+            }
+            SnippetElement::Variable(name, elements) => {
+                if elements.is_empty() {
+                    write!(f, "${{{}}}", name)
+                } else {
+                    let elements = elements.iter().map(|e| e.to_string()).join("");
+                    write!(f, "${{{}:{}}}", name, elements)
+                }
+            }
+            SnippetElement::VariableTransform(name, transform) => {
+                write!(f, "${{{}/{}}}", name, transform)
+            }
         }
     }
 }
 
+/// The `regex/format/flags` of a transformation. The format refers to the
+/// groups of the match with `$1`, `${1:/upcase}`, `${1:+if}`,
+/// `${1:?if:else}` and `${1:-else}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnippetTransform {
+    regex: String,
+    format: String,
+    flags: String,
+}
+
+impl SnippetTransform {
+    pub fn apply(&self, text: &str) -> String {
+        let mut pattern = String::new();
+        for flag in self.flags.chars().filter(|c| "ims".contains(*c)) {
+            pattern.push_str(&format!("(?{})", flag));
+        }
+        pattern.push_str(&self.regex);
+        let re = match Regex::new(&pattern) {
+            Ok(re) => re,
+            Err(_) => return text.to_string(),
+        };
+
+        let mut result = String::new();
+        let mut last = 0;
+        for caps in re.captures_iter(text) {
+            let m = caps.get(0).unwrap();
+            result.push_str(&text[last..m.start()]);
+            result.push_str(&self.expand(&caps));
+            last = m.end();
+            if !self.flags.contains('g') {
+                break;
+            }
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+
+    fn expand(&self, caps: &regex::Captures) -> String {
+        let group = |n: &str| {
+            n.parse::<usize>()
+                .ok()
+                .and_then(|n| caps.get(n))
+                .map(|m| m.as_str())
+                .unwrap_or("")
+        };
+        let simple = Regex::new(r#"^\$(?:(\d+)|\{(\d+)\})"#).unwrap();
+        let modified =
+            Regex::new(r#"^\$\{(\d+):([/+?\-]?)((?:[^}\\]|\\.)*)\}"#).unwrap();
+
+        let format = self.format.as_str();
+        let mut result = String::new();
+        let mut i = 0;
+        while i < format.len() {
+            let rest = &format[i..];
+            if let Some(caps) = simple.captures(rest) {
+                let n = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+                result.push_str(group(n));
+                i += caps.get(0).unwrap().end();
+            } else if let Some(caps) = modified.captures(rest) {
+                let value = group(&caps[1]);
+                let arg = unescape(&caps[3]);
+                match &caps[2] {
+                    "/" => result.push_str(&change_case(value, &arg)),
+                    "+" => {
+                        if value != "" {
+                            result.push_str(&arg);
+                        }
+                    }
+                    "?" => {
+                        let (if_text, else_text) = split_unescaped(&caps[3], ':');
+                        if value != "" {
+                            result.push_str(&unescape(if_text));
+                        } else {
+                            result.push_str(&unescape(else_text));
+                        }
+                    }
+                    _ => {
+                        if value != "" {
+                            result.push_str(value);
+                        } else {
+                            result.push_str(&arg);
+                        }
+                    }
+                }
+                i += caps.get(0).unwrap().end();
+            } else {
+                let mut chars = rest.chars();
+                let c = chars.next().unwrap();
+                if c == '\\' {
+                    if let Some(next) = chars.next() {
+                        result.push(next);
+                        i += next.len_utf8();
+                    }
+                } else {
+                    result.push(c);
+                }
+                i += c.len_utf8();
+            }
+        }
+        result
+    }
+}
+
+impl Display for SnippetTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}",
+            self.regex.replace('/', "\\/"),
+            self.format,
+            self.flags
+        )
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(c) = chars.next() {
+                result.push(c);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn split_unescaped(s: &str, sep: char) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == sep {
+            return (&s[..i], &s[i + c.len_utf8()..]);
+        }
+    }
+    (s, "")
+}
+
+fn change_case(s: &str, case: &str) -> String {
+    match case {
+        "upcase" => s.to_uppercase(),
+        "downcase" => s.to_lowercase(),
+        "capitalize" => {
+            let mut chars = s.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        }
+        "camelcase" | "pascalcase" => {
+            let mut result = String::new();
+            for (i, word) in s
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|w| !w.is_empty())
+                .enumerate()
+            {
+                let mut chars = word.chars();
+                let first = chars.next().unwrap();
+                if i == 0 && case == "camelcase" {
+                    result.extend(first.to_lowercase());
+                } else {
+                    result.extend(first.to_uppercase());
+                }
+                result.push_str(chars.as_str());
+            }
+            result
+        }
+        _ => s.to_string(),
+    }
+}
+
+/// The values of the date and time variables, in UTC.
+pub fn time_variable(name: &str) -> Option<String> {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    let days = (secs / 86400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    let time = secs % 86400;
+    Some(match name {
+        "CURRENT_YEAR" => year.to_string(),
+        "CURRENT_YEAR_SHORT" => format!("{:02}", year % 100),
+        "CURRENT_MONTH" => format!("{:02}", month),
+        "CURRENT_DATE" => format!("{:02}", day),
+        "CURRENT_HOUR" => format!("{:02}", time / 3600),
+        "CURRENT_MINUTE" => format!("{:02}", time % 3600 / 60),
+        "CURRENT_SECOND" => format!("{:02}", time % 60),
+        "CURRENT_SECONDS_UNIX" => secs.to_string(),
+        _ => return None,
+    })
+}
+
+/// The year, month and day of a count of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// How wide the documentation next to the list is.
+pub const COMPLETION_DOC_WIDTH: f64 = 400.0;
+const COMPLETION_DOC_PADDING: f64 = 5.0;
+
 #[derive(Clone, PartialEq)]
 pub enum CompletionStatus {
     Inactive,
@@ -252,8 +758,13 @@ pub struct CompletionData {
     pub input: String,
     pub index: usize,
     pub input_items: im::HashMap<String, Arc<Vec<ScoredCompletionItem>>>,
+    /// The items of the built-in providers, which are added after the
+    /// server's items of every input.
+    pub local_items: Arc<Vec<ScoredCompletionItem>>,
     empty: Arc<Vec<ScoredCompletionItem>>,
     pub filtered_items: Arc<Vec<ScoredCompletionItem>>,
+    /// The items the server has filled in, by `resolve_key`.
+    pub resolved: im::HashMap<String, Arc<CompletionItem>>,
     pub matcher: Arc<SkimMatcherV2>,
     pub size: Size,
 }
@@ -270,7 +781,9 @@ impl CompletionData {
             buffer_id: BufferId(0),
             input: "".to_string(),
             input_items: im::HashMap::new(),
+            local_items: Arc::new(Vec::new()),
             filtered_items: Arc::new(Vec::new()),
+            resolved: im::HashMap::new(),
             matcher: Arc::new(SkimMatcherV2::default().ignore_case()),
             size: Size::new(400.0, 300.0),
             empty: Arc::new(Vec::new()),
@@ -311,6 +824,103 @@ impl CompletionData {
         self.current_items()[self.index].item.label.as_str()
     }
 
+    pub fn resolved_item(
+        &self,
+        item: &CompletionItem,
+    ) -> Option<Arc<CompletionItem>> {
+        self.resolved.get(&resolve_key(item)).cloned()
+    }
+
+    /// The item that typing `c` accepts, when `c` is one of the commit
+    /// characters of the current item.
+    pub fn commit_item(
+        &self,
+        buffer_id: BufferId,
+        c: &str,
+    ) -> Option<Arc<CompletionItem>> {
+        if self.status == CompletionStatus::Inactive
+            || self.buffer_id != buffer_id
+            || self.len() == 0
+        {
+            return None;
+        }
+        let item = self.current_item();
+        if !item
+            .commit_characters
+            .as_ref()?
+            .iter()
+            .any(|commit| commit == c)
+        {
+            return None;
+        }
+        Some(
+            self.resolved_item(item)
+                .unwrap_or_else(|| Arc::new(item.clone())),
+        )
+    }
+
+    /// The detail and the documentation of the current item, taken from
+    /// its resolved version once that has arrived.
+    pub fn documentation(&self) -> Option<(String, String)> {
+        if self.status == CompletionStatus::Inactive || self.len() == 0 {
+            return None;
+        }
+        let item = self.current_item();
+        let resolved = self.resolved_item(item);
+        let item = resolved.as_deref().unwrap_or(item);
+        let detail = item.detail.clone().unwrap_or_default();
+        let doc = item
+            .documentation
+            .as_ref()
+            .map(documentation_text)
+            .unwrap_or_default();
+        if detail.trim() == "" && doc.trim() == "" {
+            return None;
+        }
+        Some((detail, doc))
+    }
+
+    pub fn resolve(
+        &self,
+        proxy: Arc<LapceProxy>,
+        item: CompletionItem,
+        event_sink: ExtEventSink,
+    ) {
+        let request_id = self.request_id;
+        let completion_widget_id = self.id;
+        let key = resolve_key(&item);
+        proxy.completion_resolve(
+            self.buffer_id,
+            item,
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(item) = serde_json::from_value::<CompletionItem>(res) {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateCompletionResolved(
+                                request_id, key, item,
+                            ),
+                            Target::Widget(completion_widget_id),
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
+    pub fn receive_resolved(
+        &mut self,
+        request_id: usize,
+        key: String,
+        item: CompletionItem,
+    ) {
+        if self.status == CompletionStatus::Inactive || self.request_id != request_id
+        {
+            return;
+        }
+        self.resolved.insert(key, Arc::new(item));
+    }
+
     pub fn request(
         &self,
         proxy: Arc<LapceProxy>,
@@ -353,6 +963,8 @@ impl CompletionData {
         self.status = CompletionStatus::Inactive;
         self.input = "".to_string();
         self.input_items.clear();
+        self.local_items = Arc::new(Vec::new());
+        self.resolved.clear();
         self.index = 0;
     }
 
@@ -380,20 +992,54 @@ impl CompletionData {
             CompletionResponse::Array(items) => items,
             CompletionResponse::List(list) => list.items,
         };
-        let items = items
+        let mut items = items
             .iter()
-            .map(|i| ScoredCompletionItem {
-                item: i.to_owned(),
-                score: 0,
-                index: 0,
-                indices: Vec::new(),
-            })
+            .map(|i| ScoredCompletionItem::new(i.to_owned()))
             .collect();
+        merge_items(&mut items, &self.local_items);
 
         self.input_items.insert(input, Arc::new(items));
         self.filter_items();
     }
 
+    pub fn receive_local(&mut self, request_id: usize, items: Vec<CompletionItem>) {
+        if self.status == CompletionStatus::Inactive || self.request_id != request_id
+        {
+            return;
+        }
+
+        let items: Vec<ScoredCompletionItem> =
+            items.into_iter().map(ScoredCompletionItem::new).collect();
+        if !self.input_items.contains_key("") {
+            self.input_items
+                .insert("".to_string(), Arc::new(Vec::new()));
+        }
+        for (_, input_items) in self.input_items.iter_mut() {
+            merge_items(Arc::make_mut(input_items), &items);
+        }
+        Arc::make_mut(&mut self.local_items).extend(items);
+        self.filter_items();
+    }
+
+    /// Shows `items` without asking a server, like the options of a
+    /// snippet choice.
+    pub fn show_local(
+        &mut self,
+        buffer_id: BufferId,
+        offset: usize,
+        items: Vec<CompletionItem>,
+    ) {
+        self.buffer_id = buffer_id;
+        self.offset = offset;
+        self.input = "".to_string();
+        self.index = 0;
+        self.status = CompletionStatus::Started;
+        self.input_items.clear();
+        self.local_items = Arc::new(Vec::new());
+        self.request_id += 1;
+        self.receive_local(self.request_id, items);
+    }
+
     pub fn filter_items(&mut self) {
         if self.input == "" {
             return;
@@ -427,6 +1073,38 @@ impl CompletionData {
     }
 }
 
+/// Adds the local items, leaving out the words that are labels of the
+/// server's items already.
+fn merge_items(
+    items: &mut Vec<ScoredCompletionItem>,
+    local: &[ScoredCompletionItem],
+) {
+    let labels: HashSet<String> =
+        items.iter().map(|i| i.item.label.clone()).collect();
+    items.extend(
+        local
+            .iter()
+            .filter(|i| {
+                i.item.kind != Some(CompletionItemKind::Text)
+                    || !labels.contains(&i.item.label)
+            })
+            .cloned(),
+    );
+}
+
+/// Tells items apart for resolving, since the server's data is what it
+/// needs to find an item again.
+fn resolve_key(item: &CompletionItem) -> String {
+    format!(
+        "{}{}",
+        item.label,
+        item.data
+            .as_ref()
+            .map(|data| data.to_string())
+            .unwrap_or_default()
+    )
+}
+
 pub struct CompletionContainer {
     id: WidgetId,
     scroll_id: WidgetId,
@@ -435,6 +1113,11 @@ pub struct CompletionContainer {
         LapceIdentityWrapper<LapceScrollNew<LapceTabData, CompletionNew>>,
     >,
     content_size: Size,
+    /// The items of the current request that were sent to be resolved.
+    resolving: HashSet<String>,
+    resolving_request_id: usize,
+    detail: Option<PietTextLayout>,
+    doc: Option<PietTextLayout>,
 }
 
 impl CompletionContainer {
@@ -448,7 +1131,85 @@ impl CompletionContainer {
             completion: WidgetPod::new(completion),
             scroll_id: data.scroll_id,
             content_size: Size::ZERO,
+            resolving: HashSet::new(),
+            resolving_request_id: 0,
+            detail: None,
+            doc: None,
+        }
+    }
+
+    /// Asks the server to fill in the current item, once per item, for the
+    /// documentation shown next to the list.
+    fn resolve_current(&mut self, ctx: &mut UpdateCtx, data: &LapceTabData) {
+        let completion = &data.completion;
+        if completion.status == CompletionStatus::Inactive || completion.len() == 0 {
+            return;
+        }
+        if self.resolving_request_id != completion.request_id {
+            self.resolving_request_id = completion.request_id;
+            self.resolving.clear();
+        }
+        let item = completion.current_item();
+        if item.data.is_none() {
+            return;
+        }
+        let key = resolve_key(item);
+        if completion.resolved.contains_key(&key) || !self.resolving.insert(key) {
+            return;
+        }
+        completion.resolve(
+            data.proxy.clone(),
+            item.clone(),
+            ctx.get_external_handle(),
+        );
+    }
+
+    fn build_doc_layouts(&mut self, text: &mut PietText, data: &LapceTabData) {
+        self.detail = None;
+        self.doc = None;
+        let (detail, doc) = match data.completion.documentation() {
+            Some(documentation) => documentation,
+            None => return,
+        };
+        let config: &Config = &data.config;
+        let max_width = COMPLETION_DOC_WIDTH - COMPLETION_DOC_PADDING * 2.0;
+        if detail.trim() != "" {
+            self.detail = Some(
+                text.new_text_layout(detail)
+                    .font(
+                        config.editor.font_family(),
+                        config.editor.font_size as f64,
+                    )
+                    .text_color(
+                        config.get_color_unchecked(LapceTheme::EDITOR_DIM).clone(),
+                    )
+                    .max_width(max_width)
+                    .build()
+                    .unwrap(),
+            );
+        }
+        if doc.trim() != "" {
+            self.doc = Some(
+                text.new_text_layout(doc)
+                    .font(FontFamily::SYSTEM_UI, 13.0)
+                    .text_color(
+                        config
+                            .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                            .clone(),
+                    )
+                    .max_width(max_width)
+                    .build()
+                    .unwrap(),
+            );
+        }
+    }
+
+    fn doc_height(&self) -> f64 {
+        let mut height = COMPLETION_DOC_PADDING;
+        for layout in self.detail.iter().chain(self.doc.iter()) {
+            height += layout.size().height + COMPLETION_DOC_PADDING;
         }
+        height
     }
 
     pub fn ensure_item_visble(
@@ -504,6 +1265,22 @@ impl Widget<LapceTabData> for CompletionContainer {
                             resp.to_owned(),
                         );
                     }
+                    LapceUICommand::UpdateLocalCompletion(request_id, items) => {
+                        let completion = Arc::make_mut(&mut data.completion);
+                        completion.receive_local(*request_id, items.to_owned());
+                    }
+                    LapceUICommand::UpdateCompletionResolved(
+                        request_id,
+                        key,
+                        item,
+                    ) => {
+                        let completion = Arc::make_mut(&mut data.completion);
+                        completion.receive_resolved(
+                            *request_id,
+                            key.to_owned(),
+                            item.to_owned(),
+                        );
+                    }
                     LapceUICommand::CancelCompletion(request_id) => {
                         if data.completion.request_id == *request_id {
                             let completion = Arc::make_mut(&mut data.completion);
@@ -578,6 +1355,20 @@ impl Widget<LapceTabData> for CompletionContainer {
             self.ensure_item_visble(ctx, data, env);
             ctx.request_paint();
         }
+
+        if old_completion.index != completion.index
+            || old_completion.request_id != completion.request_id
+            || old_completion.status != completion.status
+            || !old_completion
+                .current_items()
+                .same(&completion.current_items())
+        {
+            self.resolve_current(ctx, data);
+            ctx.request_layout();
+        }
+        if !old_completion.resolved.same(&completion.resolved) {
+            ctx.request_layout();
+        }
     }
 
     fn layout(
@@ -592,7 +1383,12 @@ impl Widget<LapceTabData> for CompletionContainer {
         self.content_size = self.completion.layout(ctx, &bc, data, env);
         self.completion.set_origin(ctx, data, env, Point::ZERO);
         ctx.set_paint_insets((10.0, 10.0, 10.0, 10.0));
-        size
+        self.build_doc_layouts(ctx.text(), data);
+        if self.detail.is_some() || self.doc.is_some() {
+            Size::new(size.width + COMPLETION_DOC_WIDTH, size.height)
+        } else {
+            size
+        }
     }
 
     fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
@@ -608,6 +1404,35 @@ impl Widget<LapceTabData> for CompletionContainer {
                     .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
             );
             self.completion.paint(ctx, data, env);
+
+            if self.detail.is_some() || self.doc.is_some() {
+                let rect = Size::new(
+                    COMPLETION_DOC_WIDTH,
+                    self.doc_height().min(data.completion.size.height),
+                )
+                .to_rect()
+                .with_origin(Point::new(self.content_size.width, 0.0));
+                ctx.blurred_rect(
+                    rect,
+                    shadow_width,
+                    data.config
+                        .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+                );
+                ctx.fill(
+                    rect,
+                    data.config
+                        .get_color_unchecked(LapceTheme::COMPLETION_BACKGROUND),
+                );
+                ctx.with_save(|ctx| {
+                    ctx.clip(rect);
+                    let x = rect.x0 + COMPLETION_DOC_PADDING;
+                    let mut y = COMPLETION_DOC_PADDING;
+                    for layout in self.detail.iter().chain(self.doc.iter()) {
+                        ctx.draw_text(layout, Point::new(x, y));
+                        y += layout.size().height + COMPLETION_DOC_PADDING;
+                    }
+                });
+            }
         }
     }
 }
@@ -762,6 +1587,17 @@ pub struct ScoredCompletionItem {
     indices: Vec<usize>,
 }
 
+impl ScoredCompletionItem {
+    fn new(item: CompletionItem) -> Self {
+        Self {
+            item,
+            score: 0,
+            index: 0,
+            indices: Vec::new(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct CompletionState {
     pub widget_id: WidgetId,
@@ -882,4 +1718,46 @@ mod tests {
             parsed.tabs(0)
         );
     }
+
+    #[test]
+    fn test_snippet_mirrors() {
+        let s = "${1|one,two\\,three|} $1 ${1/(.*)/${1:/upcase}/}";
+        let parsed = Snippet::from_str(s).unwrap();
+        assert_eq!(s, parsed.to_string());
+        assert_eq!("one one ONE", parsed.text());
+        assert_eq!(vec![(1, (0, 3)), (1, (4, 7))], parsed.tabs(0));
+        assert_eq!(
+            vec!["one".to_string(), "two,three".to_string()],
+            parsed.choices()[&1]
+        );
+
+        let transforms = parsed.transforms(0);
+        assert_eq!(1, transforms.len());
+        assert_eq!((1, (8, 11)), (transforms[0].0, transforms[0].1));
+        assert_eq!("FOO_BAR", transforms[0].2.apply("foo_bar"));
+    }
+
+    #[test]
+    fn test_snippet_variables() {
+        let s =
+            "${TM_FILENAME/(.*)\\..+$/$1/} $UNKNOWN ${TM_SELECTED_TEXT:${1:x}} $$";
+        let mut parsed = Snippet::from_str(s).unwrap();
+        parsed.resolve_variables(&|name| match name {
+            "TM_FILENAME" => Some("main.rs".to_string()),
+            "TM_SELECTED_TEXT" => Some("".to_string()),
+            _ => None,
+        });
+        assert_eq!("main UNKNOWN x $$", parsed.text());
+        assert_eq!(vec![(1, (13, 14))], parsed.tabs(0));
+    }
+
+    #[test]
+    fn test_snippet_transform_format() {
+        let transform = SnippetTransform {
+            regex: "(\\w+)-(\\w+)?".to_string(),
+            format: "${1:/capitalize}${2:?+:!}${2:-none}".to_string(),
+            flags: "g".to_string(),
+        };
+        assert_eq!("Foo+bar Baz!none", transform.apply("foo-bar baz-"));
+    }
 }
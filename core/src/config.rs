@@ -7,6 +7,7 @@ use druid::{
     theme, Color, Env, FontDescriptor, FontFamily, Key, Size,
 };
 use hashbrown::HashMap;
+use lapce_proxy::lsp::LspSettings;
 use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::{
@@ -37,6 +38,12 @@ impl LapceTheme {
     pub const EDITOR_CARET: &'static str = "editor.caret";
     pub const EDITOR_SELECTION: &'static str = "editor.selection";
     pub const EDITOR_CURRENT_LINE: &'static str = "editor.current_line";
+    pub const EDITOR_HIGHLIGHT_READ: &'static str = "editor.highlight.read";
+    pub const EDITOR_HIGHLIGHT_WRITE: &'static str = "editor.highlight.write";
+    pub const EDITOR_CALL_SITE: &'static str = "editor.call_site";
+
+    pub const INLAY_HINT_FOREGROUND: &'static str = "inlay_hint.foreground";
+    pub const INLAY_HINT_BACKGROUND: &'static str = "inlay_hint.background";
 
     pub const TERMINAL_CURSOR: &'static str = "terminal.cursor";
     pub const TERMINAL_BACKGROUND: &'static str = "terminal.background";
@@ -85,6 +92,14 @@ pub struct EditorConfig {
     pub font_family: String,
     pub font_size: usize,
     pub line_height: usize,
+    pub enable_inlay_hints: bool,
+    pub enable_code_lens: bool,
+    /// Whether an accepted completion replaces the word after the cursor
+    /// too, when the server offers both an insert and a replace range.
+    pub completion_replace: bool,
+    /// Whether a suggestion is shown as ghost text after the cursor while
+    /// typing.
+    pub enable_inline_completion: bool,
 }
 
 impl EditorConfig {
@@ -93,6 +108,16 @@ impl EditorConfig {
     }
 }
 
+/// How text with a semantic token modifier is drawn on top of its color,
+/// from theme entries like `"modifier.deprecated" = "strikethrough"`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModifierStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+}
+
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct Config {
     pub lapce: LapceConfig,
@@ -101,17 +126,92 @@ pub struct Config {
     pub theme: HashMap<String, Color>,
     #[serde(skip)]
     pub themes: HashMap<String, HashMap<String, Color>>,
+    /// The modifier styles of each theme, by modifier.
+    #[serde(skip)]
+    pub modifier_styles: HashMap<String, HashMap<String, ModifierStyle>>,
+    /// The `[lsp.<language>]` sections, which the proxy starts servers
+    /// from.
+    #[serde(skip)]
+    pub lsp: std::collections::HashMap<String, LspSettings>,
+    /// The user snippets by language, with the ones of every language
+    /// under `global`.
+    #[serde(skip)]
+    pub snippets: std::collections::HashMap<String, Vec<UserSnippet>>,
+    /// What was wrong in the settings and snippet files, and so left out
+    /// of the settings.
+    #[serde(skip)]
+    pub errors: Vec<String>,
+}
+
+/// A snippet of the user's snippet files, which completion offers when one
+/// of its prefixes is typed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserSnippet {
+    #[serde(skip)]
+    pub name: String,
+    #[serde(deserialize_with = "one_or_many")]
+    pub prefix: Vec<String>,
+    /// The lines of the snippet.
+    #[serde(deserialize_with = "one_or_many")]
+    pub body: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The languages a global snippet is for, separated by commas. It's for
+    /// all of them when there's none.
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
 }
 
 impl Config {
+    /// The user snippets for a file of `language_id`.
+    pub fn snippets(&self, language_id: Option<&str>) -> Vec<&UserSnippet> {
+        let mut snippets: Vec<&UserSnippet> = self
+            .snippets
+            .get("global")
+            .into_iter()
+            .flatten()
+            .filter(|snippet| match (snippet.scope.as_ref(), language_id) {
+                (None, _) => true,
+                (Some(scope), Some(language_id)) => {
+                    scope.split(',').any(|s| s.trim() == language_id)
+                }
+                (Some(_), None) => false,
+            })
+            .collect();
+        if let Some(language_id) = language_id {
+            snippets.extend(self.snippets.get(language_id).into_iter().flatten());
+        }
+        snippets
+    }
+
     pub fn load(workspace: Option<LapceWorkspace>) -> Result<Self> {
         let mut settings = config::Config::default().with_merged(
             config::File::from_str(default_settings, config::FileFormat::Toml),
         )?;
 
+        let mut paths = Vec::new();
+        let mut snippet_dirs = Vec::new();
         if let Some(proj_dirs) = ProjectDirs::from("", "", "Lapce") {
             let path = proj_dirs.config_dir().join("settings.toml");
             settings.merge(config::File::from(path.as_path()).required(false));
+            paths.push(path);
+            snippet_dirs.push(proj_dirs.config_dir().join("snippets"));
         }
 
         if let Some(workspace) = workspace {
@@ -120,12 +220,16 @@ impl Config {
                     let path = workspace.path.join("./.lapce/settings.toml");
                     settings
                         .merge(config::File::from(path.as_path()).required(false));
+                    paths.push(path);
+                    snippet_dirs.push(workspace.path.join("./.lapce/snippets"));
                 }
                 crate::state::LapceWorkspaceType::RemoteSSH(_, _) => {}
             }
         }
 
         let mut config: Config = settings.try_into()?;
+        config.lsp = get_lsp_settings(&paths, &mut config.errors);
+        config.snippets = get_snippets(&snippet_dirs, &mut config.errors);
 
         config.theme = get_theme(default_light_theme)?;
 
@@ -134,9 +238,29 @@ impl Config {
         themes.insert("Lapce Dark".to_string(), get_theme(default_dark_theme)?);
         config.themes = themes;
 
+        let mut modifier_styles = HashMap::new();
+        modifier_styles.insert(
+            "Lapce Light".to_string(),
+            get_modifier_styles(default_light_theme)?,
+        );
+        modifier_styles.insert(
+            "Lapce Dark".to_string(),
+            get_modifier_styles(default_dark_theme)?,
+        );
+        config.modifier_styles = modifier_styles;
+
         Ok(config)
     }
 
+    /// The errors of the settings and snippet files as one message for the
+    /// status bar.
+    pub fn error_message(&self) -> Option<String> {
+        if self.errors.is_empty() {
+            return None;
+        }
+        Some(self.errors.join("; "))
+    }
+
     pub fn settings_file() -> Option<PathBuf> {
         ProjectDirs::from("", "", "Lapce")
             .map(|d| d.config_dir().join("settings.toml"))
@@ -196,6 +320,13 @@ impl Config {
         theme.get(name)
     }
 
+    pub fn get_modifier_style(&self, modifier: &str) -> Option<&ModifierStyle> {
+        self.modifier_styles
+            .get(&self.lapce.color_theme)
+            .or_else(|| self.modifier_styles.get("Lapce Light"))?
+            .get(modifier)
+    }
+
     pub fn editor_text_width(&self, text: &mut PietText, c: &str) -> f64 {
         let text_layout = text
             .new_text_layout(c.to_string())
@@ -373,3 +504,233 @@ fn get_theme(content: &str) -> Result<HashMap<String, Color>> {
     }
     Ok(theme)
 }
+
+/// Reads the snippet files of the `snippets` directories: `global.json` or
+/// `global.toml` for every language, and `<language>.json` or
+/// `<language>.toml` for one. A snippet of a later directory replaces the
+/// one with the same name from an earlier one. The files that can't be read
+/// are left out and added to `errors`.
+fn get_snippets(
+    dirs: &[PathBuf],
+    errors: &mut Vec<String>,
+) -> std::collections::HashMap<String, Vec<UserSnippet>> {
+    let mut snippets: std::collections::HashMap<
+        String,
+        std::collections::BTreeMap<String, UserSnippet>,
+    > = std::collections::HashMap::new();
+    for dir in dirs {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+            let language = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(language) => language.to_string(),
+                None => continue,
+            };
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            let file: std::result::Result<
+                std::collections::BTreeMap<String, UserSnippet>,
+                String,
+            > = match path.extension().and_then(|e| e.to_str()) {
+                Some("json") => {
+                    serde_json::from_str(&content).map_err(|e| e.to_string())
+                }
+                Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+                _ => continue,
+            };
+            match file {
+                Ok(file) => {
+                    let language_snippets = snippets.entry(language).or_default();
+                    for (name, mut snippet) in file {
+                        snippet.name = name.clone();
+                        language_snippets.insert(name, snippet);
+                    }
+                }
+                Err(err) => {
+                    errors.push(format!(
+                        "invalid snippets in {}: {}",
+                        path.display(),
+                        err
+                    ));
+                }
+            }
+        }
+    }
+    snippets
+        .into_iter()
+        .map(|(language, snippets)| (language, snippets.into_values().collect()))
+        .collect()
+}
+
+/// Reads the `[lsp]` tables of the settings files, later files overriding
+/// single keys of earlier ones. They're read with toml directly, since
+/// `config` lowercases keys and would mangle server options like
+/// `checkOnSave` and environment variables. The sections that can't be
+/// read are left out and added to `errors`.
+fn get_lsp_settings(
+    paths: &[PathBuf],
+    errors: &mut Vec<String>,
+) -> std::collections::HashMap<String, LspSettings> {
+    fn merge(base: &mut toml::Value, other: toml::Value) {
+        match (base, other) {
+            (toml::Value::Table(base), toml::Value::Table(other)) => {
+                for (key, value) in other {
+                    match base.get_mut(&key) {
+                        Some(base_value) => merge(base_value, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, other) => *base = other,
+        }
+    }
+
+    let mut lsp = toml::Value::Table(toml::value::Table::new());
+    for path in paths {
+        let settings = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str::<toml::Value>(&content).ok());
+        if let Some(settings) = settings.and_then(|s| s.get("lsp").cloned()) {
+            merge(&mut lsp, settings);
+        }
+    }
+
+    let mut settings = std::collections::HashMap::new();
+    if let toml::Value::Table(lsp) = lsp {
+        for (language_id, value) in lsp {
+            match value.try_into::<LspSettings>() {
+                Ok(language_settings) => {
+                    settings.insert(language_id, language_settings);
+                }
+                Err(err) => {
+                    errors.push(format!(
+                        "invalid settings for lsp.{}: {}",
+                        language_id, err
+                    ));
+                }
+            }
+        }
+    }
+    settings
+}
+
+fn get_modifier_styles(content: &str) -> Result<HashMap<String, ModifierStyle>> {
+    let theme: std::collections::HashMap<String, String> = toml::from_str(content)?;
+    let mut styles = HashMap::new();
+    for (k, v) in theme.iter() {
+        if let Some(modifier) = k.strip_prefix("modifier.") {
+            let mut style = ModifierStyle::default();
+            for word in v.split_whitespace() {
+                match word {
+                    "bold" => style.bold = true,
+                    "italic" => style.italic = true,
+                    "underline" => style.underline = true,
+                    "strikethrough" => style.strikethrough = true,
+                    _ => {}
+                }
+            }
+            styles.insert(modifier.to_string(), style);
+        }
+    }
+    Ok(styles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(name: &str, scope: Option<&str>) -> UserSnippet {
+        UserSnippet {
+            name: name.to_string(),
+            prefix: vec![name.to_string()],
+            body: vec![name.to_string()],
+            description: None,
+            scope: scope.map(|s| s.to_string()),
+        }
+    }
+
+    fn names(snippets: Vec<&UserSnippet>) -> Vec<&str> {
+        snippets.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_snippets_scope() {
+        let mut config = Config::default();
+        config.snippets.insert(
+            "global".to_string(),
+            vec![
+                snippet("all", None),
+                snippet("rust_only", Some("rust")),
+                snippet("rust_or_go", Some("go, rust")),
+            ],
+        );
+        config
+            .snippets
+            .insert("rust".to_string(), vec![snippet("rust_file", None)]);
+        config
+            .snippets
+            .insert("go".to_string(), vec![snippet("go_file", None)]);
+
+        assert_eq!(
+            names(config.snippets(Some("rust"))),
+            vec!["all", "rust_only", "rust_or_go", "rust_file"]
+        );
+        assert_eq!(
+            names(config.snippets(Some("go"))),
+            vec!["all", "rust_or_go", "go_file"]
+        );
+        assert_eq!(names(config.snippets(Some("python"))), vec!["all"]);
+        assert_eq!(names(config.snippets(None)), vec!["all"]);
+    }
+
+    #[test]
+    fn test_get_snippets() {
+        let dir = std::env::temp_dir()
+            .join(format!("lapce-snippets-{}", std::process::id()));
+        let user = dir.join("user");
+        let workspace = dir.join("workspace");
+        std::fs::create_dir_all(&user).unwrap();
+        std::fs::create_dir_all(&workspace).unwrap();
+        std::fs::write(
+            user.join("rust.json"),
+            r##"{
+                "main": { "prefix": "main", "body": ["fn main() {", "}"] },
+                "test": { "prefix": "test", "body": "#[test]" }
+            }"##,
+        )
+        .unwrap();
+        std::fs::write(
+            workspace.join("rust.toml"),
+            r#"
+                [main]
+                prefix = ["main", "mn"]
+                body = "fn main() {}"
+            "#,
+        )
+        .unwrap();
+        std::fs::write(workspace.join("go.json"), "{ invalid").unwrap();
+
+        let mut errors = Vec::new();
+        let snippets = get_snippets(&[user, workspace], &mut errors);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // the later directory replaces the snippet with the same name
+        let rust = &snippets["rust"];
+        assert_eq!(rust.len(), 2);
+        assert_eq!(rust[0].name, "main");
+        assert_eq!(rust[0].prefix, vec!["main", "mn"]);
+        assert_eq!(rust[0].body, vec!["fn main() {}"]);
+        assert_eq!(rust[1].name, "test");
+        assert_eq!(rust[1].body, vec!["#[test]"]);
+
+        assert!(!snippets.contains_key("go"));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("invalid snippets in"));
+    }
+}
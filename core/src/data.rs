@@ -25,11 +25,14 @@ use druid::{
     Rect, Size, Target, TextLayout, Vec2, WidgetId, WindowId,
 };
 use im::{self, hashmap};
+use lapce_proxy::lsp::LspStatus;
 use lapce_proxy::terminal::TermId;
 use lsp_types::{
-    CodeActionOrCommand, CodeActionResponse, CompletionItem, CompletionResponse,
-    CompletionTextEdit, Diagnostic, DiagnosticSeverity, GotoDefinitionResponse,
-    Location, Position, TextEdit, WorkspaceClientCapabilities,
+    CodeAction, CodeActionOrCommand, CodeActionResponse, CompletionItem,
+    CompletionResponse, CompletionTextEdit, Diagnostic, DiagnosticSeverity,
+    DocumentChangeOperation, DocumentChanges, GotoDefinitionResponse, Location,
+    OneOf, OptionalVersionedTextDocumentIdentifier, Position, ResourceOp,
+    TextDocumentEdit, TextEdit, Url, WorkspaceClientCapabilities, WorkspaceEdit,
 };
 use parking_lot::Mutex;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -43,9 +46,10 @@ use xi_core_lib::{
     watcher::{FileWatcher, Notify, WatchToken},
 };
 use xi_rope::{
-    spans::SpansBuilder, DeltaBuilder, Interval, Rope, RopeDelta, Transformer,
+    spans::{Spans, SpansBuilder},
+    DeltaBuilder, Interval, Rope, RopeDelta, Transformer,
 };
-use xi_rpc::{RpcLoop, RpcPeer};
+use xi_rpc::{Callback, RpcLoop, RpcPeer};
 
 use crate::{
     buffer::{
@@ -53,22 +57,33 @@ use crate::{
         matching_pair_direction, previous_has_unmatched_pair, BufferId, BufferNew,
         BufferState, BufferUpdate, EditType, Style, UpdateEvent, WordProperty,
     },
+    code_action::{fix_edits, merge_fixes},
+    code_lens::ClientCommand,
     command::{
         CommandTarget, EnsureVisiblePosition, LapceCommand, LapceCommandNew,
         LapceUICommand, LapceWorkbenchCommand, LAPCE_COMMAND, LAPCE_NEW_COMMAND,
         LAPCE_UI_COMMAND,
     },
-    completion::{CompletionData, CompletionStatus, Snippet},
+    completion::{
+        CompletionData, CompletionStatus, Snippet, SnippetTransform,
+        COMPLETION_DOC_WIDTH,
+    },
     config::{Config, LapceTheme},
     db::{LapceDb, WorkspaceInfo},
     editor::{EditorLocationNew, LapceEditorBufferData, LapceEditorViewContent},
     find::Find,
+    hierarchy::HierarchyData,
     keypress::{KeyPressData, KeyPressFocus},
     language::{new_highlight_config, new_parser, LapceLanguage},
+    lsp_log::LspLogData,
     movement::{Cursor, CursorMode, LinePosition, Movement, SelRegion, Selection},
     palette::{PaletteData, PaletteType, PaletteViewData},
     panel::PanelPosition,
-    proxy::{LapceProxy, ProxyHandlerNew, TermEvent},
+    peek::PeekData,
+    problem::ProblemData,
+    proxy::{error_message, LapceProxy, ProxyHandlerNew, TermEvent},
+    rename::{RenameData, RENAME_PADDING},
+    signature::{SignatureState, SIGNATURE_PADDING},
     source_control::{SourceControlData, SOURCE_CONTROL_BUFFER},
     state::{LapceWorkspace, LapceWorkspaceType, Mode, VisualMode},
     terminal::TerminalSplitData,
@@ -230,6 +245,9 @@ pub fn watch_settings(event_sink: ExtEventSink) {
 pub enum FocusArea {
     Palette,
     SourceControl,
+    Problem,
+    Hierarchy,
+    LspLog,
     Editor,
     Terminal,
 }
@@ -244,6 +262,17 @@ pub struct LapceTabData {
     pub palette: Arc<PaletteData>,
     pub find: Arc<Find>,
     pub source_control: Arc<SourceControlData>,
+    pub problem: Arc<ProblemData>,
+    pub hierarchy: Arc<HierarchyData>,
+    pub lsp_log: Arc<LspLogData>,
+    pub rename: Arc<RenameData>,
+    pub signature: Arc<SignatureState>,
+    pub peek: Arc<PeekData>,
+    /// Where each language server is in its life, by name.
+    pub lsp_status: im::HashMap<String, LspStatus>,
+    /// The last thing that failed on the user, shown in the status bar
+    /// until dismissed.
+    pub error_message: Option<Arc<String>>,
     pub proxy: Arc<LapceProxy>,
     pub keypress: Arc<KeyPressData>,
     pub update_receiver: Option<Receiver<UpdateEvent>>,
@@ -267,6 +296,9 @@ impl Data for LapceTabData {
             && self.palette.same(&other.palette)
             && self.workspace.same(&other.workspace)
             && self.source_control.same(&other.source_control)
+            && self.problem.same(&other.problem)
+            && self.hierarchy.same(&other.hierarchy)
+            && self.lsp_log.same(&other.lsp_log)
             && self.panels.same(&other.panels)
             && self.panel_size.same(&other.panel_size)
             && self.window_origin.same(&other.window_origin)
@@ -276,6 +308,11 @@ impl Data for LapceTabData {
             && self.focus_area == other.focus_area
             && self.panel_active == other.panel_active
             && self.find.same(&other.find)
+            && self.rename.same(&other.rename)
+            && self.signature.same(&other.signature)
+            && self.peek.same(&other.peek)
+            && self.lsp_status == other.lsp_status
+            && self.error_message == other.error_message
     }
 }
 
@@ -314,8 +351,13 @@ impl LapceTabData {
             source_control.split_id,
             &config,
         );
+        let peek = Arc::new(PeekData::new());
+        main_split.add_peek_editor(peek.editor_view_id, &config);
 
         let terminal = Arc::new(TerminalSplitData::new(proxy.clone()));
+        let problem = Arc::new(ProblemData::new());
+        let hierarchy = Arc::new(HierarchyData::new());
+        let lsp_log = Arc::new(LspLogData::new());
 
         let mut panels = im::HashMap::new();
         panels.insert(
@@ -336,6 +378,19 @@ impl LapceTabData {
                 maximized: false,
             }),
         );
+        panels.insert(
+            PanelPosition::BottomRight,
+            Arc::new(PanelData {
+                active: problem.widget_id,
+                widgets: vec![
+                    problem.widget_id,
+                    hierarchy.widget_id,
+                    lsp_log.widget_id,
+                ],
+                shown: false,
+                maximized: false,
+            }),
+        );
         let mut tab = Self {
             id: tab_id,
             workspace: workspace.map(|w| Arc::new(w)),
@@ -345,6 +400,14 @@ impl LapceTabData {
             terminal,
             find: Arc::new(Find::new(0)),
             source_control,
+            problem,
+            hierarchy,
+            lsp_log,
+            rename: Arc::new(RenameData::new()),
+            signature: Arc::new(SignatureState::new()),
+            peek,
+            lsp_status: im::HashMap::new(),
+            error_message: config.error_message().map(Arc::new),
             term_rx: Some(term_receiver),
             term_tx: Arc::new(term_sender),
             palette,
@@ -425,6 +488,7 @@ impl LapceTabData {
                     view_id: editor_view_id,
                     main_split: self.main_split.clone(),
                     completion: self.completion.clone(),
+                    signature: self.signature.clone(),
                     proxy: self.proxy.clone(),
                     find: self.find.clone(),
                     buffer,
@@ -491,6 +555,7 @@ impl LapceTabData {
         buffer: &Arc<BufferNew>,
     ) {
         self.completion = editor_buffer_data.completion.clone();
+        self.signature = editor_buffer_data.signature.clone();
         self.main_split = editor_buffer_data.main_split.clone();
         self.find = editor_buffer_data.find.clone();
         if !editor_buffer_data.editor.same(editor) {
@@ -521,9 +586,10 @@ impl LapceTabData {
                 let buffer = self.main_split.open_files.get(path).unwrap();
                 let offset = editor.cursor.offset();
                 let (line, col) = buffer.offset_to_line_col(offset);
+                let col = buffer.visual_col(line, col, editor.cursor.is_insert());
                 let width = config.editor_text_width(text, "W");
                 let x = col as f64 * width;
-                let y = (line + 1) as f64 * line_height;
+                let y = (buffer.visual_line(line) + 1) as f64 * line_height;
                 let origin = editor.window_origin - self.window_origin.to_vec2()
                     + Vec2::new(x, y);
                 origin
@@ -531,6 +597,240 @@ impl LapceTabData {
         }
     }
 
+    /// Runs the command of a clicked code lens or a chosen code action,
+    /// handling the rust-analyzer commands that are meant for the client and
+    /// sending the rest to the server.
+    pub fn run_lsp_command(
+        &self,
+        ctx: &mut EventCtx,
+        buffer_id: BufferId,
+        command: &lsp_types::Command,
+    ) {
+        let workspace = self.workspace.as_ref().map(|w| &w.path);
+        match ClientCommand::from_command(command, workspace) {
+            Some(ClientCommand::RunInTerminal(cmd)) => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::RunInTerminal(cmd),
+                    Target::Widget(self.id),
+                ));
+            }
+            Some(ClientCommand::ShowReferences(locations)) => {
+                let locations = locations
+                    .iter()
+                    .map(|l| EditorLocationNew {
+                        path: PathBuf::from(l.uri.path()),
+                        position: Some(l.range.start.clone()),
+                        scroll_offset: None,
+                    })
+                    .collect();
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::RunPaletteReferences(locations),
+                    Target::Widget(self.palette.widget_id),
+                ));
+            }
+            None => {
+                let title = command.title.clone();
+                let tab_id = self.id;
+                let event_sink = ctx.get_external_handle();
+                self.proxy.execute_command(
+                    buffer_id,
+                    command,
+                    Box::new(move |result| {
+                        if let Err(err) = result {
+                            event_sink.submit_command(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::ShowError(format!(
+                                    "{} failed: {}",
+                                    title,
+                                    error_message(&err)
+                                )),
+                                Target::Widget(tab_id),
+                            );
+                        }
+                    }),
+                );
+            }
+        }
+    }
+
+    /// Runs a code action the user chose. One that comes without its edit
+    /// is resolved first, then `apply_code_action` goes on with it.
+    pub fn run_code_action(
+        &mut self,
+        ctx: &mut EventCtx,
+        buffer_id: BufferId,
+        action: &CodeActionOrCommand,
+    ) {
+        self.error_message = None;
+        let action = match action {
+            CodeActionOrCommand::Command(command) => {
+                self.run_lsp_command(ctx, buffer_id, command);
+                return;
+            }
+            CodeActionOrCommand::CodeAction(action) => action,
+        };
+        if let Some(disabled) = action.disabled.as_ref() {
+            self.error_message = Some(Arc::new(format!(
+                "{} is not available: {}",
+                action.title, disabled.reason
+            )));
+            return;
+        }
+        if action.edit.is_some() {
+            self.apply_code_action(ctx, buffer_id, action);
+            return;
+        }
+
+        let title = action.title.clone();
+        let tab_id = self.id;
+        let event_sink = ctx.get_external_handle();
+        self.proxy.code_action_resolve(
+            buffer_id,
+            action,
+            Box::new(move |result| {
+                let action =
+                    result.map_err(|err| error_message(&err)).and_then(|value| {
+                        serde_json::from_value::<CodeAction>(value)
+                            .map_err(|err| err.to_string())
+                    });
+                let command = match action {
+                    Ok(action) => LapceUICommand::ApplyCodeAction(buffer_id, action),
+                    Err(err) => LapceUICommand::ShowError(format!(
+                        "{} could not be resolved: {}",
+                        title, err
+                    )),
+                };
+                event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    command,
+                    Target::Widget(tab_id),
+                );
+            }),
+        );
+    }
+
+    /// Applies the edit of a code action and then runs its command, once
+    /// the whole edit was applied. The command isn't run when the edit
+    /// fails.
+    pub fn apply_code_action(
+        &mut self,
+        ctx: &mut EventCtx,
+        buffer_id: BufferId,
+        action: &CodeAction,
+    ) {
+        let edit = match action.edit.as_ref() {
+            Some(edit) => edit,
+            None => {
+                if let Some(command) = action.command.as_ref() {
+                    self.run_lsp_command(ctx, buffer_id, command);
+                }
+                return;
+            }
+        };
+        let title = action.title.clone();
+        let command = action.command.clone();
+        let tab_id = self.id;
+        let event_sink = ctx.get_external_handle();
+        self.main_split.apply_workspace_edit_and_then(
+            ctx,
+            edit,
+            Box::new(move |result| {
+                let ui_command = match (result, command) {
+                    (Ok(_), Some(command)) => LapceUICommand::RunCodeAction(
+                        buffer_id,
+                        CodeActionOrCommand::Command(command),
+                    ),
+                    (Ok(_), None) => return,
+                    (Err(err), _) => LapceUICommand::ShowError(format!(
+                        "{} could not be applied: {}",
+                        title,
+                        error_message(&err)
+                    )),
+                };
+                event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    ui_command,
+                    Target::Widget(tab_id),
+                );
+            }),
+        );
+    }
+
+    pub fn rename_origin(&self, text: &mut PietText, config: &Config) -> Point {
+        let line_height = self.config.editor.line_height as f64;
+        let editor = self.main_split.active_editor();
+        let origin = editor.window_origin - self.window_origin.to_vec2();
+        match self.main_split.open_files.get(&self.rename.path) {
+            Some(buffer) if self.rename.active => {
+                // the input is drawn over the symbol being renamed
+                let (line, col) = buffer.offset_to_line_col(self.rename.start);
+                let col = buffer.visual_col(line, col, false);
+                let width = config.editor_text_width(text, "W");
+                let x = col as f64 * width - RENAME_PADDING;
+                let y = buffer.visual_line(line) as f64 * line_height;
+                origin + Vec2::new(x, y)
+            }
+            _ => origin,
+        }
+    }
+
+    /// The signature help is drawn above the line of the call, starting where
+    /// its arguments do, and goes below it when there's no room above.
+    pub fn signature_origin(
+        &self,
+        text: &mut PietText,
+        size: Size,
+        tab_size: Size,
+        config: &Config,
+    ) -> Point {
+        let line_height = self.config.editor.line_height as f64;
+        let editor = self.main_split.active_editor();
+        let origin = editor.window_origin - self.window_origin.to_vec2();
+        match self.main_split.open_files.get(&self.signature.path) {
+            Some(buffer) if self.signature.is_active() => {
+                let offset = self.signature.offset.min(buffer.len());
+                let (line, col) = buffer.offset_to_line_col(offset);
+                let col = buffer.visual_col(line, col, false);
+                let width = config.editor_text_width(text, "W");
+                let x = col as f64 * width - SIGNATURE_PADDING;
+                let y = buffer.visual_line(line) as f64 * line_height;
+                let mut origin = origin + Vec2::new(x, y - size.height);
+                if origin.y < 0.0 {
+                    origin.y += size.height + line_height;
+                }
+                if origin.x + size.width + 1.0 > tab_size.width {
+                    origin.x = tab_size.width - size.width - 1.0;
+                }
+                if origin.x <= 0.0 {
+                    origin.x = 0.0;
+                }
+                origin
+            }
+            _ => origin,
+        }
+    }
+
+    /// The peek is drawn right under the line it was opened from.
+    pub fn peek_origin(&self) -> Point {
+        let line_height = self.config.editor.line_height as f64;
+        let editor = match self.main_split.editors.get(&self.peek.origin_view_id) {
+            Some(editor) => editor,
+            None => return Point::ZERO,
+        };
+        match self.main_split.open_files.get(&self.peek.path) {
+            Some(buffer) if self.peek.active => {
+                let offset = self.peek.offset.min(buffer.len());
+                let line = buffer.line_of_offset(offset);
+                let origin = editor.window_origin - self.window_origin.to_vec2();
+                let y = (buffer.visual_line(line) + 1) as f64 * line_height;
+                origin + Vec2::new(editor.scroll_offset.x, y)
+            }
+            _ => Point::ZERO,
+        }
+    }
+
     pub fn completion_origin(
         &self,
         text: &mut PietText,
@@ -548,9 +848,10 @@ impl LapceTabData {
                 let buffer = self.main_split.open_files.get(path).unwrap();
                 let offset = self.completion.offset;
                 let (line, col) = buffer.offset_to_line_col(offset);
+                let col = buffer.visual_col(line, col, false);
                 let width = config.editor_text_width(text, "W");
                 let x = col as f64 * width - line_height - 5.0;
-                let y = (line + 1) as f64 * line_height;
+                let y = (buffer.visual_line(line) + 1) as f64 * line_height;
                 let mut origin = editor.window_origin - self.window_origin.to_vec2()
                     + Vec2::new(x, y);
                 if origin.y + self.completion.size.height + 1.0 > tab_size.height {
@@ -560,11 +861,15 @@ impl LapceTabData {
                         .height
                         .min(self.completion.len() as f64 * line_height);
                     origin.y = editor.window_origin.y - self.window_origin.y
-                        + line as f64 * line_height
+                        + buffer.visual_line(line) as f64 * line_height
                         - height;
                 }
-                if origin.x + self.completion.size.width + 1.0 > tab_size.width {
-                    origin.x = tab_size.width - self.completion.size.width - 1.0;
+                let mut width = self.completion.size.width;
+                if self.completion.documentation().is_some() {
+                    width += COMPLETION_DOC_WIDTH;
+                }
+                if origin.x + width + 1.0 > tab_size.width {
+                    origin.x = tab_size.width - width - 1.0;
                 }
                 if origin.x <= 0.0 {
                     origin.x = 0.0;
@@ -709,6 +1014,13 @@ impl LapceTabData {
                     Target::Widget(self.palette.widget_id),
                 ));
             }
+            LapceWorkbenchCommand::PaletteWorkspaceSymbol => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::RunPalette(Some(PaletteType::WorkspaceSymbol)),
+                    Target::Widget(self.palette.widget_id),
+                ));
+            }
             LapceWorkbenchCommand::PaletteCommand => {
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -796,6 +1108,98 @@ impl LapceTabData {
                     }
                 }
             }
+            LapceWorkbenchCommand::ToggleProblem => {
+                if self.focus_area == FocusArea::Problem {
+                    for (_, panel) in self.panels.iter_mut() {
+                        if panel.widgets.contains(&self.problem.widget_id) {
+                            let panel = Arc::make_mut(panel);
+                            panel.shown = false;
+                            break;
+                        }
+                    }
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::Focus,
+                        Target::Widget(*self.main_split.active),
+                    ));
+                } else {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::FocusProblem,
+                        Target::Widget(self.id),
+                    ));
+                }
+            }
+            LapceWorkbenchCommand::ToggleLspLog => {
+                if self.focus_area == FocusArea::LspLog {
+                    for (_, panel) in self.panels.iter_mut() {
+                        if panel.widgets.contains(&self.lsp_log.widget_id) {
+                            let panel = Arc::make_mut(panel);
+                            panel.shown = false;
+                            break;
+                        }
+                    }
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::Focus,
+                        Target::Widget(*self.main_split.active),
+                    ));
+                } else {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::FocusLspLog,
+                        Target::Widget(self.id),
+                    ));
+                }
+            }
+            LapceWorkbenchCommand::ToggleInlayHints => {
+                let enabled = !self.config.editor.enable_inlay_hints;
+                let config = Arc::make_mut(&mut self.config);
+                config.editor.enable_inlay_hints = enabled;
+                Config::update_file(
+                    "editor.enable-inlay-hints",
+                    toml::Value::Boolean(enabled),
+                );
+                if !enabled {
+                    for (_, buffer) in self.main_split.open_files.iter_mut() {
+                        if buffer.inlay_hints.len() > 0 {
+                            Arc::make_mut(buffer).clear_inlay_hints();
+                        }
+                    }
+                }
+            }
+            LapceWorkbenchCommand::ToggleCodeLens => {
+                let enabled = !self.config.editor.enable_code_lens;
+                let config = Arc::make_mut(&mut self.config);
+                config.editor.enable_code_lens = enabled;
+                Config::update_file(
+                    "editor.enable-code-lens",
+                    toml::Value::Boolean(enabled),
+                );
+                if !enabled {
+                    for (_, buffer) in self.main_split.open_files.iter_mut() {
+                        if buffer.code_lens.len() > 0 {
+                            Arc::make_mut(buffer).clear_code_lens();
+                        }
+                    }
+                }
+            }
+            LapceWorkbenchCommand::ToggleInlineCompletion => {
+                let enabled = !self.config.editor.enable_inline_completion;
+                let config = Arc::make_mut(&mut self.config);
+                config.editor.enable_inline_completion = enabled;
+                Config::update_file(
+                    "editor.enable-inline-completion",
+                    toml::Value::Boolean(enabled),
+                );
+                if !enabled {
+                    for (_, buffer) in self.main_split.open_files.iter_mut() {
+                        if buffer.inline_completion.is_some() {
+                            Arc::make_mut(buffer).clear_inline_completion();
+                        }
+                    }
+                }
+            }
             LapceWorkbenchCommand::ToggleMaximizedPanel => {
                 let panel = self.panels.get_mut(&self.panel_active).unwrap();
                 let panel = Arc::make_mut(panel);
@@ -907,12 +1311,12 @@ impl LapceTabData {
         ) {
             let update = match &event {
                 UpdateEvent::Buffer(update) => update,
-                UpdateEvent::SemanticTokens(update, tokens) => update,
+                UpdateEvent::SemanticTokens(update, _, _) => update,
             };
             if let Some(current) = updates.get(&update.id) {
                 let current = match &event {
                     UpdateEvent::Buffer(update) => update,
-                    UpdateEvent::SemanticTokens(update, tokens) => update,
+                    UpdateEvent::SemanticTokens(update, _, _) => update,
                 };
                 if update.rev > current.rev {
                     updates.insert(update.id, event);
@@ -961,17 +1365,9 @@ impl LapceTabData {
                             tab_id,
                         );
                     }
-                    UpdateEvent::SemanticTokens(update, tokens) => {
-                        let mut highlights = SpansBuilder::new(update.rope.len());
-                        for (start, end, hl) in tokens {
-                            highlights.add_span(
-                                Interval::new(start, end),
-                                Style {
-                                    fg_color: Some(hl.to_string()),
-                                },
-                            );
-                        }
-                        let highlights = highlights.build();
+                    UpdateEvent::SemanticTokens(update, range, tokens) => {
+                        let highlights =
+                            semantic_tokens_styles(&update, range, tokens);
                         event_sink.submit_command(
                             LAPCE_UI_COMMAND,
                             LapceUICommand::UpdateStyle {
@@ -1077,6 +1473,28 @@ pub enum EditorKind {
     SplitActive,
 }
 
+/// What is left of a workspace edit while the proxy applies the operations
+/// in front of it, with the callback of the whole edit.
+pub struct WorkspaceEditRest {
+    /// The files renamed by the proxy, to follow in the open buffers.
+    renames: Vec<(PathBuf, PathBuf)>,
+    ops: Vec<DocumentChangeOperation>,
+    /// The revisions of the buffers `ops` edits, which mustn't change
+    /// before they're edited.
+    revs: Vec<(PathBuf, u64)>,
+    f: Mutex<Option<Box<dyn Callback>>>,
+}
+
+impl std::fmt::Debug for WorkspaceEditRest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkspaceEditRest")
+            .field("renames", &self.renames)
+            .field("ops", &self.ops)
+            .field("revs", &self.revs)
+            .finish()
+    }
+}
+
 #[derive(Clone, Data, Lens)]
 pub struct LapceMainSplitData {
     pub tab_id: Arc<WidgetId>,
@@ -1137,33 +1555,7 @@ impl LapceMainSplitData {
             return;
         }
 
-        if let Ok(res) = result {
-            let edits: Result<Vec<TextEdit>, serde_json::Error> =
-                serde_json::from_value(res.clone());
-            if let Ok(edits) = edits {
-                if edits.len() > 0 {
-                    let buffer = self.open_files.get_mut(path).unwrap();
-
-                    let edits: Vec<(Selection, String)> = edits
-                        .iter()
-                        .map(|edit| {
-                            let selection = Selection::region(
-                                buffer.offset_of_position(&edit.range.start),
-                                buffer.offset_of_position(&edit.range.end),
-                            );
-                            (selection, edit.new_text.clone())
-                        })
-                        .collect();
-
-                    self.edit(
-                        ctx,
-                        &path,
-                        edits.iter().map(|(s, c)| (s, c.as_ref())).collect(),
-                        EditType::Other,
-                    );
-                }
-            }
-        }
+        self.document_format(ctx, path, rev, result, EditType::Other);
 
         let buffer = self.open_files.get(path).unwrap();
         let rev = buffer.rev;
@@ -1185,6 +1577,122 @@ impl LapceMainSplitData {
         );
     }
 
+    /// Applies the text edits a formatting request came back with, unless the
+    /// buffer was edited since. Returns whether anything changed.
+    pub fn document_format(
+        &mut self,
+        ctx: &mut EventCtx,
+        path: &PathBuf,
+        rev: u64,
+        result: &Result<Value>,
+        edit_type: EditType,
+    ) -> bool {
+        let buffer = match self.open_files.get(path) {
+            Some(buffer) if buffer.rev == rev => buffer,
+            _ => return false,
+        };
+        let edits = match result.as_ref().ok().and_then(|res| {
+            serde_json::from_value::<Vec<TextEdit>>(res.clone()).ok()
+        }) {
+            Some(edits) if edits.len() > 0 => edits,
+            _ => return false,
+        };
+
+        let edits: Vec<(Selection, String)> = edits
+            .iter()
+            .map(|edit| {
+                let selection = Selection::region(
+                    buffer.offset_of_position(&edit.range.start),
+                    buffer.offset_of_position(&edit.range.end),
+                );
+                (selection, edit.new_text.clone())
+            })
+            .collect();
+        self.edit(
+            ctx,
+            &path,
+            edits.iter().map(|(s, c)| (s, c.as_ref())).collect(),
+            edit_type,
+        );
+        true
+    }
+
+    /// Applies the edits the preferred fixes make to the file `path` as one
+    /// undo group. A fix that does more than edit the file, or with an edit
+    /// overlapping one already taken, is left out whole, so that no fix is
+    /// half applied. Returns how many fixes were applied and how many were
+    /// left out.
+    pub fn fix_all_in_file(
+        &mut self,
+        ctx: &mut EventCtx,
+        path: &PathBuf,
+        rev: u64,
+        result: &Result<Value>,
+    ) -> Result<(usize, usize)> {
+        let buffer = match self.open_files.get(path) {
+            Some(buffer) if buffer.rev == rev => buffer,
+            _ => return Err(anyhow!("the file changed while asking for fixes")),
+        };
+        let fixes = match result {
+            Ok(value) => serde_json::from_value::<Vec<CodeAction>>(value.clone())?,
+            Err(err) => return Err(anyhow!("{}", err)),
+        };
+
+        let uri = Url::from_file_path(path).unwrap();
+        let total = fixes.len();
+        let fixes: Vec<Vec<(usize, usize, String)>> = fixes
+            .iter()
+            .filter_map(|fix| fix_edits(fix, &uri))
+            .map(|edits| {
+                edits
+                    .iter()
+                    .map(|edit| {
+                        (
+                            buffer.offset_of_position(&edit.range.start),
+                            buffer.offset_of_position(&edit.range.end),
+                            edit.new_text.clone(),
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        let (edits, applied) = merge_fixes(fixes);
+        if edits.is_empty() {
+            return Ok((applied, total - applied));
+        }
+
+        let edits: Vec<(Selection, String)> = edits
+            .into_iter()
+            .map(|(start, end, text)| (Selection::region(start, end), text))
+            .collect();
+        self.edit(
+            ctx,
+            path,
+            edits.iter().map(|(s, c)| (s, c.as_ref())).collect(),
+            EditType::Other,
+        );
+        Ok((applied, total - applied))
+    }
+
+    /// On type formatting goes into the undo group of the edit that typed
+    /// its trigger character, and closes it.
+    pub fn on_type_format(
+        &mut self,
+        ctx: &mut EventCtx,
+        path: &PathBuf,
+        rev: u64,
+        result: &Result<Value>,
+    ) {
+        let edit_type = match self.open_files.get(path) {
+            Some(buffer) => buffer.last_edit_type(),
+            None => return,
+        };
+        if self.document_format(ctx, path, rev, result, edit_type) {
+            let buffer = self.open_files.get_mut(path).unwrap();
+            Arc::make_mut(buffer).update_edit_type();
+        }
+    }
+
     fn initiate_diagnositcs_offset(&mut self, path: &PathBuf) {
         if let Some(diagnostics) = self.diagnostics.get_mut(path) {
             if let Some(buffer) = self.open_files.get(path) {
@@ -1259,6 +1767,270 @@ impl LapceMainSplitData {
         Some(delta)
     }
 
+    /// Applies a workspace edit in order. Text edits to loaded buffers are
+    /// applied here, one undo group per file; edits to files that aren't open
+    /// and resource operations are forwarded to the proxy.
+    pub fn apply_workspace_edit(
+        &mut self,
+        ctx: &mut EventCtx,
+        edit: &WorkspaceEdit,
+    ) {
+        self.apply_workspace_edit_and_then(ctx, edit, Box::new(|result| {}));
+    }
+
+    /// Like `apply_workspace_edit`, calling `f` once the whole edit has been
+    /// applied, which is after the proxy answers when it had a part to do.
+    /// Nothing is applied when an edit was made for another version of an
+    /// open file than the one it has now.
+    pub fn apply_workspace_edit_and_then(
+        &mut self,
+        ctx: &mut EventCtx,
+        edit: &WorkspaceEdit,
+        f: Box<dyn Callback>,
+    ) {
+        let ops = if let Some(document_changes) = edit.document_changes.as_ref() {
+            match document_changes {
+                DocumentChanges::Edits(edits) => edits
+                    .iter()
+                    .map(|e| DocumentChangeOperation::Edit(e.clone()))
+                    .collect(),
+                DocumentChanges::Operations(ops) => ops.clone(),
+            }
+        } else if let Some(changes) = edit.changes.as_ref() {
+            changes
+                .iter()
+                .map(|(uri, edits)| {
+                    DocumentChangeOperation::Edit(TextDocumentEdit {
+                        text_document: OptionalVersionedTextDocumentIdentifier {
+                            uri: uri.clone(),
+                            version: None,
+                        },
+                        edits: edits.iter().cloned().map(OneOf::Left).collect(),
+                    })
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        for op in ops.iter() {
+            if let DocumentChangeOperation::Edit(e) = op {
+                let path = match e.text_document.uri.to_file_path() {
+                    Ok(path) => path,
+                    Err(_) => {
+                        f.call(Err(edit_error(format!(
+                            "{} is not a local file",
+                            e.text_document.uri
+                        ))));
+                        return;
+                    }
+                };
+                if let (Some(version), Some(buffer)) =
+                    (e.text_document.version, self.loaded_buffer(&path))
+                {
+                    if version as u64 != buffer.rev {
+                        f.call(Err(edit_error(format!(
+                            "{} has changed since the edit was made",
+                            path.display()
+                        ))));
+                        return;
+                    }
+                }
+            }
+        }
+
+        self.apply_document_changes(ctx, ops, Vec::new(), f);
+    }
+
+    /// Goes on with a workspace edit once the proxy applied the operations
+    /// in front of `rest`.
+    pub fn continue_workspace_edit(
+        &mut self,
+        ctx: &mut EventCtx,
+        rest: &WorkspaceEditRest,
+    ) {
+        let f = match rest.f.lock().take() {
+            Some(f) => f,
+            None => return,
+        };
+        for (old_path, new_path) in rest.renames.iter() {
+            self.rename_open_file(old_path, new_path);
+        }
+        self.apply_document_changes(ctx, rest.ops.clone(), rest.revs.clone(), f);
+    }
+
+    /// Applies `ops` one after the other. Text edits to loaded buffers are
+    /// applied here, and each run of operations in between is sent to the
+    /// proxy, going on with the rest once it applied them. The first
+    /// failure stops the rest, and so does a change to a buffer of `revs`
+    /// made while waiting for the proxy.
+    fn apply_document_changes(
+        &mut self,
+        ctx: &mut EventCtx,
+        ops: Vec<DocumentChangeOperation>,
+        revs: Vec<(PathBuf, u64)>,
+        f: Box<dyn Callback>,
+    ) {
+        for (path, rev) in revs.iter() {
+            if self.loaded_buffer(path).map(|b| b.rev) != Some(*rev) {
+                f.call(Err(edit_error(format!(
+                    "{} changed while the edit was being applied",
+                    path.display()
+                ))));
+                return;
+            }
+        }
+
+        let mut ops = ops.into_iter().peekable();
+        while let Some(op) = ops.next() {
+            if let Some((path, edits)) = self.local_text_edits(&op) {
+                self.apply_text_edits(ctx, &path, &edits);
+                continue;
+            }
+
+            let mut run = vec![op];
+            while let Some(next) = ops.peek() {
+                if self.local_text_edits(next).is_some() {
+                    break;
+                }
+                run.push(ops.next().unwrap());
+            }
+            let renames: Vec<(PathBuf, PathBuf)> = run
+                .iter()
+                .filter_map(|op| match op {
+                    DocumentChangeOperation::Op(ResourceOp::Rename(rename)) => {
+                        Some((
+                            rename.old_uri.to_file_path().ok()?,
+                            rename.new_uri.to_file_path().ok()?,
+                        ))
+                    }
+                    _ => None,
+                })
+                .collect();
+            let rest: Vec<DocumentChangeOperation> = ops.collect();
+            let revs: Vec<(PathBuf, u64)> = rest
+                .iter()
+                .filter_map(|op| {
+                    let (path, _) = self.local_text_edits(op)?;
+                    let rev = self.loaded_buffer(&path)?.rev;
+                    Some((path, rev))
+                })
+                .collect();
+
+            let edit = WorkspaceEdit {
+                document_changes: Some(DocumentChanges::Operations(run)),
+                ..Default::default()
+            };
+            let tab_id = *self.tab_id;
+            let event_sink = ctx.get_external_handle();
+            self.proxy.apply_workspace_edit(
+                &edit,
+                Box::new(move |result| match result {
+                    Ok(_) => {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::ContinueWorkspaceEdit(Arc::new(
+                                WorkspaceEditRest {
+                                    renames,
+                                    ops: rest,
+                                    revs,
+                                    f: Mutex::new(Some(f)),
+                                },
+                            )),
+                            Target::Widget(tab_id),
+                        );
+                    }
+                    Err(err) => f.call(Err(err)),
+                }),
+            );
+            return;
+        }
+        f.call(Ok(Value::Null));
+    }
+
+    fn loaded_buffer(&self, path: &PathBuf) -> Option<&Arc<BufferNew>> {
+        self.open_files.get(path).filter(|buffer| buffer.loaded)
+    }
+
+    /// The path and edits of `op` when it's a text edit to a loaded buffer,
+    /// which is applied here rather than by the proxy.
+    fn local_text_edits(
+        &self,
+        op: &DocumentChangeOperation,
+    ) -> Option<(PathBuf, Vec<TextEdit>)> {
+        let e = match op {
+            DocumentChangeOperation::Edit(e) => e,
+            DocumentChangeOperation::Op(_) => return None,
+        };
+        let path = e.text_document.uri.to_file_path().ok()?;
+        self.loaded_buffer(&path)?;
+        let edits = e
+            .edits
+            .iter()
+            .map(|e| match e {
+                OneOf::Left(e) => e.clone(),
+                OneOf::Right(e) => e.text_edit.clone(),
+            })
+            .collect();
+        Some((path, edits))
+    }
+
+    /// Applies `edits` as one undo group, when `path` has a loaded buffer.
+    fn apply_text_edits(
+        &mut self,
+        ctx: &mut EventCtx,
+        path: &PathBuf,
+        edits: &[TextEdit],
+    ) {
+        let buffer = match self.loaded_buffer(path) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        if edits.len() == 0 {
+            return;
+        }
+
+        let edits: Vec<(Selection, String)> = edits
+            .iter()
+            .map(|edit| {
+                let selection = Selection::region(
+                    buffer.offset_of_position(&edit.range.start),
+                    buffer.offset_of_position(&edit.range.end),
+                );
+                (selection, edit.new_text.clone())
+            })
+            .collect();
+        self.edit(
+            ctx,
+            path,
+            edits.iter().map(|(s, c)| (s, c.as_ref())).collect(),
+            EditType::Other,
+        );
+    }
+
+    fn rename_open_file(&mut self, old_path: &PathBuf, new_path: &PathBuf) {
+        let mut buffer = match self.open_files.remove(old_path) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        Arc::make_mut(&mut buffer).path = new_path.clone();
+        self.open_files.insert(new_path.clone(), buffer);
+
+        for (_, editor) in self.editors.iter_mut() {
+            match &editor.content {
+                EditorContent::Buffer(path) if path == old_path => {
+                    Arc::make_mut(editor).content =
+                        EditorContent::Buffer(new_path.clone());
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(diagnostics) = self.diagnostics.remove(old_path) {
+            self.diagnostics.insert(new_path.clone(), diagnostics);
+        }
+    }
+
     pub fn jump_to_position(
         &mut self,
         ctx: &mut EventCtx,
@@ -1310,6 +2082,10 @@ impl LapceMainSplitData {
             EditorContent::Buffer(path) => path != &location.path,
             EditorContent::None => true,
         };
+        let old_path = match &editor.content {
+            EditorContent::Buffer(path) if new_buffer => Some(path.clone()),
+            _ => None,
+        };
         let path = location.path.clone();
         let buffer_exists = self.open_files.contains_key(&path);
         if !buffer_exists {
@@ -1363,6 +2139,29 @@ impl LapceMainSplitData {
                     ));
                 }
             }
+
+            if let Some(old_path) = old_path {
+                self.close_buffer_if_unused(ctx, &old_path);
+            }
+        }
+    }
+
+    /// Lets the language servers know the buffer at `path` is closed, once
+    /// no editor shows it anymore.
+    pub fn close_buffer_if_unused(&self, ctx: &mut EventCtx, path: &PathBuf) {
+        let shown = self.editors.values().any(|editor| match &editor.content {
+            EditorContent::Buffer(p) => p == path,
+            EditorContent::None => false,
+        });
+        if shown {
+            return;
+        }
+        if let Some(buffer) = self.open_files.get(path) {
+            ctx.submit_command(Command::new(
+                LAPCE_UI_COMMAND,
+                LapceUICommand::CloseBuffers(vec![buffer.id]),
+                Target::Widget(*self.tab_id),
+            ));
         }
     }
 
@@ -1526,6 +2325,17 @@ impl LapceMainSplitData {
         );
         self.editors.insert(editor.view_id, Arc::new(editor));
     }
+
+    pub fn add_peek_editor(&mut self, view_id: WidgetId, config: &Config) {
+        let editor = LapceEditorData::new(
+            Some(view_id),
+            None,
+            EditorContent::None,
+            EditorType::Peek,
+            config,
+        );
+        self.editors.insert(editor.view_id, Arc::new(editor));
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -1534,6 +2344,7 @@ pub enum EditorType {
     Normal,
     SourceControl,
     Palette,
+    Peek,
 }
 
 pub enum LapceEditorContainerKind {
@@ -1564,6 +2375,11 @@ pub struct LapceEditorData {
     pub size: Rc<RefCell<Size>>,
     pub window_origin: Point,
     pub snippet: Option<Vec<(usize, (usize, usize))>>,
+    /// The mirrors of the snippet that show a tab through a transformation,
+    /// updated as the tab is edited.
+    pub snippet_transforms: Vec<(usize, (usize, usize), SnippetTransform)>,
+    /// The options of the choice tabs of the snippet.
+    pub snippet_choices: HashMap<usize, Vec<String>>,
     pub locations: Vec<EditorLocationNew>,
     pub current_location: usize,
     pub last_movement: Movement,
@@ -1593,6 +2409,8 @@ impl LapceEditorData {
             size: Rc::new(RefCell::new(Size::ZERO)),
             window_origin: Point::ZERO,
             snippet: None,
+            snippet_transforms: Vec::new(),
+            snippet_choices: HashMap::new(),
             locations: vec![],
             current_location: 0,
             last_movement: Movement::Left,
@@ -1952,15 +2770,20 @@ impl LapceEditorViewData {
         let top = self.editor.scroll_offset.y + diff;
         let bottom = top + self.editor.size.borrow().height;
 
-        let line = if (line + 1) as f64 * line_height + line_height > bottom {
-            let line = (bottom / line_height).floor() as usize;
+        let visual_line = self.buffer.visual_line(line);
+        let line = if (visual_line + 1) as f64 * line_height + line_height > bottom {
+            let (line, _) = self
+                .buffer
+                .line_of_visual_line((bottom / line_height).floor() as usize);
             if line > 2 {
                 line - 2
             } else {
                 0
             }
-        } else if line as f64 * line_height - line_height < top {
-            let line = (top / line_height).ceil() as usize;
+        } else if visual_line as f64 * line_height - line_height < top {
+            let (line, _) = self
+                .buffer
+                .line_of_visual_line((top / line_height).ceil() as usize);
             line + 1
         } else {
             line
@@ -2228,7 +3051,9 @@ impl LapceEditorViewData {
         config: &Config,
     ) -> usize {
         let line_height = self.config.editor.line_height as f64;
-        let line = (pos.y / line_height).floor() as usize;
+        let (line, _) = self
+            .buffer
+            .line_of_visual_line((pos.y / line_height).floor() as usize);
         let last_line = self.buffer.last_line();
         let (line, col) = if line > last_line {
             (last_line, 0)
@@ -2238,12 +3063,15 @@ impl LapceEditorViewData {
                 .line_end_col(line, !self.editor.cursor.is_normal());
             let width = config.editor_text_width(text, "W");
 
-            let col = (if self.editor.cursor.is_insert() {
+            let visual_col = if self.editor.cursor.is_insert() {
                 (pos.x / width).round() as usize
             } else {
                 (pos.x / width).floor() as usize
-            })
-            .min(line_end);
+            };
+            let col = self
+                .buffer
+                .col_of_visual_col(line, visual_col)
+                .min(line_end);
             (line, col)
         };
         self.buffer.offset_of_line_col(line, col)
@@ -3605,6 +4433,11 @@ fn process_get_references(
     Ok(())
 }
 
+/// A workspace edit failure, as the proxy would report it.
+fn edit_error(message: String) -> xi_rpc::Error {
+    xi_rpc::Error::RemoteError(xi_rpc::RemoteError::custom(0, message, None))
+}
+
 pub fn hex_to_color(hex: &str) -> Result<Color> {
     let hex = hex.trim_start_matches("#");
     let (r, g, b, a) = match hex.len() {
@@ -3637,6 +4470,49 @@ pub fn hex_to_color(hex: &str) -> Result<Color> {
     ))
 }
 
+/// The styles of a buffer from its semantic tokens. Tokens for a range of
+/// it replace the styles of that range only.
+fn semantic_tokens_styles(
+    update: &BufferUpdate,
+    range: Option<(usize, usize)>,
+    tokens: Vec<(usize, usize, String, Vec<String>)>,
+) -> Spans<Style> {
+    let len = update.rope.len();
+    let (range_start, range_end) = range.unwrap_or((0, len));
+    let mut styles = Vec::new();
+    if range.is_some() {
+        for (iv, style) in update.highlights.iter_chunks(0..len) {
+            if iv.start() < range_start {
+                styles.push((iv.start(), iv.end().min(range_start), style.clone()));
+            }
+            if iv.end() > range_end {
+                styles.push((iv.start().max(range_end), iv.end(), style.clone()));
+            }
+        }
+    }
+    for (start, end, kind, modifiers) in tokens {
+        let start = start.max(range_start);
+        let end = end.min(range_end).min(len);
+        if start < end {
+            styles.push((
+                start,
+                end,
+                Style {
+                    fg_color: Some(kind),
+                    modifiers,
+                },
+            ));
+        }
+    }
+    styles.sort_by_key(|(start, _, _)| *start);
+
+    let mut highlights = SpansBuilder::new(len);
+    for (start, end, style) in styles {
+        highlights.add_span(Interval::new(start, end), style);
+    }
+    highlights.build()
+}
+
 fn buffer_receive_update(
     update: BufferUpdate,
     parsers: &mut HashMap<LapceLanguage, Parser>,
@@ -3698,6 +4574,7 @@ fn buffer_receive_update(
                                     Interval::new(start, end),
                                     Style {
                                         fg_color: Some(hl.to_string()),
+                                        modifiers: Vec::new(),
                                     },
                                 );
                             }
@@ -1,8 +1,10 @@
-use crate::buffer::{has_unmatched_pair, EditType};
+use crate::buffer::{has_unmatched_pair, EditType, EditorDocumentHighlight};
+use crate::code_action::is_fix;
+use crate::code_lens::{EditorCodeLens, CODE_LENS_SEPARATOR};
 use crate::command::{
     CommandTarget, LapceCommandNew, LapceWorkbenchCommand, LAPCE_NEW_COMMAND,
 };
-use crate::completion::{CompletionData, CompletionStatus, Snippet};
+use crate::completion::{time_variable, CompletionData, CompletionStatus, Snippet};
 use crate::config::{Config, LapceTheme, LOGO};
 use crate::data::{
     EditorContent, EditorDiagnostic, EditorKind, EditorType, FocusArea,
@@ -10,8 +12,13 @@ use crate::data::{
     RegisterData,
 };
 use crate::find::Find;
+use crate::hierarchy::HierarchyKind;
+use crate::inlay_hint::InlayHint;
 use crate::keypress::{KeyMap, KeyPress, KeyPressFocus};
-use crate::proxy::LapceProxy;
+use crate::local_completion::{
+    buffer_words, path_items, snippet_items, string_path,
+};
+use crate::proxy::{error_message, LapceProxy};
 use crate::scroll::LapceIdentityWrapper;
 use crate::signature::SignatureState;
 use crate::split::LapceSplitNew;
@@ -53,8 +60,8 @@ use druid::{
     widget::Padding, widget::Scroll, widget::SvgData, Affine, BoxConstraints, Color,
     Command, Data, Env, Event, EventCtx, FontDescriptor, FontFamily, Insets,
     KeyEvent, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Point, Rect,
-    RenderContext, Size, Target, TextLayout, UpdateCtx, Vec2, Widget, WidgetExt,
-    WidgetId, WidgetPod, WindowId,
+    RenderContext, Size, Target, TextLayout, TimerToken, UpdateCtx, Vec2, Widget,
+    WidgetExt, WidgetId, WidgetPod, WindowId,
 };
 use druid::{menu, Application, ExtEventSink, FileDialogOptions, Menu, Modifiers};
 use druid::{
@@ -66,21 +73,30 @@ use druid::{
 };
 use fzyr::has_match;
 use itertools::Itertools;
+use lapce_proxy::buffer::language_id_from_path;
+use lapce_proxy::dispatch::FileNodeItem;
+use lapce_proxy::lsp::semantic_tokens_by_range;
 use lsp_types::CompletionTextEdit;
 use lsp_types::{
-    CodeActionOrCommand, CodeActionResponse, CompletionItem, CompletionResponse,
-    Diagnostic, DiagnosticSeverity, DocumentChanges, GotoDefinitionResponse,
-    Location, Position, SignatureHelp, TextEdit, Url, WorkspaceEdit,
+    CallHierarchyItem, CodeActionOrCommand, CodeActionResponse, CodeLens,
+    CompletionItem, CompletionItemKind, CompletionResponse, Diagnostic,
+    DiagnosticSeverity, DocumentChanges, DocumentHighlight, DocumentHighlightKind,
+    GotoDefinitionResponse, Location, Position, PrepareRenameResponse, Range,
+    SignatureHelp, TextEdit, Url, WorkspaceEdit,
 };
 use serde_json::Value;
 use std::thread;
 use std::{cmp::Ordering, iter::Iterator, path::PathBuf};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 use std::{str::FromStr, time::Duration};
 use strum::EnumMessage;
 use unicode_width::UnicodeWidthStr;
 use xi_core_lib::selection::InsertDrift;
 use xi_rope::{Interval, RopeDelta, Transformer};
+use xi_rpc::Callback;
 
 pub struct LapceUI {}
 
@@ -209,6 +225,7 @@ pub struct LapceEditorBufferData {
     pub editor: Arc<LapceEditorData>,
     pub buffer: Arc<BufferNew>,
     pub completion: Arc<CompletionData>,
+    pub signature: Arc<SignatureState>,
     pub workspace: Option<Arc<LapceWorkspace>>,
     pub main_split: LapceMainSplitData,
     pub find: Arc<Find>,
@@ -299,6 +316,535 @@ impl LapceEditorBufferData {
         }
     }
 
+    /// Asks for the signature help of the call the cursor is in. `c` is the
+    /// character just typed, which the proxy lets through only when it's one
+    /// of the server's trigger characters, unless a help is already showing.
+    fn request_signature(&mut self, ctx: &mut EventCtx, c: Option<&str>) {
+        if !self.buffer.loaded || self.buffer.local {
+            return;
+        }
+        let offset = self.editor.cursor.offset();
+        let anchor = self
+            .buffer
+            .previous_unmatched('(', offset)
+            .map(|offset| offset + 1)
+            .unwrap_or(offset);
+        let context = self.signature.context(c);
+        let signature = Arc::make_mut(&mut self.signature);
+        signature.request_id += 1;
+        signature.buffer_id = self.buffer.id;
+        signature.path = self.buffer.path.clone();
+        signature.rev = self.buffer.rev;
+        signature.cursor = offset;
+        let request_id = signature.request_id;
+        let widget_id = signature.id;
+        let position = self.buffer.offset_to_position(offset);
+        let event_sink = ctx.get_external_handle();
+        self.proxy.get_signature(
+            self.buffer.id,
+            position,
+            Some(context),
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(resp) =
+                        serde_json::from_value::<Option<SignatureHelp>>(res)
+                    {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateSignature(
+                                request_id, anchor, resp,
+                            ),
+                            Target::Widget(widget_id),
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Keeps the signature help in step with what's typed. A `)` closing the
+    /// call the help is for closes it, or moves it to the enclosing call.
+    fn update_signature(&mut self, ctx: &mut EventCtx, c: &str) {
+        if c == ")" && self.signature.is_active() {
+            let offset = self.editor.cursor.offset();
+            match self.buffer.previous_unmatched('(', offset) {
+                Some(open) if open + 1 >= self.signature.offset => {}
+                Some(_) => {
+                    self.request_signature(ctx, None);
+                    return;
+                }
+                None => {
+                    Arc::make_mut(&mut self.signature).clear();
+                    return;
+                }
+            }
+        }
+        self.request_signature(ctx, Some(c));
+    }
+
+    /// Asks again for the signature help that's showing when the cursor or
+    /// the buffer changed since it was asked for.
+    fn retrigger_signature(&mut self, ctx: &mut EventCtx) {
+        if self.signature.is_active()
+            && self.signature.path == self.buffer.path
+            && (self.signature.cursor != self.editor.cursor.offset()
+                || self.signature.rev != self.buffer.rev)
+        {
+            self.request_signature(ctx, None);
+        }
+    }
+
+    fn request_range_formatting(
+        &self,
+        ctx: &mut EventCtx,
+        start: usize,
+        end: usize,
+    ) {
+        if !self.buffer.loaded || self.buffer.local {
+            return;
+        }
+        let range = Range {
+            start: self.buffer.offset_to_position(start),
+            end: self.buffer.offset_to_position(end),
+        };
+        let path = self.buffer.path.clone();
+        let rev = self.buffer.rev;
+        let event_sink = ctx.get_external_handle();
+        self.proxy.get_document_range_formatting(
+            self.buffer.id,
+            range,
+            Box::new(move |result| {
+                event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::DocumentFormat(
+                        path,
+                        rev,
+                        result.map_err(|e| anyhow!("{:?}", e)),
+                    ),
+                    Target::Auto,
+                );
+            }),
+        );
+    }
+
+    /// Asks for the quickfixes the servers prefer for the diagnostics of the
+    /// buffer, to apply all of them at once.
+    fn request_preferred_fixes(&self, ctx: &mut EventCtx) {
+        if !self.buffer.loaded || self.buffer.local {
+            return;
+        }
+        let path = self.buffer.path.clone();
+        let rev = self.buffer.rev;
+        let event_sink = ctx.get_external_handle();
+        self.proxy.get_preferred_fixes(
+            self.buffer.id,
+            Box::new(move |result| {
+                event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::FixAllInFile(
+                        path,
+                        rev,
+                        result.map_err(|e| anyhow!(error_message(&e))),
+                    ),
+                    Target::Auto,
+                );
+            }),
+        );
+    }
+
+    /// Asks for a suggestion to show as ghost text after the cursor, once
+    /// something was typed in insert mode. The language servers and plugins
+    /// are asked first, and what was written above stands in when they have
+    /// nothing.
+    fn request_inline_completion(&self, ctx: &mut EventCtx) {
+        if !self.config.editor.enable_inline_completion
+            || self.get_mode() != Mode::Insert
+            || !self.buffer.loaded
+            || self.buffer.local
+            || self.buffer.inline_completion.is_some()
+        {
+            return;
+        }
+        let selection = self.editor.cursor.edit_selection(&self.buffer);
+        if selection.regions().len() > 1 || !selection.is_caret() {
+            return;
+        }
+        let path = self.buffer.path.clone();
+        let rev = self.buffer.rev;
+        let offset = self.editor.cursor.offset();
+        let event_sink = ctx.get_external_handle();
+        self.proxy.get_inline_completion(
+            self.buffer.id,
+            self.buffer.offset_to_position(offset),
+            true,
+            Box::new(move |result| {
+                let items = result
+                    .ok()
+                    .and_then(|value| serde_json::from_value(value).ok())
+                    .unwrap_or_default();
+                event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::UpdateInlineCompletion(path, rev, offset, items),
+                    Target::Auto,
+                );
+            }),
+        );
+    }
+
+    /// Whether the ghost text is showing right after the cursor, so the
+    /// keys that accept it apply.
+    fn inline_completion_visible(&self) -> bool {
+        self.get_mode() == Mode::Insert
+            && self
+                .buffer
+                .inline_completion
+                .as_ref()
+                .map(|completion| completion.offset == self.editor.cursor.offset())
+                .unwrap_or(false)
+    }
+
+    /// Drops the ghost text once the cursor left it or insert mode ended.
+    fn dismiss_inline_completion(&mut self) {
+        if self.buffer.inline_completion.is_some()
+            && !self.inline_completion_visible()
+        {
+            Arc::make_mut(&mut self.buffer).clear_inline_completion();
+        }
+    }
+
+    /// Inserts the ghost text, or only its next word. Typing the start of
+    /// it that way leaves the rest showing.
+    fn accept_inline_completion(&mut self, ctx: &mut EventCtx, word: bool) {
+        let completion = match self.buffer.inline_completion.clone() {
+            Some(completion) => completion,
+            None => return,
+        };
+        let (selection, text, edit_type) = if word {
+            (
+                Selection::caret(completion.offset),
+                &completion.text[..completion.next_word_len()],
+                EditType::InsertChars,
+            )
+        } else {
+            (
+                Selection::region(completion.offset, completion.end),
+                &completion.text[..],
+                EditType::Other,
+            )
+        };
+        let (selection, _) = self.edit(ctx, &selection, text, None, true, edit_type);
+        let editor = Arc::make_mut(&mut self.editor);
+        editor.cursor.mode = CursorMode::Insert(selection);
+        editor.cursor.horiz = None;
+    }
+
+    /// Asks for the formatting `ch` triggers once it has been typed. The proxy
+    /// drops the characters the server doesn't format on.
+    fn request_on_type_formatting(&self, ctx: &mut EventCtx, ch: &str) {
+        if !self.buffer.loaded || self.buffer.local {
+            return;
+        }
+        if self
+            .editor
+            .cursor
+            .edit_selection(&self.buffer)
+            .regions()
+            .len()
+            > 1
+        {
+            return;
+        }
+        let position = self.buffer.offset_to_position(self.editor.cursor.offset());
+        let path = self.buffer.path.clone();
+        let rev = self.buffer.rev;
+        let event_sink = ctx.get_external_handle();
+        self.proxy.get_on_type_formatting(
+            self.buffer.id,
+            position,
+            ch.to_string(),
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    event_sink.submit_command(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::OnTypeFormat(path, rev, Ok(res)),
+                        Target::Auto,
+                    );
+                }
+            }),
+        );
+    }
+
+    fn get_inlay_hints(
+        &self,
+        event_sink: ExtEventSink,
+        start_line: usize,
+        end_line: usize,
+    ) {
+        let buffer_id = self.buffer.id;
+        let path = self.buffer.path.clone();
+        let rev = self.buffer.rev;
+        let range = Range {
+            start: Position::new(start_line as u32, 0),
+            end: self
+                .buffer
+                .offset_to_position(self.buffer.offset_of_line(end_line + 1)),
+        };
+        self.proxy.get_inlay_hints(
+            buffer_id,
+            range,
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(Some(hints)) =
+                        serde_json::from_value::<Option<Vec<InlayHint>>>(res)
+                    {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateInlayHints(path, rev, hints),
+                            Target::Auto,
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
+    fn get_semantic_tokens_range(
+        &self,
+        event_sink: ExtEventSink,
+        start_line: usize,
+        end_line: usize,
+    ) {
+        let buffer_id = self.buffer.id;
+        let path = self.buffer.path.clone();
+        let rev = self.buffer.rev;
+        let start = self.buffer.offset_of_line(start_line);
+        let end = self.buffer.offset_of_line(end_line + 1);
+        let range = Range {
+            start: self.buffer.offset_to_position(start),
+            end: self.buffer.offset_to_position(end),
+        };
+        self.proxy.get_semantic_tokens_range(
+            buffer_id,
+            range,
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(tokens) = serde_json::from_value::<
+                        Vec<(usize, usize, String, Vec<String>)>,
+                    >(res)
+                    {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateSemanticTokens(
+                                buffer_id,
+                                path,
+                                rev,
+                                Some((start, end)),
+                                tokens,
+                            ),
+                            Target::Auto,
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
+    fn get_code_lens(&self, event_sink: ExtEventSink) {
+        let buffer_id = self.buffer.id;
+        let path = self.buffer.path.clone();
+        let rev = self.buffer.rev;
+        self.proxy.get_code_lens(
+            buffer_id,
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(Some(lenses)) =
+                        serde_json::from_value::<Option<Vec<CodeLens>>>(res)
+                    {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateCodeLens(path, rev, lenses),
+                            Target::Auto,
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Jumps to where the server says the symbol under the cursor is,
+    /// or lists the places in the palette when there's more than one.
+    fn goto_location(&self, ctx: &mut EventCtx, kind: GotoKind) {
+        let offset = self.editor.cursor.offset();
+        let buffer_id = self.buffer.id;
+        let position = self.buffer.offset_to_position(offset);
+        let editor_view_id = self.editor.view_id;
+        let event_sink = ctx.get_external_handle();
+        let f = Box::new(move |result: Result<Value, xi_rpc::Error>| {
+            if let Ok(res) = result {
+                if let Ok(resp) =
+                    serde_json::from_value::<GotoDefinitionResponse>(res)
+                {
+                    let locations = goto_locations(resp);
+                    if locations.len() == 1 {
+                        let location = &locations[0];
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::GotoDefinition(
+                                editor_view_id,
+                                offset,
+                                EditorLocationNew {
+                                    path: PathBuf::from(location.uri.path()),
+                                    position: Some(location.range.start),
+                                    scroll_offset: None,
+                                },
+                            ),
+                            Target::Auto,
+                        );
+                    } else if locations.len() > 1 {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::PaletteReferences(offset, locations),
+                            Target::Auto,
+                        );
+                    }
+                }
+            }
+        });
+        match kind {
+            GotoKind::TypeDefinition => {
+                self.proxy.get_type_definition(buffer_id, position, f)
+            }
+            GotoKind::Implementation => {
+                self.proxy.get_implementation(buffer_id, position, f)
+            }
+            GotoKind::Declaration => {
+                self.proxy.get_declaration(buffer_id, position, f)
+            }
+        }
+    }
+
+    /// Asks the server for the hierarchy items under the cursor and shows
+    /// them in the hierarchy panel, grown in the direction of `kind`.
+    fn show_hierarchy(&self, ctx: &mut EventCtx, kind: HierarchyKind) {
+        let offset = self.editor.cursor.offset();
+        let position = self.buffer.offset_to_position(offset);
+        let buffer_id = self.buffer.id;
+        let event_sink = ctx.get_external_handle();
+        let f: Box<dyn Callback> = Box::new(move |result| {
+            if let Ok(res) = result {
+                if let Ok(Some(items)) =
+                    serde_json::from_value::<Option<Vec<CallHierarchyItem>>>(res)
+                {
+                    if items.len() > 0 {
+                        let _ = event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::ShowHierarchy(kind, buffer_id, items),
+                            Target::Auto,
+                        );
+                    }
+                }
+            }
+        });
+        match kind {
+            HierarchyKind::Incoming | HierarchyKind::Outgoing => {
+                self.proxy.prepare_call_hierarchy(buffer_id, position, f);
+            }
+            HierarchyKind::Supertypes | HierarchyKind::Subtypes => {
+                self.proxy.prepare_type_hierarchy(buffer_id, position, f);
+            }
+        }
+    }
+
+    /// Asks the server for the occurrences of the symbol under the cursor,
+    /// falling back to matching the word when the server can't answer.
+    fn get_document_highlights(&self, event_sink: ExtEventSink) {
+        if !self.buffer.loaded || self.buffer.local {
+            return;
+        }
+        let offset = self.editor.cursor.offset();
+        if self.buffer.identifier_at(offset).is_none() {
+            return;
+        }
+        let buffer_id = self.buffer.id;
+        let path = self.buffer.path.clone();
+        let rev = self.buffer.rev;
+        let position = self.buffer.offset_to_position(offset);
+        self.proxy.get_document_highlights(
+            buffer_id,
+            position,
+            Box::new(move |result| {
+                let highlights = result.ok().and_then(|res| {
+                    serde_json::from_value::<Option<Vec<DocumentHighlight>>>(res)
+                        .ok()
+                        .map(|highlights| highlights.unwrap_or_default())
+                });
+                event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::UpdateDocumentHighlights(
+                        path, rev, offset, highlights,
+                    ),
+                    Target::Auto,
+                );
+            }),
+        );
+    }
+
+    /// Adds the next or previous occurrence of the symbol under the cursor to
+    /// the selection, starting with the one the cursor is on.
+    fn select_next_highlight(&mut self, forward: bool) {
+        let highlights = self.buffer.document_highlights.clone();
+        let mut selection = match &self.editor.cursor.mode {
+            CursorMode::Insert(selection) => selection.clone(),
+            _ => Selection::new(),
+        };
+        let is_selected = |h: &EditorDocumentHighlight| {
+            selection
+                .regions()
+                .iter()
+                .any(|r| r.min() == h.start && r.max() == h.end)
+        };
+        if !highlights.iter().any(|h| is_selected(h)) {
+            let offset = self.editor.cursor.offset();
+            if let Some(h) = self.buffer.document_highlight_at(offset) {
+                let selection = Selection::region(h.start, h.end);
+                self.set_cursor(Cursor::new(CursorMode::Insert(selection), None));
+            }
+            return;
+        }
+        let next = if forward {
+            let anchor = selection.max_offset();
+            highlights
+                .iter()
+                .filter(|h| h.start >= anchor)
+                .chain(highlights.iter().filter(|h| h.start < anchor))
+                .find(|h| !is_selected(h))
+        } else {
+            let anchor = selection.min_offset();
+            highlights
+                .iter()
+                .rev()
+                .filter(|h| h.end <= anchor)
+                .chain(highlights.iter().rev().filter(|h| h.end > anchor))
+                .find(|h| !is_selected(h))
+        };
+        if let Some(h) = next {
+            selection.add_region(SelRegion::new(h.start, h.end, None));
+            self.set_cursor(Cursor::new(CursorMode::Insert(selection), None));
+        }
+    }
+
+    fn select_all_highlights(&mut self) {
+        let offset = self.editor.cursor.offset();
+        if self.buffer.document_highlight_at(offset).is_none() {
+            return;
+        }
+        let mut selection = Selection::new();
+        for h in self.buffer.document_highlights.iter() {
+            selection.add_region(SelRegion::new(h.start, h.end, None));
+        }
+        self.set_cursor(Cursor::new(CursorMode::Insert(selection), None));
+    }
+
     fn do_move(&mut self, movement: &Movement, count: usize) {
         if movement.is_jump() && movement != &self.editor.last_movement {
             let editor = Arc::make_mut(&mut self.editor);
@@ -365,6 +911,8 @@ impl LapceEditorBufferData {
         }
     }
 
+    /// Applies an accepted completion item. Its own edit and the additional
+    /// ones go in as one undo group, and its command runs afterwards.
     fn apply_completion_item(
         &mut self,
         ctx: &mut EventCtx,
@@ -389,90 +937,214 @@ impl LapceEditorBufferData {
                 .collect()
         });
 
-        let text_format = item
-            .insert_text_format
-            .unwrap_or(lsp_types::InsertTextFormat::PlainText);
-        if let Some(edit) = &item.text_edit {
-            match edit {
-                CompletionTextEdit::Edit(edit) => {
-                    let offset = self.editor.cursor.offset();
-                    let start_offset = self.buffer.prev_code_boundary(offset);
-                    let end_offset = self.buffer.next_code_boundary(offset);
-                    let edit_start =
-                        self.buffer.offset_of_position(&edit.range.start);
-                    let edit_end = self.buffer.offset_of_position(&edit.range.end);
-                    let selection = Selection::region(
-                        start_offset.min(edit_start),
-                        end_offset.max(edit_end),
-                    );
-                    match text_format {
-                        lsp_types::InsertTextFormat::PlainText => {
-                            let (selection, _) = self.edit(
-                                ctx,
-                                &selection,
-                                &edit.new_text,
-                                additioal_edit,
-                                true,
-                                EditType::InsertChars,
-                            );
-                            self.set_cursor_after_change(selection);
-                            return Ok(());
-                        }
-                        lsp_types::InsertTextFormat::Snippet => {
-                            let snippet = Snippet::from_str(&edit.new_text)?;
-                            let text = snippet.text();
-                            let (selection, delta) = self.edit(
-                                ctx,
-                                &selection,
-                                &text,
-                                additioal_edit,
-                                true,
-                                EditType::InsertChars,
-                            );
-
-                            let mut transformer = Transformer::new(&delta);
-                            let offset = transformer
-                                .transform(start_offset.min(edit_start), false);
-                            let snippet_tabs = snippet.tabs(offset);
+        let offset = self.editor.cursor.offset();
+        let start_offset = self.buffer.prev_code_boundary(offset);
+        let end_offset = self.buffer.next_code_boundary(offset);
+        let (start, end, new_text) = match &item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => (
+                start_offset.min(self.buffer.offset_of_position(&edit.range.start)),
+                end_offset.max(self.buffer.offset_of_position(&edit.range.end)),
+                edit.new_text.as_str(),
+            ),
+            Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+                let replace = self.config.editor.completion_replace;
+                let range = if replace { &edit.replace } else { &edit.insert };
+                let start = self.buffer.offset_of_position(&range.start);
+                let end = self.buffer.offset_of_position(&range.end);
+                // the ranges are from when the completion was asked for, and
+                // don't cover what has been typed since
+                let end = if replace {
+                    end.max(end_offset)
+                } else {
+                    end.max(offset)
+                };
+                (start.min(start_offset), end, edit.new_text.as_str())
+            }
+            None => (
+                start_offset,
+                end_offset,
+                item.insert_text.as_deref().unwrap_or(&item.label),
+            ),
+        };
 
-                            if snippet_tabs.len() == 0 {
-                                self.set_cursor_after_change(selection);
-                                return Ok(());
-                            }
+        let mut snippet = match item.insert_text_format {
+            Some(lsp_types::InsertTextFormat::Snippet) => {
+                Some(Snippet::from_str(new_text)?)
+            }
+            _ => None,
+        };
+        if let Some(snippet) = snippet.as_mut() {
+            snippet.resolve_variables(&|name| self.snippet_variable(name));
+        }
+        let text = snippet
+            .as_ref()
+            .map(|snippet| snippet.text())
+            .unwrap_or_else(|| new_text.to_string());
+        let (selection, delta) = self.edit(
+            ctx,
+            &Selection::region(start, end),
+            &text,
+            additioal_edit,
+            true,
+            EditType::Other,
+        );
 
-                            let mut selection = Selection::new();
-                            let (tab, (start, end)) = &snippet_tabs[0];
-                            let region = SelRegion::new(*start, *end, None);
-                            selection.add_region(region);
-                            self.set_cursor(Cursor::new(
-                                CursorMode::Insert(selection),
-                                None,
-                            ));
-                            Arc::make_mut(&mut self.editor)
-                                .add_snippet_placeholders(snippet_tabs);
-                            return Ok(());
-                        }
-                    }
-                }
-                CompletionTextEdit::InsertAndReplace(_) => (),
+        let (mut snippet_tabs, transforms, choices) = match snippet {
+            Some(snippet) => {
+                let mut transformer = Transformer::new(&delta);
+                let offset = transformer.transform(start, false);
+                (
+                    snippet.tabs(offset),
+                    snippet.transforms(offset),
+                    snippet.choices(),
+                )
             }
+            None => (Vec::new(), Vec::new(), HashMap::new()),
+        };
+        if snippet_tabs.len() == 0 {
+            self.set_cursor_after_change(selection);
+        } else {
+            // tabs are visited by number, and the final position `$0` last
+            snippet_tabs
+                .sort_by_key(|(tab, _)| if *tab == 0 { usize::MAX } else { *tab });
+            let editor = Arc::make_mut(&mut self.editor);
+            if editor.snippet.is_none() {
+                editor.snippet_transforms.clear();
+                editor.snippet_choices.clear();
+            }
+            editor.snippet_transforms.extend(transforms);
+            editor.snippet_choices.extend(choices);
+            self.select_snippet_tab(&snippet_tabs, snippet_tabs[0].0);
+            Arc::make_mut(&mut self.editor).add_snippet_placeholders(snippet_tabs);
         }
 
+        if let Some(command) = item.command.as_ref() {
+            self.run_completion_command(ctx, command);
+        }
+        Ok(())
+    }
+
+    /// Selects all the placeholders of `tab`, so typing changes its mirrors
+    /// too, and offers its options when it's a choice.
+    fn select_snippet_tab(
+        &mut self,
+        snippet: &[(usize, (usize, usize))],
+        tab: usize,
+    ) {
+        let regions: Vec<(usize, usize)> = snippet
+            .iter()
+            .filter(|(t, _)| *t == tab)
+            .map(|(_, region)| *region)
+            .collect();
+        let mut selection = Selection::new();
+        for (start, end) in regions.iter() {
+            selection.add_region(SelRegion::new(*start, *end, None));
+        }
+        self.set_cursor(Cursor::new(CursorMode::Insert(selection), None));
+
+        let choices = match self.editor.snippet_choices.get(&tab) {
+            Some(choices) => choices.clone(),
+            None => return,
+        };
+        let (start, end) = regions[0];
+        let range = Range {
+            start: self.buffer.offset_to_position(start),
+            end: self.buffer.offset_to_position(end),
+        };
+        let mirrors: Vec<Range> = regions[1..]
+            .iter()
+            .map(|(start, end)| Range {
+                start: self.buffer.offset_to_position(*start),
+                end: self.buffer.offset_to_position(*end),
+            })
+            .collect();
+        let items = choices
+            .into_iter()
+            .map(|choice| CompletionItem {
+                label: choice.clone(),
+                kind: Some(CompletionItemKind::Value),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: choice.clone(),
+                })),
+                additional_text_edits: Some(
+                    mirrors
+                        .iter()
+                        .map(|range| TextEdit {
+                            range: *range,
+                            new_text: choice.clone(),
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            })
+            .collect();
+        let offset = self.buffer.prev_code_boundary(self.editor.cursor.offset());
+        let buffer_id = self.buffer.id;
+        Arc::make_mut(&mut self.completion).show_local(buffer_id, offset, items);
+    }
+
+    /// The value of a snippet variable here, `None` for the ones that
+    /// aren't known.
+    fn snippet_variable(&self, name: &str) -> Option<String> {
+        let path = &self.buffer.path;
         let offset = self.editor.cursor.offset();
-        let start_offset = self.buffer.prev_code_boundary(offset);
-        let end_offset = self.buffer.next_code_boundary(offset);
-        let selection = Selection::region(start_offset, end_offset);
+        let line = self.buffer.line_of_offset(offset);
+        Some(match name {
+            "TM_FILENAME" => path.file_name()?.to_str()?.to_string(),
+            "TM_FILENAME_BASE" => path.file_stem()?.to_str()?.to_string(),
+            "TM_DIRECTORY" => path.parent()?.to_str()?.to_string(),
+            "TM_FILEPATH" => path.to_str()?.to_string(),
+            "TM_LINE_INDEX" => line.to_string(),
+            "TM_LINE_NUMBER" => (line + 1).to_string(),
+            "TM_CURRENT_LINE" => self
+                .buffer
+                .line_content(line)
+                .trim_end_matches(&['\n', '\r'][..])
+                .to_string(),
+            // the typed prefix is what a completion replaces, there's no
+            // selection
+            "TM_SELECTED_TEXT" => "".to_string(),
+            "CLIPBOARD" => Application::global()
+                .clipboard()
+                .get_string()
+                .unwrap_or_default(),
+            "WORKSPACE_NAME" => self
+                .workspace
+                .as_ref()?
+                .path
+                .file_name()?
+                .to_str()?
+                .to_string(),
+            "WORKSPACE_FOLDER" => {
+                self.workspace.as_ref()?.path.to_str()?.to_string()
+            }
+            _ => return time_variable(name),
+        })
+    }
 
-        let (selection, _) = self.edit(
-            ctx,
-            &selection,
-            item.insert_text.as_ref().unwrap_or(&item.label),
-            additioal_edit,
-            true,
-            EditType::InsertChars,
-        );
-        self.set_cursor_after_change(selection);
-        Ok(())
+    /// Asking for signature help or for more completions is left to the
+    /// editor itself, anything else goes to the server.
+    fn run_completion_command(
+        &mut self,
+        ctx: &mut EventCtx,
+        command: &lsp_types::Command,
+    ) {
+        match command.command.as_str() {
+            "editor.action.triggerParameterHints" => {
+                self.request_signature(ctx, None);
+            }
+            "editor.action.triggerSuggest" => {
+                self.update_completion(ctx);
+            }
+            _ => {
+                self.proxy.execute_command(
+                    self.buffer.id,
+                    command,
+                    Box::new(|_| {}),
+                );
+            }
+        }
     }
 
     fn cancel_completion(&mut self) {
@@ -504,8 +1176,9 @@ impl LapceEditorBufferData {
                 .slice_to_cow(start_offset - 1..start_offset)
                 .to_string()
         };
+        let path = string_path(&self.buffer, offset);
         let completion = Arc::make_mut(&mut self.completion);
-        if input == "" && char != "." && char != ":" {
+        if input == "" && char != "." && char != ":" && path.is_none() {
             completion.cancel();
             return;
         }
@@ -550,6 +1223,7 @@ impl LapceEditorBufferData {
         completion.input = input.clone();
         completion.status = CompletionStatus::Started;
         completion.input_items.clear();
+        completion.local_items = Arc::new(Vec::new());
         completion.request_id += 1;
         let event_sink = ctx.get_external_handle();
         completion.request(
@@ -561,7 +1235,21 @@ impl LapceEditorBufferData {
             completion.id,
             event_sink.clone(),
         );
+        let request_id = completion.request_id;
         if input != "" {
+            let line = self.buffer.line_of_offset(offset);
+            let mut items = snippet_items(
+                &self
+                    .config
+                    .snippets(language_id_from_path(&self.buffer.path)),
+                &self.buffer.indent_on_line(line),
+            );
+            items.extend(buffer_words(
+                &self.buffer,
+                offset,
+                self.main_split.open_files.values().map(|b| b.as_ref()),
+            ));
+            completion.receive_local(request_id, items);
             completion.request(
                 self.proxy.clone(),
                 completion.request_id,
@@ -572,15 +1260,58 @@ impl LapceEditorBufferData {
                 event_sink,
             );
         }
+        if let Some((dir, start)) = path {
+            self.request_path_completion(ctx, request_id, dir, start);
+        }
+    }
+
+    /// Lists the directory that the path typed in a string points into,
+    /// for the completion with `request_id`.
+    fn request_path_completion(
+        &self,
+        ctx: &mut EventCtx,
+        request_id: usize,
+        dir: PathBuf,
+        start: usize,
+    ) {
+        let offset = self.editor.cursor.offset();
+        let prefix = self.buffer.slice_to_cow(start..offset).to_string();
+        let start = self.buffer.offset_to_position(start);
+        let end = self.buffer.offset_to_position(offset);
+        let completion_widget_id = self.completion.id;
+        let event_sink = ctx.get_external_handle();
+        self.proxy.read_dir(
+            &dir,
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(entries) =
+                        serde_json::from_value::<Vec<FileNodeItem>>(res)
+                    {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateLocalCompletion(
+                                request_id,
+                                path_items(entries, &prefix, start, end),
+                            ),
+                            Target::Widget(completion_widget_id),
+                        );
+                    }
+                }
+            }),
+        );
     }
 
     fn cursor_region(&self, text: &mut PietText, config: &Config) -> Rect {
         let offset = self.editor.cursor.offset();
         let (line, col) = self.buffer.offset_to_line_col(offset);
+        let col = self
+            .buffer
+            .visual_col(line, col, self.editor.cursor.is_insert());
         let width = config.editor_text_width(text, "W");
         let cursor_x = col as f64 * width - width;
         let line_height = config.editor.line_height as f64;
         let cursor_x = if cursor_x < 0.0 { 0.0 } else { cursor_x };
+        let line = self.buffer.visual_line(line);
         let line = if line > 1 { line - 1 } else { 0 };
         Rect::ZERO
             .with_origin(Point::new(cursor_x.floor(), line as f64 * line_height))
@@ -873,30 +1604,75 @@ impl LapceEditorBufferData {
             buffer.edit(ctx, &selection, c, proxy, edit_type)
         };
         self.inactive_apply_delta(&delta);
-        let selection = selection.apply_delta(&delta, after, InsertDrift::Default);
-        if let Some(snippet) = self.editor.snippet.clone() {
-            let mut transformer = Transformer::new(&delta);
-            Arc::make_mut(&mut self.editor).snippet = Some(
-                snippet
-                    .iter()
-                    .map(|(tab, (start, end))| {
-                        (
-                            *tab,
-                            (
-                                transformer.transform(*start, false),
-                                transformer.transform(*end, true),
-                            ),
-                        )
-                    })
-                    .collect(),
-            );
-        }
-
+        let mut selection =
+            selection.apply_delta(&delta, after, InsertDrift::Default);
+        self.apply_snippet_delta(&delta);
         self.update_diagnositcs_offset(&delta);
 
+        if let Some(delta) = self.update_snippet_transforms(ctx, edit_type) {
+            self.inactive_apply_delta(&delta);
+            selection = selection.apply_delta(&delta, after, InsertDrift::Default);
+            self.apply_snippet_delta(&delta);
+            self.update_diagnositcs_offset(&delta);
+        }
+
         (selection, delta)
     }
 
+    fn apply_snippet_delta(&mut self, delta: &RopeDelta) {
+        if self.editor.snippet.is_none() {
+            if !self.editor.snippet_transforms.is_empty() {
+                Arc::make_mut(&mut self.editor).snippet_transforms.clear();
+            }
+            return;
+        }
+        let mut transformer = Transformer::new(delta);
+        let editor = Arc::make_mut(&mut self.editor);
+        for (_, (start, end)) in editor.snippet.as_mut().unwrap().iter_mut() {
+            *start = transformer.transform(*start, false);
+            *end = transformer.transform(*end, true);
+        }
+        for (_, (start, end), _) in editor.snippet_transforms.iter_mut() {
+            *start = transformer.transform(*start, false);
+            *end = transformer.transform(*end, true);
+        }
+    }
+
+    /// Rewrites the transformed mirrors of the snippet whose tabs have
+    /// changed, as part of the same edit.
+    fn update_snippet_transforms(
+        &mut self,
+        ctx: &mut EventCtx,
+        edit_type: EditType,
+    ) -> Option<RopeDelta> {
+        let snippet = self.editor.snippet.as_ref()?;
+        let mut edits = Vec::new();
+        for (tab, (start, end), transform) in self.editor.snippet_transforms.iter() {
+            let (tab_start, tab_end) = match snippet.iter().find(|(t, _)| t == tab) {
+                Some((_, region)) => *region,
+                None => continue,
+            };
+            let text =
+                transform.apply(&self.buffer.slice_to_cow(tab_start..tab_end));
+            if self.buffer.slice_to_cow(*start..*end) != text {
+                edits.push((Selection::region(*start, *end), text));
+            }
+        }
+        if edits.is_empty() {
+            return None;
+        }
+
+        let proxy = self.proxy.clone();
+        let edits = edits
+            .iter()
+            .map(|(selection, text)| (selection, text.as_str()))
+            .collect();
+        Some(
+            self.buffer_mut()
+                .edit_multiple(ctx, edits, proxy, edit_type),
+        )
+    }
+
     fn next_error(&mut self, ctx: &mut EventCtx, env: &Env) {
         let mut file_diagnostics = self
             .main_split
@@ -1024,19 +1800,25 @@ impl LapceEditorBufferData {
         let top = self.editor.scroll_offset.y + diff;
         let bottom = top + self.editor.size.borrow().height;
 
-        let new_line = if (line + 1) as f64 * line_height + line_height > bottom {
-            let line = (bottom / line_height).floor() as usize;
-            if line > 2 {
-                line - 2
+        let visual_line = self.buffer.visual_line(line);
+        let new_line =
+            if (visual_line + 1) as f64 * line_height + line_height > bottom {
+                let (line, _) = self
+                    .buffer
+                    .line_of_visual_line((bottom / line_height).floor() as usize);
+                if line > 2 {
+                    line - 2
+                } else {
+                    0
+                }
+            } else if visual_line as f64 * line_height - line_height < top {
+                let (line, _) = self
+                    .buffer
+                    .line_of_visual_line((top / line_height).ceil() as usize);
+                line + 1
             } else {
-                0
-            }
-        } else if line as f64 * line_height - line_height < top {
-            let line = (top / line_height).ceil() as usize;
-            line + 1
-        } else {
-            line
-        };
+                line
+            };
 
         if new_line > line {
             self.do_move(&Movement::Down, new_line - line);
@@ -1257,7 +2039,10 @@ impl LapceEditorBufferData {
         );
         let line_height = self.config.editor.line_height as f64;
         let scroll_offset = self.editor.scroll_offset;
-        let start_line = (scroll_offset.y / line_height).floor() as usize;
+        let start_line = self
+            .buffer
+            .line_of_visual_line((scroll_offset.y / line_height).floor() as usize)
+            .0;
         let num_lines = (ctx.size().height / line_height).floor() as usize;
         let last_line = self.buffer.last_line();
         let current_line = self.editor.cursor.current_line(&self.buffer);
@@ -1282,7 +2067,8 @@ impl LapceEditorBufferData {
             let x = ((last_line + 1).to_string().len() - content.to_string().len())
                 as f64
                 * width;
-            let y = line_height * line as f64 + 5.0 - scroll_offset.y;
+            let y = line_height * self.buffer.visual_line(line) as f64 + 5.0
+                - scroll_offset.y;
             let pos = Point::new(x, y);
             let content = content.to_string();
 
@@ -1304,7 +2090,8 @@ impl LapceEditorBufferData {
 
             if let Some(line_change) = self.buffer.line_changes.get(&line) {
                 let x = gutter_width + width;
-                let y = line as f64 * line_height - scroll_offset.y;
+                let y = self.buffer.visual_line(line) as f64 * line_height
+                    - scroll_offset.y;
                 let origin = Point::new(x, y);
                 let size = Size::new(3.0, line_height);
                 let rect = Rect::ZERO.with_origin(origin).with_size(size);
@@ -1318,7 +2105,7 @@ impl LapceEditorBufferData {
                     '-' => {
                         let size = Size::new(3.0, 10.0);
                         let x = gutter_width + width;
-                        let y = line as f64 * line_height
+                        let y = self.buffer.visual_line(line) as f64 * line_height
                             - size.height / 2.0
                             - scroll_offset.y;
                         let origin = Point::new(x, y);
@@ -1330,34 +2117,50 @@ impl LapceEditorBufferData {
             }
         }
 
-        if *self.main_split.active == self.view_id {
-            self.paint_code_actions_hint(ctx, gutter_width);
-        }
+        self.paint_code_actions_hint(ctx, gutter_width);
     }
 
+    /// A lightbulb on each line with fixes among its code actions, and on
+    /// the line of the cursor when it has any action in the active editor.
     fn paint_code_actions_hint(&self, ctx: &mut PaintCtx, gutter_width: f64) {
-        if let Some(actions) = self.current_code_actions() {
-            if actions.len() > 0 {
-                let line_height = self.config.editor.line_height as f64;
-                let offset = self.editor.cursor.offset();
-                let (line, _) = self.buffer.offset_to_line_col(offset);
-                let svg = get_svg("lightbulb.svg").unwrap();
-                let width = 16.0;
-                let height = 16.0;
-                let char_width = self.config.editor_text_width(ctx.text(), "W");
-                let rect =
-                    Size::new(width, height).to_rect().with_origin(Point::new(
-                        gutter_width + char_width + 3.0,
-                        (line_height - height) / 2.0 + line_height * line as f64
-                            - self.editor.scroll_offset.y,
-                    ));
-                ctx.draw_svg(
-                    &svg,
-                    rect,
-                    Some(self.config.get_color_unchecked(LapceTheme::LAPCE_WARN)),
-                );
+        let mut lines: HashSet<usize> = self
+            .buffer
+            .code_actions
+            .iter()
+            .filter(|(_, actions)| actions.iter().any(is_fix))
+            .map(|(offset, _)| self.buffer.line_of_offset(*offset))
+            .collect();
+        if *self.main_split.active == self.view_id {
+            if let Some(actions) = self.current_code_actions() {
+                if actions.len() > 0 {
+                    lines.insert(
+                        self.buffer.line_of_offset(self.editor.cursor.offset()),
+                    );
+                }
             }
         }
+        if lines.is_empty() {
+            return;
+        }
+
+        let line_height = self.config.editor.line_height as f64;
+        let svg = get_svg("lightbulb.svg").unwrap();
+        let width = 16.0;
+        let height = 16.0;
+        let char_width = self.config.editor_text_width(ctx.text(), "W");
+        for line in lines {
+            let rect = Size::new(width, height).to_rect().with_origin(Point::new(
+                gutter_width + char_width + 3.0,
+                (line_height - height) / 2.0
+                    + line_height * self.buffer.visual_line(line) as f64
+                    - self.editor.scroll_offset.y,
+            ));
+            ctx.draw_svg(
+                &svg,
+                rect,
+                Some(self.config.get_color_unchecked(LapceTheme::LAPCE_WARN)),
+            );
+        }
     }
 
     fn paint_content(
@@ -1370,9 +2173,19 @@ impl LapceEditorBufferData {
         let line_height = self.config.editor.line_height as f64;
         self.paint_cursor(ctx, is_focused, placeholder, config);
         self.paint_find(ctx);
+        self.paint_call_sites(ctx);
+        self.paint_document_highlights(ctx);
         let rect = ctx.region().bounding_box();
-        let start_line = (rect.y0 / line_height).floor() as usize;
-        let end_line = (rect.y1 / line_height).ceil() as usize;
+        let start_line = self
+            .buffer
+            .line_of_visual_line((rect.y0 / line_height).floor() as usize)
+            .0;
+        let end_line = self
+            .buffer
+            .line_of_visual_line((rect.y1 / line_height).ceil() as usize)
+            .0;
+        self.paint_inlay_hints(ctx, start_line, end_line);
+        self.paint_code_lens(ctx, start_line, end_line);
 
         let text_layout = ctx
             .text()
@@ -1419,12 +2232,16 @@ impl LapceEditorBufferData {
             );
             ctx.draw_text(
                 &text_layout,
-                Point::new(0.0, line_height * line as f64 + y_shift),
+                Point::new(
+                    0.0,
+                    line_height * self.buffer.visual_line(line) as f64 + y_shift,
+                ),
             );
         }
 
         self.paint_snippet(ctx);
         self.paint_diagnostics(ctx);
+        self.paint_inline_completion(ctx, y_shift);
         if self.buffer.len() == 0 {
             if let Some(placeholder) = placeholder {
                 let text_layout = ctx
@@ -1451,12 +2268,20 @@ impl LapceEditorBufferData {
         config: &Config,
     ) {
         let line_height = self.config.editor.line_height as f64;
-        let start_line =
-            (self.editor.scroll_offset.y / line_height).floor() as usize;
-        let end_line = ((self.editor.size.borrow().height
-            + self.editor.scroll_offset.y)
-            / line_height)
-            .ceil() as usize;
+        let start_line = self
+            .buffer
+            .line_of_visual_line(
+                (self.editor.scroll_offset.y / line_height).floor() as usize
+            )
+            .0;
+        let end_line = self
+            .buffer
+            .line_of_visual_line(
+                ((self.editor.size.borrow().height + self.editor.scroll_offset.y)
+                    / line_height)
+                    .ceil() as usize,
+            )
+            .0;
         let width = self.config.editor_text_width(ctx.text(), "W");
         match &self.editor.cursor.mode {
             CursorMode::Normal(offset) => {
@@ -1464,7 +2289,8 @@ impl LapceEditorBufferData {
                 self.paint_cursor_line(ctx, line, is_focused, placeholder);
 
                 if is_focused {
-                    let cursor_x = col as f64 * width;
+                    let cursor_x =
+                        self.buffer.visual_col(line, col, false) as f64 * width;
                     let next = self.buffer.next_grapheme_offset(
                         *offset,
                         1,
@@ -1480,7 +2306,7 @@ impl LapceEditorBufferData {
                         Rect::ZERO
                             .with_origin(Point::new(
                                 cursor_x,
-                                line as f64 * line_height,
+                                self.buffer.visual_line(line) as f64 * line_height,
                             ))
                             .with_size(Size::new(
                                 width * char_width as f64,
@@ -1523,7 +2349,8 @@ impl LapceEditorBufferData {
                             left
                         }
                     };
-                    let x0 = left_col as f64 * width;
+                    let x0 =
+                        self.buffer.visual_col(line, left_col, false) as f64 * width;
 
                     let right_col = match mode {
                         &VisualMode::Normal => match line {
@@ -1546,9 +2373,11 @@ impl LapceEditorBufferData {
                         }
                     };
                     if line_content.len() > 0 {
-                        let x1 = right_col as f64 * width;
+                        let x1 = self.buffer.visual_col(line, right_col, true)
+                            as f64
+                            * width;
 
-                        let y0 = line as f64 * line_height;
+                        let y0 = self.buffer.visual_line(line) as f64 * line_height;
                         let y1 = y0 + line_height;
                         ctx.fill(
                             Rect::new(x0, y0, x1, y1),
@@ -1559,7 +2388,8 @@ impl LapceEditorBufferData {
 
                     if is_focused {
                         let (line, col) = self.buffer.offset_to_line_col(*end);
-                        let cursor_x = col as f64 * width;
+                        let cursor_x =
+                            self.buffer.visual_col(line, col, false) as f64 * width;
                         let next = self.buffer.next_grapheme_offset(
                             *end,
                             1,
@@ -1575,7 +2405,8 @@ impl LapceEditorBufferData {
                             Rect::ZERO
                                 .with_origin(Point::new(
                                     cursor_x,
-                                    line as f64 * line_height,
+                                    self.buffer.visual_line(line) as f64
+                                        * line_height,
                                 ))
                                 .with_size(Size::new(
                                     width * char_width as f64,
@@ -1622,7 +2453,9 @@ impl LapceEditorBufferData {
                                 _ if line == start_line => start_col,
                                 _ => 0,
                             };
-                            let x0 = left_col as f64 * width;
+                            let x0 = self.buffer.visual_col(line, left_col, false)
+                                as f64
+                                * width;
 
                             let right_col = match line {
                                 _ if line == end_line => {
@@ -1634,8 +2467,12 @@ impl LapceEditorBufferData {
                             };
 
                             if line_content.len() > 0 {
-                                let x1 = right_col as f64 * width;
-                                let y0 = line as f64 * line_height;
+                                let x1 =
+                                    self.buffer.visual_col(line, right_col, true)
+                                        as f64
+                                        * width;
+                                let y0 = self.buffer.visual_line(line) as f64
+                                    * line_height;
                                 let y1 = y0 + line_height;
                                 ctx.fill(
                                     Rect::new(x0, y0, x1, y1),
@@ -1650,8 +2487,10 @@ impl LapceEditorBufferData {
                     if is_focused {
                         let (line, col) =
                             self.buffer.offset_to_line_col(region.end());
-                        let x = (col as f64 * width).round();
-                        let y = line as f64 * line_height;
+                        let x = (self.buffer.visual_col(line, col, true) as f64
+                            * width)
+                            .round();
+                        let y = self.buffer.visual_line(line) as f64 * line_height;
                         ctx.stroke(
                             Line::new(
                                 Point::new(x, y),
@@ -1681,7 +2520,10 @@ impl LapceEditorBufferData {
         let size = ctx.size();
         ctx.fill(
             Rect::ZERO
-                .with_origin(Point::new(0.0, line as f64 * line_height))
+                .with_origin(Point::new(
+                    0.0,
+                    self.buffer.visual_line(line) as f64 * line_height,
+                ))
                 .with_size(Size::new(size.width, line_height)),
             self.config
                 .get_color_unchecked(LapceTheme::EDITOR_CURRENT_LINE),
@@ -1690,12 +2532,20 @@ impl LapceEditorBufferData {
 
     fn paint_find(&self, ctx: &mut PaintCtx) {
         let line_height = self.config.editor.line_height as f64;
-        let start_line =
-            (self.editor.scroll_offset.y / line_height).floor() as usize;
-        let end_line = ((self.editor.size.borrow().height
-            + self.editor.scroll_offset.y)
-            / line_height)
-            .ceil() as usize;
+        let start_line = self
+            .buffer
+            .line_of_visual_line(
+                (self.editor.scroll_offset.y / line_height).floor() as usize
+            )
+            .0;
+        let end_line = self
+            .buffer
+            .line_of_visual_line(
+                ((self.editor.size.borrow().height + self.editor.scroll_offset.y)
+                    / line_height)
+                    .ceil() as usize,
+            )
+            .0;
         let width = self.config.editor_text_width(ctx.text(), "W");
         let start_offset = self.buffer.offset_of_line(start_line);
         let end_offset = self.buffer.offset_of_line(end_line + 1);
@@ -1720,9 +2570,11 @@ impl LapceEditorBufferData {
                     } else {
                         self.buffer.line_end_col(line, true) + 1
                     };
-                    let x0 = left_col as f64 * width;
-                    let x1 = right_col as f64 * width;
-                    let y0 = line as f64 * line_height;
+                    let x0 =
+                        self.buffer.visual_col(line, left_col, false) as f64 * width;
+                    let x1 =
+                        self.buffer.visual_col(line, right_col, true) as f64 * width;
+                    let y0 = self.buffer.visual_line(line) as f64 * line_height;
                     let y1 = y0 + line_height;
                     ctx.stroke(
                         Rect::new(x0, y0, x1, y1),
@@ -1735,14 +2587,257 @@ impl LapceEditorBufferData {
         }
     }
 
+    /// Fills the occurrences of the symbol under the cursor, unless they'd
+    /// hide a selection.
+    fn paint_document_highlights(&self, ctx: &mut PaintCtx) {
+        match &self.editor.cursor.mode {
+            CursorMode::Normal(_) => (),
+            CursorMode::Insert(selection) if selection.is_caret() => (),
+            _ => return,
+        }
+        let offset = self.editor.cursor.offset();
+        if self.buffer.document_highlight_at(offset).is_none() {
+            return;
+        }
+        for h in self.buffer.document_highlights.iter() {
+            let color = match h.kind {
+                DocumentHighlightKind::Write => self
+                    .config
+                    .get_color_unchecked(LapceTheme::EDITOR_HIGHLIGHT_WRITE),
+                _ => self
+                    .config
+                    .get_color_unchecked(LapceTheme::EDITOR_HIGHLIGHT_READ),
+            };
+            self.fill_offset_range(ctx, h.start, h.end, color);
+        }
+    }
+
+    fn paint_call_sites(&self, ctx: &mut PaintCtx) {
+        let color = self
+            .config
+            .get_color_unchecked(LapceTheme::EDITOR_CALL_SITE);
+        for (start, end) in self.buffer.call_sites.iter() {
+            self.fill_offset_range(ctx, *start, *end, color);
+        }
+    }
+
+    /// Fills the text between two offsets, line by line where it wraps
+    /// over several of them.
+    fn fill_offset_range(
+        &self,
+        ctx: &mut PaintCtx,
+        start: usize,
+        end: usize,
+        color: &Color,
+    ) {
+        let line_height = self.config.editor.line_height as f64;
+        let width = self.config.editor_text_width(ctx.text(), "W");
+        let rect = ctx.region().bounding_box();
+        let (start_line, start_col) = self.buffer.offset_to_line_col(start);
+        let (end_line, end_col) = self.buffer.offset_to_line_col(end);
+        for line in start_line..end_line + 1 {
+            let y0 = self.buffer.visual_line(line) as f64 * line_height;
+            if y0 + line_height < rect.y0 || y0 > rect.y1 {
+                continue;
+            }
+            let left_col = if line == start_line { start_col } else { 0 };
+            let right_col = if line == end_line {
+                end_col
+            } else {
+                self.buffer.line_end_col(line, true) + 1
+            };
+            let x0 = self.buffer.visual_col(line, left_col, false) as f64 * width;
+            let x1 = self.buffer.visual_col(line, right_col, true) as f64 * width;
+            ctx.fill(Rect::new(x0, y0, x1, y0 + line_height), color);
+        }
+    }
+
+    fn paint_inlay_hints(
+        &self,
+        ctx: &mut PaintCtx,
+        start_line: usize,
+        end_line: usize,
+    ) {
+        if self.buffer.inlay_hints.len() == 0 {
+            return;
+        }
+        let line_height = self.config.editor.line_height as f64;
+        let width = self.config.editor_text_width(ctx.text(), "W");
+        for line in start_line..end_line.min(self.buffer.last_line()) + 1 {
+            let mut shift = 0;
+            for (col, label_width, hint) in self.buffer.line_virtual_texts(line) {
+                let hint = match hint {
+                    Some(hint) => hint,
+                    None => {
+                        shift += label_width;
+                        continue;
+                    }
+                };
+                let padding_left = hint.hint.padding_left.unwrap_or(false) as usize;
+                let padding_right =
+                    hint.hint.padding_right.unwrap_or(false) as usize;
+                let x0 = (col + shift + padding_left) as f64 * width;
+                let x1 = (col + shift + label_width - padding_right) as f64 * width;
+                let y0 = self.buffer.visual_line(line) as f64 * line_height;
+                ctx.fill(
+                    Rect::new(x0, y0, x1, y0 + line_height)
+                        .inflate(0.0, -2.0)
+                        .to_rounded_rect(3.0),
+                    self.config
+                        .get_color_unchecked(LapceTheme::INLAY_HINT_BACKGROUND),
+                );
+                shift += label_width;
+            }
+        }
+    }
+
+    /// Draws the lines of the inline completion after the first over the
+    /// rows below the cursor line, as they aren't in the buffer to make
+    /// room for.
+    fn paint_inline_completion(&self, ctx: &mut PaintCtx, y_shift: f64) {
+        let completion = match self.buffer.inline_completion.as_ref() {
+            Some(completion) => completion,
+            None => return,
+        };
+        let lines = completion.other_lines();
+        if lines.len() == 0 {
+            return;
+        }
+        let line_height = self.config.editor.line_height as f64;
+        let line = self.buffer.line_of_offset(completion.offset);
+        let y0 = (self.buffer.visual_line(line) + 1) as f64 * line_height;
+        let rect = ctx.region().bounding_box();
+        ctx.fill(
+            Rect::new(rect.x0, y0, rect.x1, y0 + lines.len() as f64 * line_height),
+            self.config
+                .get_color_unchecked(LapceTheme::EDITOR_BACKGROUND),
+        );
+        for (i, text) in lines.into_iter().enumerate() {
+            let text_layout = ctx
+                .text()
+                .new_text_layout(text)
+                .font(
+                    self.config.editor.font_family(),
+                    self.config.editor.font_size as f64,
+                )
+                .text_color(
+                    self.config
+                        .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(
+                &text_layout,
+                Point::new(0.0, y0 + i as f64 * line_height + y_shift),
+            );
+        }
+    }
+
+    fn paint_code_lens(
+        &self,
+        ctx: &mut PaintCtx,
+        start_line: usize,
+        end_line: usize,
+    ) {
+        if self.buffer.code_lens.len() == 0 {
+            return;
+        }
+        let line_height = self.config.editor.line_height as f64;
+        let width = self.config.editor_text_width(ctx.text(), "W");
+        for line in start_line..end_line.min(self.buffer.last_line()) + 1 {
+            let lenses = self.buffer.line_code_lens(line);
+            if lenses.len() == 0 {
+                continue;
+            }
+            let text = lenses
+                .iter()
+                .map(|l| l.title())
+                .collect::<Vec<String>>()
+                .join(CODE_LENS_SEPARATOR);
+            let text_layout = ctx
+                .text()
+                .new_text_layout(text)
+                .font(
+                    self.config.editor.font_family(),
+                    self.config.editor.font_size as f64,
+                )
+                .text_color(
+                    self.config
+                        .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            let x = self.buffer.code_lens_col(line) as f64 * width;
+            let y = (self.buffer.visual_line(line) - 1) as f64 * line_height
+                + (line_height - text_layout.size().height) / 2.0;
+            ctx.draw_text(&text_layout, Point::new(x, y));
+        }
+    }
+
+    /// Draws the tooltip of the hint at `offset` underneath it.
+    fn paint_inlay_hint_tooltip(&self, ctx: &mut PaintCtx, offset: usize) {
+        let hint = match self.buffer.inlay_hints.iter().find(|h| h.offset == offset)
+        {
+            Some(hint) => hint,
+            None => return,
+        };
+        let tooltip = match hint.hint.tooltip() {
+            Some(tooltip) => tooltip,
+            None => return,
+        };
+        let line_height = self.config.editor.line_height as f64;
+        let width = self.config.editor_text_width(ctx.text(), "W");
+        let (line, col) = self.buffer.offset_to_line_col(offset);
+        let x = self.buffer.visual_col(line, col, true) as f64 * width;
+        let y = (self.buffer.visual_line(line) + 1) as f64 * line_height;
+
+        let text_layout = ctx
+            .text()
+            .new_text_layout(tooltip)
+            .font(FontFamily::SYSTEM_UI, 13.0)
+            .text_color(
+                self.config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            )
+            .max_width(600.0)
+            .build()
+            .unwrap();
+        let text_size = text_layout.size();
+        let rect = Size::new(text_size.width + 20.0, text_size.height + 10.0)
+            .to_rect()
+            .with_origin(Point::new(x, y));
+        ctx.fill(
+            rect,
+            self.config
+                .get_color_unchecked(LapceTheme::PANEL_BACKGROUND),
+        );
+        ctx.stroke(
+            rect,
+            self.config.get_color_unchecked(LapceTheme::LAPCE_BORDER),
+            1.0,
+        );
+        ctx.draw_text(&text_layout, Point::new(x + 10.0, y + 5.0));
+    }
+
     fn paint_snippet(&self, ctx: &mut PaintCtx) {
         let line_height = self.config.editor.line_height as f64;
-        let start_line =
-            (self.editor.scroll_offset.y / line_height).floor() as usize;
-        let end_line = ((self.editor.size.borrow().height
-            + self.editor.scroll_offset.y)
-            / line_height)
-            .ceil() as usize;
+        let start_line = self
+            .buffer
+            .line_of_visual_line(
+                (self.editor.scroll_offset.y / line_height).floor() as usize
+            )
+            .0;
+        let end_line = self
+            .buffer
+            .line_of_visual_line(
+                ((self.editor.size.borrow().height + self.editor.scroll_offset.y)
+                    / line_height)
+                    .ceil() as usize,
+            )
+            .0;
         let width = self.config.editor_text_width(ctx.text(), "W");
         if let Some(snippet) = self.editor.snippet.as_ref() {
             for (_, (start, end)) in snippet {
@@ -1761,7 +2856,8 @@ impl LapceEditorBufferData {
                         _ if line == start_line => start_col,
                         _ => 0,
                     };
-                    let x0 = left_col as f64 * width;
+                    let x0 =
+                        self.buffer.visual_col(line, left_col, false) as f64 * width;
 
                     let right_col = match line {
                         _ if line == end_line => {
@@ -1771,8 +2867,10 @@ impl LapceEditorBufferData {
                         _ => self.buffer.line_end_col(line, true),
                     };
                     if line_content.len() > 0 {
-                        let x1 = right_col as f64 * width;
-                        let y0 = line as f64 * line_height;
+                        let x1 = self.buffer.visual_col(line, right_col, true)
+                            as f64
+                            * width;
+                        let y0 = self.buffer.visual_line(line) as f64 * line_height;
                         let y1 = y0 + line_height;
                         ctx.stroke(
                             Rect::new(x0, y0, x1, y1).inflate(1.0, -0.5),
@@ -1788,12 +2886,20 @@ impl LapceEditorBufferData {
 
     fn paint_diagnostics(&self, ctx: &mut PaintCtx) {
         let line_height = self.config.editor.line_height as f64;
-        let start_line =
-            (self.editor.scroll_offset.y / line_height).floor() as usize;
-        let end_line = ((self.editor.size.borrow().height
-            + self.editor.scroll_offset.y)
-            / line_height)
-            .ceil() as usize;
+        let start_line = self
+            .buffer
+            .line_of_visual_line(
+                (self.editor.scroll_offset.y / line_height).floor() as usize
+            )
+            .0;
+        let end_line = self
+            .buffer
+            .line_of_visual_line(
+                ((self.editor.size.borrow().height + self.editor.scroll_offset.y)
+                    / line_height)
+                    .ceil() as usize,
+            )
+            .0;
 
         let width = self.config.editor_text_width(ctx.text(), "W");
         let mut current = None;
@@ -1822,21 +2928,34 @@ impl LapceEditorBufferData {
                         }
 
                         let x0 = if line == start.line as usize {
-                            start.character as f64 * width
+                            let (_, col) = self.buffer.offset_to_line_col(
+                                self.buffer.offset_of_position(&start),
+                            );
+                            self.buffer.visual_col(line, col, false) as f64 * width
                         } else {
                             let (_, col) = self.buffer.offset_to_line_col(
                                 self.buffer.first_non_blank_character_on_line(line),
                             );
-                            col as f64 * width
+                            self.buffer.visual_col(line, col, false) as f64 * width
                         };
                         let x1 = if line == end.line as usize {
-                            end.character as f64 * width
+                            let (_, col) = self.buffer.offset_to_line_col(
+                                self.buffer.offset_of_position(&end),
+                            );
+                            self.buffer.visual_col(line, col, true) as f64 * width
                         } else {
-                            (self.buffer.line_end_col(line, false) + 1) as f64
+                            self.buffer.visual_col(
+                                line,
+                                self.buffer.line_end_col(line, false) + 1,
+                                true,
+                            ) as f64
                                 * width
                         };
-                        let y1 = (line + 1) as f64 * line_height;
-                        let y0 = (line + 1) as f64 * line_height - 4.0;
+                        let y1 =
+                            (self.buffer.visual_line(line) + 1) as f64 * line_height;
+                        let y0 = (self.buffer.visual_line(line) + 1) as f64
+                            * line_height
+                            - 4.0;
 
                         let severity = diagnostic
                             .diagnositc
@@ -1911,7 +3030,8 @@ impl LapceEditorBufferData {
                 let rect = Rect::ZERO
                     .with_origin(Point::new(
                         0.0,
-                        (start.line + 1) as f64 * line_height,
+                        (self.buffer.visual_line(start.line as usize) + 1) as f64
+                            * line_height,
                     ))
                     .with_size(Size::new(
                         self.editor.size.borrow().width,
@@ -1942,7 +3062,9 @@ impl LapceEditorBufferData {
                     &text_layout,
                     Point::new(
                         10.0 + self.editor.scroll_offset.x,
-                        (start.line + 1) as f64 * line_height + 10.0,
+                        (self.buffer.visual_line(start.line as usize) + 1) as f64
+                            * line_height
+                            + 10.0,
                     ),
                 );
                 let mut text_height = text_size.height;
@@ -1953,7 +3075,9 @@ impl LapceEditorBufferData {
                         &text,
                         Point::new(
                             10.0 + self.editor.scroll_offset.x,
-                            (start.line + 1) as f64 * line_height
+                            (self.buffer.visual_line(start.line as usize) + 1)
+                                as f64
+                                * line_height
                                 + 10.0
                                 + text_height,
                         ),
@@ -2009,6 +3133,11 @@ impl KeyPressFocus for LapceEditorBufferData {
                 self.editor.editor_type == EditorType::SourceControl
             }
             "in_snippet" => self.editor.snippet.is_some(),
+            "inline_completion_visible" => self.inline_completion_visible(),
+            "signature_active" => {
+                self.signature.is_active() && self.signature.path == self.buffer.path
+            }
+            "peek_focus" => self.editor.editor_type == EditorType::Peek,
             "list_focus" => {
                 self.completion.status != CompletionStatus::Inactive
                     && self.completion.len() > 0
@@ -2114,7 +3243,13 @@ impl KeyPressFocus for LapceEditorBufferData {
                 }
             }
             LapceCommand::SplitClose => {
-                if let Some(split_id) = self.editor.split_id.clone() {
+                if self.editor.editor_type == EditorType::Peek {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::ClosePeek,
+                        Target::Auto,
+                    ));
+                } else if let Some(split_id) = self.editor.split_id.clone() {
                     if self.editor.editor_type == EditorType::Normal {
                         ctx.submit_command(Command::new(
                             LAPCE_UI_COMMAND,
@@ -2421,6 +3556,7 @@ impl KeyPressFocus for LapceEditorBufferData {
                 };
                 self.insert_new_line(ctx, self.editor.cursor.offset());
                 self.update_completion(ctx);
+                self.request_on_type_formatting(ctx, "\n");
             }
             LapceCommand::ToggleVisualMode => {
                 self.toggle_visual(VisualMode::Normal);
@@ -2469,56 +3605,52 @@ impl KeyPressFocus for LapceEditorBufferData {
                 completion.previous();
             }
             LapceCommand::JumpToNextSnippetPlaceholder => {
-                if let Some(snippet) = self.editor.snippet.as_ref() {
-                    let mut current = 0;
+                if let Some(snippet) = self.editor.snippet.clone() {
                     let offset = self.editor.cursor.offset();
-                    for (i, (_, (start, end))) in snippet.iter().enumerate() {
-                        if *start <= offset && offset <= *end {
-                            current = i;
-                            break;
-                        }
-                    }
+                    let current = snippet
+                        .iter()
+                        .position(|(_, (start, end))| {
+                            *start <= offset && offset <= *end
+                        })
+                        .unwrap_or(0);
+                    let tab = snippet[current].0;
 
-                    let last_placeholder = current + 1 >= snippet.len() - 1;
+                    // the placeholders of a tab, mirrors included, are next to
+                    // each other
+                    let next = snippet[current..]
+                        .iter()
+                        .find(|(t, _)| *t != tab)
+                        .map(|(t, _)| *t);
+                    let last_placeholder = next
+                        .map(|next| snippet.last().map(|(t, _)| *t) == Some(next))
+                        .unwrap_or(true);
 
-                    if let Some((_, (start, end))) = snippet.get(current + 1) {
-                        let mut selection = Selection::new();
-                        let region = SelRegion::new(*start, *end, None);
-                        selection.add_region(region);
-                        self.set_cursor(Cursor::new(
-                            CursorMode::Insert(selection),
-                            None,
-                        ));
+                    self.cancel_completion();
+                    if let Some(next) = next {
+                        self.select_snippet_tab(&snippet, next);
                     }
 
                     if last_placeholder {
                         Arc::make_mut(&mut self.editor).snippet = None;
                     }
-                    self.cancel_completion();
                 }
             }
             LapceCommand::JumpToPrevSnippetPlaceholder => {
-                if let Some(snippet) = self.editor.snippet.as_ref() {
-                    let mut current = 0;
+                if let Some(snippet) = self.editor.snippet.clone() {
                     let offset = self.editor.cursor.offset();
-                    for (i, (_, (start, end))) in snippet.iter().enumerate() {
-                        if *start <= offset && offset <= *end {
-                            current = i;
-                            break;
-                        }
-                    }
+                    let current = snippet
+                        .iter()
+                        .position(|(_, (start, end))| {
+                            *start <= offset && offset <= *end
+                        })
+                        .unwrap_or(0);
+                    let tab = snippet[current].0;
 
-                    if current > 0 {
-                        if let Some((_, (start, end))) = snippet.get(current - 1) {
-                            let mut selection = Selection::new();
-                            let region = SelRegion::new(*start, *end, None);
-                            selection.add_region(region);
-                            self.set_cursor(Cursor::new(
-                                CursorMode::Insert(selection),
-                                None,
-                            ));
-                        }
+                    if let Some((prev, _)) =
+                        snippet[..current].iter().rev().find(|(t, _)| *t != tab)
+                    {
                         self.cancel_completion();
+                        self.select_snippet_tab(&snippet, *prev);
                     }
                 }
             }
@@ -2539,8 +3671,11 @@ impl KeyPressFocus for LapceEditorBufferData {
                 };
 
                 let item = self.completion.current_item().to_owned();
+                let resolved = self.completion.resolved_item(&item);
                 self.cancel_completion();
-                if item.data.is_some() {
+                if let Some(item) = resolved {
+                    self.apply_completion_item(ctx, &item);
+                } else if item.data.is_some() {
                     let view_id = self.editor.view_id;
                     let buffer_id = self.buffer.id;
                     let rev = self.buffer.rev;
@@ -2573,6 +3708,7 @@ impl KeyPressFocus for LapceEditorBufferData {
                 }
             }
             LapceCommand::NormalMode => {
+                Arc::make_mut(&mut self.signature).clear();
                 if !self.config.lapce.modal {
                     return;
                 }
@@ -2674,6 +3810,66 @@ impl KeyPressFocus for LapceEditorBufferData {
                     }),
                 );
             }
+            LapceCommand::GotoTypeDefinition => {
+                self.goto_location(ctx, GotoKind::TypeDefinition);
+            }
+            LapceCommand::GotoImplementation => {
+                self.goto_location(ctx, GotoKind::Implementation);
+            }
+            LapceCommand::GotoDeclaration => {
+                self.goto_location(ctx, GotoKind::Declaration);
+            }
+            LapceCommand::PeekDefinition => {
+                let offset = self.editor.cursor.offset();
+                let event_sink = ctx.get_external_handle();
+                let position = self.buffer.offset_to_position(offset);
+                let editor_view_id = self.editor.view_id;
+                self.proxy.get_definition(
+                    offset,
+                    self.buffer.id,
+                    position,
+                    Box::new(move |result| {
+                        if let Ok(res) = result {
+                            if let Ok(resp) =
+                                serde_json::from_value::<GotoDefinitionResponse>(res)
+                            {
+                                if let Some(location) =
+                                    goto_locations(resp).into_iter().next()
+                                {
+                                    event_sink.submit_command(
+                                        LAPCE_UI_COMMAND,
+                                        LapceUICommand::ShowPeek(
+                                            editor_view_id,
+                                            offset,
+                                            EditorLocationNew {
+                                                path: PathBuf::from(
+                                                    location.uri.path(),
+                                                ),
+                                                position: Some(location.range.start),
+                                                scroll_offset: None,
+                                            },
+                                        ),
+                                        Target::Auto,
+                                    );
+                                }
+                            }
+                        }
+                    }),
+                );
+            }
+            LapceCommand::PeekClose => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::ClosePeek,
+                    Target::Auto,
+                ));
+            }
+            LapceCommand::ShowCallHierarchy => {
+                self.show_hierarchy(ctx, HierarchyKind::Incoming);
+            }
+            LapceCommand::ShowTypeHierarchy => {
+                self.show_hierarchy(ctx, HierarchyKind::Supertypes);
+            }
             LapceCommand::SourceControl => {
                 ctx.submit_command(Command::new(
                     LAPCE_UI_COMMAND,
@@ -2691,6 +3887,47 @@ impl KeyPressFocus for LapceEditorBufferData {
                     println!("source control cancel");
                 }
             }
+            LapceCommand::RestartLanguageServer => {
+                self.proxy.restart_language_server(self.buffer.id);
+            }
+            LapceCommand::FormatSelection => {
+                // Without a selection, the line of the cursor is formatted.
+                let selection = self.editor.cursor.edit_selection(&self.buffer);
+                let (start, end) = match &self.editor.cursor.mode {
+                    CursorMode::Visual { .. } => {
+                        (selection.min_offset(), selection.max_offset())
+                    }
+                    CursorMode::Insert(_) if !selection.is_caret() => {
+                        (selection.min_offset(), selection.max_offset())
+                    }
+                    _ => {
+                        let line =
+                            self.buffer.line_of_offset(self.editor.cursor.offset());
+                        (
+                            self.buffer.offset_of_line(line),
+                            self.buffer.offset_of_line(line + 1),
+                        )
+                    }
+                };
+                self.request_range_formatting(ctx, start, end);
+                if let CursorMode::Visual { start, end, .. } =
+                    &self.editor.cursor.mode
+                {
+                    let offset = *start.min(end);
+                    let offset =
+                        self.buffer.offset_line_end(offset, false).min(offset);
+                    self.set_cursor(Cursor::new(CursorMode::Normal(offset), None));
+                }
+            }
+            LapceCommand::ShowSignatureHelp => {
+                self.request_signature(ctx, None);
+            }
+            LapceCommand::SignatureNext => {
+                Arc::make_mut(&mut self.signature).next_signature();
+            }
+            LapceCommand::SignaturePrevious => {
+                Arc::make_mut(&mut self.signature).previous_signature();
+            }
             LapceCommand::ShowCodeActions => {
                 if let Some(actions) = self.current_code_actions() {
                     if actions.len() > 0 {
@@ -2702,6 +3939,52 @@ impl KeyPressFocus for LapceEditorBufferData {
                     }
                 }
             }
+            LapceCommand::FixAllInFile => {
+                self.request_preferred_fixes(ctx);
+            }
+            LapceCommand::InlineCompletionAccept => {
+                self.accept_inline_completion(ctx, false);
+            }
+            LapceCommand::InlineCompletionAcceptWord => {
+                self.accept_inline_completion(ctx, true);
+            }
+            LapceCommand::SelectNextHighlight => {
+                self.select_next_highlight(true);
+            }
+            LapceCommand::SelectPreviousHighlight => {
+                self.select_next_highlight(false);
+            }
+            LapceCommand::SelectAllHighlights => {
+                self.select_all_highlights();
+            }
+            LapceCommand::RenameSymbol => {
+                let offset = self.editor.cursor.offset();
+                let buffer_id = self.buffer.id;
+                let position = self.buffer.offset_to_position(offset);
+                let path = self.buffer.path.clone();
+                let rev = self.buffer.rev;
+                let event_sink = ctx.get_external_handle();
+                self.proxy.prepare_rename(
+                    buffer_id,
+                    position,
+                    Box::new(move |result| {
+                        if let Ok(res) = result {
+                            if let Ok(Some(resp)) = serde_json::from_value::<
+                                Option<PrepareRenameResponse>,
+                            >(res)
+                            {
+                                event_sink.submit_command(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::PrepareRename(
+                                        path, rev, offset, resp,
+                                    ),
+                                    Target::Auto,
+                                );
+                            }
+                        }
+                    }),
+                );
+            }
             LapceCommand::SearchWholeWordForward => {
                 let offset = self.editor.cursor.offset();
                 let (start, end) = self.buffer.select_word(offset);
@@ -2796,6 +4079,11 @@ impl KeyPressFocus for LapceEditorBufferData {
 
     fn receive_char(&mut self, ctx: &mut EventCtx, c: &str) {
         if self.get_mode() == Mode::Insert {
+            if let Some(item) = self.completion.commit_item(self.buffer.id, c) {
+                self.cancel_completion();
+                self.apply_completion_item(ctx, &item);
+            }
+
             let mut selection = self.editor.cursor.edit_selection(&self.buffer);
             let cursor_char =
                 self.buffer.char_at_offset(selection.get_cursor_offset());
@@ -2806,6 +4094,7 @@ impl KeyPressFocus for LapceEditorBufferData {
                 if !matching_pair_direction(c).unwrap_or(true) {
                     if cursor_char == Some(c) {
                         self.do_move(&Movement::Right, 1);
+                        self.update_signature(ctx, &content);
                         return;
                     } else {
                         let offset = selection.get_cursor_offset();
@@ -2868,6 +4157,9 @@ impl KeyPressFocus for LapceEditorBufferData {
                 }
             }
             self.update_completion(ctx);
+            self.update_signature(ctx, c);
+            self.request_on_type_formatting(ctx, c);
+            self.request_inline_completion(ctx);
         } else {
             if let Some(direction) = self.editor.inline_find.clone() {
                 self.inline_find(direction.clone(), c);
@@ -2883,6 +4175,14 @@ pub struct LapceEditorView {
     pub view_id: WidgetId,
     pub header: WidgetPod<LapceTabData, LapceEditorHeader>,
     pub editor: WidgetPod<LapceTabData, LapceEditorContainer>,
+    /// The path, revision and line range of the last inlay hints request.
+    inlay_hints_request: Option<(PathBuf, u64, usize, usize)>,
+    /// The same for semantic tokens of a range.
+    semantic_tokens_request: Option<(PathBuf, u64, usize, usize)>,
+    /// The path and revision of the last code lens request.
+    code_lens_request: Option<(PathBuf, u64)>,
+    /// Delays asking for document highlights until the cursor settles.
+    document_highlight_timer: TimerToken,
 }
 
 impl LapceEditorView {
@@ -2893,7 +4193,121 @@ impl LapceEditorView {
             view_id: data.view_id,
             header: WidgetPod::new(header),
             editor: WidgetPod::new(editor),
+            inlay_hints_request: None,
+            semantic_tokens_request: None,
+            code_lens_request: None,
+            document_highlight_timer: TimerToken::INVALID,
+        }
+    }
+
+    fn request_code_lens(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        data: &LapceEditorBufferData,
+    ) {
+        if !data.config.editor.enable_code_lens
+            || !data.buffer.loaded
+            || data.buffer.local
+        {
+            return;
+        }
+        let request = (data.buffer.path.clone(), data.buffer.rev);
+        if self.code_lens_request.as_ref() == Some(&request) {
+            return;
+        }
+        self.code_lens_request = Some(request);
+        data.get_code_lens(ctx.get_external_handle());
+    }
+
+    /// Requests hints for the visible lines plus a screen either side, once
+    /// per revision, or again when scrolling leaves the requested lines.
+    fn request_inlay_hints(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        data: &LapceEditorBufferData,
+    ) {
+        if !data.config.editor.enable_inlay_hints
+            || !data.buffer.loaded
+            || data.buffer.local
+        {
+            return;
+        }
+        if let Some((start, end)) =
+            self.lines_to_request(data, &self.inlay_hints_request)
+        {
+            self.inlay_hints_request =
+                Some((data.buffer.path.clone(), data.buffer.rev, start, end));
+            data.get_inlay_hints(ctx.get_external_handle(), start, end);
+        }
+    }
+
+    /// Large files get semantic tokens only for the lines around the ones
+    /// visible, asked for the same way as inlay hints.
+    fn request_semantic_tokens_range(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        data: &LapceEditorBufferData,
+    ) {
+        if !data.buffer.loaded
+            || data.buffer.local
+            || !semantic_tokens_by_range(data.buffer.num_lines())
+        {
+            return;
+        }
+        if let Some((start, end)) =
+            self.lines_to_request(data, &self.semantic_tokens_request)
+        {
+            self.semantic_tokens_request =
+                Some((data.buffer.path.clone(), data.buffer.rev, start, end));
+            data.get_semantic_tokens_range(ctx.get_external_handle(), start, end);
+        }
+    }
+
+    /// The visible lines plus a screen either side, unless `request`, the
+    /// path, revision and lines asked for last, still covers the visible
+    /// ones.
+    fn lines_to_request(
+        &self,
+        data: &LapceEditorBufferData,
+        request: &Option<(PathBuf, u64, usize, usize)>,
+    ) -> Option<(usize, usize)> {
+        let line_height = data.config.editor.line_height as f64;
+        let height = self.editor.layout_rect().height();
+        if height <= 0.0 {
+            return None;
+        }
+        let (start_line, _) = data.buffer.line_of_visual_line(
+            (data.editor.scroll_offset.y / line_height) as usize,
+        );
+        let (end_line, _) = data.buffer.line_of_visual_line(
+            ((data.editor.scroll_offset.y + height) / line_height).ceil() as usize,
+        );
+        let screen = end_line - start_line;
+        let last_line = data.buffer.last_line();
+
+        if let Some((path, rev, start, end)) = request.as_ref() {
+            if path == &data.buffer.path
+                && *rev == data.buffer.rev
+                && *start <= start_line
+                && (*end >= end_line || *end >= last_line)
+            {
+                return None;
+            }
         }
+
+        let start = start_line.saturating_sub(screen);
+        let end = (end_line + screen).min(last_line);
+        Some((start, end))
+    }
+
+    /// The peek editor is shown on top of another editor, which stays the
+    /// active one.
+    fn is_peek(&self, data: &LapceTabData) -> bool {
+        data.main_split
+            .editors
+            .get(&self.view_id)
+            .map(|editor| editor.editor_type == EditorType::Peek)
+            .unwrap_or(false)
     }
 
     pub fn hide_header(mut self) -> Self {
@@ -2957,7 +4371,8 @@ impl LapceEditorView {
                         .inner_mut()
                         .scroll_by(Vec2::new(
                             0.0,
-                            (new_line as f64 - line as f64)
+                            (data.buffer.visual_line(new_line) as f64
+                                - data.buffer.visual_line(line) as f64)
                                 * data.config.editor.line_height as f64,
                         ));
                 }
@@ -3037,13 +4452,17 @@ impl LapceEditorView {
         let line_height = data.config.editor.line_height as f64;
         let offset = data.editor.cursor.offset();
         let (line, col) = data.buffer.offset_to_line_col(offset);
+        let col = data
+            .buffer
+            .visual_col(line, col, data.editor.cursor.is_insert());
         let width = data.config.editor_text_width(ctx.text(), "W");
         let cursor_x = col as f64 * width - width;
         let cursor_x = if cursor_x < 0.0 { 0.0 } else { cursor_x };
         let rect = Rect::ZERO
             .with_origin(Point::new(
                 cursor_x.floor(),
-                line as f64 * line_height + line_height / 2.0,
+                data.buffer.visual_line(line) as f64 * line_height
+                    + line_height / 2.0,
             ))
             .with_size(Size::new((width * 3.0).ceil(), 0.0))
             .inflate(0.0, (data.editor.size.borrow().height / 2.0).ceil());
@@ -3051,7 +4470,7 @@ impl LapceEditorView {
         let size = Size::new(
             (width * data.buffer.max_len as f64)
                 .max(data.editor.size.borrow().width),
-            line_height * data.buffer.num_lines as f64
+            line_height * data.buffer.visual_num_lines() as f64
                 + data.editor.size.borrow().height
                 - line_height,
         );
@@ -3078,7 +4497,7 @@ impl LapceEditorView {
         let size = Size::new(
             (width * data.buffer.max_len as f64)
                 .max(data.editor.size.borrow().width),
-            line_height * data.buffer.num_lines as f64
+            line_height * data.buffer.visual_num_lines() as f64
                 + data.editor.size.borrow().height
                 - line_height,
         );
@@ -3136,7 +4555,9 @@ impl Widget<LapceTabData> for LapceEditorView {
                     ctx.request_focus();
                     data.focus = self.view_id;
                     data.focus_area = FocusArea::Editor;
-                    data.main_split.active = Arc::new(self.view_id);
+                    if !self.is_peek(data) {
+                        data.main_split.active = Arc::new(self.view_id);
+                    }
                 }
             }
             Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
@@ -3146,7 +4567,9 @@ impl Widget<LapceTabData> for LapceEditorView {
                         ctx.request_focus();
                         data.focus = self.view_id;
                         data.focus_area = FocusArea::Editor;
-                        data.main_split.active = Arc::new(self.view_id);
+                        if !self.is_peek(data) {
+                            data.main_split.active = Arc::new(self.view_id);
+                        }
                     }
                     _ => (),
                 }
@@ -3162,6 +4585,7 @@ impl Widget<LapceTabData> for LapceEditorView {
                     view_id: self.view_id,
                     main_split: data.main_split.clone(),
                     completion: data.completion.clone(),
+                    signature: data.signature.clone(),
                     proxy: data.proxy.clone(),
                     find: data.find.clone(),
                     buffer: buffer.clone(),
@@ -3171,6 +4595,13 @@ impl Widget<LapceTabData> for LapceEditorView {
                 };
 
                 match event {
+                    Event::Timer(token)
+                        if *token == self.document_highlight_timer =>
+                    {
+                        self.document_highlight_timer = TimerToken::INVALID;
+                        editor_data
+                            .get_document_highlights(ctx.get_external_handle());
+                    }
                     Event::KeyDown(key_event) => {
                         ctx.set_handled();
                         let mut keypress = data.keypress.clone();
@@ -3185,7 +4616,9 @@ impl Widget<LapceTabData> for LapceEditorView {
                         editor_data.sync_buffer_position(
                             self.editor.widget().editor.widget().inner().offset(),
                         );
+                        editor_data.dismiss_inline_completion();
                         editor_data.get_code_actions(ctx);
+                        editor_data.retrigger_signature(ctx);
 
                         data.keypress = keypress.clone();
                     }
@@ -3321,6 +4754,44 @@ impl Widget<LapceTabData> for LapceEditorView {
                 {
                     ctx.request_paint();
                 }
+
+                if !buffer.inlay_hints.same(&old_buffer.inlay_hints) {
+                    ctx.request_paint();
+                }
+                if old_data.config.editor.enable_inlay_hints
+                    != data.config.editor.enable_inlay_hints
+                {
+                    self.inlay_hints_request = None;
+                }
+                self.request_inlay_hints(ctx, &data);
+                self.request_semantic_tokens_range(ctx, &data);
+
+                if !buffer.code_lens.same(&old_buffer.code_lens) {
+                    ctx.request_layout();
+                    ctx.request_paint();
+                }
+                if old_data.config.editor.enable_code_lens
+                    != data.config.editor.enable_code_lens
+                {
+                    self.code_lens_request = None;
+                }
+                self.request_code_lens(ctx, &data);
+
+                if !buffer
+                    .document_highlights
+                    .same(&old_buffer.document_highlights)
+                    || !buffer.call_sites.same(&old_buffer.call_sites)
+                {
+                    ctx.request_paint();
+                }
+                let offset = data.editor.cursor.offset();
+                if (offset != old_data.editor.cursor.offset()
+                    || buffer.rev != old_buffer.rev)
+                    && buffer.document_highlight_at(offset).is_none()
+                {
+                    self.document_highlight_timer =
+                        ctx.request_timer(Duration::from_millis(200));
+                }
             }
             (LapceEditorViewContent::Buffer(_), LapceEditorViewContent::None) => {
                 ctx.request_layout();
@@ -3499,13 +4970,17 @@ impl LapceEditorContainer {
         let line_height = data.config.editor.line_height as f64;
         let offset = data.editor.cursor.offset();
         let (line, col) = data.buffer.offset_to_line_col(offset);
+        let col = data
+            .buffer
+            .visual_col(line, col, data.editor.cursor.is_insert());
         let width = data.config.editor_text_width(ctx.text(), "W");
         let cursor_x = col as f64 * width - width;
         let cursor_x = if cursor_x < 0.0 { 0.0 } else { cursor_x };
         let rect = Rect::ZERO
             .with_origin(Point::new(
                 cursor_x.floor(),
-                line as f64 * line_height + line_height / 2.0,
+                data.buffer.visual_line(line) as f64 * line_height
+                    + line_height / 2.0,
             ))
             .with_size(Size::new((width * 3.0).ceil(), 0.0))
             .inflate(0.0, (data.editor.size.borrow().height / 2.0).ceil());
@@ -3513,7 +4988,7 @@ impl LapceEditorContainer {
         let size = Size::new(
             (width * data.buffer.max_len as f64)
                 .max(data.editor.size.borrow().width),
-            line_height * data.buffer.num_lines as f64
+            line_height * data.buffer.visual_num_lines() as f64
                 + data.editor.size.borrow().height
                 - line_height,
         );
@@ -3930,6 +5405,8 @@ pub struct LapceEditor {
     view_id: WidgetId,
     placeholder: Option<String>,
     commands: Vec<(LapceCommandNew, PietTextLayout, Rect, PietTextLayout)>,
+    /// The path and offset of the inlay hint under the mouse.
+    inlay_hint_hover: Option<(PathBuf, usize)>,
 }
 
 impl LapceEditor {
@@ -3938,6 +5415,81 @@ impl LapceEditor {
             view_id,
             placeholder: None,
             commands: vec![],
+            inlay_hint_hover: None,
+        }
+    }
+
+    fn code_lens_at<'a>(
+        &self,
+        ctx: &mut EventCtx,
+        pos: Point,
+        buffer: &'a BufferNew,
+        config: &Config,
+    ) -> Option<&'a EditorCodeLens> {
+        if buffer.code_lens.len() == 0 {
+            return None;
+        }
+        let line_height = config.editor.line_height as f64;
+        let width = config.editor_text_width(ctx.text(), "W");
+        let (line, is_lens) =
+            buffer.line_of_visual_line((pos.y / line_height).floor() as usize);
+        if !is_lens {
+            return None;
+        }
+        buffer.code_lens_at_visual_col(line, (pos.x / width).floor() as usize)
+    }
+
+    fn update_inlay_hint_hover(
+        &mut self,
+        ctx: &mut EventCtx,
+        pos: Point,
+        buffer: &BufferNew,
+        proxy: &LapceProxy,
+        config: &Config,
+    ) {
+        let line_height = config.editor.line_height as f64;
+        let width = config.editor_text_width(ctx.text(), "W");
+        let (line, is_lens) =
+            buffer.line_of_visual_line((pos.y / line_height).floor() as usize);
+        let visual_col = (pos.x / width).floor() as usize;
+        let hint = if !is_lens && line <= buffer.last_line() {
+            buffer.inlay_hint_at_visual_col(line, visual_col)
+        } else {
+            None
+        };
+        let hover = hint.map(|h| (buffer.path.clone(), h.offset));
+        if hover == self.inlay_hint_hover {
+            return;
+        }
+        self.inlay_hint_hover = hover;
+        ctx.request_paint();
+
+        if let Some(hint) = hint {
+            if hint.hint.tooltip().is_none() && hint.hint.data.is_some() {
+                let path = buffer.path.clone();
+                let rev = buffer.rev;
+                let offset = hint.offset;
+                let event_sink = ctx.get_external_handle();
+                proxy.resolve_inlay_hint(
+                    buffer.id,
+                    &hint.hint,
+                    Box::new(move |result| {
+                        if let Ok(res) = result {
+                            if let Ok(hint) =
+                                serde_json::from_value::<InlayHint>(res)
+                            {
+                                event_sink.submit_command(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::UpdateInlayHint(
+                                        path, rev, offset, hint,
+                                    ),
+                                    Target::Auto,
+                                );
+                            }
+                        }
+                    }),
+                );
+            }
         }
     }
 }
@@ -3968,7 +5520,25 @@ impl Widget<LapceTabData> for LapceEditor {
                     }
                 }
                 EditorContent::Buffer(path) => {
-                    ctx.set_cursor(&druid::Cursor::IBeam);
+                    let buffer = data.main_split.open_files.get(path).unwrap();
+                    if !ctx.is_active()
+                        && self
+                            .code_lens_at(ctx, mouse_event.pos, buffer, &data.config)
+                            .is_some()
+                    {
+                        ctx.set_cursor(&druid::Cursor::Pointer);
+                    } else {
+                        ctx.set_cursor(&druid::Cursor::IBeam);
+                    }
+                    if !ctx.is_active() {
+                        self.update_inlay_hint_hover(
+                            ctx,
+                            mouse_event.pos,
+                            buffer,
+                            &data.proxy,
+                            &data.config,
+                        );
+                    }
                     if ctx.is_active() {
                         let buffer =
                             data.main_split.open_files.get(path).unwrap().clone();
@@ -4048,28 +5618,26 @@ impl Widget<LapceTabData> for LapceEditor {
                 }
                 EditorContent::Buffer(path) => {
                     ctx.set_handled();
-                    ctx.set_active(true);
                     let buffer =
                         data.main_split.open_files.get(path).unwrap().clone();
-                    let line_height = data.config.editor.line_height as f64;
-                    let line = (mouse_event.pos.y / line_height).floor() as usize;
-                    let last_line = buffer.last_line();
-                    let (line, col) = if line > last_line {
-                        (last_line, 0)
-                    } else {
-                        let line_end =
-                            buffer.line_end_col(line, !editor.cursor.is_normal());
-                        let width = data.config.editor_text_width(ctx.text(), "W");
-
-                        let col = (if editor.cursor.is_insert() {
-                            (mouse_event.pos.x / width).round() as usize
-                        } else {
-                            (mouse_event.pos.x / width).floor() as usize
-                        })
-                        .min(line_end);
-                        (line, col)
-                    };
-                    let new_offset = buffer.offset_of_line_col(line, col);
+                    if let Some(lens) = self.code_lens_at(
+                        ctx,
+                        mouse_event.pos,
+                        &buffer,
+                        &data.config,
+                    ) {
+                        if let Some(command) = lens.lens.command.as_ref() {
+                            data.run_lsp_command(ctx, buffer.id, command);
+                        }
+                        return;
+                    }
+                    ctx.set_active(true);
+                    let new_offset = buffer.offset_of_mouse(
+                        ctx.text(),
+                        mouse_event.pos,
+                        editor.cursor.get_mode(),
+                        &data.config,
+                    );
                     let editor = Arc::make_mut(editor);
                     match editor.cursor.mode.clone() {
                         CursorMode::Normal(offset) => {
@@ -4131,6 +5699,16 @@ impl Widget<LapceTabData> for LapceEditor {
                             }
                         }
                     }
+                    let cursor_offset = editor.cursor.offset();
+                    if let Some(completion) = buffer.inline_completion.as_ref() {
+                        if completion.offset != cursor_offset {
+                            if let Some(buffer) =
+                                data.main_split.open_files.get_mut(&buffer.path)
+                            {
+                                Arc::make_mut(buffer).clear_inline_completion();
+                            }
+                        }
+                    }
                 }
             },
             Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
@@ -4158,6 +5736,14 @@ impl Widget<LapceTabData> for LapceEditor {
         data: &LapceTabData,
         env: &Env,
     ) {
+        match event {
+            LifeCycle::HotChanged(false) => {
+                if self.inlay_hint_hover.take().is_some() {
+                    ctx.request_paint();
+                }
+            }
+            _ => (),
+        }
     }
 
     fn update(
@@ -4235,7 +5821,8 @@ impl Widget<LapceTabData> for LapceEditor {
                 let width = data.config.editor_text_width(ctx.text(), "W");
                 Size::new(
                     (width * data.buffer.max_len as f64).max(bc.max().width),
-                    line_height * data.buffer.num_lines as f64 + bc.max().height
+                    line_height * data.buffer.visual_num_lines() as f64
+                        + bc.max().height
                         - line_height,
                 )
             }
@@ -4316,6 +5903,11 @@ impl Widget<LapceTabData> for LapceEditor {
                     self.placeholder.as_ref(),
                     &data.config,
                 );
+                if let Some((path, offset)) = self.inlay_hint_hover.as_ref() {
+                    if path == &data.buffer.path {
+                        data.paint_inlay_hint_tooltip(ctx, *offset);
+                    }
+                }
             }
             LapceEditorViewContent::None => {
                 let svg = logo_svg();
@@ -4463,6 +6055,27 @@ fn str_matching_pair(c: &str) -> Option<char> {
     None
 }
 
+#[derive(Clone, Copy)]
+enum GotoKind {
+    TypeDefinition,
+    Implementation,
+    Declaration,
+}
+
+fn goto_locations(resp: GotoDefinitionResponse) -> Vec<Location> {
+    match resp {
+        GotoDefinitionResponse::Scalar(location) => vec![location],
+        GotoDefinitionResponse::Array(locations) => locations,
+        GotoDefinitionResponse::Link(links) => links
+            .into_iter()
+            .map(|link| Location {
+                uri: link.target_uri,
+                range: link.target_selection_range,
+            })
+            .collect(),
+    }
+}
+
 fn process_get_references(
     editor_view_id: WidgetId,
     offset: usize,
@@ -0,0 +1,925 @@
+use std::{path::PathBuf, sync::Arc};
+
+use druid::{
+    piet::{Text, TextLayout as PietTextLayout, TextLayoutBuilder},
+    BoxConstraints, Command, Env, Event, EventCtx, ExtEventSink, FontFamily,
+    LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Point, Rect, RenderContext, Size,
+    Target, UpdateCtx, Widget, WidgetId, WidgetPod,
+};
+use lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, Range,
+};
+
+use crate::{
+    buffer::BufferId,
+    command::{LapceCommand, LapceUICommand, LAPCE_UI_COMMAND},
+    config::LapceTheme,
+    data::{EditorKind, FocusArea, LapceTabData},
+    editor::EditorLocationNew,
+    keypress::KeyPressFocus,
+    movement::Movement,
+    panel::PanelPosition,
+    proxy::LapceProxy,
+    scroll::LapceScrollNew,
+    state::Mode,
+    svg::{get_svg, symbol_svg_new},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HierarchyKind {
+    Incoming,
+    Outgoing,
+    Supertypes,
+    Subtypes,
+}
+
+impl HierarchyKind {
+    fn title(&self) -> &'static str {
+        match self {
+            HierarchyKind::Incoming | HierarchyKind::Outgoing => "Call Hierarchy",
+            HierarchyKind::Supertypes | HierarchyKind::Subtypes => "Type Hierarchy",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            HierarchyKind::Incoming => "Incoming Calls",
+            HierarchyKind::Outgoing => "Outgoing Calls",
+            HierarchyKind::Supertypes => "Supertypes",
+            HierarchyKind::Subtypes => "Subtypes",
+        }
+    }
+
+    /// The two directions the tree can be grown in for this kind of
+    /// hierarchy.
+    fn directions(&self) -> [HierarchyKind; 2] {
+        match self {
+            HierarchyKind::Incoming | HierarchyKind::Outgoing => {
+                [HierarchyKind::Incoming, HierarchyKind::Outgoing]
+            }
+            HierarchyKind::Supertypes | HierarchyKind::Subtypes => {
+                [HierarchyKind::Supertypes, HierarchyKind::Subtypes]
+            }
+        }
+    }
+
+    fn method(&self) -> &'static str {
+        match self {
+            HierarchyKind::Incoming => "get_incoming_calls",
+            HierarchyKind::Outgoing => "get_outgoing_calls",
+            HierarchyKind::Supertypes => "get_supertypes",
+            HierarchyKind::Subtypes => "get_subtypes",
+        }
+    }
+
+    /// Reads the answer to `method` as the children of `parent`.
+    fn parse_children(
+        &self,
+        parent: &CallHierarchyItem,
+        depth: usize,
+        value: serde_json::Value,
+    ) -> Vec<HierarchyNode> {
+        match self {
+            HierarchyKind::Incoming => serde_json::from_value::<
+                Option<Vec<CallHierarchyIncomingCall>>,
+            >(value)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|call| {
+                let path = PathBuf::from(call.from.uri.path());
+                HierarchyNode::new(call.from, Some((path, call.from_ranges)), depth)
+            })
+            .collect(),
+            HierarchyKind::Outgoing => serde_json::from_value::<
+                Option<Vec<CallHierarchyOutgoingCall>>,
+            >(value)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|call| {
+                // The calls are made from the parent, so that's where
+                // their ranges are.
+                let path = PathBuf::from(parent.uri.path());
+                HierarchyNode::new(call.to, Some((path, call.from_ranges)), depth)
+            })
+            .collect(),
+            HierarchyKind::Supertypes | HierarchyKind::Subtypes => {
+                serde_json::from_value::<Option<Vec<CallHierarchyItem>>>(value)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|item| HierarchyNode::new(item, None, depth))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HierarchyNode {
+    /// Type hierarchy items have the same shape as call hierarchy ones, so
+    /// both kinds of tree are made of `CallHierarchyItem`s.
+    pub item: CallHierarchyItem,
+    /// The file and ranges of the calls between this node and its parent.
+    pub call_sites: Option<(PathBuf, Vec<Range>)>,
+    pub depth: usize,
+    /// `None` until the node is expanded for the first time.
+    pub children: Option<Vec<usize>>,
+    pub expanded: bool,
+}
+
+impl HierarchyNode {
+    fn new(
+        item: CallHierarchyItem,
+        call_sites: Option<(PathBuf, Vec<Range>)>,
+        depth: usize,
+    ) -> Self {
+        Self {
+            item,
+            call_sites,
+            depth,
+            children: None,
+            expanded: false,
+        }
+    }
+
+    /// Where selecting the node jumps to, and the calls to highlight there.
+    fn location(&self) -> (EditorLocationNew, PathBuf, Vec<Range>) {
+        match &self.call_sites {
+            Some((path, ranges)) if ranges.len() > 0 => (
+                EditorLocationNew {
+                    path: path.clone(),
+                    position: Some(ranges[0].start),
+                    scroll_offset: None,
+                },
+                path.clone(),
+                ranges.clone(),
+            ),
+            _ => {
+                let path = PathBuf::from(self.item.uri.path());
+                (
+                    EditorLocationNew {
+                        path: path.clone(),
+                        position: Some(self.item.selection_range.start),
+                        scroll_offset: None,
+                    },
+                    path,
+                    Vec::new(),
+                )
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HierarchyData {
+    pub widget_id: WidgetId,
+    pub list_id: WidgetId,
+    pub kind: HierarchyKind,
+    /// The buffer the hierarchy was asked from, which decides the language
+    /// server that's asked for the children.
+    pub buffer_id: BufferId,
+    /// Bumped every time the tree is rebuilt, so that children arriving
+    /// for an older tree are dropped.
+    pub generation: u64,
+    /// The items the server prepared, kept to rebuild the tree when the
+    /// direction is switched.
+    pub items: Vec<CallHierarchyItem>,
+    pub nodes: Vec<HierarchyNode>,
+    pub roots: Vec<usize>,
+    pub index: usize,
+    /// Call sites to highlight once the file they're in has been loaded.
+    pub pending_call_sites: Option<(PathBuf, Vec<Range>)>,
+}
+
+impl HierarchyData {
+    pub fn new() -> Self {
+        Self {
+            widget_id: WidgetId::next(),
+            list_id: WidgetId::next(),
+            kind: HierarchyKind::Incoming,
+            buffer_id: BufferId(0),
+            generation: 0,
+            items: Vec::new(),
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            index: 0,
+            pending_call_sites: None,
+        }
+    }
+
+    /// Starts a new tree from the prepared items, with the roots expanded.
+    pub fn set_items(
+        &mut self,
+        kind: HierarchyKind,
+        buffer_id: BufferId,
+        items: Vec<CallHierarchyItem>,
+        proxy: &LapceProxy,
+        event_sink: ExtEventSink,
+    ) {
+        self.kind = kind;
+        self.buffer_id = buffer_id;
+        self.generation += 1;
+        self.nodes = items
+            .iter()
+            .map(|item| HierarchyNode::new(item.clone(), None, 0))
+            .collect();
+        self.roots = (0..self.nodes.len()).collect();
+        self.items = items;
+        self.index = 0;
+        for node in self.roots.clone() {
+            self.expand(node, proxy, event_sink.clone());
+        }
+    }
+
+    /// The nodes shown in the panel, depth first through the expanded
+    /// ones.
+    pub fn rows(&self) -> Vec<usize> {
+        let mut rows = Vec::new();
+        let mut stack: Vec<usize> = self.roots.iter().rev().cloned().collect();
+        while let Some(node) = stack.pop() {
+            rows.push(node);
+            let node = &self.nodes[node];
+            if node.expanded {
+                if let Some(children) = node.children.as_ref() {
+                    stack.extend(children.iter().rev());
+                }
+            }
+        }
+        rows
+    }
+
+    /// Expands a node, asking the server for its children the first time.
+    pub fn expand(
+        &mut self,
+        node: usize,
+        proxy: &LapceProxy,
+        event_sink: ExtEventSink,
+    ) {
+        let hierarchy_node = &mut self.nodes[node];
+        hierarchy_node.expanded = true;
+        if hierarchy_node.children.is_some() {
+            return;
+        }
+        hierarchy_node.children = Some(Vec::new());
+
+        let kind = self.kind;
+        let generation = self.generation;
+        let parent = hierarchy_node.item.clone();
+        let depth = hierarchy_node.depth + 1;
+        proxy.get_hierarchy_items(
+            kind.method(),
+            self.buffer_id,
+            &hierarchy_node.item,
+            Box::new(move |result| {
+                if let Ok(value) = result {
+                    let children = kind.parse_children(&parent, depth, value);
+                    let _ = event_sink.submit_command(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::UpdateHierarchyChildren(
+                            generation, node, children,
+                        ),
+                        Target::Auto,
+                    );
+                }
+            }),
+        );
+    }
+
+    pub fn collapse(&mut self, node: usize) {
+        self.nodes[node].expanded = false;
+    }
+
+    pub fn set_children(
+        &mut self,
+        generation: u64,
+        node: usize,
+        children: Vec<HierarchyNode>,
+    ) {
+        if generation != self.generation || node >= self.nodes.len() {
+            return;
+        }
+        let start = self.nodes.len();
+        self.nodes.extend(children);
+        self.nodes[node].children = Some((start..self.nodes.len()).collect());
+    }
+
+    /// Whether a node can still have children, as far as is known.
+    fn has_children(&self, node: usize) -> bool {
+        match self.nodes[node].children.as_ref() {
+            Some(children) => children.len() > 0,
+            None => true,
+        }
+    }
+}
+
+pub struct HierarchyViewData {
+    pub hierarchy: Arc<HierarchyData>,
+    pub proxy: Arc<LapceProxy>,
+    pub rows: Vec<usize>,
+}
+
+impl HierarchyViewData {
+    pub fn new(hierarchy: Arc<HierarchyData>, proxy: Arc<LapceProxy>) -> Self {
+        let rows = hierarchy.rows();
+        Self {
+            hierarchy,
+            proxy,
+            rows,
+        }
+    }
+
+    fn toggle(&mut self, ctx: &mut EventCtx) {
+        if let Some(node) = self.rows.get(self.hierarchy.index).cloned() {
+            let hierarchy = Arc::make_mut(&mut self.hierarchy);
+            if hierarchy.nodes[node].expanded {
+                hierarchy.collapse(node);
+            } else {
+                hierarchy.expand(node, &self.proxy, ctx.get_external_handle());
+            }
+        }
+    }
+
+    fn select(&mut self, ctx: &mut EventCtx) {
+        if let Some(node) = self.rows.get(self.hierarchy.index) {
+            let (location, path, ranges) = self.hierarchy.nodes[*node].location();
+            ctx.submit_command(Command::new(
+                LAPCE_UI_COMMAND,
+                LapceUICommand::JumpToLocation(EditorKind::SplitActive, location),
+                Target::Auto,
+            ));
+            ctx.submit_command(Command::new(
+                LAPCE_UI_COMMAND,
+                LapceUICommand::ShowCallSites(path, ranges),
+                Target::Auto,
+            ));
+        }
+    }
+}
+
+impl KeyPressFocus for HierarchyViewData {
+    fn get_mode(&self) -> Mode {
+        Mode::Normal
+    }
+
+    fn check_condition(&self, condition: &str) -> bool {
+        match condition {
+            "hierarchy_focus" => true,
+            "list_focus" => true,
+            _ => false,
+        }
+    }
+
+    fn run_command(
+        &mut self,
+        ctx: &mut EventCtx,
+        command: &LapceCommand,
+        count: Option<usize>,
+        env: &Env,
+    ) {
+        match command {
+            LapceCommand::HierarchyCancel => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::FocusEditor,
+                    Target::Auto,
+                ));
+            }
+            LapceCommand::Up | LapceCommand::ListPrevious => {
+                let hierarchy = Arc::make_mut(&mut self.hierarchy);
+                hierarchy.index = Movement::Up.update_index(
+                    hierarchy.index,
+                    self.rows.len(),
+                    1,
+                    true,
+                );
+            }
+            LapceCommand::Down | LapceCommand::ListNext => {
+                let hierarchy = Arc::make_mut(&mut self.hierarchy);
+                hierarchy.index = Movement::Down.update_index(
+                    hierarchy.index,
+                    self.rows.len(),
+                    1,
+                    true,
+                );
+            }
+            LapceCommand::ListSelect => {
+                self.select(ctx);
+            }
+            LapceCommand::ListExpand => {
+                self.toggle(ctx);
+            }
+            LapceCommand::Right => {
+                if let Some(node) = self.rows.get(self.hierarchy.index).cloned() {
+                    Arc::make_mut(&mut self.hierarchy).expand(
+                        node,
+                        &self.proxy,
+                        ctx.get_external_handle(),
+                    );
+                }
+            }
+            LapceCommand::Left => {
+                if let Some(node) = self.rows.get(self.hierarchy.index).cloned() {
+                    let hierarchy = Arc::make_mut(&mut self.hierarchy);
+                    if hierarchy.nodes[node].expanded {
+                        hierarchy.collapse(node);
+                    } else if let Some(parent) =
+                        self.rows[..hierarchy.index].iter().rposition(|n| {
+                            hierarchy.nodes[*n].depth + 1
+                                == hierarchy.nodes[node].depth
+                        })
+                    {
+                        hierarchy.index = parent;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_char(&mut self, ctx: &mut EventCtx, c: &str) {}
+}
+
+pub struct HierarchyPanel {
+    widget_id: WidgetId,
+    list_id: WidgetId,
+    list: WidgetPod<LapceTabData, LapceScrollNew<LapceTabData, HierarchyList>>,
+    direction_rects: Vec<(HierarchyKind, Rect)>,
+}
+
+impl HierarchyPanel {
+    pub fn new(data: &LapceTabData) -> Self {
+        let list = LapceScrollNew::new(HierarchyList::new(data.hierarchy.list_id));
+        Self {
+            widget_id: data.hierarchy.widget_id,
+            list_id: data.hierarchy.list_id,
+            list: WidgetPod::new(list),
+            direction_rects: Vec::new(),
+        }
+    }
+
+    fn header_height(data: &LapceTabData) -> f64 {
+        data.config.editor.line_height as f64 + 10.0
+    }
+
+    fn ensure_item_visible(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        let line_height = data.config.editor.line_height as f64;
+        let rect = Size::new(ctx.size().width, line_height)
+            .to_rect()
+            .with_origin(Point::new(0.0, data.hierarchy.index as f64 * line_height));
+        if self.list.widget_mut().scroll_to_visible(rect, env) {
+            ctx.request_paint();
+        }
+    }
+}
+
+impl Widget<LapceTabData> for HierarchyPanel {
+    fn id(&self) -> Option<WidgetId> {
+        Some(self.widget_id)
+    }
+
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseMove(mouse_event) => {
+                if self
+                    .direction_rects
+                    .iter()
+                    .any(|(_, rect)| rect.contains(mouse_event.pos))
+                {
+                    ctx.set_cursor(&druid::Cursor::Pointer);
+                } else {
+                    ctx.clear_cursor();
+                }
+            }
+            Event::MouseDown(mouse_event) => {
+                if mouse_event.pos.y < Self::header_height(data) {
+                    for (kind, rect) in self.direction_rects.iter() {
+                        if rect.contains(mouse_event.pos)
+                            && *kind != data.hierarchy.kind
+                        {
+                            let hierarchy = Arc::make_mut(&mut data.hierarchy);
+                            let buffer_id = hierarchy.buffer_id;
+                            let items = hierarchy.items.clone();
+                            hierarchy.set_items(
+                                *kind,
+                                buffer_id,
+                                items,
+                                &data.proxy,
+                                ctx.get_external_handle(),
+                            );
+                            break;
+                        }
+                    }
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::Focus,
+                        Target::Widget(self.list_id),
+                    ));
+                    ctx.set_handled();
+                }
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                match command {
+                    LapceUICommand::Focus => {
+                        ctx.submit_command(Command::new(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::Focus,
+                            Target::Widget(self.list_id),
+                        ));
+                        ctx.set_handled();
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+        self.list.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            LifeCycle::FocusChanged(_) => {
+                ctx.request_paint();
+            }
+            _ => (),
+        }
+        self.list.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        if !data.hierarchy.same(&old_data.hierarchy) {
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+        if data.hierarchy.index != old_data.hierarchy.index {
+            self.ensure_item_visible(ctx, data, env);
+        }
+        self.list.update(ctx, data, env);
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        for (pos, panel) in data.panels.iter() {
+            if panel.active == self.widget_id {
+                match pos {
+                    PanelPosition::LeftTop | PanelPosition::LeftBottom => {
+                        ctx.set_paint_insets((0.0, 0.0, 10.0, 0.0));
+                    }
+                    PanelPosition::BottomLeft | PanelPosition::BottomRight => {
+                        ctx.set_paint_insets((0.0, 10.0, 0.0, 0.0));
+                    }
+                    PanelPosition::RightTop | PanelPosition::RightBottom => {
+                        ctx.set_paint_insets((10.0, 0.0, 0.0, 0.0));
+                    }
+                }
+            }
+        }
+        let size = bc.max();
+        let header_height = Self::header_height(data);
+        let list_size =
+            Size::new(size.width, (size.height - header_height).max(0.0));
+        self.list
+            .layout(ctx, &BoxConstraints::tight(list_size), data, env);
+        self.list
+            .set_origin(ctx, data, env, Point::new(0.0, header_height));
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        let line_height = data.config.editor.line_height as f64;
+        let size = ctx.size();
+        let rect = Size::new(size.width, line_height)
+            .to_rect()
+            .with_origin(Point::new(0.0, 5.0));
+        ctx.blurred_rect(
+            rect,
+            5.0,
+            data.config
+                .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+        );
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::PANEL_BACKGROUND),
+        );
+
+        let text_layout = ctx
+            .text()
+            .new_text_layout(data.hierarchy.kind.title())
+            .font(FontFamily::SYSTEM_UI, 13.0)
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(&text_layout, Point::new(5.0, 5.0 + 4.0));
+        let mut x = 5.0 + text_layout.size().width + 20.0;
+
+        self.direction_rects.clear();
+        for kind in data.hierarchy.kind.directions().iter() {
+            let active = *kind == data.hierarchy.kind;
+            let text_layout = ctx
+                .text()
+                .new_text_layout(kind.label())
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(if active {
+                            LapceTheme::EDITOR_FOREGROUND
+                        } else {
+                            LapceTheme::EDITOR_DIM
+                        })
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            let width = text_layout.size().width;
+            let rect = Size::new(width + 10.0, line_height)
+                .to_rect()
+                .with_origin(Point::new(x, 5.0));
+            if active {
+                ctx.stroke(
+                    rect.inset(-0.5),
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND),
+                    1.0,
+                );
+            }
+            ctx.draw_text(&text_layout, Point::new(x + 5.0, 5.0 + 4.0));
+            self.direction_rects.push((*kind, rect));
+            x += width + 20.0;
+        }
+
+        self.list.paint(ctx, data, env);
+    }
+}
+
+pub struct HierarchyList {
+    widget_id: WidgetId,
+}
+
+impl HierarchyList {
+    pub fn new(widget_id: WidgetId) -> Self {
+        Self { widget_id }
+    }
+
+    fn indent(data: &LapceTabData, depth: usize) -> f64 {
+        let line_height = data.config.editor.line_height as f64;
+        5.0 + depth as f64 * line_height
+    }
+}
+
+impl Widget<LapceTabData> for HierarchyList {
+    fn id(&self) -> Option<WidgetId> {
+        Some(self.widget_id)
+    }
+
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseMove(mouse_event) => {
+                ctx.set_cursor(&druid::Cursor::Pointer);
+                ctx.set_handled();
+            }
+            Event::MouseDown(mouse_event) => {
+                let line_height = data.config.editor.line_height as f64;
+                let line = (mouse_event.pos.y / line_height).floor() as usize;
+                let mut view_data = HierarchyViewData::new(
+                    data.hierarchy.clone(),
+                    data.proxy.clone(),
+                );
+                if let Some(node) = view_data.rows.get(line).cloned() {
+                    Arc::make_mut(&mut view_data.hierarchy).index = line;
+                    let depth = view_data.hierarchy.nodes[node].depth;
+                    let chevron_x = Self::indent(data, depth);
+                    if mouse_event.pos.x >= chevron_x
+                        && mouse_event.pos.x < chevron_x + line_height
+                    {
+                        view_data.toggle(ctx);
+                    } else {
+                        view_data.select(ctx);
+                    }
+                }
+                data.hierarchy = view_data.hierarchy;
+                ctx.request_focus();
+                data.focus_area = FocusArea::Hierarchy;
+                ctx.set_handled();
+            }
+            Event::KeyDown(key_event) => {
+                let mut keypress = data.keypress.clone();
+                let mut view_data = HierarchyViewData::new(
+                    data.hierarchy.clone(),
+                    data.proxy.clone(),
+                );
+                Arc::make_mut(&mut keypress).key_down(
+                    ctx,
+                    key_event,
+                    &mut view_data,
+                    env,
+                );
+                data.keypress = keypress;
+                data.hierarchy = view_data.hierarchy;
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                match command {
+                    LapceUICommand::Focus => {
+                        data.focus_area = FocusArea::Hierarchy;
+                        ctx.request_focus();
+                        ctx.set_handled();
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            LifeCycle::FocusChanged(_) => {
+                ctx.request_paint();
+            }
+            _ => (),
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        if !data.hierarchy.same(&old_data.hierarchy) {
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        let line_height = data.config.editor.line_height as f64;
+        let rows = data.hierarchy.rows();
+        Size::new(bc.max().width, line_height * rows.len() as f64)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        let line_height = data.config.editor.line_height as f64;
+        let rows = data.hierarchy.rows();
+
+        if ctx.is_focused() && data.hierarchy.index < rows.len() {
+            let rect = Size::new(ctx.size().width, line_height)
+                .to_rect()
+                .with_origin(Point::new(
+                    0.0,
+                    data.hierarchy.index as f64 * line_height,
+                ));
+            ctx.fill(
+                rect,
+                data.config.get_color_unchecked(LapceTheme::PANEL_CURRENT),
+            );
+        }
+
+        let rect = ctx.region().bounding_box();
+        let start_line = (rect.y0 / line_height).floor() as usize;
+        let end_line = (rect.y1 / line_height).ceil() as usize;
+        for line in start_line..end_line {
+            if line >= rows.len() {
+                break;
+            }
+            let y = line_height * line as f64;
+            let node = &data.hierarchy.nodes[rows[line]];
+            let x = Self::indent(data, node.depth);
+
+            let icon_size = 13.0;
+            if data.hierarchy.has_children(rows[line]) {
+                let svg = get_svg(if node.expanded {
+                    "chevron-down.svg"
+                } else {
+                    "chevron-right.svg"
+                });
+                if let Some(svg) = svg.as_ref() {
+                    let rect = Size::new(icon_size, icon_size)
+                        .to_rect()
+                        .with_origin(Point::new(
+                            x + (line_height - icon_size) / 2.0,
+                            y + (line_height - icon_size) / 2.0,
+                        ));
+                    ctx.draw_svg(
+                        &svg,
+                        rect,
+                        Some(
+                            data.config
+                                .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND),
+                        ),
+                    );
+                }
+            }
+            let x = x + line_height;
+
+            if let Some(svg) = symbol_svg_new(&node.item.kind).as_ref() {
+                let rect = Size::new(icon_size, icon_size).to_rect().with_origin(
+                    Point::new(
+                        x + (line_height - icon_size) / 2.0,
+                        y + (line_height - icon_size) / 2.0,
+                    ),
+                );
+                ctx.draw_svg(&svg, rect, None);
+            }
+            let x = x + line_height;
+
+            let text_layout = ctx
+                .text()
+                .new_text_layout(node.item.name.clone())
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(&text_layout, Point::new(x, y + 4.0));
+
+            let mut path = PathBuf::from(node.item.uri.path());
+            if let Some(workspace) = data.workspace.as_ref() {
+                path = path
+                    .strip_prefix(&workspace.path)
+                    .unwrap_or(&path)
+                    .to_path_buf();
+            }
+            let hint = match node.item.detail.as_ref() {
+                Some(detail) if detail != "" => {
+                    format!("{} {}", detail, path.to_string_lossy())
+                }
+                _ => path.to_string_lossy().to_string(),
+            };
+            let x = x + text_layout.size().width + 5.0;
+            let text_layout = ctx
+                .text()
+                .new_text_layout(hint)
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(&text_layout, Point::new(x, y + 4.0));
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use lsp_types::{Command, Location, MarkupContent, Position, TextEdit};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// An item of a `textDocument/inlayHint` response, which lsp-types doesn't
+/// have yet.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: InlayHintLabel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<InlayHintKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_edits: Option<Vec<TextEdit>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<InlayHintTooltip>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub padding_left: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub padding_right: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InlayHintKind(i32);
+
+impl InlayHintKind {
+    pub const TYPE: InlayHintKind = InlayHintKind(1);
+    pub const PARAMETER: InlayHintKind = InlayHintKind(2);
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InlayHintLabel {
+    String(String),
+    LabelParts(Vec<InlayHintLabelPart>),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintLabelPart {
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<InlayHintTooltip>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InlayHintTooltip {
+    String(String),
+    MarkupContent(MarkupContent),
+}
+
+impl InlayHintTooltip {
+    fn value(&self) -> &str {
+        match self {
+            InlayHintTooltip::String(s) => s,
+            InlayHintTooltip::MarkupContent(content) => &content.value,
+        }
+    }
+}
+
+impl InlayHint {
+    /// The text drawn in the editor, padding included.
+    pub fn label(&self) -> String {
+        let label = match &self.label {
+            InlayHintLabel::String(s) => s.clone(),
+            InlayHintLabel::LabelParts(parts) => {
+                parts.iter().map(|p| p.value.as_str()).collect()
+            }
+        };
+        format!(
+            "{}{}{}",
+            if self.padding_left.unwrap_or(false) {
+                " "
+            } else {
+                ""
+            },
+            label.replace('\n', " "),
+            if self.padding_right.unwrap_or(false) {
+                " "
+            } else {
+                ""
+            },
+        )
+    }
+
+    pub fn tooltip(&self) -> Option<String> {
+        if let Some(tooltip) = self.tooltip.as_ref() {
+            return Some(tooltip.value().to_string());
+        }
+        match &self.label {
+            InlayHintLabel::String(_) => None,
+            InlayHintLabel::LabelParts(parts) => {
+                let tooltips: Vec<&str> = parts
+                    .iter()
+                    .filter_map(|p| p.tooltip.as_ref().map(|t| t.value()))
+                    .collect();
+                if tooltips.len() > 0 {
+                    Some(tooltips.join("\n"))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// An inlay hint anchored to a buffer offset, which moves along with edits
+/// until fresh hints arrive for the new revision.
+#[derive(Clone, Debug)]
+pub struct EditorInlayHint {
+    pub offset: usize,
+    pub hint: InlayHint,
+}
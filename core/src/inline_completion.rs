@@ -0,0 +1,166 @@
+//! Inline completion: a single suggestion drawn as dimmed ghost text after
+//! the cursor, which tab accepts whole or a key accepts a word at a time.
+//!
+//! The ghost text is never in the rope. Its first line is spliced into the
+//! text layout of the cursor line, the way inlay hints are, and the lines
+//! after it are drawn over the editor below.
+
+use std::str::FromStr;
+
+use lapce_proxy::inline_completion::InlineCompletionItem;
+
+use crate::{buffer::BufferNew, completion::Snippet};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InlineCompletion {
+    /// Where the ghost text is drawn, the cursor offset it was asked for.
+    pub offset: usize,
+    /// The end of what accepting it replaces, `offset` when it only
+    /// inserts.
+    pub end: usize,
+    pub text: String,
+}
+
+impl InlineCompletion {
+    /// What `item` suggests at `offset` in `buffer`. An item replacing text
+    /// before the cursor must start with that text, which is then left out
+    /// of the ghost text. None when it adds nothing there.
+    pub fn from_item(
+        buffer: &BufferNew,
+        offset: usize,
+        item: &InlineCompletionItem,
+    ) -> Option<InlineCompletion> {
+        let text = if item.is_snippet {
+            Snippet::from_str(&item.insert_text).ok()?.text()
+        } else {
+            item.insert_text.clone()
+        };
+        let (start, end) = match item.range.as_ref() {
+            Some(range) => (
+                buffer.offset_of_position(&range.start),
+                buffer.offset_of_position(&range.end),
+            ),
+            None => (offset, offset),
+        };
+        if start > offset || end < offset {
+            return None;
+        }
+        let typed = buffer.slice_to_cow(start..offset);
+        let text = text.strip_prefix(typed.as_ref())?;
+        if text.is_empty() {
+            return None;
+        }
+        Some(InlineCompletion {
+            offset,
+            end,
+            text: text.to_string(),
+        })
+    }
+
+    /// What is left to suggest once `typed` was typed at `offset`, None
+    /// when it doesn't match the start of the ghost text or uses it up.
+    pub fn after_typing(&self, typed: &str) -> Option<InlineCompletion> {
+        let text = self.text.strip_prefix(typed)?;
+        if text.is_empty() {
+            return None;
+        }
+        Some(InlineCompletion {
+            offset: self.offset + typed.len(),
+            end: self.end + typed.len(),
+            text: text.to_string(),
+        })
+    }
+
+    /// The length of the next word of the ghost text, with the blanks in
+    /// front of it. A run of punctuation counts as a word, and a line
+    /// break ends one.
+    pub fn next_word_len(&self) -> usize {
+        let mut chars = self.text.char_indices().peekable();
+        while let Some((_, c)) = chars.peek() {
+            if *c == '\n' || !c.is_whitespace() {
+                break;
+            }
+            chars.next();
+        }
+        let first = match chars.next() {
+            Some((i, '\n')) => return i + 1,
+            Some((_, c)) => c,
+            None => return self.text.len(),
+        };
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        for (i, c) in chars {
+            if c.is_whitespace() || is_word(c) != is_word(first) {
+                return i;
+            }
+        }
+        self.text.len()
+    }
+
+    /// The ghost text drawn on the cursor line, tabs expanded as the
+    /// editor draws them.
+    pub fn first_line(&self) -> String {
+        self.text.lines().next().unwrap_or("").replace('\t', "    ")
+    }
+
+    /// The ghost text drawn below the cursor line, a line each.
+    pub fn other_lines(&self) -> Vec<String> {
+        self.text
+            .split('\n')
+            .skip(1)
+            .map(|line| line.trim_end_matches('\r').replace('\t', "    "))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn completion(text: &str) -> InlineCompletion {
+        InlineCompletion {
+            offset: 10,
+            end: 12,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_after_typing() {
+        let completion = completion("foo(bar)");
+        assert_eq!(
+            completion.after_typing("fo"),
+            Some(InlineCompletion {
+                offset: 12,
+                end: 14,
+                text: "o(bar)".to_string(),
+            })
+        );
+        assert_eq!(completion.after_typing(""), Some(completion.clone()));
+        assert_eq!(completion.after_typing("fx"), None);
+        assert_eq!(completion.after_typing("foo(bar)"), None);
+        assert_eq!(completion.after_typing("foo(bar))"), None);
+
+        // offsets move by bytes
+        let completion = InlineCompletion {
+            offset: 0,
+            end: 0,
+            text: "éa".to_string(),
+        };
+        assert_eq!(completion.after_typing("é").map(|c| c.offset), Some(2));
+    }
+
+    #[test]
+    fn test_next_word_len() {
+        assert_eq!(completion("foo_bar baz").next_word_len(), 7);
+        assert_eq!(completion("  foo bar").next_word_len(), 5);
+        assert_eq!(completion("foo(bar)").next_word_len(), 3);
+        assert_eq!(completion("(bar)").next_word_len(), 1);
+        assert_eq!(completion("::new()").next_word_len(), 2);
+        assert_eq!(completion("foo\nbar").next_word_len(), 3);
+        assert_eq!(completion("\nbar").next_word_len(), 1);
+        assert_eq!(completion("  \n  bar").next_word_len(), 3);
+        assert_eq!(completion("héllo wörld").next_word_len(), 6);
+        assert_eq!(completion("word").next_word_len(), 4);
+        assert_eq!(completion("   ").next_word_len(), 3);
+    }
+}
@@ -424,6 +424,7 @@ impl KeyPressData {
                 "enter" => druid::keyboard_types::Key::Enter,
                 "delete" => druid::keyboard_types::Key::Delete,
                 "del" => druid::keyboard_types::Key::Delete,
+                "f2" => druid::keyboard_types::Key::F2,
                 _ => druid::keyboard_types::Key::Character(
                     parts[parts.len() - 1].to_string(),
                 ),
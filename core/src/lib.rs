@@ -1,6 +1,7 @@
 pub mod app;
 pub mod buffer;
 pub mod code_action;
+pub mod code_lens;
 pub mod command;
 pub mod completion;
 pub mod config;
@@ -10,14 +11,22 @@ pub mod db;
 pub mod editor;
 pub mod explorer;
 pub mod find;
+pub mod hierarchy;
+pub mod inlay_hint;
+pub mod inline_completion;
 pub mod keypress;
 pub mod language;
+pub mod local_completion;
 pub mod lsp;
+pub mod lsp_log;
 pub mod movement;
 pub mod outline;
 pub mod palette;
 pub mod panel;
+pub mod peek;
+pub mod problem;
 pub mod proxy;
+pub mod rename;
 pub mod scroll;
 pub mod signature;
 pub mod source_control;
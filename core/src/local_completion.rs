@@ -0,0 +1,390 @@
+//! Completions that don't need a language server: the words of the open
+//! buffers, the user snippets, the paths on disk when typing inside a
+//! string, and the lines already written as inline completions.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use lapce_proxy::dispatch::FileNodeItem;
+use lapce_proxy::inline_completion::InlineCompletionItem;
+use lsp_types::{
+    Command, CompletionItem, CompletionItemKind, CompletionTextEdit, Documentation,
+    InsertTextFormat, Position, Range, TextEdit,
+};
+
+use crate::{
+    buffer::{get_word_property, BufferNew, WordProperty},
+    config::UserSnippet,
+};
+
+/// How many words are offered at most.
+const MAX_WORDS: usize = 500;
+
+/// How many lines above and below the cursor the words of its own buffer
+/// are taken from.
+const WORD_LINES: usize = 1000;
+
+/// How many lines up a line to repeat is looked for.
+const MAX_SUGGESTION_LINES: usize = 1000;
+
+/// How much of a line has to be typed before it's suggested.
+const MIN_SUGGESTION_PREFIX: usize = 3;
+
+/// The words of the open buffers. The ones closest to `offset` in `buffer`
+/// come first, then the ones used most often. Only the lines around the
+/// cursor are read from `buffer`, which changes with every key, and the
+/// words of the others are counted once for each of their revs.
+pub fn buffer_words<'a>(
+    buffer: &BufferNew,
+    offset: usize,
+    buffers: impl Iterator<Item = &'a BufferNew>,
+) -> Vec<CompletionItem> {
+    let start = buffer.prev_code_boundary(offset);
+    let end = buffer.next_code_boundary(offset);
+    let line = buffer.line_of_offset(offset);
+    let window_start = buffer.offset_of_line(line.saturating_sub(WORD_LINES));
+    let window_end = buffer.offset_of_line(line + WORD_LINES + 1);
+
+    // the distance to the nearest use and the number of uses of each word
+    let mut words: HashMap<String, (usize, usize)> = HashMap::new();
+    let text = buffer.slice_to_cow(window_start..window_end);
+    for (word_start, word) in words_of(&text) {
+        let word_start = window_start + word_start;
+        let word_end = word_start + word.len();
+        if word_end >= start && word_start <= end {
+            // the word being typed
+            continue;
+        }
+        let distance = if word_end < start {
+            start - word_end
+        } else {
+            word_start - end
+        };
+        let entry = words.entry(word.to_string()).or_insert((usize::MAX, 0));
+        entry.0 = entry.0.min(distance);
+        entry.1 += 1;
+    }
+    for other in buffers {
+        if other.id == buffer.id {
+            continue;
+        }
+        for (word, count) in word_counts(other).iter() {
+            words.entry(word.clone()).or_insert((usize::MAX, 0)).1 += count;
+        }
+    }
+
+    rank_words(words)
+        .into_iter()
+        .map(|word| CompletionItem {
+            label: word,
+            kind: Some(CompletionItemKind::Text),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// The words by their distance to the cursor and then by how often
+/// they're used, the first `MAX_WORDS` of them.
+fn rank_words(words: HashMap<String, (usize, usize)>) -> Vec<String> {
+    let mut words: Vec<(String, (usize, usize))> = words.into_iter().collect();
+    words.sort_by(|(a, (a_distance, a_count)), (b, (b_distance, b_count))| {
+        a_distance
+            .cmp(b_distance)
+            .then(b_count.cmp(a_count))
+            .then(a.cmp(b))
+    });
+    words
+        .into_iter()
+        .take(MAX_WORDS)
+        .map(|(word, _)| word)
+        .collect()
+}
+
+/// How often each word is used in `buffer`, counted a line at a time the
+/// first time it's asked for at a rev.
+fn word_counts(buffer: &BufferNew) -> Arc<HashMap<String, usize>> {
+    if let Some((rev, counts)) = buffer.word_counts.borrow().as_ref() {
+        if *rev == buffer.rev {
+            return counts.clone();
+        }
+    }
+    let mut counts = HashMap::new();
+    for line in buffer.rope.lines_raw(0..buffer.len()) {
+        for (_, word) in words_of(&line) {
+            *counts.entry(word.to_string()).or_insert(0) += 1;
+        }
+    }
+    let counts = Arc::new(counts);
+    *buffer.word_counts.borrow_mut() = Some((buffer.rev, counts.clone()));
+    counts
+}
+
+/// An item for each prefix of the snippets. The lines of a body after the
+/// first get `indent`, the indentation of the line it goes in.
+pub fn snippet_items(
+    snippets: &[&UserSnippet],
+    indent: &str,
+) -> Vec<CompletionItem> {
+    snippets
+        .iter()
+        .flat_map(|snippet| {
+            let body = snippet.body.join(&format!("\n{}", indent));
+            snippet.prefix.iter().map(move |prefix| CompletionItem {
+                label: prefix.clone(),
+                kind: Some(CompletionItemKind::Snippet),
+                detail: Some(
+                    snippet
+                        .description
+                        .clone()
+                        .unwrap_or_else(|| snippet.name.clone()),
+                ),
+                documentation: Some(Documentation::String(body.clone())),
+                insert_text: Some(body.clone()),
+                insert_text_format: Some(InsertTextFormat::Snippet),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// The words of `text` with their byte offsets. Numbers and single
+/// characters are left out.
+fn words_of(text: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(Some((text.len(), ' '))) {
+        let is_word = get_word_property(c) == WordProperty::Other;
+        match start {
+            Some(s) if !is_word => {
+                let word = &text[s..i];
+                if word.chars().count() > 1
+                    && !word.chars().all(|c| c.is_ascii_digit())
+                {
+                    words.push((s, word));
+                }
+                start = None;
+            }
+            None if is_word => start = Some(i),
+            _ => (),
+        }
+    }
+    words
+}
+
+/// The inline completion used when no language server or plugin has one:
+/// the rest of the closest line above that starts like the one being
+/// typed, when the cursor is at the end of it.
+pub fn line_suggestion(
+    buffer: &BufferNew,
+    offset: usize,
+) -> Option<InlineCompletionItem> {
+    let line = buffer.line_of_offset(offset);
+    let after = buffer.slice_to_cow(offset..buffer.offset_of_line(line + 1));
+    if !after.trim().is_empty() {
+        return None;
+    }
+    let before = buffer.slice_to_cow(buffer.offset_of_line(line)..offset);
+    let prefix = before.trim_start();
+    if prefix.chars().count() < MIN_SUGGESTION_PREFIX {
+        return None;
+    }
+    (line.saturating_sub(MAX_SUGGESTION_LINES)..line)
+        .rev()
+        .find_map(|l| {
+            let text = buffer.slice_to_cow(
+                buffer.offset_of_line(l)..buffer.offset_of_line(l + 1),
+            );
+            let rest = text
+                .trim_end_matches(|c| c == '\n' || c == '\r')
+                .trim_start()
+                .strip_prefix(prefix)?;
+            if rest.trim().is_empty() {
+                return None;
+            }
+            Some(InlineCompletionItem {
+                insert_text: rest.to_string(),
+                is_snippet: false,
+                range: None,
+                provider: "local".to_string(),
+            })
+        })
+}
+
+/// Where to list paths from, when the cursor is inside a string that
+/// looks like a path. Returns the directory and the offset of the part
+/// after the last `/`, which the chosen entry replaces.
+pub fn string_path(buffer: &BufferNew, offset: usize) -> Option<(PathBuf, usize)> {
+    let line = buffer.line_of_offset(offset);
+    let line_start = buffer.offset_of_line(line);
+    let before = buffer.slice_to_cow(line_start..offset);
+
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in before.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match quote {
+            Some((q, _)) if c == q => quote = None,
+            Some(_) if c == '\\' => escaped = true,
+            None if c == '"' || c == '\'' || c == '`' => quote = Some((c, i + 1)),
+            _ => (),
+        }
+    }
+    let (_, string_start) = quote?;
+    let content = &before[string_start..];
+    let slash = content.rfind('/')?;
+    let dir = &content[..slash + 1];
+    if dir.contains(char::is_whitespace) {
+        return None;
+    }
+    let dir = if dir.starts_with('/') {
+        PathBuf::from(dir)
+    } else {
+        buffer.path.parent()?.join(dir)
+    };
+    Some((dir, line_start + string_start + slash + 1))
+}
+
+/// The entries of a directory as completion items replacing `range`.
+/// Choosing a directory asks for completions again, to go on into it.
+pub fn path_items(
+    entries: Vec<FileNodeItem>,
+    prefix: &str,
+    start: Position,
+    end: Position,
+) -> Vec<CompletionItem> {
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let name = file_name(&entry.path_buf)?;
+            if name.starts_with('.') && !prefix.starts_with('.') {
+                return None;
+            }
+            let (label, kind, command) = if entry.is_dir {
+                (
+                    format!("{}/", name),
+                    CompletionItemKind::Folder,
+                    Some(Command {
+                        title: "".to_string(),
+                        command: "editor.action.triggerSuggest".to_string(),
+                        arguments: None,
+                    }),
+                )
+            } else {
+                (name, CompletionItemKind::File, None)
+            };
+            Some(CompletionItem {
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range: Range { start, end },
+                    new_text: label.clone(),
+                })),
+                label,
+                kind: Some(kind),
+                command,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    Some(path.file_name()?.to_str()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::unbounded;
+
+    use super::*;
+
+    fn buffer(path: &str, content: &str) -> BufferNew {
+        let (sender, _) = unbounded();
+        let mut buffer = BufferNew::new(PathBuf::from(path), Arc::new(sender));
+        buffer.load_content(content);
+        buffer
+    }
+
+    #[test]
+    fn test_words_of() {
+        assert_eq!(
+            words_of("let foo_bar = x1 + 42;\néa b"),
+            vec![(0, "let"), (4, "foo_bar"), (14, "x1"), (23, "éa")]
+        );
+        assert_eq!(words_of(""), vec![]);
+        assert_eq!(words_of("a.b(c, 10)"), vec![]);
+    }
+
+    #[test]
+    fn test_rank_words() {
+        let mut words = HashMap::new();
+        words.insert("rare".to_string(), (usize::MAX, 1));
+        words.insert("far".to_string(), (10, 5));
+        words.insert("common".to_string(), (usize::MAX, 9));
+        words.insert("near".to_string(), (1, 1));
+        words.insert("alpha".to_string(), (usize::MAX, 1));
+        words.insert("nearer".to_string(), (1, 3));
+        assert_eq!(
+            rank_words(words),
+            vec!["nearer", "near", "far", "common", "alpha", "rare"]
+        );
+    }
+
+    #[test]
+    fn test_buffer_words() {
+        let active = buffer("/work/a.txt", "alpha beta\ngamma al\nbeta delta");
+        let other = buffer("/work/b.txt", "zeta zeta eta gamma");
+        let labels = |items: Vec<CompletionItem>| -> Vec<String> {
+            items.into_iter().map(|item| item.label).collect()
+        };
+
+        // the word being typed is left out, the closest come first, and the
+        // words of the other buffers only count
+        assert_eq!(
+            labels(buffer_words(&active, 19, vec![&active, &other].into_iter())),
+            vec!["beta", "gamma", "delta", "alpha", "zeta", "eta"]
+        );
+        // the other buffer's words were counted once for its rev
+        assert_eq!(
+            other
+                .word_counts
+                .borrow()
+                .as_ref()
+                .map(|(rev, counts)| (*rev, counts.get("zeta").copied())),
+            Some((other.rev, Some(2)))
+        );
+    }
+
+    #[test]
+    fn test_string_path() {
+        let path = |content: &str| {
+            let buffer = buffer("/work/src/main.txt", content);
+            string_path(&buffer, buffer.len())
+        };
+        assert_eq!(
+            path("let p = \"./foo/ba"),
+            Some((Path::new("/work/src").join("./foo/"), 15))
+        );
+        assert_eq!(
+            path("x\nlet p = '/etc/ho"),
+            Some((PathBuf::from("/etc/"), 16))
+        );
+        assert_eq!(
+            path("f(\"a\", \"b/c"),
+            Some((Path::new("/work/src").join("b/"), 10))
+        );
+        assert_eq!(
+            path("\"a\\\"b/c"),
+            Some((Path::new("/work/src").join("a\\\"b/"), 6))
+        );
+        // not in a string, closed, no directory or not a path
+        assert_eq!(path("let a = b/c"), None);
+        assert_eq!(path("\"a/b\" + c"), None);
+        assert_eq!(path("\"abc"), None);
+        assert_eq!(path("\"hello world/x"), None);
+    }
+}
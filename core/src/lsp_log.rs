@@ -0,0 +1,757 @@
+use std::{sync::Arc, time::Duration};
+
+use druid::{
+    kurbo::Line,
+    piet::{Text, TextLayout as PietTextLayout, TextLayoutBuilder},
+    BoxConstraints, Command, Env, Event, EventCtx, ExtEventSink, FontFamily,
+    LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Point, Rect, RenderContext, Size,
+    Target, TimerToken, UpdateCtx, Widget, WidgetId, WidgetPod,
+};
+use lapce_proxy::lsp::{LspLog, LspLogKind, LspLogResponse};
+use lsp_types::TraceOption;
+
+use crate::{
+    command::{LapceCommand, LapceUICommand, LAPCE_UI_COMMAND},
+    config::LapceTheme,
+    data::{FocusArea, LapceTabData},
+    keypress::KeyPressFocus,
+    panel::PanelPosition,
+    proxy::LapceProxy,
+    scroll::LapceScrollNew,
+    state::Mode,
+};
+
+/// How often the log is fetched again while the panel is shown.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(1000);
+
+fn kind_label(kind: LspLogKind) -> &'static str {
+    match kind {
+        LspLogKind::Stderr => "Stderr",
+        LspLogKind::Messages => "Messages",
+        LspLogKind::Trace => "Trace",
+    }
+}
+
+fn trace_label(trace: TraceOption) -> &'static str {
+    match trace {
+        TraceOption::Off => "Off",
+        TraceOption::Messages => "Messages",
+        TraceOption::Verbose => "Verbose",
+    }
+}
+
+#[derive(Clone)]
+pub struct LspLogData {
+    pub widget_id: WidgetId,
+    pub list_id: WidgetId,
+    /// The names of the servers running.
+    pub servers: Vec<String>,
+    /// The server whose log is shown, the first one until another is
+    /// chosen.
+    pub server: Option<String>,
+    pub kind: LspLogKind,
+    pub trace: TraceOption,
+    pub log: Option<Arc<LspLog>>,
+    /// Free text typed into the panel. Only the entries with all its words
+    /// in them are shown.
+    pub filter: String,
+    /// Bumped every time another server is chosen, so that a log asked for
+    /// before is dropped.
+    pub generation: u64,
+}
+
+impl LspLogData {
+    pub fn new() -> Self {
+        Self {
+            widget_id: WidgetId::next(),
+            list_id: WidgetId::next(),
+            servers: Vec::new(),
+            server: None,
+            kind: LspLogKind::Messages,
+            trace: TraceOption::Off,
+            log: None,
+            filter: "".to_string(),
+            generation: 0,
+        }
+    }
+
+    /// Asks the proxy for the log, which only sends it back if it changed.
+    pub fn refresh(&self, proxy: &LapceProxy, event_sink: ExtEventSink) {
+        let generation = self.generation;
+        proxy.get_lsp_log(
+            self.server.as_deref(),
+            self.log.as_ref().map(|log| log.version),
+            Box::new(move |result| {
+                if let Ok(value) = result {
+                    if let Ok(response) =
+                        serde_json::from_value::<LspLogResponse>(value)
+                    {
+                        let _ = event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdateLspLog(generation, response),
+                            Target::Auto,
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
+    pub fn set_response(&mut self, generation: u64, response: LspLogResponse) {
+        if generation != self.generation {
+            return;
+        }
+        if response.server != self.server {
+            self.log = None;
+        }
+        self.servers = response.servers;
+        self.server = response.server;
+        self.trace = response.trace;
+        if let Some(log) = response.log {
+            self.log = Some(Arc::new(log));
+        }
+    }
+
+    pub fn select_server(
+        &mut self,
+        server: &str,
+        proxy: &LapceProxy,
+        event_sink: ExtEventSink,
+    ) {
+        if self.server.as_deref() == Some(server) {
+            return;
+        }
+        self.server = Some(server.to_string());
+        self.log = None;
+        self.generation += 1;
+        self.refresh(proxy, event_sink);
+    }
+
+    pub fn set_trace(&mut self, trace: TraceOption, proxy: &LapceProxy) {
+        if let Some(server) = self.server.as_ref() {
+            proxy.set_lsp_trace(server, trace);
+            self.trace = trace;
+        }
+    }
+
+    /// The lines shown in the panel: the entries of the chosen log that
+    /// match the filter, split where they span several lines.
+    pub fn lines(&self) -> Vec<&str> {
+        let log = match self.log.as_ref() {
+            Some(log) => log,
+            None => return Vec::new(),
+        };
+        let words: Vec<String> = self
+            .filter
+            .to_lowercase()
+            .split_whitespace()
+            .map(|w| w.to_string())
+            .collect();
+        log.entries(self.kind)
+            .iter()
+            .filter(|entry| {
+                words.len() == 0 || {
+                    let entry = entry.to_lowercase();
+                    words.iter().all(|w| entry.contains(w))
+                }
+            })
+            .flat_map(|entry| entry.lines())
+            .collect()
+    }
+}
+
+pub struct LspLogViewData {
+    pub lsp_log: Arc<LspLogData>,
+}
+
+impl KeyPressFocus for LspLogViewData {
+    fn get_mode(&self) -> Mode {
+        Mode::Insert
+    }
+
+    fn check_condition(&self, condition: &str) -> bool {
+        match condition {
+            "lsp_log_focus" => true,
+            _ => false,
+        }
+    }
+
+    fn run_command(
+        &mut self,
+        ctx: &mut EventCtx,
+        command: &LapceCommand,
+        count: Option<usize>,
+        env: &Env,
+    ) {
+        match command {
+            LapceCommand::LspLogCancel => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::FocusEditor,
+                    Target::Auto,
+                ));
+            }
+            LapceCommand::DeleteBackward => {
+                Arc::make_mut(&mut self.lsp_log).filter.pop();
+            }
+            LapceCommand::DeleteToBeginningOfLine => {
+                Arc::make_mut(&mut self.lsp_log).filter.clear();
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_char(&mut self, ctx: &mut EventCtx, c: &str) {
+        Arc::make_mut(&mut self.lsp_log).filter.push_str(c);
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum LspLogOption {
+    Server(String),
+    Kind(LspLogKind),
+    Trace(TraceOption),
+}
+
+pub struct LspLogPanel {
+    widget_id: WidgetId,
+    list_id: WidgetId,
+    list: WidgetPod<LapceTabData, LapceScrollNew<LapceTabData, LspLogList>>,
+    option_rects: Vec<(LspLogOption, Rect)>,
+    refresh_timer: TimerToken,
+    list_height: f64,
+    /// Set when new entries arrive while the end of the log is in view, so
+    /// that the view keeps following it.
+    follow: bool,
+}
+
+impl LspLogPanel {
+    pub fn new(data: &LapceTabData) -> Self {
+        let list = LapceScrollNew::new(LspLogList::new(data.lsp_log.list_id));
+        Self {
+            widget_id: data.lsp_log.widget_id,
+            list_id: data.lsp_log.list_id,
+            list: WidgetPod::new(list),
+            option_rects: Vec::new(),
+            refresh_timer: TimerToken::INVALID,
+            list_height: 0.0,
+            follow: false,
+        }
+    }
+
+    fn header_height(data: &LapceTabData) -> f64 {
+        data.config.editor.line_height as f64 * 2.0 + 10.0
+    }
+
+    fn is_shown(&self, data: &LapceTabData) -> bool {
+        data.panels
+            .values()
+            .any(|panel| panel.shown && panel.active == self.widget_id)
+    }
+
+    fn is_at_end(&self, data: &LapceTabData) -> bool {
+        let line_height = data.config.editor.line_height as f64;
+        let list = self.list.widget();
+        list.offset().y + self.list_height >= list.child_size().height - line_height
+    }
+
+    /// Draws a row of options from `right` leftwards, the selected one
+    /// outlined, and returns where the row starts.
+    fn paint_options(
+        &mut self,
+        ctx: &mut PaintCtx,
+        data: &LapceTabData,
+        options: Vec<(LspLogOption, &str, bool)>,
+        y: f64,
+        mut right: f64,
+    ) -> f64 {
+        let line_height = data.config.editor.line_height as f64;
+        for (option, label, selected) in options.into_iter().rev() {
+            let text_layout = ctx
+                .text()
+                .new_text_layout(label.to_string())
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(if selected {
+                            LapceTheme::EDITOR_FOREGROUND
+                        } else {
+                            LapceTheme::EDITOR_DIM
+                        })
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            let width = text_layout.size().width;
+            let origin = Point::new(right - width - 10.0, y);
+            let rect = Size::new(width + 10.0, line_height)
+                .to_rect()
+                .with_origin(origin);
+            if selected {
+                ctx.stroke(
+                    rect.inset(-0.5),
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND),
+                    1.0,
+                );
+            }
+            ctx.draw_text(&text_layout, Point::new(origin.x + 5.0, y + 4.0));
+            self.option_rects.push((option, rect));
+            right = origin.x - 5.0;
+        }
+        right
+    }
+}
+
+impl Widget<LapceTabData> for LspLogPanel {
+    fn id(&self) -> Option<WidgetId> {
+        Some(self.widget_id)
+    }
+
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::Timer(token) if *token == self.refresh_timer => {
+                if self.is_shown(data) {
+                    data.lsp_log.refresh(&data.proxy, ctx.get_external_handle());
+                }
+                self.refresh_timer = ctx.request_timer(REFRESH_INTERVAL);
+            }
+            Event::MouseMove(mouse_event) => {
+                if self
+                    .option_rects
+                    .iter()
+                    .any(|(_, rect)| rect.contains(mouse_event.pos))
+                {
+                    ctx.set_cursor(&druid::Cursor::Pointer);
+                } else {
+                    ctx.clear_cursor();
+                }
+            }
+            Event::MouseDown(mouse_event) => {
+                if mouse_event.pos.y < Self::header_height(data) {
+                    let option = self
+                        .option_rects
+                        .iter()
+                        .find(|(_, rect)| rect.contains(mouse_event.pos))
+                        .map(|(option, _)| option.clone());
+                    let lsp_log = Arc::make_mut(&mut data.lsp_log);
+                    match option {
+                        Some(LspLogOption::Server(server)) => {
+                            lsp_log.select_server(
+                                &server,
+                                &data.proxy,
+                                ctx.get_external_handle(),
+                            );
+                        }
+                        Some(LspLogOption::Kind(kind)) => {
+                            lsp_log.kind = kind;
+                        }
+                        Some(LspLogOption::Trace(trace)) => {
+                            lsp_log.set_trace(trace, &data.proxy);
+                        }
+                        None => {}
+                    }
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::Focus,
+                        Target::Widget(self.list_id),
+                    ));
+                    ctx.set_handled();
+                }
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                match command {
+                    LapceUICommand::Focus => {
+                        data.lsp_log.refresh(&data.proxy, ctx.get_external_handle());
+                        ctx.submit_command(Command::new(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::Focus,
+                            Target::Widget(self.list_id),
+                        ));
+                        ctx.set_handled();
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+        self.list.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            LifeCycle::WidgetAdded => {
+                self.refresh_timer = ctx.request_timer(REFRESH_INTERVAL);
+            }
+            LifeCycle::FocusChanged(_) => {
+                ctx.request_paint();
+            }
+            _ => (),
+        }
+        self.list.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        if !data.lsp_log.same(&old_data.lsp_log) {
+            let old_version = old_data.lsp_log.log.as_ref().map(|log| log.version);
+            let version = data.lsp_log.log.as_ref().map(|log| log.version);
+            if data.lsp_log.server != old_data.lsp_log.server
+                || data.lsp_log.kind != old_data.lsp_log.kind
+                || (version != old_version && self.is_at_end(old_data))
+            {
+                self.follow = true;
+            }
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+        self.list.update(ctx, data, env);
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        for (pos, panel) in data.panels.iter() {
+            if panel.active == self.widget_id {
+                match pos {
+                    PanelPosition::LeftTop | PanelPosition::LeftBottom => {
+                        ctx.set_paint_insets((0.0, 0.0, 10.0, 0.0));
+                    }
+                    PanelPosition::BottomLeft | PanelPosition::BottomRight => {
+                        ctx.set_paint_insets((0.0, 10.0, 0.0, 0.0));
+                    }
+                    PanelPosition::RightTop | PanelPosition::RightBottom => {
+                        ctx.set_paint_insets((10.0, 0.0, 0.0, 0.0));
+                    }
+                }
+            }
+        }
+        let size = bc.max();
+        let header_height = Self::header_height(data);
+        self.list_height = (size.height - header_height).max(0.0);
+        let list_size = Size::new(size.width, self.list_height);
+        self.list
+            .layout(ctx, &BoxConstraints::tight(list_size), data, env);
+        self.list
+            .set_origin(ctx, data, env, Point::new(0.0, header_height));
+        if self.follow {
+            self.follow = false;
+            let content_height = self.list.widget().child_size().height;
+            let rect = Size::new(size.width, 1.0)
+                .to_rect()
+                .with_origin(Point::new(0.0, (content_height - 1.0).max(0.0)));
+            self.list.widget_mut().scroll_to_visible(rect, env);
+        }
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        let line_height = data.config.editor.line_height as f64;
+        let size = ctx.size();
+        let rect = Size::new(size.width, line_height * 2.0)
+            .to_rect()
+            .with_origin(Point::new(0.0, 5.0));
+        ctx.blurred_rect(
+            rect,
+            5.0,
+            data.config
+                .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+        );
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::PANEL_BACKGROUND),
+        );
+
+        let text_layout = ctx
+            .text()
+            .new_text_layout("Language Servers")
+            .font(FontFamily::SYSTEM_UI, 13.0)
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(&text_layout, Point::new(5.0, 5.0 + 4.0));
+        let x = 5.0 + text_layout.size().width + 20.0;
+
+        self.option_rects.clear();
+        let lsp_log = &data.lsp_log;
+        let kinds = [LspLogKind::Stderr, LspLogKind::Messages, LspLogKind::Trace]
+            .iter()
+            .map(|kind| {
+                (
+                    LspLogOption::Kind(*kind),
+                    kind_label(*kind),
+                    *kind == lsp_log.kind,
+                )
+            })
+            .collect();
+        let right = self.paint_options(ctx, data, kinds, 5.0, size.width - 5.0);
+
+        let mut server_x = x;
+        for server in lsp_log.servers.iter() {
+            let selected = lsp_log.server.as_ref() == Some(server);
+            let text_layout = ctx
+                .text()
+                .new_text_layout(server.clone())
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(if selected {
+                            LapceTheme::EDITOR_FOREGROUND
+                        } else {
+                            LapceTheme::EDITOR_DIM
+                        })
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            let width = text_layout.size().width;
+            if server_x + width + 10.0 > right - 10.0 {
+                break;
+            }
+            let rect = Size::new(width + 10.0, line_height)
+                .to_rect()
+                .with_origin(Point::new(server_x, 5.0));
+            if selected {
+                ctx.stroke(
+                    rect.inset(-0.5),
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND),
+                    1.0,
+                );
+            }
+            ctx.draw_text(&text_layout, Point::new(server_x + 5.0, 5.0 + 4.0));
+            self.option_rects
+                .push((LspLogOption::Server(server.clone()), rect));
+            server_x += width + 20.0;
+        }
+
+        let y = 5.0 + line_height;
+        let traces = [
+            TraceOption::Off,
+            TraceOption::Messages,
+            TraceOption::Verbose,
+        ]
+        .iter()
+        .map(|trace| {
+            (
+                LspLogOption::Trace(*trace),
+                trace_label(*trace),
+                *trace == lsp_log.trace,
+            )
+        })
+        .collect();
+        let right = self.paint_options(ctx, data, traces, y, size.width - 5.0);
+        let text_layout = ctx
+            .text()
+            .new_text_layout("Trace")
+            .font(FontFamily::SYSTEM_UI, 13.0)
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(
+            &text_layout,
+            Point::new(right - text_layout.size().width - 5.0, y + 4.0),
+        );
+
+        let filter_empty = lsp_log.filter == "";
+        let text_layout = ctx
+            .text()
+            .new_text_layout(if filter_empty {
+                "Search".to_string()
+            } else {
+                lsp_log.filter.clone()
+            })
+            .font(FontFamily::SYSTEM_UI, 13.0)
+            .text_color(
+                data.config
+                    .get_color_unchecked(if filter_empty {
+                        LapceTheme::EDITOR_DIM
+                    } else {
+                        LapceTheme::EDITOR_FOREGROUND
+                    })
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(&text_layout, Point::new(5.0, y + 4.0));
+        if ctx.has_focus() {
+            let caret_x = if filter_empty {
+                5.0
+            } else {
+                5.0 + text_layout.size().width
+            };
+            ctx.stroke(
+                Line::new(
+                    Point::new(caret_x, y + 4.0),
+                    Point::new(caret_x, y + line_height - 4.0),
+                ),
+                data.config.get_color_unchecked(LapceTheme::EDITOR_CARET),
+                1.0,
+            );
+        }
+
+        self.list.paint(ctx, data, env);
+    }
+}
+
+pub struct LspLogList {
+    widget_id: WidgetId,
+}
+
+impl LspLogList {
+    pub fn new(widget_id: WidgetId) -> Self {
+        Self { widget_id }
+    }
+}
+
+impl Widget<LapceTabData> for LspLogList {
+    fn id(&self) -> Option<WidgetId> {
+        Some(self.widget_id)
+    }
+
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseDown(mouse_event) => {
+                ctx.request_focus();
+                data.focus_area = FocusArea::LspLog;
+                ctx.set_handled();
+            }
+            Event::KeyDown(key_event) => {
+                let mut keypress = data.keypress.clone();
+                let mut view_data = LspLogViewData {
+                    lsp_log: data.lsp_log.clone(),
+                };
+                Arc::make_mut(&mut keypress).key_down(
+                    ctx,
+                    key_event,
+                    &mut view_data,
+                    env,
+                );
+                data.keypress = keypress;
+                data.lsp_log = view_data.lsp_log;
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                match command {
+                    LapceUICommand::Focus => {
+                        data.focus_area = FocusArea::LspLog;
+                        ctx.request_focus();
+                        ctx.set_handled();
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            LifeCycle::FocusChanged(_) => {
+                ctx.request_paint();
+            }
+            _ => (),
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        if !data.lsp_log.same(&old_data.lsp_log) {
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        let line_height = data.config.editor.line_height as f64;
+        let lines = data.lsp_log.lines();
+        Size::new(bc.max().width, line_height * lines.len() as f64)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        let line_height = data.config.editor.line_height as f64;
+        let lines = data.lsp_log.lines();
+
+        let rect = ctx.region().bounding_box();
+        let start_line = (rect.y0 / line_height).floor() as usize;
+        let end_line = (rect.y1 / line_height).ceil() as usize;
+        for line in start_line..end_line {
+            if line >= lines.len() {
+                break;
+            }
+            let text_layout = ctx
+                .text()
+                .new_text_layout(lines[line].to_string())
+                .font(
+                    data.config.editor.font_family(),
+                    data.config.editor.font_size as f64,
+                )
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(
+                &text_layout,
+                Point::new(5.0, line_height * line as f64 + 4.0),
+            );
+        }
+    }
+}
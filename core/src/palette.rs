@@ -23,7 +23,10 @@ use fuzzy_matcher::FuzzyMatcher;
 use fzyr::{has_match, locate, Score};
 use itertools::Itertools;
 use lapce_proxy::terminal::TermId;
-use lsp_types::{DocumentSymbolResponse, Location, Position, Range, SymbolKind};
+use lsp_types::{
+    DocumentSymbolResponse, Location, MessageActionItem, Position, Range,
+    ShowMessageRequestParams, SymbolInformation, SymbolKind,
+};
 use serde_json::{self, json, Value};
 use std::fs::{self, DirEntry};
 use std::marker::PhantomData;
@@ -67,10 +70,12 @@ pub enum PaletteType {
     Line,
     GlobalSearch,
     DocumentSymbol,
+    WorkspaceSymbol,
     Workspace,
     Command,
     Reference,
     Theme,
+    MessageAction,
 }
 
 impl PaletteType {
@@ -79,11 +84,13 @@ impl PaletteType {
             PaletteType::File => "".to_string(),
             PaletteType::Line => "/".to_string(),
             PaletteType::DocumentSymbol => "@".to_string(),
+            PaletteType::WorkspaceSymbol => "#".to_string(),
             PaletteType::GlobalSearch => "?".to_string(),
             PaletteType::Workspace => ">".to_string(),
             PaletteType::Command => ":".to_string(),
             PaletteType::Reference => "".to_string(),
             PaletteType::Theme => "".to_string(),
+            PaletteType::MessageAction => "".to_string(),
         }
     }
 
@@ -91,6 +98,7 @@ impl PaletteType {
         match &self {
             PaletteType::Line
             | PaletteType::DocumentSymbol
+            | PaletteType::WorkspaceSymbol
             | PaletteType::GlobalSearch
             | PaletteType::Reference => true,
             _ => false,
@@ -123,10 +131,17 @@ pub enum PaletteItemContent {
         range: Range,
         container_name: Option<String>,
     },
+    WorkspaceSymbol {
+        kind: SymbolKind,
+        name: String,
+        container_name: Option<String>,
+        location: EditorLocationNew,
+    },
     ReferenceLocation(PathBuf, EditorLocationNew),
     Workspace(LapceWorkspace),
     Command(LapceCommandNew),
     Theme(String),
+    MessageAction(MessageActionItem),
 }
 
 impl PaletteItemContent {
@@ -170,6 +185,18 @@ impl PaletteItemContent {
                     Target::Auto,
                 ));
             }
+            PaletteItemContent::WorkspaceSymbol { location, .. } => {
+                let kind = if preview {
+                    EditorKind::PalettePreview
+                } else {
+                    EditorKind::SplitActive
+                };
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::JumpToLocation(kind, location.clone()),
+                    Target::Auto,
+                ));
+            }
             PaletteItemContent::ReferenceLocation(rel_path, location) => {
                 let kind = if preview {
                     EditorKind::PalettePreview
@@ -216,6 +243,7 @@ impl PaletteItemContent {
                     ));
                 }
             }
+            PaletteItemContent::MessageAction(_) => {}
         }
         None
     }
@@ -233,8 +261,14 @@ impl PaletteItemContent {
             PaletteItemContent::DocumentSymbol {
                 kind,
                 name,
-                range,
                 container_name,
+                ..
+            }
+            | PaletteItemContent::WorkspaceSymbol {
+                kind,
+                name,
+                container_name,
+                ..
             } => {
                 let text = name.to_string();
                 let hint = container_name.clone().unwrap_or("".to_string());
@@ -303,6 +337,13 @@ impl PaletteItemContent {
                 "".to_string(),
                 vec![],
             ),
+            PaletteItemContent::MessageAction(action) => (
+                None,
+                action.title.clone(),
+                indices.to_vec(),
+                "".to_string(),
+                vec![],
+            ),
         };
 
         if let Some(svg) = svg.as_ref() {
@@ -444,6 +485,9 @@ pub struct PaletteData {
     items: Vec<NewPaletteItem>,
     filtered_items: Vec<NewPaletteItem>,
     pub preview_editor: WidgetId,
+    /// The language server, request id and message of a message request
+    /// whose actions are being picked from.
+    message_request: Option<(String, Value, String)>,
 }
 
 impl KeyPressFocus for PaletteViewData {
@@ -518,6 +562,18 @@ impl PaletteData {
             items: Vec::new(),
             filtered_items: Vec::new(),
             preview_editor,
+            message_request: None,
+        }
+    }
+
+    /// Answers the pending message request with the action that was picked,
+    /// `None` meaning it was dismissed.
+    fn respond_message_request(&mut self, action: Option<&MessageActionItem>) {
+        if let Some((server, id, _)) = self.message_request.take() {
+            let result = action
+                .map(|action| serde_json::to_value(action).unwrap())
+                .unwrap_or(Value::Null);
+            self.proxy.lsp_response(&server, &id, result);
         }
     }
 
@@ -552,8 +608,10 @@ impl PaletteData {
             PaletteType::File => &self.input,
             PaletteType::Reference => &self.input,
             PaletteType::Theme => &self.input,
+            PaletteType::MessageAction => &self.input,
             PaletteType::Line => &self.input[1..],
             PaletteType::DocumentSymbol => &self.input[1..],
+            PaletteType::WorkspaceSymbol => &self.input[1..],
             PaletteType::Workspace => &self.input[1..],
             PaletteType::Command => &self.input[1..],
             PaletteType::GlobalSearch => &self.input[1..],
@@ -564,6 +622,7 @@ impl PaletteData {
 impl PaletteViewData {
     fn cancel(&mut self, ctx: &mut EventCtx) {
         let palette = Arc::make_mut(&mut self.palette);
+        palette.respond_message_request(None);
         palette.status = PaletteStatus::Inactive;
         palette.input = "".to_string();
         palette.cursor = 0;
@@ -610,8 +669,40 @@ impl PaletteViewData {
         palette.preview(ctx);
     }
 
+    /// Lets the user pick one of the actions of a language server's message.
+    pub fn run_message_request(
+        &mut self,
+        ctx: &mut EventCtx,
+        server: &str,
+        id: &Value,
+        params: &ShowMessageRequestParams,
+    ) {
+        self.run(ctx, Some(PaletteType::MessageAction));
+        let items: Vec<NewPaletteItem> = params
+            .actions
+            .as_ref()
+            .map(|actions| {
+                actions
+                    .iter()
+                    .map(|action| NewPaletteItem {
+                        content: PaletteItemContent::MessageAction(action.clone()),
+                        filter_text: action.title.clone(),
+                        score: 0,
+                        indices: vec![],
+                    })
+                    .collect()
+            })
+            .unwrap_or(Vec::new());
+        let palette = Arc::make_mut(&mut self.palette);
+        palette.message_request =
+            Some((server.to_string(), id.clone(), params.message.clone()));
+        palette.items = items;
+    }
+
     pub fn run(&mut self, ctx: &mut EventCtx, palette_type: Option<PaletteType>) {
         let palette = Arc::make_mut(&mut self.palette);
+        // Another request, or anything else run over it, dismisses it.
+        palette.respond_message_request(None);
         palette.status = PaletteStatus::Started;
         palette.palette_type = palette_type.unwrap_or(PaletteType::File);
         palette.input = palette.palette_type.string();
@@ -637,10 +728,14 @@ impl PaletteViewData {
             &PaletteType::DocumentSymbol => {
                 self.get_document_symbols(ctx);
             }
+            &PaletteType::WorkspaceSymbol => {
+                self.get_workspace_symbols(ctx);
+            }
             &PaletteType::Workspace => {
                 self.get_workspaces(ctx);
             }
             &PaletteType::Reference => {}
+            &PaletteType::MessageAction => {}
             &PaletteType::GlobalSearch => {
                 self.get_global_search(ctx);
             }
@@ -675,8 +770,10 @@ impl PaletteViewData {
             &PaletteType::File => 0,
             &PaletteType::Reference => 0,
             &PaletteType::Theme => 0,
+            &PaletteType::MessageAction => 0,
             &PaletteType::Line => 1,
             &PaletteType::DocumentSymbol => 1,
+            &PaletteType::WorkspaceSymbol => 1,
             &PaletteType::Workspace => 1,
             &PaletteType::Command => 1,
             &PaletteType::GlobalSearch => 1,
@@ -716,6 +813,13 @@ impl PaletteViewData {
             );
         }
         let palette = Arc::make_mut(&mut self.palette);
+        if let Some(NewPaletteItem {
+            content: PaletteItemContent::MessageAction(action),
+            ..
+        }) = palette.get_item().cloned()
+        {
+            palette.respond_message_request(Some(&action));
+        }
         if let Some(item) = palette.get_item() {
             if let Some(palette_type) = item.content.select(ctx, false) {
                 self.run(ctx, Some(palette_type));
@@ -735,6 +839,14 @@ impl PaletteViewData {
             self.run(ctx, Some(palette_type));
             return;
         }
+        if self.palette.palette_type == PaletteType::WorkspaceSymbol {
+            // The server does the filtering, so every query is a new run; the
+            // current items stay until the new ones arrive.
+            let palette = Arc::make_mut(&mut self.palette);
+            palette.run_id = Uuid::new_v4().to_string();
+            self.get_workspace_symbols(ctx);
+            return;
+        }
         if self.palette.get_input() != "" {
             self.palette.sender.send((
                 self.palette.run_id.clone(),
@@ -750,12 +862,16 @@ impl PaletteViewData {
         if self.palette.palette_type == PaletteType::Reference {
             return PaletteType::Reference;
         }
+        if self.palette.palette_type == PaletteType::MessageAction {
+            return PaletteType::MessageAction;
+        }
         if self.palette.input == "" {
             return PaletteType::File;
         }
         match self.palette.input {
             _ if self.palette.input.starts_with("/") => PaletteType::Line,
             _ if self.palette.input.starts_with("@") => PaletteType::DocumentSymbol,
+            _ if self.palette.input.starts_with("#") => PaletteType::WorkspaceSymbol,
             _ if self.palette.input.starts_with(">") => PaletteType::Workspace,
             _ if self.palette.input.starts_with(":") => PaletteType::Command,
             _ => PaletteType::File,
@@ -1028,6 +1144,80 @@ impl PaletteViewData {
         }
     }
 
+    fn get_workspace_symbols(&mut self, ctx: &mut EventCtx) {
+        let editor = self.main_split.active_editor();
+        let buffer_id = match &editor.content {
+            EditorContent::Buffer(path) => {
+                self.main_split.open_files.get(path).unwrap().id
+            }
+            EditorContent::None => return,
+        };
+        let widget_id = self.palette.widget_id;
+        let run_id = self.palette.run_id.clone();
+        let workspace = self.workspace.clone();
+        let event_sink = ctx.get_external_handle();
+
+        self.palette.proxy.get_workspace_symbols(
+            buffer_id,
+            self.palette.get_input(),
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    let resp: Result<
+                        Option<Vec<SymbolInformation>>,
+                        serde_json::Error,
+                    > = serde_json::from_value(res);
+                    if let Ok(resp) = resp {
+                        let items: Vec<NewPaletteItem> = resp
+                            .unwrap_or(Vec::new())
+                            .iter()
+                            .map(|s| {
+                                let path = PathBuf::from(s.location.uri.path());
+                                // Without a container name, the file the
+                                // symbol is in is the next best hint.
+                                let container_name =
+                                    s.container_name.clone().or_else(|| {
+                                        workspace
+                                            .as_ref()
+                                            .and_then(|w| {
+                                                path.strip_prefix(&w.path).ok()
+                                            })
+                                            .unwrap_or(&path)
+                                            .to_str()
+                                            .map(|p| p.to_string())
+                                    });
+                                let mut filter_text = s.name.clone();
+                                if let Some(container_name) = container_name.as_ref()
+                                {
+                                    filter_text += container_name;
+                                }
+                                NewPaletteItem {
+                                    content: PaletteItemContent::WorkspaceSymbol {
+                                        kind: s.kind,
+                                        name: s.name.clone(),
+                                        container_name,
+                                        location: EditorLocationNew {
+                                            path,
+                                            position: Some(s.location.range.start),
+                                            scroll_offset: None,
+                                        },
+                                    },
+                                    filter_text,
+                                    score: 0,
+                                    indices: Vec::new(),
+                                }
+                            })
+                            .collect();
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::UpdatePaletteItems(run_id, items),
+                            Target::Widget(widget_id),
+                        );
+                    }
+                }
+            }),
+        );
+    }
+
     pub fn update_process(
         receiver: Receiver<(String, String, Vec<NewPaletteItem>)>,
         widget_id: WidgetId,
@@ -1178,6 +1368,15 @@ impl Widget<LapceTabData> for NewPalette {
                         data.workspace = palette_data.workspace.clone();
                         data.main_split = palette_data.main_split.clone();
                     }
+                    LapceUICommand::RunPaletteMessageRequest(server, id, params) => {
+                        ctx.request_focus();
+                        let mut palette_data = data.palette_view_data();
+                        palette_data.run_message_request(ctx, server, id, params);
+                        data.palette = palette_data.palette.clone();
+                        data.keypress = palette_data.keypress.clone();
+                        data.workspace = palette_data.workspace.clone();
+                        data.main_split = palette_data.main_split.clone();
+                    }
                     LapceUICommand::CancelPalette => {
                         let mut palette_data = data.palette_view_data();
                         palette_data.cancel(ctx);
@@ -1534,15 +1733,18 @@ impl Widget<PaletteViewData> for NewPaletteInput {
         let text = data.palette.input.clone();
         let cursor = data.palette.cursor;
 
+        // The message of a message request stands in for the empty input.
+        let (text, color) = match data.palette.message_request.as_ref() {
+            Some((_, _, message)) if text == "" => {
+                (message.clone(), LapceTheme::EDITOR_DIM)
+            }
+            _ => (text, LapceTheme::EDITOR_FOREGROUND),
+        };
         let text_layout = ctx
             .text()
             .new_text_layout(text)
             .font(FontFamily::SYSTEM_UI, 14.0)
-            .text_color(
-                data.config
-                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
-                    .clone(),
-            )
+            .text_color(data.config.get_color_unchecked(color).clone())
             .build()
             .unwrap();
         let line = text_layout.cursor_line_for_text_position(cursor);
@@ -0,0 +1,195 @@
+use std::path::PathBuf;
+
+use druid::{
+    BoxConstraints, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx,
+    PaintCtx, Point, RenderContext, Size, UpdateCtx, Widget, WidgetExt, WidgetId,
+    WidgetPod,
+};
+
+use crate::{
+    config::LapceTheme,
+    data::{EditorContent, LapceTabData},
+    editor::LapceEditorView,
+};
+
+/// How many lines of the target the peek editor shows.
+pub const PEEK_LINES: usize = 12;
+/// The height of the editor header the peek keeps for its title.
+const PEEK_HEADER_HEIGHT: f64 = 30.0;
+
+/// A definition shown in an editor embedded under the line it was asked
+/// from, so it can be read without leaving that line.
+#[derive(Clone)]
+pub struct PeekData {
+    pub active: bool,
+    /// The embedded editor.
+    pub editor_view_id: WidgetId,
+    /// The editor the peek was opened from, and the buffer and offset it's
+    /// shown under.
+    pub origin_view_id: WidgetId,
+    pub path: PathBuf,
+    pub offset: usize,
+}
+
+impl PeekData {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            editor_view_id: WidgetId::next(),
+            origin_view_id: WidgetId::next(),
+            path: PathBuf::new(),
+            offset: 0,
+        }
+    }
+
+    pub fn activate(
+        &mut self,
+        origin_view_id: WidgetId,
+        path: PathBuf,
+        offset: usize,
+    ) {
+        self.active = true;
+        self.origin_view_id = origin_view_id;
+        self.path = path;
+        self.offset = offset;
+    }
+}
+
+/// Whether the editor the peek was opened from still shows the same buffer.
+fn is_shown(data: &LapceTabData) -> bool {
+    if !data.peek.active {
+        return false;
+    }
+    match data.main_split.editors.get(&data.peek.origin_view_id) {
+        Some(editor) => match &editor.content {
+            EditorContent::Buffer(path) => path == &data.peek.path,
+            EditorContent::None => false,
+        },
+        None => false,
+    }
+}
+
+pub struct Peek {
+    editor: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
+}
+
+impl Peek {
+    pub fn new(data: &LapceTabData) -> Self {
+        let editor = data
+            .main_split
+            .editors
+            .get(&data.peek.editor_view_id)
+            .unwrap();
+        let editor = LapceEditorView::new(editor);
+        Self {
+            editor: WidgetPod::new(editor.boxed()),
+        }
+    }
+}
+
+impl Widget<LapceTabData> for Peek {
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseDown(mouse_event)
+            | Event::MouseUp(mouse_event)
+            | Event::MouseMove(mouse_event)
+            | Event::Wheel(mouse_event) => {
+                if !is_shown(data) {
+                    return;
+                }
+                self.editor.event(ctx, event, data, env);
+                if ctx.size().to_rect().contains(mouse_event.pos) {
+                    ctx.set_handled();
+                }
+            }
+            _ => {
+                self.editor.event(ctx, event, data, env);
+            }
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        self.editor.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        if !old_data.peek.same(&data.peek) {
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+        let old_editor = old_data.main_split.editors.get(&data.peek.origin_view_id);
+        let editor = data.main_split.editors.get(&data.peek.origin_view_id);
+        if let (Some(old_editor), Some(editor)) = (old_editor, editor) {
+            if editor.window_origin != old_editor.window_origin {
+                ctx.request_layout();
+            }
+            if !editor.same(old_editor) {
+                ctx.request_paint();
+            }
+        }
+        self.editor.update(ctx, data, env);
+    }
+
+    /// The editor is laid out even while the peek is hidden, so that it
+    /// already has a size when a location is shown in it.
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        let line_height = data.config.editor.line_height as f64;
+        let width = data
+            .main_split
+            .editors
+            .get(&data.peek.origin_view_id)
+            .map(|editor| editor.size.borrow().width)
+            .filter(|width| *width > 0.0)
+            .unwrap_or(bc.max().width);
+        let size =
+            Size::new(width, PEEK_HEADER_HEIGHT + PEEK_LINES as f64 * line_height);
+        self.editor
+            .layout(ctx, &BoxConstraints::tight(size), data, env);
+        self.editor.set_origin(ctx, data, env, Point::ZERO);
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        if !is_shown(data) {
+            return;
+        }
+        let rect = ctx.size().to_rect();
+        let shadow_width = 5.0;
+        ctx.blurred_rect(
+            rect,
+            shadow_width,
+            data.config
+                .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+        );
+        self.editor.paint(ctx, data, env);
+        ctx.stroke(
+            rect.inset(-0.5),
+            data.config.get_color_unchecked(LapceTheme::LAPCE_BORDER),
+            1.0,
+        );
+    }
+}
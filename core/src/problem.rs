@@ -0,0 +1,918 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    rc::Rc,
+    sync::{Arc, Weak},
+};
+
+use druid::{
+    kurbo::Line,
+    piet::{Text, TextLayout as PietTextLayout, TextLayoutBuilder},
+    BoxConstraints, Command, Env, Event, EventCtx, FontFamily, LayoutCtx, LifeCycle,
+    LifeCycleCtx, PaintCtx, Point, Rect, RenderContext, Size, Target, UpdateCtx,
+    Widget, WidgetId, WidgetPod,
+};
+use lsp_types::{Diagnostic, DiagnosticSeverity};
+
+use crate::{
+    command::{LapceCommand, LapceUICommand, LAPCE_UI_COMMAND},
+    config::LapceTheme,
+    data::{EditorDiagnostic, EditorKind, FocusArea, LapceTabData},
+    editor::EditorLocationNew,
+    keypress::KeyPressFocus,
+    movement::Movement,
+    panel::PanelPosition,
+    scroll::LapceScrollNew,
+    state::Mode,
+    svg::file_svg_new,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ProblemSeverity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl ProblemSeverity {
+    pub fn of(diagnostic: &Diagnostic) -> Self {
+        match diagnostic.severity {
+            Some(DiagnosticSeverity::Error) => ProblemSeverity::Error,
+            Some(DiagnosticSeverity::Warning) => ProblemSeverity::Warning,
+            _ => ProblemSeverity::Info,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            ProblemSeverity::Error => "Errors",
+            ProblemSeverity::Warning => "Warnings",
+            ProblemSeverity::Info => "Info",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            ProblemSeverity::Error => LapceTheme::LAPCE_ERROR,
+            ProblemSeverity::Warning => LapceTheme::LAPCE_WARN,
+            ProblemSeverity::Info => LapceTheme::EDITOR_DIM,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ProblemItem<'a> {
+    File {
+        path: &'a PathBuf,
+        count: usize,
+    },
+    Diagnostic {
+        path: &'a PathBuf,
+        diagnostic: &'a Diagnostic,
+    },
+}
+
+impl<'a> ProblemItem<'a> {
+    pub fn path(&self) -> &'a PathBuf {
+        match *self {
+            ProblemItem::File { path, .. } => path,
+            ProblemItem::Diagnostic { path, .. } => path,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ProblemData {
+    pub widget_id: WidgetId,
+    pub list_id: WidgetId,
+    pub index: usize,
+    /// Free text typed into the panel. Words of the form `source:<name>`
+    /// filter on the diagnostic source, everything else on the message.
+    pub filter: String,
+    pub hidden: HashSet<ProblemSeverity>,
+    pub collapsed: HashSet<PathBuf>,
+    matching: Rc<RefCell<MatchingDiagnostics>>,
+}
+
+/// The diagnostics of each file that pass the filter, by their index in
+/// the file's diagnostics. A file's are only looked at again once its
+/// diagnostics were replaced or edited, which gives them a new `Arc`, or
+/// once the filter changed.
+#[derive(Default)]
+struct MatchingDiagnostics {
+    filter: String,
+    hidden: HashSet<ProblemSeverity>,
+    files: HashMap<PathBuf, (Weak<Vec<EditorDiagnostic>>, Vec<usize>)>,
+}
+
+impl ProblemData {
+    pub fn new() -> Self {
+        Self {
+            widget_id: WidgetId::next(),
+            list_id: WidgetId::next(),
+            index: 0,
+            filter: "".to_string(),
+            hidden: HashSet::new(),
+            collapsed: HashSet::new(),
+            matching: Rc::new(RefCell::new(MatchingDiagnostics::default())),
+        }
+    }
+
+    pub fn toggle_severity(&mut self, severity: ProblemSeverity) {
+        if !self.hidden.remove(&severity) {
+            self.hidden.insert(severity);
+        }
+        self.index = 0;
+    }
+
+    pub fn toggle_collapsed(&mut self, path: &PathBuf) {
+        if !self.collapsed.remove(path) {
+            self.collapsed.insert(path.clone());
+        }
+    }
+
+    fn matches(
+        &self,
+        diagnostic: &Diagnostic,
+        sources: &[String],
+        words: &[String],
+    ) -> bool {
+        if self.hidden.contains(&ProblemSeverity::of(diagnostic)) {
+            return false;
+        }
+        if sources.len() > 0 {
+            let source = diagnostic
+                .source
+                .as_ref()
+                .map(|s| s.to_lowercase())
+                .unwrap_or("".to_string());
+            if !sources.iter().any(|s| source.contains(s)) {
+                return false;
+            }
+        }
+        let message = diagnostic.message.to_lowercase();
+        words.iter().all(|w| message.contains(w))
+    }
+
+    /// The rows shown in the panel, files sorted by path, each followed by
+    /// its diagnostics in document order unless the file is collapsed. The
+    /// diagnostics of a file are kept sorted as they're published.
+    pub fn items<'a>(
+        &self,
+        diagnostics: &'a im::HashMap<PathBuf, Arc<Vec<EditorDiagnostic>>>,
+    ) -> Vec<ProblemItem<'a>> {
+        let mut sources = Vec::new();
+        let mut words = Vec::new();
+        for word in self.filter.to_lowercase().split_whitespace() {
+            if let Some(source) = word.strip_prefix("source:") {
+                if source != "" {
+                    sources.push(source.to_string());
+                }
+            } else {
+                words.push(word.to_string());
+            }
+        }
+
+        let mut matching = self.matching.borrow_mut();
+        if matching.filter != self.filter || matching.hidden != self.hidden {
+            matching.filter = self.filter.clone();
+            matching.hidden = self.hidden.clone();
+            matching.files.clear();
+        }
+        matching
+            .files
+            .retain(|path, _| diagnostics.contains_key(path));
+
+        let mut files: Vec<(&PathBuf, &Arc<Vec<EditorDiagnostic>>)> =
+            diagnostics.iter().collect();
+        files.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut items = Vec::new();
+        for (path, file_diagnostics) in files {
+            let unchanged = matching
+                .files
+                .get(path)
+                .map(|(cached, _)| cached.as_ptr() == Arc::as_ptr(file_diagnostics))
+                .unwrap_or(false);
+            if !unchanged {
+                let indices = file_diagnostics
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, d)| self.matches(&d.diagnositc, &sources, &words))
+                    .map(|(i, _)| i)
+                    .collect();
+                matching.files.insert(
+                    path.clone(),
+                    (Arc::downgrade(file_diagnostics), indices),
+                );
+            }
+            let indices = &matching.files[path].1;
+            if indices.len() == 0 {
+                continue;
+            }
+            items.push(ProblemItem::File {
+                path,
+                count: indices.len(),
+            });
+            if !self.collapsed.contains(path) {
+                items.extend(indices.iter().map(|i| ProblemItem::Diagnostic {
+                    path,
+                    diagnostic: &file_diagnostics[*i].diagnositc,
+                }));
+            }
+        }
+        items
+    }
+
+    pub fn count(
+        diagnostics: &im::HashMap<PathBuf, Arc<Vec<EditorDiagnostic>>>,
+        severity: ProblemSeverity,
+    ) -> usize {
+        diagnostics
+            .values()
+            .map(|d| {
+                d.iter()
+                    .filter(|d| ProblemSeverity::of(&d.diagnositc) == severity)
+                    .count()
+            })
+            .sum()
+    }
+}
+
+pub struct ProblemViewData<'a> {
+    pub problem: Arc<ProblemData>,
+    pub items: Vec<ProblemItem<'a>>,
+}
+
+impl<'a> ProblemViewData<'a> {
+    pub fn new(
+        problem: Arc<ProblemData>,
+        diagnostics: &'a im::HashMap<PathBuf, Arc<Vec<EditorDiagnostic>>>,
+    ) -> Self {
+        let items = problem.items(diagnostics);
+        let mut view_data = Self { problem, items };
+        if view_data.problem.index >= view_data.items.len() {
+            Arc::make_mut(&mut view_data.problem).index =
+                view_data.items.len().saturating_sub(1);
+        }
+        view_data
+    }
+
+    fn select(&mut self, ctx: &mut EventCtx) {
+        match self.items.get(self.problem.index).copied() {
+            Some(ProblemItem::File { path, .. }) => {
+                Arc::make_mut(&mut self.problem).toggle_collapsed(path);
+            }
+            Some(ProblemItem::Diagnostic { path, diagnostic }) => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::JumpToLocation(
+                        EditorKind::SplitActive,
+                        EditorLocationNew {
+                            path: path.clone(),
+                            position: Some(diagnostic.range.start),
+                            scroll_offset: None,
+                        },
+                    ),
+                    Target::Auto,
+                ));
+            }
+            None => {}
+        }
+    }
+}
+
+impl KeyPressFocus for ProblemViewData<'_> {
+    fn get_mode(&self) -> Mode {
+        Mode::Insert
+    }
+
+    fn check_condition(&self, condition: &str) -> bool {
+        match condition {
+            "problem_focus" => true,
+            "list_focus" => true,
+            _ => false,
+        }
+    }
+
+    fn run_command(
+        &mut self,
+        ctx: &mut EventCtx,
+        command: &LapceCommand,
+        count: Option<usize>,
+        env: &Env,
+    ) {
+        match command {
+            LapceCommand::ProblemCancel => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::FocusEditor,
+                    Target::Auto,
+                ));
+            }
+            LapceCommand::Up | LapceCommand::ListPrevious => {
+                let problem = Arc::make_mut(&mut self.problem);
+                problem.index = Movement::Up.update_index(
+                    problem.index,
+                    self.items.len(),
+                    1,
+                    true,
+                );
+            }
+            LapceCommand::Down | LapceCommand::ListNext => {
+                let problem = Arc::make_mut(&mut self.problem);
+                problem.index = Movement::Down.update_index(
+                    problem.index,
+                    self.items.len(),
+                    1,
+                    true,
+                );
+            }
+            LapceCommand::ListSelect => {
+                self.select(ctx);
+            }
+            LapceCommand::ListExpand => {
+                if let Some(item) = self.items.get(self.problem.index) {
+                    let path = item.path().clone();
+                    Arc::make_mut(&mut self.problem).toggle_collapsed(&path);
+                }
+            }
+            LapceCommand::DeleteBackward => {
+                let problem = Arc::make_mut(&mut self.problem);
+                problem.filter.pop();
+                problem.index = 0;
+            }
+            LapceCommand::DeleteToBeginningOfLine => {
+                let problem = Arc::make_mut(&mut self.problem);
+                problem.filter.clear();
+                problem.index = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_char(&mut self, ctx: &mut EventCtx, c: &str) {
+        let problem = Arc::make_mut(&mut self.problem);
+        problem.filter.push_str(c);
+        problem.index = 0;
+    }
+}
+
+pub struct ProblemPanel {
+    widget_id: WidgetId,
+    list_id: WidgetId,
+    list: WidgetPod<LapceTabData, LapceScrollNew<LapceTabData, ProblemList>>,
+    severity_rects: Vec<(ProblemSeverity, Rect)>,
+}
+
+impl ProblemPanel {
+    pub fn new(data: &LapceTabData) -> Self {
+        let list = LapceScrollNew::new(ProblemList::new(data.problem.list_id));
+        Self {
+            widget_id: data.problem.widget_id,
+            list_id: data.problem.list_id,
+            list: WidgetPod::new(list),
+            severity_rects: Vec::new(),
+        }
+    }
+
+    fn header_height(data: &LapceTabData) -> f64 {
+        data.config.editor.line_height as f64 + 10.0
+    }
+
+    fn ensure_item_visible(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        let line_height = data.config.editor.line_height as f64;
+        let rect = Size::new(ctx.size().width, line_height)
+            .to_rect()
+            .with_origin(Point::new(0.0, data.problem.index as f64 * line_height));
+        if self.list.widget_mut().scroll_to_visible(rect, env) {
+            ctx.request_paint();
+        }
+    }
+}
+
+impl Widget<LapceTabData> for ProblemPanel {
+    fn id(&self) -> Option<WidgetId> {
+        Some(self.widget_id)
+    }
+
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseMove(mouse_event) => {
+                if self
+                    .severity_rects
+                    .iter()
+                    .any(|(_, rect)| rect.contains(mouse_event.pos))
+                {
+                    ctx.set_cursor(&druid::Cursor::Pointer);
+                } else {
+                    ctx.clear_cursor();
+                }
+            }
+            Event::MouseDown(mouse_event) => {
+                if mouse_event.pos.y < Self::header_height(data) {
+                    for (severity, rect) in self.severity_rects.iter() {
+                        if rect.contains(mouse_event.pos) {
+                            Arc::make_mut(&mut data.problem)
+                                .toggle_severity(*severity);
+                            break;
+                        }
+                    }
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::Focus,
+                        Target::Widget(self.list_id),
+                    ));
+                    ctx.set_handled();
+                }
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                match command {
+                    LapceUICommand::Focus => {
+                        ctx.submit_command(Command::new(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::Focus,
+                            Target::Widget(self.list_id),
+                        ));
+                        ctx.set_handled();
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+        self.list.event(ctx, event, data, env);
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            LifeCycle::FocusChanged(_) => {
+                ctx.request_paint();
+            }
+            _ => (),
+        }
+        self.list.lifecycle(ctx, event, data, env);
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        if !data.problem.same(&old_data.problem)
+            || !data
+                .main_split
+                .diagnostics
+                .same(&old_data.main_split.diagnostics)
+        {
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+        if data.problem.index != old_data.problem.index {
+            self.ensure_item_visible(ctx, data, env);
+        }
+        self.list.update(ctx, data, env);
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        for (pos, panel) in data.panels.iter() {
+            if panel.active == self.widget_id {
+                match pos {
+                    PanelPosition::LeftTop | PanelPosition::LeftBottom => {
+                        ctx.set_paint_insets((0.0, 0.0, 10.0, 0.0));
+                    }
+                    PanelPosition::BottomLeft | PanelPosition::BottomRight => {
+                        ctx.set_paint_insets((0.0, 10.0, 0.0, 0.0));
+                    }
+                    PanelPosition::RightTop | PanelPosition::RightBottom => {
+                        ctx.set_paint_insets((10.0, 0.0, 0.0, 0.0));
+                    }
+                }
+            }
+        }
+        let size = bc.max();
+        let header_height = Self::header_height(data);
+        let list_size =
+            Size::new(size.width, (size.height - header_height).max(0.0));
+        self.list
+            .layout(ctx, &BoxConstraints::tight(list_size), data, env);
+        self.list
+            .set_origin(ctx, data, env, Point::new(0.0, header_height));
+        size
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        let line_height = data.config.editor.line_height as f64;
+        let size = ctx.size();
+        let rect = Size::new(size.width, line_height)
+            .to_rect()
+            .with_origin(Point::new(0.0, 5.0));
+        ctx.blurred_rect(
+            rect,
+            5.0,
+            data.config
+                .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+        );
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::PANEL_BACKGROUND),
+        );
+
+        let text_layout = ctx
+            .text()
+            .new_text_layout("Problems")
+            .font(FontFamily::SYSTEM_UI, 13.0)
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(&text_layout, Point::new(5.0, 5.0 + 4.0));
+        let mut x = 5.0 + text_layout.size().width + 20.0;
+
+        let filter_empty = data.problem.filter == "";
+        let text_layout = ctx
+            .text()
+            .new_text_layout(if filter_empty {
+                "Filter (e.g. text, source:rustc)".to_string()
+            } else {
+                data.problem.filter.clone()
+            })
+            .font(FontFamily::SYSTEM_UI, 13.0)
+            .text_color(
+                data.config
+                    .get_color_unchecked(if filter_empty {
+                        LapceTheme::EDITOR_DIM
+                    } else {
+                        LapceTheme::EDITOR_FOREGROUND
+                    })
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        ctx.draw_text(&text_layout, Point::new(x, 5.0 + 4.0));
+        if ctx.has_focus() {
+            let caret_x = if filter_empty {
+                x
+            } else {
+                x + text_layout.size().width
+            };
+            ctx.stroke(
+                Line::new(
+                    Point::new(caret_x, 5.0 + 4.0),
+                    Point::new(caret_x, 5.0 + line_height - 4.0),
+                ),
+                data.config.get_color_unchecked(LapceTheme::EDITOR_CARET),
+                1.0,
+            );
+        }
+        x += text_layout.size().width;
+
+        self.severity_rects.clear();
+        let mut right = size.width - 5.0;
+        for severity in [
+            ProblemSeverity::Info,
+            ProblemSeverity::Warning,
+            ProblemSeverity::Error,
+        ]
+        .iter()
+        {
+            let count = ProblemData::count(&data.main_split.diagnostics, *severity);
+            let hidden = data.problem.hidden.contains(severity);
+            let text_layout = ctx
+                .text()
+                .new_text_layout(format!("{} {}", severity.label(), count))
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(if hidden {
+                            LapceTheme::EDITOR_DIM
+                        } else {
+                            LapceTheme::EDITOR_FOREGROUND
+                        })
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            let width = text_layout.size().width;
+            if right - width - 10.0 < x + 10.0 {
+                break;
+            }
+            let origin = Point::new(right - width - 10.0, 5.0);
+            let rect = Size::new(width + 10.0, line_height)
+                .to_rect()
+                .with_origin(origin);
+            if !hidden {
+                ctx.stroke(
+                    rect.inset(-0.5),
+                    data.config.get_color_unchecked(severity.color()),
+                    1.0,
+                );
+            }
+            ctx.draw_text(&text_layout, Point::new(origin.x + 5.0, 5.0 + 4.0));
+            self.severity_rects.push((*severity, rect));
+            right = origin.x - 5.0;
+        }
+
+        self.list.paint(ctx, data, env);
+    }
+}
+
+pub struct ProblemList {
+    widget_id: WidgetId,
+}
+
+impl ProblemList {
+    pub fn new(widget_id: WidgetId) -> Self {
+        Self { widget_id }
+    }
+}
+
+impl Widget<LapceTabData> for ProblemList {
+    fn id(&self) -> Option<WidgetId> {
+        Some(self.widget_id)
+    }
+
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseMove(mouse_event) => {
+                ctx.set_cursor(&druid::Cursor::Pointer);
+                ctx.set_handled();
+            }
+            Event::MouseDown(mouse_event) => {
+                let line_height = data.config.editor.line_height as f64;
+                let line = (mouse_event.pos.y / line_height).floor() as usize;
+                let mut view_data = ProblemViewData::new(
+                    data.problem.clone(),
+                    &data.main_split.diagnostics,
+                );
+                if line < view_data.items.len() {
+                    Arc::make_mut(&mut view_data.problem).index = line;
+                    view_data.select(ctx);
+                }
+                data.problem = view_data.problem;
+                ctx.request_focus();
+                data.focus_area = FocusArea::Problem;
+                ctx.set_handled();
+            }
+            Event::KeyDown(key_event) => {
+                let mut keypress = data.keypress.clone();
+                let mut view_data = ProblemViewData::new(
+                    data.problem.clone(),
+                    &data.main_split.diagnostics,
+                );
+                Arc::make_mut(&mut keypress).key_down(
+                    ctx,
+                    key_event,
+                    &mut view_data,
+                    env,
+                );
+                data.keypress = keypress;
+                data.problem = view_data.problem;
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                match command {
+                    LapceUICommand::Focus => {
+                        data.focus_area = FocusArea::Problem;
+                        ctx.request_focus();
+                        ctx.set_handled();
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            LifeCycle::FocusChanged(_) => {
+                ctx.request_paint();
+            }
+            _ => (),
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        if !data.problem.same(&old_data.problem)
+            || !data
+                .main_split
+                .diagnostics
+                .same(&old_data.main_split.diagnostics)
+        {
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        let line_height = data.config.editor.line_height as f64;
+        let items = data.problem.items(&data.main_split.diagnostics);
+        Size::new(bc.max().width, line_height * items.len() as f64)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        let line_height = data.config.editor.line_height as f64;
+        let items = data.problem.items(&data.main_split.diagnostics);
+
+        if ctx.is_focused() && data.problem.index < items.len() {
+            let rect = Size::new(ctx.size().width, line_height)
+                .to_rect()
+                .with_origin(Point::new(
+                    0.0,
+                    data.problem.index as f64 * line_height,
+                ));
+            ctx.fill(
+                rect,
+                data.config.get_color_unchecked(LapceTheme::PANEL_CURRENT),
+            );
+        }
+
+        let rect = ctx.region().bounding_box();
+        let start_line = (rect.y0 / line_height).floor() as usize;
+        let end_line = (rect.y1 / line_height).ceil() as usize;
+        for line in start_line..end_line {
+            if line >= items.len() {
+                break;
+            }
+            let y = line_height * line as f64;
+            match items[line] {
+                ProblemItem::File { path, count } => {
+                    let mut path = path.clone();
+                    if let Some(workspace) = data.workspace.as_ref() {
+                        path = path
+                            .strip_prefix(&workspace.path)
+                            .unwrap_or(&path)
+                            .to_path_buf();
+                    }
+                    let svg = file_svg_new(
+                        &path
+                            .extension()
+                            .and_then(|s| s.to_str())
+                            .unwrap_or("")
+                            .to_string(),
+                    );
+                    if let Some(svg) = svg.as_ref() {
+                        let width = 13.0;
+                        let height = 13.0;
+                        let rect = Size::new(width, height).to_rect().with_origin(
+                            Point::new(
+                                (line_height - width) / 2.0,
+                                (line_height - height) / 2.0 + y,
+                            ),
+                        );
+                        ctx.draw_svg(&svg, rect, None);
+                    }
+                    let file_name = path
+                        .file_name()
+                        .and_then(|s| s.to_str())
+                        .unwrap_or("")
+                        .to_string();
+                    let text_layout = ctx
+                        .text()
+                        .new_text_layout(file_name)
+                        .font(FontFamily::SYSTEM_UI, 13.0)
+                        .text_color(
+                            data.config
+                                .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                                .clone(),
+                        )
+                        .build()
+                        .unwrap();
+                    ctx.draw_text(&text_layout, Point::new(line_height, y + 4.0));
+                    let folder = path
+                        .parent()
+                        .and_then(|s| s.to_str())
+                        .unwrap_or("")
+                        .to_string();
+                    let text_layout_width = text_layout.size().width;
+                    let text_layout = ctx
+                        .text()
+                        .new_text_layout(format!("{} {}", folder, count))
+                        .font(FontFamily::SYSTEM_UI, 13.0)
+                        .text_color(
+                            data.config
+                                .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                                .clone(),
+                        )
+                        .build()
+                        .unwrap();
+                    ctx.draw_text(
+                        &text_layout,
+                        Point::new(line_height + text_layout_width + 5.0, y + 4.0),
+                    );
+                }
+                ProblemItem::Diagnostic { diagnostic, .. } => {
+                    let severity = ProblemSeverity::of(diagnostic);
+                    let radius = 3.5;
+                    ctx.fill(
+                        druid::kurbo::Circle::new(
+                            Point::new(
+                                line_height + line_height / 2.0,
+                                y + line_height / 2.0,
+                            ),
+                            radius,
+                        ),
+                        data.config.get_color_unchecked(severity.color()),
+                    );
+
+                    let message =
+                        diagnostic.message.lines().next().unwrap_or("").to_string();
+                    let text_layout = ctx
+                        .text()
+                        .new_text_layout(message)
+                        .font(FontFamily::SYSTEM_UI, 13.0)
+                        .text_color(
+                            data.config
+                                .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                                .clone(),
+                        )
+                        .build()
+                        .unwrap();
+                    let x = line_height * 2.0;
+                    ctx.draw_text(&text_layout, Point::new(x, y + 4.0));
+
+                    let location = format!(
+                        "{}[Ln {}, Col {}]",
+                        diagnostic
+                            .source
+                            .as_ref()
+                            .map(|s| format!("{} ", s))
+                            .unwrap_or("".to_string()),
+                        diagnostic.range.start.line + 1,
+                        diagnostic.range.start.character + 1,
+                    );
+                    let x = x + text_layout.size().width + 5.0;
+                    let text_layout = ctx
+                        .text()
+                        .new_text_layout(location)
+                        .font(FontFamily::SYSTEM_UI, 13.0)
+                        .text_color(
+                            data.config
+                                .get_color_unchecked(LapceTheme::EDITOR_DIM)
+                                .clone(),
+                        )
+                        .build()
+                        .unwrap();
+                    ctx.draw_text(&text_layout, Point::new(x, y + 4.0));
+                }
+            }
+        }
+    }
+}
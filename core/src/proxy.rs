@@ -3,19 +3,30 @@ use std::io::BufReader;
 use std::process::Command;
 use std::process::Stdio;
 use std::thread;
+use std::time::Duration;
 use std::{path::PathBuf, process::Child, sync::Arc};
 
 use alacritty_terminal::term::cell::Cell;
 use anyhow::{anyhow, Result};
-use crossbeam_channel::Sender;
+use crossbeam_channel::{bounded, Sender};
 use crossbeam_utils::sync::WaitGroup;
 use druid::{ExtEventSink, WidgetId};
 use druid::{Target, WindowId};
+use lapce_proxy::buffer::PositionEncoding;
 use lapce_proxy::dispatch::{FileNodeItem, NewBufferResponse};
+use lapce_proxy::lsp::{LspSettings, LspStatus};
 use lapce_proxy::terminal::TermId;
+use lsp_types::CallHierarchyItem;
+use lsp_types::CodeAction;
+use lsp_types::CodeLens;
 use lsp_types::CompletionItem;
 use lsp_types::Position;
 use lsp_types::PublishDiagnosticsParams;
+use lsp_types::Range;
+use lsp_types::ShowMessageRequestParams;
+use lsp_types::SignatureHelpContext;
+use lsp_types::TraceOption;
+use lsp_types::WorkspaceEdit;
 use parking_lot::{Condvar, Mutex};
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
@@ -27,6 +38,7 @@ use xi_rpc::RpcLoop;
 use xi_rpc::RpcPeer;
 
 use crate::command::LapceUICommand;
+use crate::inlay_hint::InlayHint;
 use crate::state::LapceWorkspace;
 use crate::state::LapceWorkspaceType;
 use crate::terminal::RawTerminal;
@@ -61,7 +73,12 @@ impl LapceProxy {
         proxy
     }
 
-    pub fn start(&self, workspace: LapceWorkspace, event_sink: ExtEventSink) {
+    pub fn start(
+        &self,
+        workspace: LapceWorkspace,
+        lsp_settings: HashMap<String, LspSettings>,
+        event_sink: ExtEventSink,
+    ) {
         let proxy = self.clone();
         *proxy.initiated.lock() = false;
         let tab_id = self.tab_id;
@@ -109,7 +126,7 @@ impl LapceProxy {
                     old.kill();
                 }
             }
-            proxy.initialize(workspace.path.clone());
+            proxy.initialize(workspace.path.clone(), lsp_settings);
             {
                 *proxy.initiated.lock() = true;
                 proxy.cond.notify_all();
@@ -136,15 +153,31 @@ impl LapceProxy {
         }
     }
 
-    pub fn initialize(&self, workspace: PathBuf) {
+    pub fn initialize(
+        &self,
+        workspace: PathBuf,
+        lsp_settings: HashMap<String, LspSettings>,
+    ) {
         self.peer.lock().as_ref().unwrap().send_rpc_notification(
             "initialize",
             &json!({
                 "workspace": workspace,
+                "lsp_settings": lsp_settings,
             }),
         )
     }
 
+    pub fn update_lsp_settings(&self, lsp_settings: HashMap<String, LspSettings>) {
+        if let Some(peer) = self.peer.lock().as_ref() {
+            peer.send_rpc_notification(
+                "update_lsp_settings",
+                &json!({
+                    "lsp_settings": lsp_settings,
+                }),
+            );
+        }
+    }
+
     pub fn terminal_resize(&self, term_id: TermId, width: usize, height: usize) {
         self.wait();
         self.peer.lock().as_ref().unwrap().send_rpc_notification(
@@ -255,14 +288,48 @@ impl LapceProxy {
         );
     }
 
+    pub fn code_action_resolve(
+        &self,
+        buffer_id: BufferId,
+        action: &CodeAction,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "code_action_resolve",
+            &json!({
+                "buffer_id": buffer_id,
+                "action": action,
+            }),
+            f,
+        );
+    }
+
     pub fn get_signature(
         &self,
         buffer_id: BufferId,
         position: Position,
+        context: Option<SignatureHelpContext>,
         f: Box<dyn Callback>,
     ) {
         self.peer.lock().as_ref().unwrap().send_rpc_request_async(
             "get_signature",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+                "context": context,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_hover(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_hover",
             &json!({
                 "buffer_id": buffer_id,
                 "position": position,
@@ -287,6 +354,123 @@ impl LapceProxy {
         );
     }
 
+    pub fn get_type_definition(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_type_definition",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_implementation(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_implementation",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_declaration(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_declaration",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_document_highlights(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_document_highlights",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+            }),
+            f,
+        );
+    }
+
+    pub fn prepare_call_hierarchy(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "prepare_call_hierarchy",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+            }),
+            f,
+        );
+    }
+
+    pub fn prepare_type_hierarchy(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "prepare_type_hierarchy",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+            }),
+            f,
+        );
+    }
+
+    /// Asks for the children of a hierarchy item, `method` being one of
+    /// `get_incoming_calls`, `get_outgoing_calls`, `get_supertypes` and
+    /// `get_subtypes`. Type hierarchy items have the same shape as call
+    /// hierarchy ones, so both are passed as a `CallHierarchyItem`.
+    pub fn get_hierarchy_items(
+        &self,
+        method: &str,
+        buffer_id: BufferId,
+        item: &CallHierarchyItem,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            method,
+            &json!({
+                "buffer_id": buffer_id,
+                "item": item,
+            }),
+            f,
+        );
+    }
+
     pub fn get_files(&self, f: Box<dyn Callback>) {
         if let Some(peer) = self.peer.lock().as_ref() {
             peer.send_rpc_request_async(
@@ -355,6 +539,32 @@ impl LapceProxy {
         }
     }
 
+    pub fn get_preferred_fixes(&self, buffer_id: BufferId, f: Box<dyn Callback>) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_preferred_fixes",
+            &json!({
+                "buffer_id": buffer_id,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_workspace_symbols(
+        &self,
+        buffer_id: BufferId,
+        query: &str,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_workspace_symbols",
+            &json!({
+                "buffer_id": buffer_id,
+                "query": query,
+            }),
+            f,
+        );
+    }
+
     pub fn get_document_formatting(
         &self,
         buffer_id: BufferId,
@@ -369,12 +579,274 @@ impl LapceProxy {
         );
     }
 
-    pub fn stop(&self) {
-        let mut process = self.process.lock();
-        if let Some(mut p) = process.as_mut() {
-            p.kill();
+    pub fn get_document_range_formatting(
+        &self,
+        buffer_id: BufferId,
+        range: Range,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_document_range_formatting",
+            &json!({
+                "buffer_id": buffer_id,
+                "range": range,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_on_type_formatting(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        ch: String,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_on_type_formatting",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+                "ch": ch,
+            }),
+            f,
+        );
+    }
+
+    pub fn prepare_rename(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "prepare_rename",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+            }),
+            f,
+        );
+    }
+
+    pub fn rename(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        new_name: &str,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "rename",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+                "new_name": new_name,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_semantic_tokens_range(
+        &self,
+        buffer_id: BufferId,
+        range: Range,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_semantic_tokens_range",
+            &json!({
+                "buffer_id": buffer_id,
+                "range": range,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_inlay_hints(
+        &self,
+        buffer_id: BufferId,
+        range: Range,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_inlay_hints",
+            &json!({
+                "buffer_id": buffer_id,
+                "range": range,
+            }),
+            f,
+        );
+    }
+
+    /// `automatic` is set when it's asked for because of typing.
+    pub fn get_inline_completion(
+        &self,
+        buffer_id: BufferId,
+        position: Position,
+        automatic: bool,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_inline_completion",
+            &json!({
+                "buffer_id": buffer_id,
+                "position": position,
+                "automatic": automatic,
+            }),
+            f,
+        );
+    }
+
+    pub fn resolve_inlay_hint(
+        &self,
+        buffer_id: BufferId,
+        hint: &InlayHint,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "resolve_inlay_hint",
+            &json!({
+                "buffer_id": buffer_id,
+                "hint": hint,
+            }),
+            f,
+        );
+    }
+
+    pub fn get_code_lens(&self, buffer_id: BufferId, f: Box<dyn Callback>) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "get_code_lens",
+            &json!({
+                "buffer_id": buffer_id,
+            }),
+            f,
+        );
+    }
+
+    pub fn resolve_code_lens(
+        &self,
+        buffer_id: BufferId,
+        lens: &CodeLens,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "resolve_code_lens",
+            &json!({
+                "buffer_id": buffer_id,
+                "lens": lens,
+            }),
+            f,
+        );
+    }
+
+    pub fn execute_command(
+        &self,
+        buffer_id: BufferId,
+        command: &lsp_types::Command,
+        f: Box<dyn Callback>,
+    ) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "execute_command",
+            &json!({
+                "buffer_id": buffer_id,
+                "command": command,
+            }),
+            f,
+        );
+    }
+
+    pub fn apply_workspace_edit(&self, edit: &WorkspaceEdit, f: Box<dyn Callback>) {
+        self.peer.lock().as_ref().unwrap().send_rpc_request_async(
+            "apply_workspace_edit",
+            &json!({
+                "edit": edit,
+            }),
+            f,
+        );
+    }
+
+    /// Answers a request a language server sent, with the id it came with.
+    pub fn lsp_response(&self, server: &str, id: &Value, result: Value) {
+        self.peer.lock().as_ref().unwrap().send_rpc_notification(
+            "lsp_response",
+            &json!({
+                "server": server,
+                "id": id,
+                "result": result,
+            }),
+        )
+    }
+
+    /// Tells the language servers the buffers aren't shown anymore.
+    pub fn close_buffers(&self, buffer_ids: Vec<BufferId>) {
+        self.peer.lock().as_ref().unwrap().send_rpc_notification(
+            "close_buffers",
+            &json!({
+                "buffer_ids": buffer_ids,
+            }),
+        )
+    }
+
+    pub fn restart_language_server(&self, buffer_id: BufferId) {
+        self.peer.lock().as_ref().unwrap().send_rpc_notification(
+            "restart_language_server",
+            &json!({
+                "buffer_id": buffer_id,
+            }),
+        )
+    }
+
+    pub fn get_lsp_log(
+        &self,
+        server: Option<&str>,
+        version: Option<u64>,
+        f: Box<dyn Callback>,
+    ) {
+        if let Some(peer) = self.peer.lock().as_ref() {
+            peer.send_rpc_request_async(
+                "get_lsp_log",
+                &json!({
+                    "server": server,
+                    "version": version,
+                }),
+                f,
+            );
         }
     }
+
+    pub fn set_lsp_trace(&self, server: &str, trace: TraceOption) {
+        self.peer.lock().as_ref().unwrap().send_rpc_notification(
+            "set_lsp_trace",
+            &json!({
+                "server": server,
+                "trace": trace,
+            }),
+        )
+    }
+
+    /// Gives the proxy a few seconds to shut its language servers down
+    /// before it's killed.
+    pub fn stop(&self) {
+        let proxy = self.clone();
+        thread::spawn(move || {
+            let (sender, receiver) = bounded(1);
+            if let Some(peer) = proxy.peer.lock().as_ref() {
+                peer.send_rpc_request_async(
+                    "shutdown",
+                    &json!({}),
+                    Box::new(move |result| {
+                        sender.send(result);
+                    }),
+                );
+            }
+            receiver.recv_timeout(Duration::from_secs(3));
+            let mut process = proxy.process.lock();
+            if let Some(mut p) = process.as_mut() {
+                p.kill();
+            }
+        });
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -403,7 +875,7 @@ pub enum Notification {
         rev: u64,
         buffer_id: BufferId,
         path: PathBuf,
-        tokens: Vec<(usize, usize, String)>,
+        tokens: Vec<(usize, usize, String, Vec<String>)>,
     },
     UpdateGit {
         buffer_id: BufferId,
@@ -431,6 +903,24 @@ pub enum Notification {
     CloseTerminal {
         term_id: TermId,
     },
+    LspApplyEdit {
+        server: String,
+        id: Value,
+        edit: WorkspaceEdit,
+    },
+    LspShowMessageRequest {
+        server: String,
+        id: Value,
+        params: ShowMessageRequestParams,
+    },
+    LspStatus {
+        server: String,
+        status: LspStatus,
+    },
+    PositionEncoding {
+        path: PathBuf,
+        encoding: PositionEncoding,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -461,7 +951,7 @@ impl Handler for ProxyHandlerNew {
                 self.event_sink.submit_command(
                     LAPCE_UI_COMMAND,
                     LapceUICommand::UpdateSemanticTokens(
-                        buffer_id, path, rev, tokens,
+                        buffer_id, path, rev, None, tokens,
                     ),
                     Target::Widget(self.tab_id),
                 );
@@ -519,6 +1009,34 @@ impl Handler for ProxyHandlerNew {
                     Target::Widget(self.tab_id),
                 );
             }
+            Notification::LspApplyEdit { server, id, edit } => {
+                self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::ApplyServerEdit(server, id, edit),
+                    Target::Widget(self.tab_id),
+                );
+            }
+            Notification::LspShowMessageRequest { server, id, params } => {
+                self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::ShowMessageRequest(server, id, params),
+                    Target::Widget(self.tab_id),
+                );
+            }
+            Notification::LspStatus { server, status } => {
+                self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::UpdateLspStatus(server, status),
+                    Target::Widget(self.tab_id),
+                );
+            }
+            Notification::PositionEncoding { path, encoding } => {
+                self.event_sink.submit_command(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::UpdatePositionEncoding(path, encoding),
+                    Target::Widget(self.tab_id),
+                );
+            }
         }
     }
 
@@ -530,3 +1048,15 @@ impl Handler for ProxyHandlerNew {
         Err(xi_rpc::RemoteError::InvalidRequest(None))
     }
 }
+
+/// What went wrong with a request to the proxy, as said by whoever failed:
+/// the proxy or the language server behind it.
+pub fn error_message(err: &xi_rpc::Error) -> String {
+    match err {
+        xi_rpc::Error::RemoteError(xi_rpc::RemoteError::Custom {
+            message, ..
+        }) => message.clone(),
+        xi_rpc::Error::PeerDisconnect => "the proxy is gone".to_string(),
+        err => format!("{:?}", err),
+    }
+}
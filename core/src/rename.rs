@@ -0,0 +1,343 @@
+use std::{path::PathBuf, sync::Arc};
+
+use druid::{
+    kurbo::Line,
+    piet::{Text, TextLayout as TextLayoutTrait, TextLayoutBuilder},
+    BoxConstraints, Command, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle,
+    LifeCycleCtx, PaintCtx, Point, RenderContext, Size, Target, UpdateCtx, Widget,
+};
+use lsp_types::{Position, WorkspaceEdit};
+
+use crate::{
+    command::{LapceCommand, LapceUICommand, LAPCE_UI_COMMAND},
+    config::LapceTheme,
+    data::{LapceMainSplitData, LapceTabData},
+    keypress::KeyPressFocus,
+    proxy::LapceProxy,
+    state::Mode,
+};
+
+pub const RENAME_PADDING: f64 = 5.0;
+
+#[derive(Clone)]
+pub struct RenameData {
+    pub active: bool,
+    pub path: PathBuf,
+    pub rev: u64,
+    pub start: usize,
+    pub position: Position,
+    pub input: String,
+    pub cursor: usize,
+}
+
+impl RenameData {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            path: PathBuf::new(),
+            rev: 0,
+            start: 0,
+            position: Position::new(0, 0),
+            input: "".to_string(),
+            cursor: 0,
+        }
+    }
+
+    /// `start` is where the symbol being renamed begins, which is where the
+    /// input is drawn; `position` is what gets sent with the rename request.
+    pub fn activate(
+        &mut self,
+        path: PathBuf,
+        rev: u64,
+        start: usize,
+        position: Position,
+        placeholder: String,
+    ) {
+        self.active = true;
+        self.path = path;
+        self.rev = rev;
+        self.start = start;
+        self.position = position;
+        self.cursor = placeholder.len();
+        self.input = placeholder;
+    }
+}
+
+#[derive(Clone, Data)]
+pub struct RenameViewData {
+    pub rename: Arc<RenameData>,
+    pub main_split: LapceMainSplitData,
+    pub proxy: Arc<LapceProxy>,
+}
+
+impl KeyPressFocus for RenameViewData {
+    fn get_mode(&self) -> Mode {
+        Mode::Insert
+    }
+
+    fn check_condition(&self, condition: &str) -> bool {
+        match condition {
+            "rename_focus" => true,
+            _ => false,
+        }
+    }
+
+    fn run_command(
+        &mut self,
+        ctx: &mut EventCtx,
+        command: &LapceCommand,
+        count: Option<usize>,
+        env: &Env,
+    ) {
+        match command {
+            LapceCommand::RenameCancel => {
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::CancelRename,
+                    Target::Auto,
+                ));
+            }
+            LapceCommand::RenameConfirm => {
+                self.confirm(ctx);
+                ctx.submit_command(Command::new(
+                    LAPCE_UI_COMMAND,
+                    LapceUICommand::CancelRename,
+                    Target::Auto,
+                ));
+            }
+            LapceCommand::DeleteBackward => {
+                let rename = Arc::make_mut(&mut self.rename);
+                if let Some(c) = rename.input[..rename.cursor].chars().last() {
+                    rename.cursor -= c.len_utf8();
+                    rename.input.remove(rename.cursor);
+                }
+            }
+            LapceCommand::DeleteToBeginningOfLine => {
+                let rename = Arc::make_mut(&mut self.rename);
+                rename.input.replace_range(..rename.cursor, "");
+                rename.cursor = 0;
+            }
+            LapceCommand::Left => {
+                let rename = Arc::make_mut(&mut self.rename);
+                if let Some(c) = rename.input[..rename.cursor].chars().last() {
+                    rename.cursor -= c.len_utf8();
+                }
+            }
+            LapceCommand::Right => {
+                let rename = Arc::make_mut(&mut self.rename);
+                if let Some(c) = rename.input[rename.cursor..].chars().next() {
+                    rename.cursor += c.len_utf8();
+                }
+            }
+            LapceCommand::LineStart => {
+                Arc::make_mut(&mut self.rename).cursor = 0;
+            }
+            LapceCommand::LineEnd => {
+                let rename = Arc::make_mut(&mut self.rename);
+                rename.cursor = rename.input.len();
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_char(&mut self, ctx: &mut EventCtx, c: &str) {
+        let rename = Arc::make_mut(&mut self.rename);
+        rename.input.insert_str(rename.cursor, c);
+        rename.cursor += c.len();
+    }
+}
+
+impl RenameViewData {
+    fn confirm(&self, ctx: &mut EventCtx) {
+        if self.rename.input == "" {
+            return;
+        }
+        let buffer = match self.main_split.open_files.get(&self.rename.path) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        if buffer.rev != self.rename.rev {
+            return;
+        }
+
+        let tab_id = self.proxy.tab_id;
+        let event_sink = ctx.get_external_handle();
+        self.proxy.rename(
+            buffer.id,
+            self.rename.position,
+            &self.rename.input,
+            Box::new(move |result| {
+                if let Ok(res) = result {
+                    if let Ok(Some(edit)) =
+                        serde_json::from_value::<Option<WorkspaceEdit>>(res)
+                    {
+                        event_sink.submit_command(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::ApplyWorkspaceEdit(edit),
+                            Target::Widget(tab_id),
+                        );
+                    }
+                }
+            }),
+        );
+    }
+}
+
+pub struct Rename {}
+
+impl Rename {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Widget<LapceTabData> for Rename {
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::KeyDown(key_event) => {
+                let mut keypress = data.keypress.clone();
+                let mut_keypress = Arc::make_mut(&mut keypress);
+                let mut rename_data = RenameViewData {
+                    rename: data.rename.clone(),
+                    main_split: data.main_split.clone(),
+                    proxy: data.proxy.clone(),
+                };
+                mut_keypress.key_down(ctx, key_event, &mut rename_data, env);
+                data.keypress = keypress;
+                data.rename = rename_data.rename.clone();
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                match command {
+                    LapceUICommand::ShowRename => {
+                        ctx.request_focus();
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::CancelRename => {
+                        Arc::make_mut(&mut data.rename).active = false;
+                        if ctx.has_focus() {
+                            ctx.resign_focus();
+                            ctx.submit_command(Command::new(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::FocusEditor,
+                                Target::Auto,
+                            ));
+                        }
+                        ctx.set_handled();
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            LifeCycle::FocusChanged(false) => {
+                if data.rename.active {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::CancelRename,
+                        Target::Auto,
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        let old_editor = old_data.main_split.active_editor();
+        let editor = data.main_split.active_editor();
+
+        if !old_data.rename.same(&data.rename)
+            || editor.window_origin != old_editor.window_origin
+        {
+            ctx.request_layout();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        let line_height = data.config.editor.line_height as f64;
+        let text_width = data
+            .config
+            .editor_text_width(ctx.text(), &format!("{}W", data.rename.input));
+        Size::new(text_width + RENAME_PADDING * 2.0, line_height)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        if !data.rename.active {
+            return;
+        }
+
+        let rect = ctx.size().to_rect();
+        let shadow_width = 5.0;
+        ctx.blurred_rect(
+            rect,
+            shadow_width,
+            data.config
+                .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+        );
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::EDITOR_BACKGROUND),
+        );
+        ctx.stroke(
+            rect.inset(-0.5),
+            data.config.get_color_unchecked(LapceTheme::EDITOR_CARET),
+            1.0,
+        );
+
+        let text_layout = ctx
+            .text()
+            .new_text_layout(data.rename.input.clone())
+            .font(
+                data.config.editor.font_family(),
+                data.config.editor.font_size as f64,
+            )
+            .text_color(
+                data.config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            )
+            .build()
+            .unwrap();
+        let y = (rect.height() - text_layout.size().height) / 2.0;
+        let origin = Point::new(RENAME_PADDING, y);
+        ctx.draw_text(&text_layout, origin);
+
+        let line = text_layout.cursor_line_for_text_position(data.rename.cursor);
+        ctx.stroke(
+            Line::new(line.p0 + origin.to_vec2(), line.p1 + origin.to_vec2()),
+            data.config.get_color_unchecked(LapceTheme::EDITOR_CARET),
+            1.0,
+        );
+    }
+}
@@ -1,62 +1,473 @@
-use lsp_types::ParameterLabel;
-use lsp_types::SignatureHelp;
-use tree_sitter::Node;
+use std::{path::PathBuf, sync::Arc};
 
-use crate::command::LapceUICommand;
+use druid::{
+    piet::{
+        PietText, PietTextLayout, Text, TextAttribute,
+        TextLayout as TextLayoutTrait, TextLayoutBuilder,
+    },
+    BoxConstraints, Data, Env, Event, EventCtx, FontFamily, FontWeight, LayoutCtx,
+    LifeCycle, LifeCycleCtx, PaintCtx, Point, RenderContext, Size, UpdateCtx,
+    Widget, WidgetId,
+};
+use lsp_types::{
+    Documentation, ParameterLabel, SignatureHelp, SignatureHelpContext,
+    SignatureHelpTriggerKind, SignatureInformation,
+};
+
+use crate::{
+    buffer::BufferId,
+    command::{LapceUICommand, LAPCE_UI_COMMAND},
+    config::{Config, LapceTheme},
+    data::{EditorContent, LapceTabData},
+};
+
+pub const SIGNATURE_PADDING: f64 = 5.0;
+/// How wide the documentation under the signature is allowed to get.
+const SIGNATURE_DOC_WIDTH: f64 = 600.0;
+/// How many lines of documentation are shown at most.
+const SIGNATURE_DOC_LINES: usize = 10;
 
 #[derive(Clone)]
 pub struct SignatureState {
-    pub offset: Option<usize>,
+    pub id: WidgetId,
+    pub request_id: usize,
+    /// The newest request an answer was taken from. Answers can arrive out
+    /// of order, and the ones for requests older than this are dropped.
+    received_id: usize,
+    pub buffer_id: BufferId,
+    pub path: PathBuf,
+    /// The revision and cursor of the last request, to tell when the help
+    /// that's showing has to be asked for again.
+    pub rev: u64,
+    pub cursor: usize,
+    /// Where the arguments of the call start. The popup is drawn there.
+    pub offset: usize,
     pub signature: Option<SignatureHelp>,
-    pub active: Option<(usize, usize)>,
+    pub active_signature: usize,
 }
 
 impl SignatureState {
     pub fn new() -> Self {
         Self {
-            offset: None,
+            id: WidgetId::next(),
+            request_id: 0,
+            received_id: 0,
+            buffer_id: BufferId(0),
+            path: PathBuf::new(),
+            rev: 0,
+            cursor: 0,
+            offset: 0,
             signature: None,
-            active: None,
+            active_signature: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.signature.is_some()
+    }
+
+    /// The context to send with a request, typed characters being passed
+    /// as trigger characters for the proxy to check against the server's.
+    pub fn context(&self, c: Option<&str>) -> SignatureHelpContext {
+        let active_signature_help = self.signature.as_ref().map(|help| {
+            let mut help = help.clone();
+            help.active_signature = Some(self.active_signature as u32);
+            help
+        });
+        SignatureHelpContext {
+            trigger_kind: if c.is_some() {
+                SignatureHelpTriggerKind::TriggerCharacter
+            } else if self.is_active() {
+                SignatureHelpTriggerKind::ContentChange
+            } else {
+                SignatureHelpTriggerKind::Invoked
+            },
+            trigger_character: c.map(|c| c.to_string()),
+            is_retrigger: self.is_active(),
+            active_signature_help,
+        }
+    }
+
+    pub fn receive(
+        &mut self,
+        request_id: usize,
+        offset: usize,
+        signature: Option<SignatureHelp>,
+    ) {
+        if request_id <= self.received_id {
+            return;
+        }
+        self.received_id = request_id;
+        match signature {
+            Some(signature) if signature.signatures.len() > 0 => {
+                let active = signature.active_signature.unwrap_or(0) as usize;
+                self.active_signature = if active < signature.signatures.len() {
+                    active
+                } else {
+                    0
+                };
+                self.offset = offset;
+                self.signature = Some(signature);
+            }
+            _ => {
+                self.signature = None;
+            }
+        }
+    }
+
+    pub fn next_signature(&mut self) {
+        if let Some(signature) = self.signature.as_ref() {
+            self.active_signature =
+                (self.active_signature + 1) % signature.signatures.len();
         }
     }
 
-    pub fn update(&mut self, offset: usize, commas: Vec<usize>) -> Option<bool> {
+    pub fn previous_signature(&mut self) {
+        if let Some(signature) = self.signature.as_ref() {
+            let len = signature.signatures.len();
+            self.active_signature = (self.active_signature + len - 1) % len;
+        }
+    }
+
+    pub fn active(&self) -> Option<&SignatureInformation> {
+        self.signature
+            .as_ref()?
+            .signatures
+            .get(self.active_signature)
+    }
+
+    /// The byte range of the active parameter in the label of the active
+    /// signature.
+    pub fn active_parameter(&self) -> Option<(usize, usize)> {
         let signature = self.signature.as_ref()?;
-        let params = signature.signatures[0].parameters.as_ref()?;
-        if params.len() == 0 {
-            return None;
-        }
-        let mut index = commas.len();
-        for (i, c) in commas.iter().enumerate() {
-            if offset <= *c {
-                index = i;
-                break;
-            }
-        }
-
-        let label = signature.signatures[0].label.clone();
-        let active = if index >= params.len() {
-            None
-        } else {
-            match &params[index].label {
-                ParameterLabel::Simple(s) => {
-                    let start = label.find(s)?;
-                    Some((start, start + s.len()))
-                }
-                ParameterLabel::LabelOffsets(offsets) => {
-                    Some((offsets[0] as usize, offsets[1] as usize))
-                }
+        let info = self.active()?;
+        let index = info
+            .active_parameter
+            .or(signature.active_parameter)
+            .unwrap_or(0) as usize;
+        let param = info.parameters.as_ref()?.get(index)?;
+        match &param.label {
+            ParameterLabel::Simple(s) => {
+                // Skip the name, which a parameter can share.
+                let start = info.label.find('(').map(|i| i + 1).unwrap_or(0);
+                let i = info.label[start..].find(s.as_str())? + start;
+                Some((i, i + s.len()))
             }
-        };
+            ParameterLabel::LabelOffsets(offsets) => Some((
+                utf16_to_byte_offset(&info.label, offsets[0] as usize),
+                utf16_to_byte_offset(&info.label, offsets[1] as usize),
+            )),
+        }
+    }
 
-        let changed = self.active != active;
-        self.active = active;
-        Some(changed)
+    /// The documentation of the active parameter followed by the one of the
+    /// active signature.
+    pub fn documentation(&self) -> String {
+        let info = match self.active() {
+            Some(info) => info,
+            None => return "".to_string(),
+        };
+        let signature = self.signature.as_ref().unwrap();
+        let index = info
+            .active_parameter
+            .or(signature.active_parameter)
+            .unwrap_or(0) as usize;
+        let mut docs = Vec::new();
+        if let Some(doc) = info
+            .parameters
+            .as_ref()
+            .and_then(|params| params.get(index))
+            .and_then(|param| param.documentation.as_ref())
+        {
+            docs.push(documentation_text(doc));
+        }
+        if let Some(doc) = info.documentation.as_ref() {
+            docs.push(documentation_text(doc));
+        }
+        docs.retain(|doc| doc.trim() != "");
+        docs.join("\n\n")
+            .lines()
+            .take(SIGNATURE_DOC_LINES)
+            .collect::<Vec<&str>>()
+            .join("\n")
     }
 
     pub fn clear(&mut self) {
-        self.offset = None;
         self.signature = None;
-        self.active = None;
+        self.received_id = self.request_id;
+    }
+}
+
+pub(crate) fn documentation_text(doc: &Documentation) -> String {
+    match doc {
+        Documentation::String(s) => s.clone(),
+        Documentation::MarkupContent(content) => content.value.clone(),
+    }
+}
+
+/// Label offsets are counted in UTF-16 code units.
+fn utf16_to_byte_offset(s: &str, offset: usize) -> usize {
+    let mut utf16 = 0;
+    for (i, c) in s.char_indices() {
+        if utf16 >= offset {
+            return i;
+        }
+        utf16 += c.len_utf16();
+    }
+    s.len()
+}
+
+/// Whether the signature help is for the buffer of the active editor.
+fn is_shown(data: &LapceTabData) -> bool {
+    if !data.signature.is_active() {
+        return false;
+    }
+    match &data.main_split.active_editor().content {
+        EditorContent::Buffer(path) => path == &data.signature.path,
+        EditorContent::None => false,
+    }
+}
+
+pub struct Signature {
+    id: WidgetId,
+    label: Option<PietTextLayout>,
+    counter: Option<PietTextLayout>,
+    doc: Option<PietTextLayout>,
+}
+
+impl Signature {
+    pub fn new(data: &LapceTabData) -> Self {
+        Self {
+            id: data.signature.id,
+            label: None,
+            counter: None,
+            doc: None,
+        }
+    }
+
+    fn build_layouts(&mut self, text: &mut PietText, data: &LapceTabData) {
+        self.label = None;
+        self.counter = None;
+        self.doc = None;
+        let config: &Config = &data.config;
+        let info = match data.signature.active() {
+            Some(info) => info,
+            None => return,
+        };
+
+        let len = data.signature.signature.as_ref().unwrap().signatures.len();
+        if len > 1 {
+            self.counter = Some(
+                text.new_text_layout(format!(
+                    "{}/{}",
+                    data.signature.active_signature + 1,
+                    len
+                ))
+                .font(config.editor.font_family(), config.editor.font_size as f64)
+                .text_color(
+                    config.get_color_unchecked(LapceTheme::EDITOR_DIM).clone(),
+                )
+                .build()
+                .unwrap(),
+            );
+        }
+
+        let mut label = text
+            .new_text_layout(info.label.clone())
+            .font(config.editor.font_family(), config.editor.font_size as f64)
+            .text_color(
+                config
+                    .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                    .clone(),
+            );
+        if let Some((start, end)) = data.signature.active_parameter() {
+            if start < end && end <= info.label.len() {
+                label = label
+                    .range_attribute(
+                        start..end,
+                        TextAttribute::Weight(FontWeight::BOLD),
+                    )
+                    .range_attribute(start..end, TextAttribute::Underline(true))
+                    .range_attribute(
+                        start..end,
+                        TextAttribute::TextColor(
+                            config
+                                .get_color_unchecked(LapceTheme::EDITOR_CARET)
+                                .clone(),
+                        ),
+                    );
+            }
+        }
+        self.label = Some(label.build().unwrap());
+
+        let doc = data.signature.documentation();
+        if doc != "" {
+            self.doc = Some(
+                text.new_text_layout(doc)
+                    .font(FontFamily::SYSTEM_UI, 13.0)
+                    .text_color(
+                        config
+                            .get_color_unchecked(LapceTheme::EDITOR_FOREGROUND)
+                            .clone(),
+                    )
+                    .max_width(SIGNATURE_DOC_WIDTH - SIGNATURE_PADDING * 2.0)
+                    .build()
+                    .unwrap(),
+            );
+        }
+    }
+
+    fn counter_width(&self) -> f64 {
+        self.counter
+            .as_ref()
+            .map(|counter| counter.size().width + SIGNATURE_PADDING * 2.0)
+            .unwrap_or(0.0)
+    }
+}
+
+impl Widget<LapceTabData> for Signature {
+    fn id(&self) -> Option<WidgetId> {
+        Some(self.id)
+    }
+
+    fn event(
+        &mut self,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut LapceTabData,
+        env: &Env,
+    ) {
+        match event {
+            Event::MouseDown(mouse_event) => {
+                if is_shown(data) && ctx.size().to_rect().contains(mouse_event.pos) {
+                    Arc::make_mut(&mut data.signature).next_signature();
+                    ctx.set_handled();
+                }
+            }
+            Event::Command(cmd) if cmd.is(LAPCE_UI_COMMAND) => {
+                let command = cmd.get_unchecked(LAPCE_UI_COMMAND);
+                match command {
+                    LapceUICommand::UpdateSignature(
+                        request_id,
+                        offset,
+                        signature,
+                    ) => {
+                        Arc::make_mut(&mut data.signature).receive(
+                            *request_id,
+                            *offset,
+                            signature.clone(),
+                        );
+                        ctx.set_handled();
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old_data: &LapceTabData,
+        data: &LapceTabData,
+        env: &Env,
+    ) {
+        let old_editor = old_data.main_split.active_editor();
+        let editor = data.main_split.active_editor();
+        if !old_data.signature.same(&data.signature)
+            || editor.window_origin != old_editor.window_origin
+            || !old_data.config.same(&data.config)
+        {
+            ctx.request_layout();
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        data: &LapceTabData,
+        env: &Env,
+    ) -> Size {
+        if !is_shown(data) {
+            self.label = None;
+            self.counter = None;
+            self.doc = None;
+            return Size::ZERO;
+        }
+        self.build_layouts(ctx.text(), data);
+        let line_height = data.config.editor.line_height as f64;
+        let label_width = self
+            .label
+            .as_ref()
+            .map(|label| label.size().width)
+            .unwrap_or(0.0);
+        let mut width = self.counter_width() + label_width;
+        let mut height = line_height;
+        if let Some(doc) = self.doc.as_ref() {
+            width = width.max(doc.size().width);
+            height += doc.size().height + SIGNATURE_PADDING * 2.0;
+        }
+        let width = (width + SIGNATURE_PADDING * 2.0).min(bc.max().width);
+        Size::new(width, height)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &LapceTabData, env: &Env) {
+        if !is_shown(data) {
+            return;
+        }
+        let line_height = data.config.editor.line_height as f64;
+        let rect = ctx.size().to_rect();
+        let shadow_width = 5.0;
+        ctx.blurred_rect(
+            rect,
+            shadow_width,
+            data.config
+                .get_color_unchecked(LapceTheme::LAPCE_DROPDOWN_SHADOW),
+        );
+        ctx.fill(
+            rect,
+            data.config
+                .get_color_unchecked(LapceTheme::COMPLETION_BACKGROUND),
+        );
+
+        ctx.with_save(|ctx| {
+            ctx.clip(rect);
+            let mut x = SIGNATURE_PADDING;
+            if let Some(counter) = self.counter.as_ref() {
+                let y = (line_height - counter.size().height) / 2.0;
+                ctx.draw_text(counter, Point::new(x, y));
+                x += self.counter_width();
+            }
+            if let Some(label) = self.label.as_ref() {
+                let y = (line_height - label.size().height) / 2.0;
+                ctx.draw_text(label, Point::new(x, y));
+            }
+            if let Some(doc) = self.doc.as_ref() {
+                ctx.stroke(
+                    druid::kurbo::Line::new(
+                        Point::new(0.0, line_height + 0.5),
+                        Point::new(rect.width(), line_height + 0.5),
+                    ),
+                    data.config.get_color_unchecked(LapceTheme::LAPCE_BORDER),
+                    1.0,
+                );
+                ctx.draw_text(
+                    doc,
+                    Point::new(SIGNATURE_PADDING, line_height + SIGNATURE_PADDING),
+                );
+            }
+        });
     }
 }
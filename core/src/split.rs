@@ -164,7 +164,13 @@ impl LapceSplitNew {
         if self.children.len() == 1 {
             let view_id = self.children[0].widget.id();
             let editor = data.main_split.editors.get_mut(&view_id).unwrap();
-            Arc::make_mut(editor).content = EditorContent::None;
+            let content = std::mem::replace(
+                &mut Arc::make_mut(editor).content,
+                EditorContent::None,
+            );
+            if let EditorContent::Buffer(path) = content {
+                data.main_split.close_buffer_if_unused(ctx, &path);
+            }
             return;
         }
 
@@ -189,10 +195,15 @@ impl LapceSplitNew {
             data.focus = new_editor.view_id;
             ctx.set_focus(new_editor.view_id);
         }
-        data.main_split.editors.remove(&view_id);
+        let editor = data.main_split.editors.remove(&view_id);
         self.children.remove(index);
         self.children_ids.remove(index);
         data.main_split.editors_order = Arc::new(self.children_ids.clone());
+        if let Some(EditorContent::Buffer(path)) =
+            editor.as_ref().map(|editor| &editor.content)
+        {
+            data.main_split.close_buffer_if_unused(ctx, path);
+        }
 
         self.even_flex_children();
         ctx.children_changed();
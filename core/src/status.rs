@@ -5,9 +5,10 @@ use druid::theme;
 use druid::Color;
 use druid::Vec2;
 use druid::{
-    kurbo::Line, Event, FontDescriptor, FontFamily, Point, RenderContext, Size,
-    Widget, WidgetId, WindowId,
+    kurbo::Line, Command, Event, FontDescriptor, FontFamily, Point, Rect,
+    RenderContext, Size, Target, Widget, WidgetId, WindowId,
 };
+use lapce_proxy::lsp::LspStatus;
 use lsp_types::DiagnosticSeverity;
 
 use crate::command::{LapceUICommand, LAPCE_UI_COMMAND};
@@ -17,11 +18,17 @@ use crate::data::LapceTabData;
 use crate::state::Mode;
 use crate::theme::OldLapceTheme;
 
-pub struct LapceStatusNew {}
+pub struct LapceStatusNew {
+    diagnostics_rect: Rect,
+    error_rect: Rect,
+}
 
 impl LapceStatusNew {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            diagnostics_rect: Rect::ZERO,
+            error_rect: Rect::ZERO,
+        }
     }
 }
 
@@ -33,6 +40,32 @@ impl Widget<LapceTabData> for LapceStatusNew {
         data: &mut LapceTabData,
         env: &druid::Env,
     ) {
+        match event {
+            Event::MouseMove(mouse_event) => {
+                if self.diagnostics_rect.contains(mouse_event.pos)
+                    || self.error_rect.contains(mouse_event.pos)
+                {
+                    ctx.set_cursor(&druid::Cursor::Pointer);
+                } else {
+                    ctx.clear_cursor();
+                }
+            }
+            Event::MouseDown(mouse_event) => {
+                if self.diagnostics_rect.contains(mouse_event.pos) {
+                    ctx.submit_command(Command::new(
+                        LAPCE_UI_COMMAND,
+                        LapceUICommand::FocusProblem,
+                        Target::Widget(data.id),
+                    ));
+                    ctx.set_handled();
+                } else if self.error_rect.contains(mouse_event.pos) {
+                    // clicking the error dismisses it
+                    data.error_message = None;
+                    ctx.set_handled();
+                }
+            }
+            _ => (),
+        }
     }
 
     fn lifecycle(
@@ -64,6 +97,13 @@ impl Widget<LapceTabData> for LapceStatusNew {
             ctx.request_paint();
             return;
         }
+
+        if old_data.lsp_status != data.lsp_status
+            || old_data.error_message != data.error_message
+        {
+            ctx.request_paint();
+            return;
+        }
     }
 
     fn layout(
@@ -151,6 +191,50 @@ impl Widget<LapceTabData> for LapceStatusNew {
             .build()
             .unwrap();
         ctx.draw_text(&text_layout, Point::new(left + 10.0, 4.0));
+        self.diagnostics_rect =
+            Size::new(text_layout.size().width + 20.0, size.height)
+                .to_rect()
+                .with_origin(Point::new(left, 0.0));
         left += 10.0 + text_layout.size().width;
+
+        let mut servers: Vec<&String> = data.lsp_status.keys().collect();
+        servers.sort();
+        for server in servers {
+            let (status, color) = match data.lsp_status[server] {
+                LspStatus::Starting => ("starting", LapceTheme::EDITOR_DIM),
+                LspStatus::Running => ("running", LapceTheme::EDITOR_DIM),
+                LspStatus::Crashed => ("crashed", LapceTheme::LAPCE_ERROR),
+                LspStatus::Stopped => ("stopped", LapceTheme::EDITOR_DIM),
+            };
+            let text_layout = ctx
+                .text()
+                .new_text_layout(format!("{}: {}", server, status))
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(data.config.get_color_unchecked(color).clone())
+                .build()
+                .unwrap();
+            ctx.draw_text(&text_layout, Point::new(left + 10.0, 4.0));
+            left += 10.0 + text_layout.size().width;
+        }
+
+        self.error_rect = Rect::ZERO;
+        if let Some(message) = data.error_message.as_ref() {
+            let text_layout = ctx
+                .text()
+                .new_text_layout(message.to_string())
+                .font(FontFamily::SYSTEM_UI, 13.0)
+                .text_color(
+                    data.config
+                        .get_color_unchecked(LapceTheme::LAPCE_ERROR)
+                        .clone(),
+                )
+                .build()
+                .unwrap();
+            ctx.draw_text(&text_layout, Point::new(left + 10.0, 4.0));
+            self.error_rect =
+                Size::new(text_layout.size().width + 20.0, size.height)
+                    .to_rect()
+                    .with_origin(Point::new(left, 0.0));
+        }
     }
 }
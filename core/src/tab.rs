@@ -9,11 +9,15 @@ use druid::{
     Point, Rect, RenderContext, Size, Target, Vec2, Widget, WidgetExt, WidgetId,
     WidgetPod, WindowConfig,
 };
-use lsp_types::{CallHierarchyOptions, DiagnosticSeverity};
+use lsp_types::{CallHierarchyOptions, DiagnosticSeverity, PrepareRenameResponse};
+use serde_json::{json, Value};
 
 use crate::{
-    buffer::{BufferId, BufferNew, BufferState, BufferUpdate, UpdateEvent},
+    buffer::{
+        BufferId, BufferNew, BufferState, BufferUpdate, EditType, UpdateEvent,
+    },
     code_action::CodeAction,
+    code_lens::resolve_code_lens,
     command::{
         LapceCommand, LapceUICommand, LAPCE_COMMAND, LAPCE_NEW_COMMAND,
         LAPCE_UI_COMMAND,
@@ -25,10 +29,19 @@ use crate::{
         LapceTabData,
     },
     editor::{EditorLocationNew, LapceEditorView},
+    hierarchy::HierarchyPanel,
+    inline_completion::InlineCompletion,
+    local_completion::line_suggestion,
+    lsp_log::LspLogPanel,
     movement::{self, CursorMode, Selection},
     palette::{NewPalette, PaletteViewLens},
     panel::{PanelPosition, PanelResizePosition},
+    peek::Peek,
+    problem::ProblemPanel,
+    proxy::error_message,
+    rename::Rename,
     scroll::LapceScrollNew,
+    signature::Signature,
     source_control::SourceControlNew,
     split::LapceSplitNew,
     state::{LapceWorkspace, LapceWorkspaceType},
@@ -42,6 +55,9 @@ pub struct LapceTabNew {
     completion: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     palette: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     code_action: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
+    rename: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
+    signature: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
+    peek: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     status: WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>,
     panels:
         HashMap<WidgetId, WidgetPod<LapceTabData, Box<dyn Widget<LapceTabData>>>>,
@@ -72,6 +88,9 @@ impl LapceTabNew {
         );
         let status = LapceStatusNew::new();
         let code_action = CodeAction::new();
+        let rename = Rename::new();
+        let signature = Signature::new(&data);
+        let peek = Peek::new(&data);
 
         let mut panels = HashMap::new();
         let source_control = SourceControlNew::new(&data);
@@ -81,12 +100,21 @@ impl LapceTabNew {
         );
         let terminal = TerminalPanel::new(&data);
         panels.insert(data.terminal.widget_id, WidgetPod::new(terminal.boxed()));
+        let problem = ProblemPanel::new(&data);
+        panels.insert(data.problem.widget_id, WidgetPod::new(problem.boxed()));
+        let hierarchy = HierarchyPanel::new(&data);
+        panels.insert(data.hierarchy.widget_id, WidgetPod::new(hierarchy.boxed()));
+        let lsp_log = LspLogPanel::new(&data);
+        panels.insert(data.lsp_log.widget_id, WidgetPod::new(lsp_log.boxed()));
 
         Self {
             id: data.id,
             main_split: WidgetPod::new(main_split.boxed()),
             completion: WidgetPod::new(completion.boxed()),
             code_action: WidgetPod::new(code_action.boxed()),
+            rename: WidgetPod::new(rename.boxed()),
+            signature: WidgetPod::new(signature.boxed()),
+            peek: WidgetPod::new(peek.boxed()),
             palette: WidgetPod::new(palette.boxed()),
             status: WidgetPod::new(status.boxed()),
             panels,
@@ -234,6 +262,19 @@ impl Widget<LapceTabData> for LapceTabNew {
                         let buffer =
                             data.main_split.open_files.get_mut(path).unwrap();
                         Arc::make_mut(buffer).load_content(content);
+                        if data
+                            .hierarchy
+                            .pending_call_sites
+                            .as_ref()
+                            .map(|(p, _)| p == path)
+                            .unwrap_or(false)
+                        {
+                            let (_, ranges) = Arc::make_mut(&mut data.hierarchy)
+                                .pending_call_sites
+                                .take()
+                                .unwrap();
+                            Arc::make_mut(buffer).set_call_sites(&ranges);
+                        }
                         for (view_id, location) in locations {
                             data.main_split.go_to_location(
                                 ctx,
@@ -287,7 +328,7 @@ impl Widget<LapceTabData> for LapceTabNew {
                     }
                     LapceUICommand::PublishDiagnostics(diagnostics) => {
                         let path = PathBuf::from(diagnostics.uri.path());
-                        let diagnostics = diagnostics
+                        let mut diagnostics: Vec<EditorDiagnostic> = diagnostics
                             .diagnostics
                             .iter()
                             .map(|d| EditorDiagnostic {
@@ -295,6 +336,11 @@ impl Widget<LapceTabData> for LapceTabNew {
                                 diagnositc: d.clone(),
                             })
                             .collect();
+                        // Kept in document order, which edits don't change.
+                        diagnostics.sort_by_key(|d| {
+                            let start = d.diagnositc.range.start;
+                            (start.line, start.character)
+                        });
                         data.main_split
                             .diagnostics
                             .insert(path, Arc::new(diagnostics));
@@ -324,6 +370,41 @@ impl Widget<LapceTabData> for LapceTabNew {
                             .document_format_and_save(ctx, path, *rev, result);
                         ctx.set_handled();
                     }
+                    LapceUICommand::DocumentFormat(path, rev, result) => {
+                        data.main_split.document_format(
+                            ctx,
+                            path,
+                            *rev,
+                            result,
+                            EditType::Other,
+                        );
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::FixAllInFile(path, rev, result) => {
+                        match data
+                            .main_split
+                            .fix_all_in_file(ctx, path, *rev, result)
+                        {
+                            Ok((_, 0)) => {}
+                            Ok((applied, left_out)) => {
+                                data.error_message = Some(Arc::new(format!(
+                                    "{} fixes applied, {} left out: they do more than edit the file or overlap another fix",
+                                    applied, left_out
+                                )));
+                            }
+                            Err(err) => {
+                                data.error_message = Some(Arc::new(format!(
+                                    "Fixing all in file failed: {}",
+                                    err
+                                )));
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::OnTypeFormat(path, rev, result) => {
+                        data.main_split.on_type_format(ctx, path, *rev, result);
+                        ctx.set_handled();
+                    }
                     LapceUICommand::BufferSave(path, rev) => {
                         let buffer =
                             data.main_split.open_files.get_mut(path).unwrap();
@@ -468,6 +549,120 @@ impl Widget<LapceTabData> for LapceTabNew {
                             }
                         }
                     }
+                    LapceUICommand::UpdateInlayHints(path, rev, hints) => {
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            if buffer.rev == *rev {
+                                Arc::make_mut(buffer)
+                                    .update_inlay_hints(*rev, hints.clone());
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateInlineCompletion(
+                        path,
+                        rev,
+                        offset,
+                        items,
+                    ) => {
+                        let cursor = data.main_split.active_editor().cursor.offset();
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            if buffer.rev == *rev && cursor == *offset {
+                                let completion = items
+                                    .iter()
+                                    .find_map(|item| {
+                                        InlineCompletion::from_item(
+                                            buffer, *offset, item,
+                                        )
+                                    })
+                                    .or_else(|| {
+                                        let item = line_suggestion(buffer, *offset)?;
+                                        InlineCompletion::from_item(
+                                            buffer, *offset, &item,
+                                        )
+                                    });
+                                Arc::make_mut(buffer)
+                                    .set_inline_completion(*rev, completion);
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateCodeLens(path, rev, lenses) => {
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            if buffer.rev == *rev {
+                                Arc::make_mut(buffer)
+                                    .update_code_lens(*rev, lenses.clone());
+                                for lens in lenses.iter() {
+                                    if lens.command.is_none() {
+                                        resolve_code_lens(
+                                            &data.proxy,
+                                            buffer.id,
+                                            path.clone(),
+                                            *rev,
+                                            lens,
+                                            ctx.get_external_handle(),
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateDocumentHighlights(
+                        path,
+                        rev,
+                        offset,
+                        highlights,
+                    ) => {
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            if buffer.rev == *rev {
+                                let buffer = Arc::make_mut(buffer);
+                                match highlights {
+                                    Some(highlights) => buffer
+                                        .update_document_highlights(
+                                            *rev,
+                                            highlights.clone(),
+                                        ),
+                                    None => {
+                                        buffer.update_word_highlights(*rev, *offset)
+                                    }
+                                }
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ResolveCodeLens(path, rev, lens) => {
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            if buffer.rev == *rev {
+                                Arc::make_mut(buffer)
+                                    .resolve_code_lens(*rev, lens.clone());
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateInlayHint(path, rev, offset, hint) => {
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            if buffer.rev == *rev {
+                                Arc::make_mut(buffer).update_inlay_hint(
+                                    *rev,
+                                    *offset,
+                                    hint.clone(),
+                                );
+                            }
+                        }
+                        ctx.set_handled();
+                    }
                     LapceUICommand::PaletteReferences(offset, locations) => {
                         if *offset == data.main_split.active_editor().cursor.offset()
                         {
@@ -541,7 +736,13 @@ impl Widget<LapceTabData> for LapceTabNew {
                         }
                         ctx.set_handled();
                     }
-                    LapceUICommand::UpdateSemanticTokens(id, path, rev, tokens) => {
+                    LapceUICommand::UpdateSemanticTokens(
+                        id,
+                        path,
+                        rev,
+                        range,
+                        tokens,
+                    ) => {
                         let buffer =
                             data.main_split.open_files.get_mut(path).unwrap();
                         if buffer.rev == *rev {
@@ -557,6 +758,7 @@ impl Widget<LapceTabData> for LapceTabNew {
                                             highlights: buffer.styles.clone(),
                                             semantic_tokens: true,
                                         },
+                                        *range,
                                         tokens.to_owned(),
                                     ),
                                 );
@@ -568,6 +770,193 @@ impl Widget<LapceTabData> for LapceTabNew {
                     | LapceUICommand::CancelCodeActions => {
                         self.code_action.event(ctx, event, data, env);
                     }
+                    LapceUICommand::PrepareRename(path, rev, offset, resp) => {
+                        let editor = data.main_split.active_editor();
+                        let is_current = match &editor.content {
+                            EditorContent::Buffer(current_path) => {
+                                current_path == path
+                                    && editor.cursor.offset() == *offset
+                            }
+                            EditorContent::None => false,
+                        };
+                        let buffer = data.main_split.open_files.get(path);
+                        if let Some(buffer) =
+                            buffer.filter(|b| is_current && b.rev == *rev)
+                        {
+                            let (start, end, placeholder) = match resp {
+                                PrepareRenameResponse::Range(range) => (
+                                    buffer.offset_of_position(&range.start),
+                                    buffer.offset_of_position(&range.end),
+                                    None,
+                                ),
+                                PrepareRenameResponse::RangeWithPlaceholder {
+                                    range,
+                                    placeholder,
+                                } => (
+                                    buffer.offset_of_position(&range.start),
+                                    buffer.offset_of_position(&range.end),
+                                    Some(placeholder.to_string()),
+                                ),
+                                PrepareRenameResponse::DefaultBehavior {
+                                    ..
+                                } => {
+                                    let (start, end) = buffer.select_word(*offset);
+                                    (start, end, None)
+                                }
+                            };
+                            if start < end {
+                                let placeholder = placeholder.unwrap_or_else(|| {
+                                    buffer.slice_to_cow(start..end).to_string()
+                                });
+                                let position = buffer.offset_to_position(*offset);
+                                Arc::make_mut(&mut data.rename).activate(
+                                    path.clone(),
+                                    *rev,
+                                    start,
+                                    position,
+                                    placeholder,
+                                );
+                                ctx.submit_command(Command::new(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::ShowRename,
+                                    Target::Auto,
+                                ));
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ShowPeek(editor_view_id, offset, location) => {
+                        let editor = data.main_split.active_editor();
+                        if *editor_view_id == editor.view_id
+                            && *offset == editor.cursor.offset()
+                        {
+                            if let EditorContent::Buffer(path) = &editor.content {
+                                Arc::make_mut(&mut data.peek).activate(
+                                    *editor_view_id,
+                                    path.clone(),
+                                    *offset,
+                                );
+                                data.main_split.go_to_location(
+                                    ctx,
+                                    data.peek.editor_view_id,
+                                    location.clone(),
+                                    &data.config,
+                                );
+                                ctx.submit_command(Command::new(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::Focus,
+                                    Target::Widget(data.peek.editor_view_id),
+                                ));
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ClosePeek => {
+                        if data.peek.active {
+                            Arc::make_mut(&mut data.peek).active = false;
+                            ctx.submit_command(Command::new(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::Focus,
+                                Target::Widget(data.peek.origin_view_id),
+                            ));
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ShowRename | LapceUICommand::CancelRename => {
+                        self.rename.event(ctx, event, data, env);
+                    }
+                    LapceUICommand::ApplyWorkspaceEdit(edit) => {
+                        data.main_split.apply_workspace_edit(ctx, edit);
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ContinueWorkspaceEdit(rest) => {
+                        data.main_split.continue_workspace_edit(ctx, rest);
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::RunCodeAction(buffer_id, action) => {
+                        data.run_code_action(ctx, *buffer_id, action);
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ApplyCodeAction(buffer_id, action) => {
+                        data.apply_code_action(ctx, *buffer_id, action);
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ShowError(message) => {
+                        data.error_message = Some(Arc::new(message.clone()));
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ApplyServerEdit(server, id, edit) => {
+                        let proxy = data.proxy.clone();
+                        let server = server.clone();
+                        let id = id.clone();
+                        let tab_id = data.id;
+                        let event_sink = ctx.get_external_handle();
+                        data.main_split.apply_workspace_edit_and_then(
+                            ctx,
+                            edit,
+                            Box::new(move |result| {
+                                let response = match result {
+                                    Ok(_) => json!({ "applied": true }),
+                                    Err(e) => {
+                                        let reason = error_message(&e);
+                                        event_sink.submit_command(
+                                            LAPCE_UI_COMMAND,
+                                            LapceUICommand::ShowError(format!(
+                                                "The edit from {} could not be applied: {}",
+                                                server, reason
+                                            )),
+                                            Target::Widget(tab_id),
+                                        );
+                                        json!({
+                                            "applied": false,
+                                            "failureReason": reason,
+                                        })
+                                    }
+                                };
+                                proxy.lsp_response(&server, &id, response);
+                            }),
+                        );
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ShowMessageRequest(server, id, params) => {
+                        // Without actions there's nothing to pick, and the
+                        // answer is the same as when the user dismisses it.
+                        if params
+                            .actions
+                            .as_ref()
+                            .map(|actions| actions.is_empty())
+                            .unwrap_or(true)
+                        {
+                            data.proxy.lsp_response(server, id, Value::Null);
+                        } else {
+                            ctx.submit_command(Command::new(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::RunPaletteMessageRequest(
+                                    server.clone(),
+                                    id.clone(),
+                                    params.clone(),
+                                ),
+                                Target::Widget(data.palette.widget_id),
+                            ));
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::CloseBuffers(buffer_ids) => {
+                        data.proxy.close_buffers(buffer_ids.clone());
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateLspStatus(server, status) => {
+                        data.lsp_status.insert(server.clone(), *status);
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdatePositionEncoding(path, encoding) => {
+                        if let Some(buffer) =
+                            data.main_split.open_files.get_mut(path)
+                        {
+                            Arc::make_mut(buffer).position_encoding = *encoding;
+                        }
+                        ctx.set_handled();
+                    }
                     LapceUICommand::Focus => {
                         let dir = data
                             .workspace
@@ -625,6 +1014,135 @@ impl Widget<LapceTabData> for LapceTabNew {
                         }
                         ctx.set_handled();
                     }
+                    LapceUICommand::FocusProblem => {
+                        for (_, panel) in data.panels.iter_mut() {
+                            if panel.widgets.contains(&data.problem.widget_id) {
+                                let panel = Arc::make_mut(panel);
+                                panel.active = data.problem.widget_id;
+                                panel.shown = true;
+                                ctx.submit_command(Command::new(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::Focus,
+                                    Target::Widget(data.problem.widget_id),
+                                ));
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::FocusHierarchy => {
+                        for (_, panel) in data.panels.iter_mut() {
+                            if panel.widgets.contains(&data.hierarchy.widget_id) {
+                                let panel = Arc::make_mut(panel);
+                                panel.active = data.hierarchy.widget_id;
+                                panel.shown = true;
+                                ctx.submit_command(Command::new(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::Focus,
+                                    Target::Widget(data.hierarchy.widget_id),
+                                ));
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::FocusLspLog => {
+                        for (_, panel) in data.panels.iter_mut() {
+                            if panel.widgets.contains(&data.lsp_log.widget_id) {
+                                let panel = Arc::make_mut(panel);
+                                panel.active = data.lsp_log.widget_id;
+                                panel.shown = true;
+                                ctx.submit_command(Command::new(
+                                    LAPCE_UI_COMMAND,
+                                    LapceUICommand::Focus,
+                                    Target::Widget(data.lsp_log.widget_id),
+                                ));
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateLspLog(generation, response) => {
+                        Arc::make_mut(&mut data.lsp_log)
+                            .set_response(*generation, response.clone());
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ShowHierarchy(kind, buffer_id, items) => {
+                        Arc::make_mut(&mut data.hierarchy).set_items(
+                            *kind,
+                            *buffer_id,
+                            items.clone(),
+                            &data.proxy,
+                            ctx.get_external_handle(),
+                        );
+                        ctx.submit_command(Command::new(
+                            LAPCE_UI_COMMAND,
+                            LapceUICommand::FocusHierarchy,
+                            Target::Auto,
+                        ));
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::UpdateHierarchyChildren(
+                        generation,
+                        node,
+                        children,
+                    ) => {
+                        Arc::make_mut(&mut data.hierarchy).set_children(
+                            *generation,
+                            *node,
+                            children.clone(),
+                        );
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::ShowCallSites(path, ranges) => {
+                        for (_, buffer) in data.main_split.open_files.iter_mut() {
+                            if buffer.call_sites.len() > 0 {
+                                Arc::make_mut(buffer).clear_call_sites();
+                            }
+                        }
+                        let hierarchy = Arc::make_mut(&mut data.hierarchy);
+                        hierarchy.pending_call_sites = None;
+                        match data.main_split.open_files.get_mut(path) {
+                            Some(buffer) if buffer.loaded => {
+                                Arc::make_mut(buffer).set_call_sites(ranges);
+                            }
+                            _ => {
+                                hierarchy.pending_call_sites =
+                                    Some((path.clone(), ranges.clone()));
+                            }
+                        }
+                        ctx.set_handled();
+                    }
+                    LapceUICommand::RunInTerminal(cmd) => {
+                        for (_, panel) in data.panels.iter_mut() {
+                            if panel.widgets.contains(&data.terminal.widget_id) {
+                                let panel = Arc::make_mut(panel);
+                                panel.active = data.terminal.widget_id;
+                                panel.shown = true;
+                            }
+                        }
+                        if data.terminal.terminals.len() == 0 {
+                            // run it again once the terminal has been created
+                            ctx.submit_command(Command::new(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::InitTerminalPanel(true),
+                                Target::Widget(data.terminal.split_id),
+                            ));
+                            ctx.submit_command(Command::new(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::RunInTerminal(cmd.clone()),
+                                Target::Widget(data.id),
+                            ));
+                        } else {
+                            data.proxy.terminal_write(
+                                data.terminal.active_term_id,
+                                &format!("{}\r", cmd),
+                            );
+                            ctx.submit_command(Command::new(
+                                LAPCE_UI_COMMAND,
+                                LapceUICommand::Focus,
+                                Target::Widget(data.terminal.active),
+                            ));
+                        }
+                        ctx.set_handled();
+                    }
                     LapceUICommand::FocusEditor => {
                         ctx.submit_command(Command::new(
                             LAPCE_UI_COMMAND,
@@ -653,6 +1171,9 @@ impl Widget<LapceTabData> for LapceTabNew {
         self.palette.event(ctx, event, data, env);
         self.completion.event(ctx, event, data, env);
         self.code_action.event(ctx, event, data, env);
+        self.rename.event(ctx, event, data, env);
+        self.signature.event(ctx, event, data, env);
+        self.peek.event(ctx, event, data, env);
         self.main_split.event(ctx, event, data, env);
         self.status.event(ctx, event, data, env);
         for (_, panel) in data.panels.clone().iter() {
@@ -679,6 +1200,7 @@ impl Widget<LapceTabData> for LapceTabNew {
                         .clone()
                         .map(|w| (*w).clone())
                         .unwrap_or(LapceWorkspace::default()),
+                    data.config.lsp.clone(),
                     ctx.get_external_handle(),
                 );
             }
@@ -688,6 +1210,9 @@ impl Widget<LapceTabData> for LapceTabNew {
         self.palette.lifecycle(ctx, event, data, env);
         self.main_split.lifecycle(ctx, event, data, env);
         self.code_action.lifecycle(ctx, event, data, env);
+        self.rename.lifecycle(ctx, event, data, env);
+        self.signature.lifecycle(ctx, event, data, env);
+        self.peek.lifecycle(ctx, event, data, env);
         self.status.lifecycle(ctx, event, data, env);
         self.completion.lifecycle(ctx, event, data, env);
 
@@ -727,6 +1252,9 @@ impl Widget<LapceTabData> for LapceTabNew {
         self.main_split.update(ctx, data, env);
         self.completion.update(ctx, data, env);
         self.code_action.update(ctx, data, env);
+        self.rename.update(ctx, data, env);
+        self.signature.update(ctx, data, env);
+        self.peek.update(ctx, data, env);
         self.status.update(ctx, data, env);
         for (_, panel) in data.panels.iter() {
             if panel.is_shown() {
@@ -974,6 +1502,10 @@ impl Widget<LapceTabData> for LapceTabNew {
         );
         self.main_split_height = main_split_size.height;
 
+        let peek_origin = data.peek_origin();
+        self.peek.layout(ctx, bc, data, env);
+        self.peek.set_origin(ctx, data, env, peek_origin);
+
         let completion_origin =
             data.completion_origin(ctx.text(), self_size.clone(), &data.config);
         self.completion.layout(ctx, bc, data, env);
@@ -986,6 +1518,19 @@ impl Widget<LapceTabData> for LapceTabNew {
         self.code_action
             .set_origin(ctx, data, env, code_action_origin);
 
+        let rename_origin = data.rename_origin(ctx.text(), &data.config);
+        self.rename.layout(ctx, bc, data, env);
+        self.rename.set_origin(ctx, data, env, rename_origin);
+
+        let signature_size = self.signature.layout(ctx, bc, data, env);
+        let signature_origin = data.signature_origin(
+            ctx.text(),
+            signature_size,
+            self_size.clone(),
+            &data.config,
+        );
+        self.signature.set_origin(ctx, data, env, signature_origin);
+
         let palette_size = self.palette.layout(ctx, bc, data, env);
         self.palette.set_origin(
             ctx,
@@ -1036,9 +1581,12 @@ impl Widget<LapceTabData> for LapceTabNew {
                 }
             }
         }
+        self.peek.paint(ctx, data, env);
         self.status.paint(ctx, data, env);
+        self.signature.paint(ctx, data, env);
         self.completion.paint(ctx, data, env);
         self.code_action.paint(ctx, data, env);
+        self.rename.paint(ctx, data, env);
         self.palette.paint(ctx, data, env);
     }
 }
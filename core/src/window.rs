@@ -193,6 +193,10 @@ impl Widget<LapceWindowData> for LapceWindowNew {
                                 )
                                 .unwrap_or_default(),
                             );
+                            tab.proxy.update_lsp_settings(tab.config.lsp.clone());
+                            if let Some(message) = tab.config.error_message() {
+                                tab.error_message = Some(Arc::new(message));
+                            }
                         }
                         Arc::make_mut(&mut data.keypress).update_keymaps();
                         ctx.set_handled();
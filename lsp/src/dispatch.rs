@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use crate::plugin::PluginId;
-use crate::plugin::{CoreProxy, Plugin};
+use crate::plugin::{CoreProxy, InlineCompletionParams, Plugin};
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use xi_rpc::{Handler, RpcCtx};
@@ -21,7 +21,9 @@ pub enum HostNotification {
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "method", content = "params")]
 /// RPC Request sent from the host
-pub enum HostRequest {}
+pub enum HostRequest {
+    InlineCompletion(InlineCompletionParams),
+}
 
 pub struct Dispatcher<'a, P: 'a + Plugin> {
     plugin: &'a mut P,
@@ -94,7 +96,12 @@ impl<'a, P: Plugin> Handler for Dispatcher<'a, P> {
         ctx: &xi_rpc::RpcCtx,
         rpc: Self::Request,
     ) -> Result<Value, xi_rpc::RemoteError> {
-        Err(xi_rpc::RemoteError::InvalidRequest(None))
+        match rpc {
+            HostRequest::InlineCompletion(params) => {
+                let items = self.plugin.inline_completion(params);
+                Ok(serde_json::to_value(items).unwrap())
+            }
+        }
     }
 
     fn idle(&mut self, ctx: &RpcCtx, token: usize) {
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use languageserver_types::{Position, Range};
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::{json, Value};
@@ -11,6 +12,29 @@ pub struct PluginId(pub usize);
 #[derive(Eq, PartialEq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct BufferId(pub usize);
 
+/// What the host sends to ask for an inline completion. Positions are
+/// counted as the language servers of the buffer count them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InlineCompletionParams {
+    pub buffer_id: BufferId,
+    pub path: String,
+    pub language_id: String,
+    pub position: Position,
+    /// The text before the cursor, or the end of it for a long document.
+    pub prefix: String,
+    /// The text after the cursor, or the start of it for a long document.
+    pub suffix: String,
+}
+
+/// A suggestion the editor shows as ghost text after the cursor.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionItem {
+    pub insert_text: String,
+    /// What accepting it replaces, the cursor position when there's none.
+    pub range: Option<Range>,
+}
+
 #[derive(Clone)]
 pub struct CoreProxy {
     plugin_id: PluginId,
@@ -58,6 +82,18 @@ impl CoreProxy {
         self.peer.send_rpc_notification("show_completion", &params);
     }
 
+    /// Makes the host ask the plugin for inline completions in the
+    /// languages of `language_ids`, or in all of them when it's empty.
+    pub fn register_inline_completion_provider(&mut self, language_ids: &[&str]) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "language_ids": language_ids,
+        });
+
+        self.peer
+            .send_rpc_notification("register_inline_completion_provider", &params);
+    }
+
     pub fn schedule_idle(&mut self, buffer_id: BufferId) {
         let token: usize = buffer_id.0;
         self.peer.schedule_idle(token);
@@ -67,6 +103,15 @@ impl CoreProxy {
 pub trait Plugin {
     fn initialize(&mut self, core: CoreProxy, configuration: Option<Value>);
 
+    /// Suggests what could follow the cursor, the first item being shown.
+    /// Only asked once the plugin registered as a provider.
+    fn inline_completion(
+        &mut self,
+        _params: InlineCompletionParams,
+    ) -> Vec<InlineCompletionItem> {
+        Vec::new()
+    }
+
     //    fn new_buffer(&mut self, buffer: &mut Buffer);
     //
     //    fn update(&mut self, buffer: &mut Buffer, delta: &RopeDelta, rev: u64);
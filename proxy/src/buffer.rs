@@ -11,13 +11,63 @@ use std::{fs, str::FromStr};
 use lsp_types::*;
 use serde::{Deserialize, Deserializer, Serialize};
 use xi_rope::{
-    interval::IntervalBounds, rope::Rope, Cursor, Delta, DeltaBuilder, Interval,
-    LinesMetric, RopeDelta, RopeInfo, Transformer,
+    interval::IntervalBounds, rope::Rope, Cursor, Delta, DeltaBuilder, DeltaElement,
+    Interval, LinesMetric, RopeDelta, RopeInfo, Transformer,
 };
 
 #[derive(Eq, PartialEq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct BufferId(pub usize);
 
+/// What the character of an LSP position counts, agreed on with each server
+/// when it's initialized. Servers that don't say count UTF-16 code units.
+#[derive(Eq, PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum PositionEncoding {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    #[serde(rename = "utf-16")]
+    Utf16,
+    #[serde(rename = "utf-32")]
+    Utf32,
+}
+
+impl Default for PositionEncoding {
+    fn default() -> Self {
+        PositionEncoding::Utf16
+    }
+}
+
+impl PositionEncoding {
+    fn char_len(&self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    /// The length of `s` in the units of the encoding.
+    pub fn len(&self, s: &str) -> usize {
+        match self {
+            PositionEncoding::Utf8 => s.len(),
+            _ => s.chars().map(|c| self.char_len(c)).sum(),
+        }
+    }
+
+    /// The byte offset in `s` of the character `units` units into it. A
+    /// count that ends inside a character goes to the end of it, and one
+    /// past the end of `s` stops there.
+    pub fn byte_offset(&self, s: &str, units: usize) -> usize {
+        let mut count = 0;
+        for (i, c) in s.char_indices() {
+            if count >= units {
+                return i;
+            }
+            count += self.char_len(c);
+        }
+        s.len()
+    }
+}
+
 pub struct Buffer {
     pub language_id: String,
     pub id: BufferId,
@@ -94,24 +144,17 @@ impl Buffer {
         &mut self,
         delta: &RopeDelta,
         rev: u64,
-    ) -> Option<TextDocumentContentChangeEvent> {
+        encoding: PositionEncoding,
+    ) -> Option<Vec<TextDocumentContentChangeEvent>> {
         if self.rev + 1 != rev {
             return None;
         }
         self.rev += 1;
         self.dirty = true;
-        let content_change = get_document_content_changes(delta, self);
+        let content_changes = get_document_content_changes(delta, self, encoding);
         self.rope = delta.apply(&self.rope);
-        let content_change = match content_change {
-            Some(content_change) => content_change,
-            None => TextDocumentContentChangeEvent {
-                range: None,
-                range_length: None,
-                text: self.get_document(),
-            },
-        };
         self.sender.send((self.id, self.rev));
-        Some(content_change)
+        Some(content_changes)
     }
 
     pub fn get_document(&self) -> String {
@@ -131,14 +174,27 @@ impl Buffer {
         (line, offset - self.offset_of_line(line))
     }
 
-    pub fn offset_to_position(&self, offset: usize) -> Position {
-        let (line, col) = self.offset_to_line_col(offset);
+    pub fn offset_to_position(
+        &self,
+        offset: usize,
+        encoding: PositionEncoding,
+    ) -> Position {
+        let line = self.line_of_offset(offset);
+        let line_start = self.offset_of_line(line);
         Position {
             line: line as u32,
-            character: col as u32,
+            character: encoding.len(&self.slice_to_cow(line_start..offset)) as u32,
         }
     }
 
+    pub fn offset_of_position(
+        &self,
+        position: &Position,
+        encoding: PositionEncoding,
+    ) -> usize {
+        offset_of_position(&self.rope, position, encoding)
+    }
+
     pub fn slice_to_cow<T: IntervalBounds>(&self, range: T) -> Cow<str> {
         self.rope.slice_to_cow(range)
     }
@@ -155,61 +211,327 @@ fn load_file(path: &PathBuf) -> Result<Rope> {
     Ok(Rope::from(std::str::from_utf8(&bytes)?))
 }
 
-fn language_id_from_path(path: &PathBuf) -> Option<&str> {
+/// Applies `edits` to a file that has no open buffer, reading it from and
+/// writing it back to disk.
+pub fn apply_edits_to_file(
+    path: &PathBuf,
+    edits: &[TextEdit],
+    encoding: PositionEncoding,
+) -> Result<()> {
+    let rope = load_file(path)?;
+    let mut edits: Vec<(usize, usize, &str)> = edits
+        .iter()
+        .map(|edit| {
+            (
+                offset_of_position(&rope, &edit.range.start, encoding),
+                offset_of_position(&rope, &edit.range.end, encoding),
+                edit.new_text.as_str(),
+            )
+        })
+        .collect();
+    edits.sort_by_key(|(start, end, _)| (*start, *end));
+
+    let mut builder = DeltaBuilder::new(rope.len());
+    for (start, end, text) in edits {
+        builder.replace(start..end, Rope::from(text));
+    }
+    let rope = builder.build().apply(&rope);
+
+    let mut f = File::create(path)?;
+    for chunk in rope.iter_chunks(..rope.len()) {
+        f.write_all(chunk.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// The offset of `position` in `rope`. A character past the end of its line
+/// stops at the line break rather than running into the next line.
+fn offset_of_position(
+    rope: &Rope,
+    position: &Position,
+    encoding: PositionEncoding,
+) -> usize {
+    let last_line = rope.line_of_offset(rope.len());
+    let line = (position.line as usize).min(last_line);
+    let line_start = rope.offset_of_line(line);
+    let line_end = if line < last_line {
+        rope.offset_of_line(line + 1)
+    } else {
+        rope.len()
+    };
+    let content = rope.slice_to_cow(line_start..line_end);
+    let content = content.trim_end_matches(&['\n', '\r'][..]);
+    line_start + encoding.byte_offset(content, position.character as usize)
+}
+
+pub fn language_id_from_path(path: &PathBuf) -> Option<&str> {
     Some(match path.extension()?.to_str()? {
         "rs" => "rust",
         "go" => "go",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "javascriptreact",
+        "ts" => "typescript",
+        "tsx" => "typescriptreact",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" => "cpp",
+        "java" => "java",
+        "rb" => "ruby",
+        "lua" => "lua",
+        "zig" => "zig",
         _ => return None,
     })
 }
 
+/// Turns `delta` into the changes a language server applies to get the same
+/// document, with positions in `buffer` before the edit. The server applies
+/// them one after the other, so they're listed from the end of the document
+/// to the start, where a change never moves the text of the ones after it.
 fn get_document_content_changes(
     delta: &RopeDelta,
     buffer: &Buffer,
-) -> Option<TextDocumentContentChangeEvent> {
-    let (interval, _) = delta.summary();
-    let (start, end) = interval.start_end();
+    encoding: PositionEncoding,
+) -> Vec<TextDocumentContentChangeEvent> {
+    // The edited ranges of the old document, with the text that replaces
+    // them. A deletion right next to an insertion is a single replacement.
+    let mut edits: Vec<(usize, usize, String)> = Vec::new();
+    let mut push = |start: usize, end: usize, text: &str| match edits.last_mut() {
+        Some(last) if last.1 == start => {
+            last.1 = end;
+            last.2.push_str(text);
+        }
+        _ => edits.push((start, end, text.to_string())),
+    };
 
-    // TODO: Handle more trivial cases like typing when there's a selection or transpose
-    if let Some(node) = delta.as_simple_insert() {
-        let text = String::from(node);
+    let mut offset = 0;
+    for el in delta.els.iter() {
+        match el {
+            DeltaElement::Copy(start, end) => {
+                if *start > offset {
+                    push(offset, *start, "");
+                }
+                offset = *end;
+            }
+            DeltaElement::Insert(node) => {
+                push(offset, offset, &String::from(node));
+            }
+        }
+    }
+    if offset < delta.base_len {
+        push(offset, delta.base_len, "");
+    }
 
-        let (start, end) = interval.start_end();
-        let text_document_content_change_event = TextDocumentContentChangeEvent {
+    edits
+        .into_iter()
+        .rev()
+        .map(|(start, end, text)| TextDocumentContentChangeEvent {
             range: Some(Range {
-                start: buffer.offset_to_position(start),
-                end: buffer.offset_to_position(end),
+                start: buffer.offset_to_position(start, encoding),
+                end: buffer.offset_to_position(end, encoding),
             }),
-            range_length: Some((end - start) as u32),
+            range_length: None,
             text,
-        };
+        })
+        .collect()
+}
+
+/// Returns the modification timestamp for the file at a given path,
+/// if present.
+pub fn get_mod_time<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    File::open(path)
+        .and_then(|f| f.metadata())
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        return Some(text_document_content_change_event);
+    fn buffer(text: &str) -> Buffer {
+        let (sender, _) = crossbeam_channel::unbounded();
+        Buffer {
+            language_id: "".to_string(),
+            id: BufferId(0),
+            rope: Rope::from(text),
+            path: PathBuf::from("test.txt"),
+            rev: 0,
+            dirty: false,
+            sender,
+            mod_time: None,
+        }
     }
-    // Or a simple delete
-    else if delta.is_simple_delete() {
-        let mut end_position = buffer.offset_to_position(end);
 
-        let text_document_content_change_event = TextDocumentContentChangeEvent {
+    fn change(
+        start: (u32, u32),
+        end: (u32, u32),
+        text: &str,
+    ) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
             range: Some(Range {
-                start: buffer.offset_to_position(start),
-                end: end_position,
+                start: Position::new(start.0, start.1),
+                end: Position::new(end.0, end.1),
             }),
-            range_length: Some((end - start) as u32),
-            text: String::new(),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    /// The changes of `delta` to `text`, checked to give what the delta
+    /// gives when a server applies them one after the other.
+    fn changes(
+        text: &str,
+        delta: &RopeDelta,
+    ) -> Vec<TextDocumentContentChangeEvent> {
+        let buffer = buffer(text);
+        let changes =
+            get_document_content_changes(delta, &buffer, PositionEncoding::Utf16);
+        let mut rope = buffer.rope.clone();
+        for change in changes.iter() {
+            let range = change.range.unwrap();
+            let start =
+                offset_of_position(&rope, &range.start, PositionEncoding::Utf16);
+            let end = offset_of_position(&rope, &range.end, PositionEncoding::Utf16);
+            rope.edit(start..end, change.text.as_str());
+        }
+        assert_eq!(String::from(rope), String::from(delta.apply(&buffer.rope)));
+        changes
+    }
+
+    #[test]
+    fn test_encoding_len() {
+        // é is 2 bytes and 1 UTF-16 unit, 😀 is 4 bytes and a surrogate pair
+        let s = "aé😀";
+        assert_eq!(PositionEncoding::Utf8.len(s), 7);
+        assert_eq!(PositionEncoding::Utf16.len(s), 4);
+        assert_eq!(PositionEncoding::Utf32.len(s), 3);
+        assert_eq!(PositionEncoding::Utf16.len(""), 0);
+    }
+
+    #[test]
+    fn test_encoding_byte_offset() {
+        let s = "aé😀b";
+        let utf16 = PositionEncoding::Utf16;
+        assert_eq!(utf16.byte_offset(s, 0), 0);
+        assert_eq!(utf16.byte_offset(s, 1), 1);
+        assert_eq!(utf16.byte_offset(s, 2), 3);
+        // inside the surrogate pair goes to the end of it
+        assert_eq!(utf16.byte_offset(s, 3), 7);
+        assert_eq!(utf16.byte_offset(s, 4), 7);
+        assert_eq!(utf16.byte_offset(s, 5), 8);
+        // past the end stops there
+        assert_eq!(utf16.byte_offset(s, 100), 8);
+
+        let utf8 = PositionEncoding::Utf8;
+        assert_eq!(utf8.byte_offset(s, 3), 3);
+        // inside é goes to the end of it
+        assert_eq!(utf8.byte_offset(s, 2), 3);
+        assert_eq!(utf8.byte_offset(s, 5), 7);
+
+        let utf32 = PositionEncoding::Utf32;
+        assert_eq!(utf32.byte_offset(s, 2), 3);
+        assert_eq!(utf32.byte_offset(s, 3), 7);
+        assert_eq!(utf32.byte_offset(s, 4), 8);
+    }
+
+    #[test]
+    fn test_offset_of_position() {
+        let rope = Rope::from(
+            "héllo
+😀 x
+end",
+        );
+        let utf16 = PositionEncoding::Utf16;
+        let offset = |line, character| {
+            offset_of_position(&rope, &Position::new(line, character), utf16)
         };
+        assert_eq!(offset(0, 0), 0);
+        assert_eq!(offset(0, 2), 3);
+        assert_eq!(offset(1, 0), 7);
+        assert_eq!(offset(1, 2), 11);
+        assert_eq!(offset(1, 3), 12);
+        // past the end of a line stops before its line break
+        assert_eq!(offset(0, 50), 6);
+        assert_eq!(offset(1, 50), 13);
+        assert_eq!(offset(2, 50), 18);
+        // past the last line stays on it
+        assert_eq!(offset(9, 1), 16);
 
-        return Some(text_document_content_change_event);
+        let buffer = buffer(
+            "héllo
+😀 x
+end",
+        );
+        for offset in [0, 1, 3, 6, 7, 11, 12, 13, 15, 18] {
+            let position = buffer.offset_to_position(offset, utf16);
+            assert_eq!(buffer.offset_of_position(&position, utf16), offset);
+        }
+        assert_eq!(buffer.offset_to_position(11, utf16), Position::new(1, 2));
+        assert_eq!(
+            buffer.offset_to_position(11, PositionEncoding::Utf8),
+            Position::new(1, 4)
+        );
     }
 
-    None
-}
+    #[test]
+    fn test_content_changes_insert() {
+        let text = "hello\nworld\n";
+        let mut builder = DeltaBuilder::new(text.len());
+        builder.replace(6..6, Rope::from("big "));
+        assert_eq!(
+            changes(text, &builder.build()),
+            vec![change((1, 0), (1, 0), "big ")]
+        );
+    }
 
-/// Returns the modification timestamp for the file at a given path,
-/// if present.
-pub fn get_mod_time<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
-    File::open(path)
-        .and_then(|f| f.metadata())
-        .and_then(|meta| meta.modified())
-        .ok()
+    #[test]
+    fn test_content_changes_replace() {
+        let text = "hello\nworld\n";
+        let mut builder = DeltaBuilder::new(text.len());
+        builder.replace(3..8, Rope::from("p\nwo"));
+        assert_eq!(
+            changes(text, &builder.build()),
+            vec![change((0, 3), (1, 2), "p\nwo")]
+        );
+    }
+
+    #[test]
+    fn test_content_changes_delete_at_end() {
+        let text = "hello\nworld\n";
+        let mut builder = DeltaBuilder::new(text.len());
+        builder.delete(5..text.len());
+        assert_eq!(
+            changes(text, &builder.build()),
+            vec![change((0, 5), (2, 0), "")]
+        );
+    }
+
+    #[test]
+    fn test_content_changes_multi_cursor() {
+        let text = "abcdef\nghijkl\n";
+        let mut builder = DeltaBuilder::new(text.len());
+        builder.replace(1..1, Rope::from("x"));
+        builder.delete(3..4);
+        builder.replace(8..10, Rope::from("yy"));
+        builder.replace(13..13, Rope::from("z"));
+        assert_eq!(
+            changes(text, &builder.build()),
+            vec![
+                change((1, 6), (1, 6), "z"),
+                change((1, 1), (1, 3), "yy"),
+                change((0, 3), (0, 4), ""),
+                change((0, 1), (0, 1), "x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_content_changes_empty() {
+        let text = "hello\nworld\n";
+        let builder = DeltaBuilder::new(text.len());
+        assert_eq!(changes(text, &builder.build()), vec![]);
+
+        let builder = DeltaBuilder::new(0);
+        assert_eq!(changes("", &builder.build()), vec![]);
+    }
 }
@@ -1,6 +1,10 @@
-use crate::buffer::{get_mod_time, Buffer, BufferId};
+use crate::buffer::{
+    apply_edits_to_file, get_mod_time, language_id_from_path, Buffer, BufferId,
+    PositionEncoding,
+};
 use crate::core_proxy::CoreProxy;
-use crate::lsp::LspCatalog;
+use crate::inline_completion::InlineCompletionParams;
+use crate::lsp::{LspCatalog, LspSettings};
 use crate::plugin::PluginCatalog;
 use crate::terminal::{TermId, Terminal};
 use alacritty_terminal::event_loop::Msg;
@@ -8,9 +12,14 @@ use alacritty_terminal::term::SizeInfo;
 use anyhow::{anyhow, Result};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use git2::{DiffOptions, Oid, Repository};
-use jsonrpc_lite::{self, JsonRpc};
+use jsonrpc_lite::{self, Id, JsonRpc};
 use lapce_rpc::{self, Call, RequestId, RpcObject};
-use lsp_types::{CompletionItem, Position, TextDocumentContentChangeEvent};
+use lsp_types::{
+    CodeAction, CodeLens, CompletionItem, DocumentChangeOperation, DocumentChanges,
+    OneOf, Position, Range, ResourceOp, SignatureHelpContext,
+    TextDocumentContentChangeEvent, TextDocumentEdit, TextEdit, TraceOption, Url,
+    WorkspaceEdit,
+};
 use notify::DebouncedEvent;
 use parking_lot::Mutex;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -36,7 +45,7 @@ pub struct Dispatcher {
     pub buffers: Arc<Mutex<HashMap<BufferId, Buffer>>>,
     pub terminals: Arc<Mutex<HashMap<TermId, mio::channel::Sender<Msg>>>>,
     open_files: Arc<Mutex<HashMap<String, BufferId>>>,
-    plugins: Arc<Mutex<PluginCatalog>>,
+    pub plugins: Arc<Mutex<PluginCatalog>>,
     pub lsp: Arc<Mutex<LspCatalog>>,
     pub watcher: Arc<Mutex<Option<FileWatcher>>>,
     pub workspace_updated: Arc<AtomicBool>,
@@ -96,6 +105,7 @@ impl Notify for Dispatcher {
                         dispatcher
                             .workspace_updated
                             .store(true, atomic::Ordering::Relaxed);
+                        dispatcher.lsp.lock().file_changed(&event);
                     }
                     WatchToken(_) => {}
                 }
@@ -110,6 +120,11 @@ impl Notify for Dispatcher {
 pub enum Notification {
     Initialize {
         workspace: PathBuf,
+        #[serde(default)]
+        lsp_settings: HashMap<String, LspSettings>,
+    },
+    UpdateLspSettings {
+        lsp_settings: HashMap<String, LspSettings>,
     },
     Update {
         buffer_id: BufferId,
@@ -129,6 +144,21 @@ pub enum Notification {
         width: usize,
         height: usize,
     },
+    LspResponse {
+        server: String,
+        id: Id,
+        result: Value,
+    },
+    RestartLanguageServer {
+        buffer_id: BufferId,
+    },
+    CloseBuffers {
+        buffer_ids: Vec<BufferId>,
+    },
+    SetLspTrace {
+        server: String,
+        trace: TraceOption,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -148,9 +178,18 @@ pub enum Request {
         buffer_id: BufferId,
         completion_item: CompletionItem,
     },
+    CodeActionResolve {
+        buffer_id: BufferId,
+        action: CodeAction,
+    },
     GetSignature {
         buffer_id: BufferId,
         position: Position,
+        context: Option<SignatureHelpContext>,
+    },
+    GetHover {
+        buffer_id: BufferId,
+        position: Position,
     },
     GetReferences {
         buffer_id: BufferId,
@@ -161,16 +200,112 @@ pub enum Request {
         buffer_id: BufferId,
         position: Position,
     },
+    GetTypeDefinition {
+        buffer_id: BufferId,
+        position: Position,
+    },
+    GetImplementation {
+        buffer_id: BufferId,
+        position: Position,
+    },
+    GetDeclaration {
+        buffer_id: BufferId,
+        position: Position,
+    },
     GetCodeActions {
         buffer_id: BufferId,
         position: Position,
     },
+    GetPreferredFixes {
+        buffer_id: BufferId,
+    },
     GetDocumentSymbols {
         buffer_id: BufferId,
     },
     GetDocumentFormatting {
         buffer_id: BufferId,
     },
+    GetDocumentRangeFormatting {
+        buffer_id: BufferId,
+        range: Range,
+    },
+    GetOnTypeFormatting {
+        buffer_id: BufferId,
+        position: Position,
+        ch: String,
+    },
+    GetWorkspaceSymbols {
+        buffer_id: BufferId,
+        query: String,
+    },
+    PrepareRename {
+        buffer_id: BufferId,
+        position: Position,
+    },
+    Rename {
+        buffer_id: BufferId,
+        position: Position,
+        new_name: String,
+    },
+    ApplyWorkspaceEdit {
+        edit: WorkspaceEdit,
+    },
+    GetInlayHints {
+        buffer_id: BufferId,
+        range: Range,
+    },
+    GetInlineCompletion {
+        buffer_id: BufferId,
+        position: Position,
+        automatic: bool,
+    },
+    GetSemanticTokensRange {
+        buffer_id: BufferId,
+        range: Range,
+    },
+    ResolveInlayHint {
+        buffer_id: BufferId,
+        hint: Value,
+    },
+    GetDocumentHighlights {
+        buffer_id: BufferId,
+        position: Position,
+    },
+    PrepareCallHierarchy {
+        buffer_id: BufferId,
+        position: Position,
+    },
+    PrepareTypeHierarchy {
+        buffer_id: BufferId,
+        position: Position,
+    },
+    GetIncomingCalls {
+        buffer_id: BufferId,
+        item: Value,
+    },
+    GetOutgoingCalls {
+        buffer_id: BufferId,
+        item: Value,
+    },
+    GetSupertypes {
+        buffer_id: BufferId,
+        item: Value,
+    },
+    GetSubtypes {
+        buffer_id: BufferId,
+        item: Value,
+    },
+    GetCodeLens {
+        buffer_id: BufferId,
+    },
+    ResolveCodeLens {
+        buffer_id: BufferId,
+        lens: CodeLens,
+    },
+    ExecuteCommand {
+        buffer_id: BufferId,
+        command: lsp_types::Command,
+    },
     GetFiles {
         path: String,
     },
@@ -181,6 +316,11 @@ pub enum Request {
         rev: u64,
         buffer_id: BufferId,
     },
+    GetLspLog {
+        server: Option<String>,
+        version: Option<u64>,
+    },
+    Shutdown {},
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -368,10 +508,171 @@ impl Dispatcher {
         }));
     }
 
+    /// Applies the parts of a workspace edit that the core can't apply itself:
+    /// text edits to files without an open buffer and resource operations.
+    /// Operations are applied in order, and the first failure stops the rest.
+    pub fn apply_workspace_edit(&self, edit: &WorkspaceEdit) -> Result<()> {
+        if let Some(document_changes) = edit.document_changes.as_ref() {
+            match document_changes {
+                DocumentChanges::Edits(edits) => {
+                    for edit in edits {
+                        self.apply_text_document_edit(edit)?;
+                    }
+                }
+                DocumentChanges::Operations(ops) => {
+                    for op in ops {
+                        match op {
+                            DocumentChangeOperation::Op(op) => {
+                                self.apply_resource_op(op)?;
+                            }
+                            DocumentChangeOperation::Edit(edit) => {
+                                self.apply_text_document_edit(edit)?;
+                            }
+                        }
+                    }
+                }
+            }
+        } else if let Some(changes) = edit.changes.as_ref() {
+            for (uri, edits) in changes {
+                let path = file_path(uri)?;
+                apply_edits_to_file(&path, edits, self.position_encoding(&path))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_text_document_edit(&self, edit: &TextDocumentEdit) -> Result<()> {
+        let edits: Vec<TextEdit> = edit
+            .edits
+            .iter()
+            .map(|e| match e {
+                OneOf::Left(e) => e.clone(),
+                OneOf::Right(e) => e.text_edit.clone(),
+            })
+            .collect();
+        let path = file_path(&edit.text_document.uri)?;
+        apply_edits_to_file(&path, &edits, self.position_encoding(&path))
+    }
+
+    /// How the server of the file at `path` counts characters.
+    fn position_encoding(&self, path: &PathBuf) -> PositionEncoding {
+        let language_id = language_id_from_path(path).unwrap_or("");
+        self.lsp.lock().position_encoding(language_id)
+    }
+
+    fn apply_resource_op(&self, op: &ResourceOp) -> Result<()> {
+        match op {
+            ResourceOp::Create(create) => {
+                let path = file_path(&create.uri)?;
+                let (overwrite, ignore_if_exists) = create
+                    .options
+                    .as_ref()
+                    .map(|o| {
+                        (
+                            o.overwrite.unwrap_or(false),
+                            o.ignore_if_exists.unwrap_or(false),
+                        )
+                    })
+                    .unwrap_or((false, false));
+                if path.exists() {
+                    if ignore_if_exists && !overwrite {
+                        return Ok(());
+                    }
+                    if !overwrite {
+                        return Err(anyhow!("{} already exists", path.display()));
+                    }
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::File::create(&path)?;
+            }
+            ResourceOp::Rename(rename) => {
+                let old_path = file_path(&rename.old_uri)?;
+                let new_path = file_path(&rename.new_uri)?;
+                let (overwrite, ignore_if_exists) = rename
+                    .options
+                    .as_ref()
+                    .map(|o| {
+                        (
+                            o.overwrite.unwrap_or(false),
+                            o.ignore_if_exists.unwrap_or(false),
+                        )
+                    })
+                    .unwrap_or((false, false));
+                if new_path.exists() {
+                    if ignore_if_exists && !overwrite {
+                        return Ok(());
+                    }
+                    if !overwrite {
+                        return Err(anyhow!(
+                            "{} already exists",
+                            new_path.display()
+                        ));
+                    }
+                }
+                if let Some(parent) = new_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(&old_path, &new_path)?;
+
+                let buffer_id =
+                    self.open_files.lock().remove(old_path.to_str().unwrap());
+                if let Some(buffer_id) = buffer_id {
+                    self.open_files
+                        .lock()
+                        .insert(new_path.to_str().unwrap().to_string(), buffer_id);
+                    if let Some(buffer) = self.buffers.lock().get_mut(&buffer_id) {
+                        buffer.path = new_path.clone();
+                        buffer.mod_time = get_mod_time(&new_path);
+                    }
+                    self.watcher.lock().as_mut().unwrap().watch(
+                        &new_path,
+                        true,
+                        OPEN_FILE_EVENT_TOKEN,
+                    );
+                }
+            }
+            ResourceOp::Delete(delete) => {
+                let path = file_path(&delete.uri)?;
+                let (recursive, ignore_if_not_exists) = delete
+                    .options
+                    .as_ref()
+                    .map(|o| {
+                        (
+                            o.recursive.unwrap_or(false),
+                            o.ignore_if_not_exists.unwrap_or(false),
+                        )
+                    })
+                    .unwrap_or((false, false));
+                if !path.exists() {
+                    if ignore_if_not_exists {
+                        return Ok(());
+                    }
+                    return Err(anyhow!("{} doesn't exist", path.display()));
+                }
+                if path.is_dir() {
+                    if recursive {
+                        fs::remove_dir_all(&path)?;
+                    } else {
+                        fs::remove_dir(&path)?;
+                    }
+                } else {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn handle_notification(&self, rpc: Notification) {
         match rpc {
-            Notification::Initialize { workspace } => {
+            Notification::Initialize {
+                workspace,
+                lsp_settings,
+            } => {
                 *self.workspace.lock() = workspace.clone();
+                self.lsp.lock().set_settings(lsp_settings);
                 let mut items = Vec::new();
                 if let Ok(entries) = fs::read_dir(&workspace) {
                     for entry in entries {
@@ -414,8 +715,10 @@ impl Dispatcher {
             } => {
                 let mut buffers = self.buffers.lock();
                 let buffer = buffers.get_mut(&buffer_id).unwrap();
-                if let Some(content_change) = buffer.update(&delta, rev) {
-                    self.lsp.lock().update(buffer, &content_change, buffer.rev);
+                let encoding =
+                    self.lsp.lock().position_encoding(&buffer.language_id);
+                if let Some(content_changes) = buffer.update(&delta, rev, encoding) {
+                    self.lsp.lock().update(buffer, &content_changes, buffer.rev);
                 }
             }
             Notification::NewTerminal { term_id, cwd } => {
@@ -450,6 +753,30 @@ impl Dispatcher {
                 );
                 tx.send(Msg::Resize(size));
             }
+            Notification::LspResponse { server, id, result } => {
+                self.lsp.lock().respond_server_request(&server, id, result);
+            }
+            Notification::CloseBuffers { buffer_ids } => {
+                let buffers = self.buffers.lock();
+                for buffer_id in buffer_ids {
+                    if let Some(buffer) = buffers.get(&buffer_id) {
+                        self.lsp.lock().close_buffer(buffer);
+                    }
+                }
+            }
+            Notification::RestartLanguageServer { buffer_id } => {
+                let language_id = match self.buffers.lock().get(&buffer_id) {
+                    Some(buffer) => buffer.language_id.clone(),
+                    None => return,
+                };
+                self.lsp.lock().restart_server(&language_id);
+            }
+            Notification::SetLspTrace { server, trace } => {
+                self.lsp.lock().set_trace(&server, trace);
+            }
+            Notification::UpdateLspSettings { lsp_settings } => {
+                self.lsp.lock().set_settings(lsp_settings);
+            }
         }
     }
 
@@ -473,6 +800,9 @@ impl Dispatcher {
                     "id": id,
                     "result": resp,
                 }));
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().new_buffer(buffer);
             }
             Request::GetCompletion {
                 buffer_id,
@@ -495,13 +825,27 @@ impl Dispatcher {
                     .lock()
                     .completion_resolve(id, buffer, &completion_item);
             }
+            Request::CodeActionResolve { buffer_id, action } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().code_action_resolve(id, buffer, &action);
+            }
             Request::GetSignature {
                 buffer_id,
                 position,
+                context,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_signature(id, buffer, position, context);
+            }
+            Request::GetHover {
+                buffer_id,
+                position,
             } => {
                 let buffers = self.buffers.lock();
                 let buffer = buffers.get(&buffer_id).unwrap();
-                self.lsp.lock().get_signature(id, buffer, position);
+                self.lsp.lock().get_hover(id, buffer, position);
             }
             Request::GetReferences {
                 buffer_id,
@@ -522,6 +866,30 @@ impl Dispatcher {
                     .lock()
                     .get_definition(id, request_id, buffer, position);
             }
+            Request::GetTypeDefinition {
+                buffer_id,
+                position,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_type_definition(id, buffer, position);
+            }
+            Request::GetImplementation {
+                buffer_id,
+                position,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_implementation(id, buffer, position);
+            }
+            Request::GetDeclaration {
+                buffer_id,
+                position,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_declaration(id, buffer, position);
+            }
             Request::GetCodeActions {
                 buffer_id,
                 position,
@@ -530,6 +898,11 @@ impl Dispatcher {
                 let buffer = buffers.get(&buffer_id).unwrap();
                 self.lsp.lock().get_code_actions(id, buffer, position);
             }
+            Request::GetPreferredFixes { buffer_id } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_preferred_fixes(id, buffer);
+            }
             Request::GetDocumentSymbols { buffer_id } => {
                 let buffers = self.buffers.lock();
                 let buffer = buffers.get(&buffer_id).unwrap();
@@ -540,6 +913,161 @@ impl Dispatcher {
                 let buffer = buffers.get(&buffer_id).unwrap();
                 self.lsp.lock().get_document_formatting(id, buffer);
             }
+            Request::GetDocumentRangeFormatting { buffer_id, range } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp
+                    .lock()
+                    .get_document_range_formatting(id, buffer, range);
+            }
+            Request::GetOnTypeFormatting {
+                buffer_id,
+                position,
+                ch,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp
+                    .lock()
+                    .get_on_type_formatting(id, buffer, position, ch);
+            }
+            Request::GetWorkspaceSymbols { buffer_id, query } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_workspace_symbols(id, buffer, query);
+            }
+            Request::PrepareRename {
+                buffer_id,
+                position,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().prepare_rename(id, buffer, position);
+            }
+            Request::Rename {
+                buffer_id,
+                position,
+                new_name,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().rename(id, buffer, position, new_name);
+            }
+            Request::ApplyWorkspaceEdit { edit } => {
+                let resp = self.apply_workspace_edit(&edit).map(|_| json!({}));
+                self.respond(id, resp);
+            }
+            Request::GetSemanticTokensRange { buffer_id, range } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_semantic_tokens_range(id, buffer, range);
+            }
+            Request::GetInlayHints { buffer_id, range } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_inlay_hints(id, buffer, range);
+            }
+            Request::GetInlineCompletion {
+                buffer_id,
+                position,
+                automatic,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                let lsp = self.lsp.lock();
+                let encoding = lsp.position_encoding(&buffer.language_id);
+                let params = InlineCompletionParams::new(buffer, position, encoding);
+                let plugins = self.plugins.clone();
+                let dispatcher = self.clone();
+                lsp.get_inline_completion(
+                    buffer,
+                    position,
+                    automatic,
+                    move |mut items| {
+                        plugins.lock().get_inline_completion(
+                            &params,
+                            move |plugin_items, failures| {
+                                items.extend(plugin_items);
+                                // Failures only matter when nothing else
+                                // suggested anything.
+                                let result =
+                                    if items.is_empty() && !failures.is_empty() {
+                                        Err(anyhow!(failures.join("\n")))
+                                    } else {
+                                        Ok(serde_json::to_value(items).unwrap())
+                                    };
+                                dispatcher.respond(id, result);
+                            },
+                        );
+                    },
+                );
+            }
+            Request::ResolveInlayHint { buffer_id, hint } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().resolve_inlay_hint(id, buffer, hint);
+            }
+            Request::GetDocumentHighlights {
+                buffer_id,
+                position,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp
+                    .lock()
+                    .get_document_highlights(id, buffer, position);
+            }
+            Request::PrepareCallHierarchy {
+                buffer_id,
+                position,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().prepare_call_hierarchy(id, buffer, position);
+            }
+            Request::PrepareTypeHierarchy {
+                buffer_id,
+                position,
+            } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().prepare_type_hierarchy(id, buffer, position);
+            }
+            Request::GetIncomingCalls { buffer_id, item } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_incoming_calls(id, buffer, item);
+            }
+            Request::GetOutgoingCalls { buffer_id, item } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_outgoing_calls(id, buffer, item);
+            }
+            Request::GetSupertypes { buffer_id, item } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_supertypes(id, buffer, item);
+            }
+            Request::GetSubtypes { buffer_id, item } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_subtypes(id, buffer, item);
+            }
+            Request::GetCodeLens { buffer_id } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().get_code_lens(id, buffer);
+            }
+            Request::ResolveCodeLens { buffer_id, lens } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().resolve_code_lens(id, buffer, lens);
+            }
+            Request::ExecuteCommand { buffer_id, command } => {
+                let buffers = self.buffers.lock();
+                let buffer = buffers.get(&buffer_id).unwrap();
+                self.lsp.lock().execute_command(id, buffer, command);
+            }
             Request::ReadDir { path } => {
                 let local_dispatcher = self.clone();
                 thread::spawn(move || {
@@ -614,6 +1142,16 @@ impl Dispatcher {
                 self.lsp.lock().save_buffer(buffer);
                 self.respond(id, resp);
             }
+            Request::GetLspLog { server, version } => {
+                self.lsp.lock().get_log(id, server, version);
+            }
+            Request::Shutdown {} => {
+                let local_dispatcher = self.clone();
+                thread::spawn(move || {
+                    local_dispatcher.lsp.lock().stop();
+                    local_dispatcher.respond(id, Ok(json!({})));
+                });
+            }
         }
     }
 }
@@ -627,6 +1165,13 @@ pub struct DiffHunk {
     pub header: String,
 }
 
+/// The path a `file:` URI points to. Anything that isn't a local file path
+/// is an error, rather than a path guessed from the URI.
+fn file_path(uri: &Url) -> Result<PathBuf> {
+    uri.to_file_path()
+        .map_err(|_| anyhow!("{} is not a local file", uri))
+}
+
 fn git_diff(workspace_path: &PathBuf) -> Option<Vec<String>> {
     let repo = Repository::open(workspace_path.to_str()?).ok()?;
     let mut diff_files = HashSet::new();
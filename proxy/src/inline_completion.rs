@@ -0,0 +1,100 @@
+use lsp_types::{Position, Range};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+
+use crate::buffer::{Buffer, BufferId, PositionEncoding};
+
+/// How much of the document on each side of the cursor a plugin is sent.
+const CONTEXT_LEN: usize = 8192;
+
+/// A suggestion to show as ghost text after the cursor.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionItem {
+    pub insert_text: String,
+    /// Whether `insert_text` is snippet syntax rather than plain text.
+    #[serde(default)]
+    pub is_snippet: bool,
+    /// What accepting it replaces, the cursor position when there's none.
+    pub range: Option<Range>,
+    /// The server or plugin that suggested it.
+    #[serde(default)]
+    pub provider: String,
+}
+
+/// What a plugin is sent to suggest an inline completion. Positions are
+/// counted as the language servers of the buffer count them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InlineCompletionParams {
+    pub buffer_id: BufferId,
+    pub path: PathBuf,
+    pub language_id: String,
+    pub position: Position,
+    /// The text before the cursor, cut to the last `CONTEXT_LEN` bytes.
+    pub prefix: String,
+    /// The text after the cursor, cut to the first `CONTEXT_LEN` bytes.
+    pub suffix: String,
+}
+
+impl InlineCompletionParams {
+    pub fn new(
+        buffer: &Buffer,
+        position: Position,
+        encoding: PositionEncoding,
+    ) -> InlineCompletionParams {
+        let offset = buffer.offset_of_position(&position, encoding);
+        let mut start = offset.saturating_sub(CONTEXT_LEN);
+        while !buffer.rope.is_codepoint_boundary(start) {
+            start += 1;
+        }
+        let mut end = (offset + CONTEXT_LEN).min(buffer.len());
+        while !buffer.rope.is_codepoint_boundary(end) {
+            end -= 1;
+        }
+        InlineCompletionParams {
+            buffer_id: buffer.id,
+            path: buffer.path.clone(),
+            language_id: buffer.language_id.clone(),
+            position,
+            prefix: buffer.slice_to_cow(start..offset).to_string(),
+            suffix: buffer.slice_to_cow(offset..end).to_string(),
+        }
+    }
+}
+
+/// Reads what `provider` answered: an `InlineCompletionList`, or an array
+/// of items, as in `textDocument/inlineCompletion`. Items that can't be
+/// read are left out.
+pub fn parse_items(provider: &str, value: Value) -> Vec<InlineCompletionItem> {
+    let items = match value {
+        Value::Array(items) => items,
+        Value::Object(mut list) => match list.remove("items") {
+            Some(Value::Array(items)) => items,
+            _ => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+    items
+        .into_iter()
+        .filter_map(|item| {
+            let (insert_text, is_snippet) = match item.get("insertText")? {
+                Value::String(text) => (text.clone(), false),
+                Value::Object(string) => (
+                    string.get("value")?.as_str()?.to_string(),
+                    string.get("kind").and_then(|k| k.as_str()) == Some("snippet"),
+                ),
+                _ => return None,
+            };
+            let range = item
+                .get("range")
+                .and_then(|range| serde_json::from_value(range.clone()).ok());
+            Some(InlineCompletionItem {
+                insert_text,
+                is_snippet,
+                range,
+                provider: provider.to_string(),
+            })
+        })
+        .collect()
+}
@@ -1,6 +1,7 @@
 pub mod buffer;
 pub mod core_proxy;
 pub mod dispatch;
+pub mod inline_completion;
 pub mod lsp;
 pub mod plugin;
 pub mod terminal;
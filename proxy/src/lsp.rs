@@ -1,28 +1,50 @@
 use std::{
-    collections::HashMap,
-    io::BufRead,
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::{self, BufRead},
     io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
     process::{self, Child, Command, Stdio},
     sync::{mpsc::channel, Arc},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Result};
-use jsonrpc_lite::{Id, JsonRpc, Params};
+use jsonrpc_lite::{Error as RpcError, Id, JsonRpc, Params};
 use lapce_rpc::RequestId;
 use lsp_types::*;
+use notify::DebouncedEvent;
 use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, to_value, Value};
 
 use crate::buffer::Buffer;
 use crate::buffer::BufferId;
+use crate::buffer::PositionEncoding;
 use crate::dispatch::Dispatcher;
 use crate::dispatch::Request;
+use crate::inline_completion::{parse_items, InlineCompletionItem};
 
 pub type Callback = Box<dyn Callable>;
 const HEADER_CONTENT_LENGTH: &str = "content-length";
 const HEADER_CONTENT_TYPE: &str = "content-type";
+/// A server that ran at least this long before crashing starts over with
+/// a clean crash count.
+const CRASH_RESET: Duration = Duration::from_secs(60);
+/// After this many crashes in a row the server is left alone until it's
+/// restarted by hand.
+const MAX_CRASHES: u32 = 5;
+/// Files with more lines than this only get semantic tokens for the lines
+/// on screen, when the server can send tokens for a range.
+pub const SEMANTIC_TOKENS_RANGE_LINES: usize = 10000;
+
+/// Whether a file of `lines` lines gets its semantic tokens a range at a
+/// time. Both the proxy and the editor decide with this.
+pub fn semantic_tokens_by_range(lines: usize) -> bool {
+    lines > SEMANTIC_TOKENS_RANGE_LINES
+}
+/// Each log of a server keeps this many entries, dropping the oldest.
+const LOG_ENTRIES: usize = 2000;
 
 pub trait Callable: Send {
     fn call(self: Box<Self>, client: &LspClient, result: Result<Value>);
@@ -36,23 +58,166 @@ impl<F: Send + FnOnce(&LspClient, Result<Value>)> Callable for F {
 
 pub struct LspCatalog {
     pub dispatcher: Option<Dispatcher>,
-    clients: HashMap<String, Arc<LspClient>>,
+    /// The servers of each language, the main one first.
+    clients: HashMap<String, Vec<Arc<LspClient>>>,
+    /// The servers configured in the `[lsp.<language>]` settings, started
+    /// when the first buffer of their language is opened.
+    settings: HashMap<String, LspSettings>,
+    diagnostics: Diagnostics,
+}
+
+/// The diagnostics of each document, kept apart by the server that sent
+/// them, so that one server publishing doesn't drop those of another.
+type Diagnostics = Arc<Mutex<HashMap<Url, BTreeMap<String, Vec<Diagnostic>>>>>;
+
+/// How to run the language server of a language, from a `[lsp.<language>]`
+/// section of the settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct LspSettings {
+    pub command: String,
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub initialization_options: Option<Value>,
+    /// What `workspace/configuration` is answered from, instead of the
+    /// initialization options.
+    pub settings: Option<Value>,
+    /// Files like `Cargo.toml` or `go.mod` that mark the root of a project.
+    /// The server is started in the closest directory above the first
+    /// buffer that has one, or else in the workspace.
+    pub root_markers: Vec<String>,
+    /// More servers to run next to this one for the same documents, like a
+    /// linter, by name. Their own `servers` and primary servers are unused.
+    pub servers: HashMap<String, LspSettings>,
+    /// The name of the server, out of `servers` or the language for this
+    /// one, that formats documents. Otherwise it's the first that can.
+    pub formatting_server: Option<String>,
+    /// The server that answers hover requests, picked like the formatter.
+    pub hover_server: Option<String>,
+}
+
+impl LspSettings {
+    fn formatting_server(&self) -> Option<&String> {
+        self.formatting_server.as_ref()
+    }
+
+    fn hover_server(&self) -> Option<&String> {
+        self.hover_server.as_ref()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LspStatus {
+    Starting,
+    Running,
+    Crashed,
+    Stopped,
+}
+
+/// The logs kept of each server.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LspLogKind {
+    Stderr,
+    /// What the server sends with `window/logMessage`, and the
+    /// notifications that aren't handled.
+    Messages,
+    /// The messages sent to and from the server, when tracing is on.
+    Trace,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LspLog {
+    /// Bumped with every entry, so that the editor can tell when nothing
+    /// changed since it last asked.
+    pub version: u64,
+    pub stderr: VecDeque<String>,
+    pub messages: VecDeque<String>,
+    pub trace: VecDeque<String>,
+}
+
+impl LspLog {
+    pub fn entries(&self, kind: LspLogKind) -> &VecDeque<String> {
+        match kind {
+            LspLogKind::Stderr => &self.stderr,
+            LspLogKind::Messages => &self.messages,
+            LspLogKind::Trace => &self.trace,
+        }
+    }
+
+    fn push(&mut self, kind: LspLogKind, entry: String) {
+        let entries = match kind {
+            LspLogKind::Stderr => &mut self.stderr,
+            LspLogKind::Messages => &mut self.messages,
+            LspLogKind::Trace => &mut self.trace,
+        };
+        if entries.len() >= LOG_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+        self.version += 1;
+    }
+}
+
+/// The answer to `get_lsp_log`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LspLogResponse {
+    pub servers: Vec<String>,
+    pub server: Option<String>,
+    pub trace: TraceOption,
+    /// `None` when the log hasn't changed since the version asked with.
+    pub log: Option<LspLog>,
 }
 
 pub struct LspState {
     next_id: u64,
     writer: Box<dyn Write + Send>,
-    process: Child,
+    process: Option<Child>,
+    /// Counts the times the server was started, so that the reader of a
+    /// process that was replaced can tell its end isn't a crash.
+    generation: u64,
+    status: LspStatus,
+    crashes: u32,
+    started: Instant,
     pending: HashMap<u64, Callback>,
     pub server_capabilities: Option<ServerCapabilities>,
+    /// The capabilities exactly as the server sent them, for providers
+    /// that lsp-types doesn't know about yet.
+    pub raw_server_capabilities: Option<Value>,
     pub opened_documents: HashMap<BufferId, Url>,
     pub is_initialized: bool,
+    pub position_encoding: PositionEncoding,
+    /// Capabilities the server registered after initialization, by id.
+    pub registrations: HashMap<String, Registration>,
+    /// The last full tokens of each document, which delta responses edit.
+    semantic_tokens: HashMap<BufferId, SemanticTokens>,
+    /// The number of the latest tokens request of each document. Responses
+    /// to earlier ones are stale: their edits were made against tokens the
+    /// latest one may have replaced.
+    semantic_tokens_requests: HashMap<BufferId, u64>,
+    semantic_tokens_request_count: u64,
+    trace: TraceOption,
+    /// The method and send time of the requests traced, to show how long
+    /// the server took to answer them.
+    traced_requests: HashMap<u64, (String, Instant)>,
 }
 
 pub struct LspClient {
-    language_id: String,
-    options: Option<Value>,
+    /// The language for the main server of a language, and the language
+    /// and the name from the settings joined by a dot for the others.
+    name: String,
+    settings: LspSettings,
+    /// The directory the server is started in, the workspace when `None`.
+    root: Option<PathBuf>,
+    /// Whether the server may pick how positions are counted, instead of
+    /// the UTF-16 every server understands.
+    negotiate_encoding: bool,
+    diagnostics: Diagnostics,
     state: Arc<Mutex<LspState>>,
+    log: Mutex<LspLog>,
+    /// Log entries are stamped with the time since this.
+    created: Instant,
     dispatcher: Dispatcher,
 }
 
@@ -61,44 +226,319 @@ impl LspCatalog {
         LspCatalog {
             dispatcher: None,
             clients: HashMap::new(),
+            settings: HashMap::new(),
+            diagnostics: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Starts a server a plugin asked for, unless the settings say how to
+    /// run the servers of that language.
     pub fn start_server(
         &mut self,
         exec_path: &str,
         language_id: &str,
         options: Option<Value>,
     ) {
+        if self.settings.contains_key(language_id) {
+            return;
+        }
+        let settings = LspSettings {
+            command: exec_path.to_string(),
+            initialization_options: options,
+            ..Default::default()
+        };
         let client = LspClient::new(
             language_id.to_string(),
-            exec_path,
-            options,
+            settings,
+            None,
+            true,
+            self.diagnostics.clone(),
             self.dispatcher.clone().unwrap(),
         );
-        self.clients.insert(language_id.to_string(), client);
+        self.clients.insert(language_id.to_string(), vec![client]);
+    }
+
+    /// Takes the `[lsp.<language>]` settings. The running servers of a
+    /// language whose settings changed are replaced, and their documents are
+    /// opened on the new ones as they're asked about. The ones of a language
+    /// whose settings are gone are stopped.
+    pub fn set_settings(&mut self, settings: HashMap<String, LspSettings>) {
+        let removed: Vec<String> = self
+            .settings
+            .keys()
+            .filter(|language_id| !settings.contains_key(*language_id))
+            .cloned()
+            .collect();
+        let removed: Vec<Arc<LspClient>> = removed
+            .iter()
+            .filter_map(|language_id| self.clients.remove(language_id))
+            .flatten()
+            .collect();
+        if !removed.is_empty() {
+            thread::spawn(move || {
+                for client in removed {
+                    client.stop();
+                }
+            });
+        }
+
+        for (language_id, language_settings) in settings.iter() {
+            let old = match self.clients.get(language_id) {
+                Some(clients) if clients[0].settings != *language_settings => {
+                    clients.clone()
+                }
+                _ => continue,
+            };
+            let clients =
+                self.new_clients(language_id, language_settings, |name, _| {
+                    old.iter()
+                        .find(|client| client.name == name)
+                        .unwrap_or(&old[0])
+                        .root
+                        .clone()
+                });
+            self.clients.insert(language_id.clone(), clients.clone());
+            thread::spawn(move || {
+                for client in old {
+                    client.stop();
+                }
+                // The old servers saying they stopped isn't about the new ones.
+                for client in clients {
+                    let status = client.state.lock().status;
+                    client.set_status(status);
+                }
+            });
+        }
+        self.settings = settings;
     }
 
-    pub fn new_buffer(
+    /// Creates the main server of a language and the ones listed under its
+    /// `servers`, in the directories `root` picks for them.
+    fn new_clients(
+        &self,
+        language_id: &str,
+        settings: &LspSettings,
+        root: impl Fn(&str, &LspSettings) -> Option<PathBuf>,
+    ) -> Vec<Arc<LspClient>> {
+        // Servers that share documents have to agree on what positions mean,
+        // so only a server running alone gets to pick the encoding.
+        let negotiate_encoding = settings.servers.is_empty();
+        let mut servers: Vec<(String, &LspSettings)> = settings
+            .servers
+            .iter()
+            .map(|(name, settings)| (format!("{}.{}", language_id, name), settings))
+            .collect();
+        servers.sort_by(|a, b| a.0.cmp(&b.0));
+        servers.insert(0, (language_id.to_string(), settings));
+        servers
+            .into_iter()
+            .map(|(name, settings)| {
+                let root = root(&name, settings);
+                LspClient::new(
+                    name,
+                    settings.clone(),
+                    root,
+                    negotiate_encoding,
+                    self.diagnostics.clone(),
+                    self.dispatcher.clone().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// The servers of `language_id`, the main one first.
+    fn language_clients(&self, language_id: &str) -> &[Arc<LspClient>] {
+        self.clients
+            .get(language_id)
+            .map(|clients| clients.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// The first server of `language_id` that `supported` says can answer a
+    /// request, or else the main one, which then says why it can't.
+    fn client_for(
+        &self,
+        language_id: &str,
+        supported: fn(&LspClient) -> bool,
+    ) -> Option<&Arc<LspClient>> {
+        let clients = self.language_clients(language_id);
+        clients
+            .iter()
+            .find(|client| supported(client))
+            .or_else(|| clients.first())
+    }
+
+    /// Like `client_for`, but the server `primary` names in the settings is
+    /// asked first when it's running.
+    fn primary_client_for(
         &self,
-        buffer_id: &BufferId,
-        path: &str,
         language_id: &str,
-        text: String,
+        primary: fn(&LspSettings) -> Option<&String>,
+        supported: fn(&LspClient) -> bool,
+    ) -> Option<&Arc<LspClient>> {
+        let name = self.settings.get(language_id).and_then(primary);
+        if let Some(name) = name {
+            let full_name = format!("{}.{}", language_id, name);
+            if let Some(client) = self
+                .language_clients(language_id)
+                .iter()
+                .find(|client| client.name == *name || client.name == full_name)
+            {
+                return Some(client);
+            }
+        }
+        self.client_for(language_id, supported)
+    }
+
+    fn client_by_name(&self, name: &str) -> Option<&Arc<LspClient>> {
+        self.clients
+            .values()
+            .flatten()
+            .find(|client| client.name == name)
+    }
+
+    /// Restarts the servers of `language_id` on threads of their own, since
+    /// waiting for them to shut down and come back up takes a while.
+    pub fn restart_server(&self, language_id: &str) {
+        for client in self.language_clients(language_id) {
+            let client = client.clone();
+            thread::spawn(move || {
+                LspClient::restart(&client);
+            });
+        }
+    }
+
+    /// Shuts all the servers down together, returning once they're gone.
+    pub fn stop(&self) {
+        let handles: Vec<thread::JoinHandle<()>> = self
+            .clients
+            .values()
+            .flatten()
+            .map(|client| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    client.stop();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join();
+        }
+    }
+
+    /// How the servers of `language_id` count the characters of positions.
+    pub fn position_encoding(&self, language_id: &str) -> PositionEncoding {
+        self.language_clients(language_id)
+            .first()
+            .map(|client| client.state.lock().position_encoding)
+            .unwrap_or_default()
+    }
+
+    /// Answers `get_lsp_log` with the log of `server`, or of the first one
+    /// when there's no such server. The log is left out when it's still at
+    /// `version`.
+    pub fn get_log(
+        &self,
+        id: RequestId,
+        server: Option<String>,
+        version: Option<u64>,
     ) {
-        let document_uri = Url::from_file_path(path).unwrap();
-        if let Some(client) = self.clients.get(language_id) {
-            client.send_did_open(
-                buffer_id,
-                document_uri.clone(),
-                language_id,
-                text.clone(),
-            );
+        let mut servers: Vec<String> = self
+            .clients
+            .values()
+            .flatten()
+            .map(|client| client.name.clone())
+            .collect();
+        servers.sort();
+        let server = server
+            .filter(|server| servers.contains(server))
+            .or_else(|| servers.first().cloned());
+        let client = server
+            .as_ref()
+            .and_then(|server| self.client_by_name(server));
+        let trace = client
+            .map(|client| client.state.lock().trace)
+            .unwrap_or(TraceOption::Off);
+        let log = client.and_then(|client| {
+            let log = client.log.lock();
+            if Some(log.version) == version {
+                None
+            } else {
+                Some(log.clone())
+            }
+        });
+        let response = LspLogResponse {
+            servers,
+            server,
+            trace,
+            log,
+        };
+        self.dispatcher
+            .as_ref()
+            .unwrap()
+            .respond(id, Ok(to_value(response).unwrap()));
+    }
+
+    pub fn set_trace(&self, server: &str, trace: TraceOption) {
+        if let Some(client) = self.client_by_name(server) {
+            client.set_trace(trace);
+        }
+    }
+
+    /// Answers a request `server` sent, once the editor has dealt with it.
+    pub fn respond_server_request(&self, server: &str, id: Id, result: Value) {
+        if let Some(client) = self.client_by_name(server) {
+            client.send_response(id, Ok(result));
+        }
+    }
+
+    /// Opens a new buffer on the servers of its language, starting them from
+    /// the settings if it's the first buffer of the language.
+    pub fn new_buffer(&mut self, buffer: &Buffer) {
+        if !self.clients.contains_key(&buffer.language_id) {
+            let settings = match self.settings.get(&buffer.language_id) {
+                Some(settings) => settings.clone(),
+                None => return,
+            };
+            let clients =
+                self.new_clients(&buffer.language_id, &settings, |_, settings| {
+                    find_root(&buffer.path, &settings.root_markers)
+                });
+            self.clients.insert(buffer.language_id.clone(), clients);
+        }
+        for client in self.language_clients(&buffer.language_id) {
+            client.get_uri(buffer);
+        }
+    }
+
+    /// Closes a buffer no editor shows anymore on the servers of its
+    /// language.
+    pub fn close_buffer(&self, buffer: &Buffer) {
+        for client in self.language_clients(&buffer.language_id) {
+            client.send_did_close(&buffer.id);
+        }
+    }
+
+    /// Passes a change on disk in the workspace to the servers that
+    /// registered a watcher for the file.
+    pub fn file_changed(&self, event: &DebouncedEvent) {
+        let changes = match event {
+            DebouncedEvent::Create(path) => vec![(path, FileChangeType::Created)],
+            DebouncedEvent::Write(path) => vec![(path, FileChangeType::Changed)],
+            DebouncedEvent::Remove(path) => vec![(path, FileChangeType::Deleted)],
+            DebouncedEvent::Rename(old_path, new_path) => vec![
+                (old_path, FileChangeType::Deleted),
+                (new_path, FileChangeType::Created),
+            ],
+            _ => return,
+        };
+        for client in self.clients.values().flatten() {
+            client.send_did_change_watched_files(&changes);
         }
     }
 
     pub fn save_buffer(&self, buffer: &Buffer) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
+        for client in self.language_clients(&buffer.language_id) {
             let uri = client.get_uri(buffer);
             client.send_did_save(uri);
         }
@@ -108,42 +548,99 @@ impl LspCatalog {
         let buffer_id = buffer.id;
         let path = buffer.path.clone();
         let rev = buffer.rev;
-        if let Some(client) = self.clients.get(&buffer.language_id) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_semantic_tokens)
+        {
+            // The editor asks for the lines on screen of large files itself.
+            if semantic_tokens_by_range(buffer.line_of_offset(buffer.len()) + 1)
+                && client.supports_semantic_tokens_range()
+            {
+                return;
+            }
             let uri = client.get_uri(buffer);
             let local_dispatcher = self.dispatcher.clone().unwrap();
-            client.request_semantic_tokens(uri, move |lsp_client, result| {
-                if let Ok(res) = result {
-                    let buffers = local_dispatcher.buffers.lock();
-                    let buffer = buffers.get(&buffer_id).unwrap();
-                    if buffer.rev != rev {
-                        return;
-                    }
-                    let lsp_state = lsp_client.state.lock();
-                    let semantic_tokens_provider = &lsp_state
-                        .server_capabilities
-                        .as_ref()
-                        .unwrap()
-                        .semantic_tokens_provider;
-                    if let Some(tokens) =
-                        format_semantic_tokens(buffer, semantic_tokens_provider, res)
-                    {
-                        local_dispatcher.send_notification(
-                            "semantic_tokens",
-                            json!({
-                                "rev": rev,
-                                "buffer_id": buffer_id,
-                                "path": path,
-                                "tokens": tokens,
-                            }),
-                        )
+            client.request_semantic_tokens(
+                uri,
+                buffer_id,
+                move |lsp_client, result| {
+                    if let Ok(tokens) = result {
+                        let buffers = local_dispatcher.buffers.lock();
+                        let buffer = match buffers.get(&buffer_id) {
+                            Some(buffer) => buffer,
+                            None => return,
+                        };
+                        if buffer.rev != rev {
+                            return;
+                        }
+                        if let Some(tokens) =
+                            lsp_client.format_semantic_tokens(buffer, &tokens)
+                        {
+                            local_dispatcher.send_notification(
+                                "semantic_tokens",
+                                json!({
+                                    "rev": rev,
+                                    "buffer_id": buffer_id,
+                                    "path": path,
+                                    "tokens": tokens,
+                                }),
+                            )
+                        }
                     }
-                }
-            });
+                },
+            );
+        }
+    }
+
+    pub fn get_semantic_tokens_range(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        range: Range,
+    ) {
+        if let Some(client) = self.client_for(
+            &buffer.language_id,
+            LspClient::supports_semantic_tokens_range,
+        ) {
+            let uri = client.get_uri(buffer);
+            if !client.supports_semantic_tokens_range() {
+                client.dispatcher.respond(
+                    id,
+                    Err(anyhow!("semantic tokens range not supported")),
+                );
+                return;
+            }
+            let buffer_id = buffer.id;
+            let local_dispatcher = self.dispatcher.clone().unwrap();
+            client.request_semantic_tokens_range(
+                uri,
+                range,
+                move |lsp_client, result| {
+                    let result = result.and_then(|value| {
+                        let tokens: SemanticTokens = serde_json::from_value(value)?;
+                        let buffers = local_dispatcher.buffers.lock();
+                        let buffer = buffers
+                            .get(&buffer_id)
+                            .ok_or_else(|| anyhow!("buffer closed"))?;
+                        let tokens = lsp_client
+                            .format_semantic_tokens(buffer, &tokens.data)
+                            .ok_or_else(|| anyhow!("no semantic tokens legend"))?;
+                        Ok(to_value(tokens)?)
+                    });
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no semantic tokens")));
         }
     }
 
     pub fn get_document_symbols(&self, id: RequestId, buffer: &Buffer) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
+        if let Some(client) = self
+            .client_for(&buffer.language_id, LspClient::supports_document_symbols)
+        {
             let uri = client.get_uri(buffer);
             client.request_document_symbols(uri, move |lsp_client, result| {
                 lsp_client.dispatcher.respond(id, result);
@@ -151,8 +648,33 @@ impl LspCatalog {
         }
     }
 
+    pub fn get_workspace_symbols(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        query: String,
+    ) {
+        if let Some(client) = self
+            .client_for(&buffer.language_id, LspClient::supports_workspace_symbols)
+        {
+            client.get_uri(buffer);
+            client.request_workspace_symbols(query, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
+            });
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no workspace symbols")));
+        }
+    }
+
     pub fn get_document_formatting(&self, id: RequestId, buffer: &Buffer) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
+        if let Some(client) = self.primary_client_for(
+            &buffer.language_id,
+            LspSettings::formatting_server,
+            LspClient::supports_formatting,
+        ) {
             let uri = client.get_uri(buffer);
             client.request_document_formatting(uri, move |lsp_client, result| {
                 lsp_client.dispatcher.respond(id, result);
@@ -165,6 +687,80 @@ impl LspCatalog {
         }
     }
 
+    pub fn get_document_range_formatting(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        range: Range,
+    ) {
+        if let Some(client) = self.primary_client_for(
+            &buffer.language_id,
+            LspSettings::formatting_server,
+            LspClient::supports_range_formatting,
+        ) {
+            if !client.supports_range_formatting() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("range formatting not supported")));
+                return;
+            }
+            let uri = client.get_uri(buffer);
+            client.request_document_range_formatting(
+                uri,
+                range,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no range formatting")));
+        }
+    }
+
+    /// Like signature help, a request is sent for every typed character and
+    /// only the server's trigger characters are passed on.
+    pub fn get_on_type_formatting(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+        ch: String,
+    ) {
+        if let Some(client) = self.primary_client_for(
+            &buffer.language_id,
+            LspSettings::formatting_server,
+            LspClient::supports_on_type_formatting,
+        ) {
+            if !client.is_on_type_formatting_trigger(&ch) {
+                client.dispatcher.respond(
+                    id,
+                    Err(anyhow!("not an on type formatting trigger character")),
+                );
+                return;
+            }
+            let uri = client.get_uri(buffer);
+            client.request_on_type_formatting(
+                uri,
+                position,
+                ch,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no on type formatting")));
+        }
+    }
+
+    /// Asks every server of the language that completes for its items, and
+    /// answers with all of them in one list. Each item remembers the server
+    /// it came from, for resolving it later.
     pub fn get_completion(
         &self,
         id: RequestId,
@@ -172,632 +768,3019 @@ impl LspCatalog {
         buffer: &Buffer,
         position: Position,
     ) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
-            let uri = client.get_uri(buffer);
-            client.request_completion(uri, position, move |lsp_client, result| {
-                let mut resp = json!({ "id": id });
-                match result {
-                    Ok(v) => resp["result"] = v,
-                    Err(e) => {
-                        resp["error"] = json!({
-                            "code": 0,
-                            "message": format!("{}",e),
-                        })
-                    }
-                }
-                lsp_client.dispatcher.sender.send(resp);
-            });
+        let clients =
+            self.supporting_clients(buffer, LspClient::supports_completion);
+        if clients.is_empty() {
+            return;
         }
+        let dispatcher = self.dispatcher.clone().unwrap();
+        gather(
+            &clients,
+            |client, done| {
+                let uri = client.get_uri(buffer);
+                client.request_completion(uri, position, done);
+            },
+            move |results| {
+                let mut is_incomplete = false;
+                let mut items = Vec::new();
+                let mut error = None;
+                for (server, result) in results {
+                    let response = result.and_then(|value| {
+                        Ok(serde_json::from_value::<Option<CompletionResponse>>(
+                            value,
+                        )?)
+                    });
+                    let list = match response {
+                        Ok(Some(CompletionResponse::Array(items))) => {
+                            CompletionList {
+                                is_incomplete: false,
+                                items,
+                            }
+                        }
+                        Ok(Some(CompletionResponse::List(list))) => list,
+                        Ok(None) => continue,
+                        Err(err) => {
+                            error = Some(err);
+                            continue;
+                        }
+                    };
+                    is_incomplete |= list.is_incomplete;
+                    items.extend(list.items.into_iter().map(|mut item| {
+                        item.data = Some(json!({
+                            "server": server,
+                            "data": item.data.take(),
+                        }));
+                        item
+                    }));
+                }
+                let result = match error {
+                    Some(err) if items.is_empty() => Err(err),
+                    _ => Ok(to_value(CompletionResponse::List(CompletionList {
+                        is_incomplete,
+                        items,
+                    }))
+                    .unwrap()),
+                };
+                dispatcher.respond(id, result);
+            },
+        );
     }
 
+    /// Resolves an item with the server it came from, which gets it back
+    /// with the data it gave it.
     pub fn completion_resolve(
         &self,
         id: RequestId,
         buffer: &Buffer,
         completion_item: &CompletionItem,
     ) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
-            client.completion_resolve(completion_item, move |lsp_client, result| {
-                let mut resp = json!({ "id": id });
-                match result {
-                    Ok(v) => resp["result"] = v,
-                    Err(e) => {
-                        resp["error"] = json!({
-                            "code": 0,
-                            "message": format!("{}",e),
-                        })
-                    }
-                }
-                lsp_client.dispatcher.sender.send(resp);
-            });
+        let mut completion_item = completion_item.clone();
+        let wrapped = completion_item.data.clone();
+        let (server, data) = unwrap_data(completion_item.data.take());
+        completion_item.data = data;
+        let client = server
+            .as_ref()
+            .and_then(|server| self.client_by_name(server))
+            .or_else(|| self.language_clients(&buffer.language_id).first());
+        if let Some(client) = client {
+            if !client.supports_completion_resolve() {
+                // nothing more to learn about the item, so it goes back as
+                // it came
+                completion_item.data = wrapped;
+                client
+                    .dispatcher
+                    .respond(id, Ok(serde_json::to_value(completion_item).unwrap()));
+                return;
+            }
+            client.completion_resolve(
+                &completion_item,
+                move |lsp_client, result| {
+                    let result = result.map(|mut item| {
+                        if item.is_object() {
+                            let data = item
+                                .get_mut("data")
+                                .map(|data| data.take())
+                                .unwrap_or(Value::Null);
+                            item["data"] = json!({
+                                "server": lsp_client.name,
+                                "data": data,
+                            });
+                        }
+                        item
+                    });
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
         }
     }
 
-    pub fn get_signature(&self, id: RequestId, buffer: &Buffer, position: Position) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
-            let uri = client.get_uri(buffer);
-            client.request_signature(uri, position, move |lsp_client, result| {
-                let mut resp = json!({ "id": id });
-                match result {
-                    Ok(v) => resp["result"] = v,
-                    Err(e) => {
-                        resp["error"] = json!({
-                            "code": 0,
-                            "message": format!("{}",e),
-                        })
-                    }
-                }
-                lsp_client.dispatcher.sender.send(resp);
-            });
+    /// The servers of the buffer's language that `supported` says can
+    /// answer, or the main one alone when none can.
+    fn supporting_clients(
+        &self,
+        buffer: &Buffer,
+        supported: fn(&LspClient) -> bool,
+    ) -> Vec<Arc<LspClient>> {
+        let clients = self.language_clients(&buffer.language_id);
+        let supporting: Vec<Arc<LspClient>> = clients
+            .iter()
+            .filter(|client| supported(client))
+            .cloned()
+            .collect();
+        if supporting.is_empty() {
+            clients.iter().take(1).cloned().collect()
+        } else {
+            supporting
         }
     }
 
-    pub fn get_references(
+    /// Asks for signature help. A request triggered by a typed character
+    /// is only passed on when the server listed that character, so the
+    /// editor can send one for every character without knowing them.
+    pub fn get_signature(
         &self,
         id: RequestId,
         buffer: &Buffer,
         position: Position,
+        context: Option<SignatureHelpContext>,
     ) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
-            let uri = client.get_uri(buffer);
-            client.request_references(uri, position, move |lsp_client, result| {
-                let mut resp = json!({ "id": id });
-                match result {
-                    Ok(v) => resp["result"] = v,
-                    Err(e) => {
-                        resp["error"] = json!({
-                            "code": 0,
-                            "message": format!("{}",e),
-                        })
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_signature_help)
+        {
+            if !client.supports_signature_help() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("signature help not supported")));
+                return;
+            }
+            let context = match context {
+                Some(context) => match client.signature_help_context(context) {
+                    Some(context) => Some(context),
+                    None => {
+                        client.dispatcher.respond(
+                            id,
+                            Err(anyhow!("not a signature help trigger character")),
+                        );
+                        return;
                     }
-                }
-                lsp_client.dispatcher.sender.send(resp);
+                },
+                None => None,
+            };
+            let uri = client.get_uri(buffer);
+            client.request_signature(
+                uri,
+                position,
+                context,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no signature help")));
+        }
+    }
+
+    /// Asks for hover, from the server the settings make primary for it
+    /// when there is one.
+    pub fn get_hover(&self, id: RequestId, buffer: &Buffer, position: Position) {
+        if let Some(client) = self.primary_client_for(
+            &buffer.language_id,
+            LspSettings::hover_server,
+            LspClient::supports_hover,
+        ) {
+            if !client.supports_hover() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("hover not supported")));
+                return;
+            }
+            let uri = client.get_uri(buffer);
+            client.request_hover(uri, position, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
             });
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no hover")));
         }
     }
 
-    pub fn get_code_actions(
+    pub fn get_references(
         &self,
         id: RequestId,
         buffer: &Buffer,
         position: Position,
     ) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_references)
+        {
             let uri = client.get_uri(buffer);
-            let range = Range {
-                start: position,
-                end: position,
-            };
-            client.request_code_actions(uri, range, move |lsp_client, result| {
-                let mut resp = json!({ "id": id });
-                match result {
-                    Ok(v) => resp["result"] = v,
-                    Err(e) => {
-                        resp["error"] = json!({
-                            "code": 0,
-                            "message": format!("{}",e),
-                        })
-                    }
-                }
-                lsp_client.dispatcher.sender.send(resp);
+            client.request_references(uri, position, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
             });
         }
     }
 
-    pub fn get_definition(
+    /// Asks every server of the language that has code actions for them,
+    /// and answers with all of them. Each action remembers the server it
+    /// came from, for resolving it later. Each server is told about its own
+    /// diagnostics at `position`, to offer the fixes for them.
+    pub fn get_code_actions(
         &self,
         id: RequestId,
-        request_id: usize,
         buffer: &Buffer,
         position: Position,
     ) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
-            let uri = client.get_uri(buffer);
-            client.request_definition(uri, position, move |lsp_client, result| {
-                let mut resp = json!({ "id": id });
-                match result {
-                    Ok(v) => resp["result"] = v,
-                    Err(e) => {
-                        resp["error"] = json!({
-                            "code": 0,
-                            "message": format!("{}",e),
-                        })
+        let clients =
+            self.supporting_clients(buffer, LspClient::supports_code_actions);
+        if clients.is_empty() {
+            return;
+        }
+        let range = Range {
+            start: position,
+            end: position,
+        };
+        let dispatcher = self.dispatcher.clone().unwrap();
+        gather(
+            &clients,
+            |client, done| {
+                let uri = client.get_uri(buffer);
+                let diagnostics = client
+                    .diagnostics_of(&uri)
+                    .into_iter()
+                    .filter(|d| d.range.start <= position && position <= d.range.end)
+                    .collect();
+                let context = CodeActionContext {
+                    diagnostics,
+                    only: None,
+                };
+                client.request_code_actions(uri, range, context, done);
+            },
+            move |results| {
+                let mut actions = Vec::new();
+                let mut error = None;
+                for (server, result) in results {
+                    match result {
+                        Ok(Value::Array(server_actions)) => actions.extend(
+                            server_actions
+                                .into_iter()
+                                .map(|action| wrap_code_action(&server, action)),
+                        ),
+                        Ok(_) => {}
+                        Err(err) => error = Some(err),
                     }
                 }
-                lsp_client.dispatcher.sender.send(resp);
-            });
+                let result = match error {
+                    Some(err) if actions.is_empty() => Err(err),
+                    _ => Ok(Value::Array(actions)),
+                };
+                dispatcher.respond(id, result);
+            },
+        );
+    }
+
+    /// Collects the quickfixes each server prefers for the diagnostics it
+    /// published for the buffer, resolved so that they all come with their
+    /// edits.
+    pub fn get_preferred_fixes(&self, id: RequestId, buffer: &Buffer) {
+        let dispatcher = self.dispatcher.clone().unwrap();
+        let clients =
+            self.supporting_clients(buffer, LspClient::supports_code_actions);
+        if clients.is_empty() {
+            dispatcher.respond(id, Ok(json!([])));
+            return;
         }
+        let resolving = clients.clone();
+        gather(
+            &clients,
+            |client, done| {
+                // one request for each diagnostic, all answered as one
+                let uri = client.get_uri(buffer);
+                let diagnostics = client.diagnostics_of(&uri);
+                if diagnostics.is_empty() {
+                    done(client, Ok(json!([])));
+                    return;
+                }
+                let answers = Arc::new(Mutex::new((
+                    diagnostics.len(),
+                    Vec::new(),
+                    Some(done),
+                )));
+                for diagnostic in &diagnostics {
+                    let answers = answers.clone();
+                    let context = CodeActionContext {
+                        diagnostics: vec![diagnostic.clone()],
+                        only: Some(vec![CodeActionKind::QUICKFIX]),
+                    };
+                    client.request_code_actions(
+                        uri.clone(),
+                        diagnostic.range,
+                        context,
+                        move |lsp_client, result| {
+                            let mut answers = answers.lock();
+                            if let Ok(Value::Array(actions)) = result {
+                                answers.1.extend(actions);
+                            }
+                            answers.0 -= 1;
+                            if answers.0 == 0 {
+                                let actions = std::mem::take(&mut answers.1);
+                                if let Some(done) = answers.2.take() {
+                                    done(lsp_client, Ok(Value::Array(actions)));
+                                }
+                            }
+                        },
+                    );
+                }
+            },
+            move |results| {
+                let mut fixes: Vec<(Arc<LspClient>, CodeAction)> = Vec::new();
+                for (server, result) in results {
+                    let client = match resolving
+                        .iter()
+                        .find(|client| client.name == server)
+                    {
+                        Some(client) => client,
+                        None => continue,
+                    };
+                    let actions = match result {
+                        Ok(Value::Array(actions)) => actions,
+                        _ => continue,
+                    };
+                    for action in actions {
+                        let action = match serde_json::from_value(action) {
+                            Ok(CodeActionOrCommand::CodeAction(action)) => action,
+                            _ => continue,
+                        };
+                        let is_quickfix = action
+                            .kind
+                            .as_ref()
+                            .map(|kind| {
+                                kind.as_str()
+                                    .starts_with(CodeActionKind::QUICKFIX.as_str())
+                            })
+                            .unwrap_or(false);
+                        if is_quickfix
+                            && action.is_preferred == Some(true)
+                            && action.disabled.is_none()
+                            && !fixes.iter().any(|(_, fix)| *fix == action)
+                        {
+                            fixes.push((client.clone(), action));
+                        }
+                    }
+                }
+                resolve_fixes(
+                    fixes,
+                    Vec::new(),
+                    Box::new(move |fixes| {
+                        dispatcher.respond(id, Ok(to_value(fixes).unwrap()));
+                    }),
+                );
+            },
+        );
     }
 
-    pub fn update(
+    /// Fills in what a code action left out, the edit mostly, with the
+    /// server it came from.
+    pub fn code_action_resolve(
         &self,
+        id: RequestId,
         buffer: &Buffer,
-        content_change: &TextDocumentContentChangeEvent,
-        rev: u64,
+        action: &CodeAction,
     ) {
-        if let Some(client) = self.clients.get(&buffer.language_id) {
-            client.update(buffer, content_change, rev);
-        }
+        let mut action = action.clone();
+        let wrapped = action.data.clone();
+        let (server, data) = unwrap_data(action.data.take());
+        action.data = data;
+        let client = server
+            .as_ref()
+            .and_then(|server| self.client_by_name(server))
+            .or_else(|| self.language_clients(&buffer.language_id).first());
+        if let Some(client) = client {
+            if !client.supports_code_action_resolve() {
+                action.data = wrapped;
+                client
+                    .dispatcher
+                    .respond(id, Ok(serde_json::to_value(action).unwrap()));
+                return;
+            }
+            client.request_code_action_resolve(
+                &action,
+                move |lsp_client, result| {
+                    let result = result
+                        .map(|action| wrap_code_action(&lsp_client.name, action));
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no code action resolve")));
+        }
     }
-}
-
-impl LspClient {
-    pub fn new(
-        language_id: String,
-        exec_path: &str,
-        options: Option<Value>,
-        dispatcher: Dispatcher,
-    ) -> Arc<LspClient> {
-        let mut process = Command::new(exec_path)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("Error Occurred");
-
-        let writer = Box::new(BufWriter::new(process.stdin.take().unwrap()));
-        let stdout = process.stdout.take().unwrap();
 
-        let lsp_client = Arc::new(LspClient {
-            dispatcher,
-            language_id,
-            options,
-            state: Arc::new(Mutex::new(LspState {
-                next_id: 0,
-                writer,
-                process,
-                pending: HashMap::new(),
-                server_capabilities: None,
-                opened_documents: HashMap::new(),
-                is_initialized: false,
-            })),
-        });
+    pub fn get_definition(
+        &self,
+        id: RequestId,
+        request_id: usize,
+        buffer: &Buffer,
+        position: Position,
+    ) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_definition)
+        {
+            let uri = client.get_uri(buffer);
+            client.request_definition(uri, position, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
+            });
+        }
+    }
 
-        let local_lsp_client = lsp_client.clone();
-        thread::spawn(move || {
-            let mut reader = Box::new(BufReader::new(stdout));
-            loop {
-                match read_message(&mut reader) {
-                    Ok(message_str) => {
-                        local_lsp_client.handle_message(message_str.as_ref());
-                    }
-                    Err(err) => {
-                        eprintln!("lsp read Error occurred {:?}", err);
-                        return;
-                    }
-                };
+    pub fn prepare_rename(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+    ) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_rename)
+        {
+            let uri = client.get_uri(buffer);
+            if !client.supports_rename() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("rename not supported")));
+                return;
             }
-        });
-
-        lsp_client
+            if !client.supports_prepare_rename() {
+                // The server can still rename, the client just has to pick
+                // the range itself.
+                client
+                    .dispatcher
+                    .respond(id, Ok(json!({ "defaultBehavior": true })));
+                return;
+            }
+            client.request_prepare_rename(
+                uri,
+                position,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no prepare rename")));
+        }
     }
 
-    pub fn get_uri(&self, buffer: &Buffer) -> Url {
-        let exits = {
-            let state = self.state.lock();
-            state.opened_documents.contains_key(&buffer.id)
-        };
-        if !exits {
-            let document_uri = Url::from_file_path(&buffer.path).unwrap();
-            self.send_did_open(
-                &buffer.id,
-                document_uri,
-                &buffer.language_id,
-                buffer.get_document(),
+    pub fn rename(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+        new_name: String,
+    ) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_rename)
+        {
+            let uri = client.get_uri(buffer);
+            client.request_rename(
+                uri,
+                position,
+                new_name,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
             );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no rename")));
         }
-        self.state
-            .lock()
-            .opened_documents
-            .get(&buffer.id)
-            .unwrap()
-            .clone()
     }
 
-    pub fn handle_message(&self, message: &str) {
-        match JsonRpc::parse(message) {
-            Ok(JsonRpc::Request(obj)) => {
-                // trace!("client received unexpected request: {:?}", obj)
-            }
-            Ok(value @ JsonRpc::Notification(_)) => {
-                self.handle_notification(
-                    value.get_method().unwrap(),
-                    value.get_params().unwrap(),
-                );
-            }
-            Ok(value @ JsonRpc::Success(_)) => {
-                let id = number_from_id(&value.get_id().unwrap());
-                let result = value.get_result().unwrap();
-                self.handle_response(id, Ok(result.clone()));
-            }
-            Ok(value @ JsonRpc::Error(_)) => {
-                let id = number_from_id(&value.get_id().unwrap());
-                let error = value.get_error().unwrap();
-                self.handle_response(id, Err(anyhow!("{}", error)));
+    pub fn get_inlay_hints(&self, id: RequestId, buffer: &Buffer, range: Range) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_inlay_hints)
+        {
+            let uri = client.get_uri(buffer);
+            if !client.supports_inlay_hints() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("inlay hints not supported")));
+                return;
             }
-            Err(err) => {
-                eprintln!("Error in parsing incoming string: {} \n {}", err, message)
+            client.request_inlay_hints(uri, range, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
+            });
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no inlay hints")));
+        }
+    }
+
+    /// Hints are resolved by the server that was asked for them.
+    pub fn resolve_inlay_hint(&self, id: RequestId, buffer: &Buffer, hint: Value) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_inlay_hints)
+        {
+            if !client.supports_inlay_hint_resolve() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("inlay hint resolve not supported")));
+                return;
             }
+            client.request_inlay_hint_resolve(hint, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
+            });
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no inlay hint resolve")));
         }
     }
 
-    pub fn handle_notification(&self, method: &str, params: Params) {
-        match method {
-            "textDocument/publishDiagnostics" => {
-                self.dispatcher.send_notification(
-                    "publish_diagnostics",
-                    json!({
-                        "diagnostics": params,
-                    }),
+    /// Asks the servers of the buffer's language that can for inline
+    /// completions at `position`, and calls `done` with what they all
+    /// suggested, in the order of the servers. A server that fails only
+    /// suggests nothing, and has the failure in its log.
+    pub fn get_inline_completion<D>(
+        &self,
+        buffer: &Buffer,
+        position: Position,
+        automatic: bool,
+        done: D,
+    ) where
+        D: 'static + Send + FnOnce(Vec<InlineCompletionItem>),
+    {
+        let clients: Vec<Arc<LspClient>> = self
+            .language_clients(&buffer.language_id)
+            .iter()
+            .filter(|client| client.supports_inline_completion())
+            .cloned()
+            .collect();
+        if clients.is_empty() {
+            done(Vec::new());
+            return;
+        }
+        gather(
+            &clients,
+            |client, done| {
+                let uri = client.get_uri(buffer);
+                client.request_inline_completion(
+                    uri,
+                    position,
+                    automatic,
+                    move |lsp_client, result| {
+                        if let Err(err) = result.as_ref() {
+                            lsp_client.append_log(
+                                LspLogKind::Messages,
+                                format!("inline completion failed: {}", err),
+                            );
+                        }
+                        done(lsp_client, result);
+                    },
                 );
+            },
+            move |results| {
+                let mut items = Vec::new();
+                for (server, result) in results {
+                    if let Ok(value) = result {
+                        items.extend(parse_items(&server, value));
+                    }
+                }
+                done(items);
+            },
+        );
+    }
+
+    pub fn get_type_definition(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+    ) {
+        self.get_goto_location(
+            id,
+            buffer,
+            position,
+            "textDocument/typeDefinition",
+            LspClient::supports_type_definition,
+        );
+    }
+
+    pub fn get_implementation(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+    ) {
+        self.get_goto_location(
+            id,
+            buffer,
+            position,
+            "textDocument/implementation",
+            LspClient::supports_implementation,
+        );
+    }
+
+    pub fn get_declaration(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+    ) {
+        self.get_goto_location(
+            id,
+            buffer,
+            position,
+            "textDocument/declaration",
+            LspClient::supports_declaration,
+        );
+    }
+
+    /// Sends one of the requests that take a position and answer with
+    /// locations the way `textDocument/definition` does.
+    fn get_goto_location(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+        method: &str,
+        supported: fn(&LspClient) -> bool,
+    ) {
+        if let Some(client) = self.client_for(&buffer.language_id, supported) {
+            if !supported(client) {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("{} not supported", method)));
+                return;
             }
-            _ => eprintln!("{} {:?}", method, params),
+            let uri = client.get_uri(buffer);
+            client.request_goto_location(
+                method,
+                uri,
+                position,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no {}", method)));
         }
     }
 
-    pub fn handle_response(&self, id: u64, result: Result<Value>) {
-        let callback =
-            {
-                self.state.lock().pending.remove(&id).unwrap_or_else(|| {
-                    panic!("id {} missing from request table", id)
-                })
-            };
-        callback.call(self, result);
+    pub fn prepare_call_hierarchy(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+    ) {
+        self.prepare_hierarchy(
+            id,
+            buffer,
+            position,
+            "textDocument/prepareCallHierarchy",
+            LspClient::supports_call_hierarchy,
+        );
     }
 
-    pub fn write(&self, msg: &str) {
-        let mut state = self.state.lock();
-        state
-            .writer
-            .write_all(msg.as_bytes())
-            .expect("error writing to stdin");
-        state.writer.flush().expect("error flushing child stdin");
+    pub fn prepare_type_hierarchy(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+    ) {
+        self.prepare_hierarchy(
+            id,
+            buffer,
+            position,
+            "textDocument/prepareTypeHierarchy",
+            LspClient::supports_type_hierarchy,
+        );
     }
 
-    fn send_rpc(&self, value: &Value) {
-        let rpc = match prepare_lsp_json(value) {
-            Ok(r) => r,
-            Err(err) => panic!("Encoding Error {:?}", err),
-        };
-
-        self.write(rpc.as_ref());
+    pub fn get_incoming_calls(&self, id: RequestId, buffer: &Buffer, item: Value) {
+        self.get_hierarchy_items(
+            id,
+            buffer,
+            item,
+            "callHierarchy/incomingCalls",
+            LspClient::supports_call_hierarchy,
+        );
     }
 
-    pub fn send_notification(&self, method: &str, params: Params) {
-        let notification = JsonRpc::notification_with_params(method, params);
-        let res = to_value(&notification).unwrap();
-        self.send_rpc(&res);
+    pub fn get_outgoing_calls(&self, id: RequestId, buffer: &Buffer, item: Value) {
+        self.get_hierarchy_items(
+            id,
+            buffer,
+            item,
+            "callHierarchy/outgoingCalls",
+            LspClient::supports_call_hierarchy,
+        );
     }
 
-    pub fn send_request(&self, method: &str, params: Params, completion: Callback) {
-        let request = {
-            let mut state = self.state.lock();
-            let next_id = state.next_id;
-            state.pending.insert(next_id, completion);
-            state.next_id += 1;
+    pub fn get_supertypes(&self, id: RequestId, buffer: &Buffer, item: Value) {
+        self.get_hierarchy_items(
+            id,
+            buffer,
+            item,
+            "typeHierarchy/supertypes",
+            LspClient::supports_type_hierarchy,
+        );
+    }
 
-            JsonRpc::request_with_params(Id::Num(next_id as i64), method, params)
-        };
+    pub fn get_subtypes(&self, id: RequestId, buffer: &Buffer, item: Value) {
+        self.get_hierarchy_items(
+            id,
+            buffer,
+            item,
+            "typeHierarchy/subtypes",
+            LspClient::supports_type_hierarchy,
+        );
+    }
 
-        self.send_rpc(&to_value(&request).unwrap());
+    /// Sends one of the `prepare*Hierarchy` requests, which answer with the
+    /// items at a position that the tree is then grown from.
+    fn prepare_hierarchy(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+        method: &str,
+        supported: fn(&LspClient) -> bool,
+    ) {
+        if let Some(client) = self.client_for(&buffer.language_id, supported) {
+            if !supported(client) {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("{} not supported", method)));
+                return;
+            }
+            let uri = client.get_uri(buffer);
+            client.request_prepare_hierarchy(
+                method,
+                uri,
+                position,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no {}", method)));
+        }
     }
 
-    pub fn send_did_open(
+    /// Sends one of the requests that expand an item the server handed out
+    /// earlier, which it gets back untouched.
+    fn get_hierarchy_items(
         &self,
-        buffer_id: &BufferId,
-        document_uri: Url,
-        language_id: &str,
-        document_text: String,
+        id: RequestId,
+        buffer: &Buffer,
+        item: Value,
+        method: &str,
+        supported: fn(&LspClient) -> bool,
     ) {
-        let is_initialized = {
-            let mut state = self.state.lock();
-            state
+        if let Some(client) = self.client_for(&buffer.language_id, supported) {
+            if !supported(client) {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("{} not supported", method)));
+                return;
+            }
+            client.request_hierarchy_items(
+                method,
+                item,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no {}", method)));
+        }
+    }
+
+    pub fn get_document_highlights(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        position: Position,
+    ) {
+        if let Some(client) = self
+            .client_for(&buffer.language_id, LspClient::supports_document_highlight)
+        {
+            let uri = client.get_uri(buffer);
+            if !client.supports_document_highlight() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("document highlight not supported")));
+                return;
+            }
+            client.request_document_highlights(
+                uri,
+                position,
+                move |lsp_client, result| {
+                    lsp_client.dispatcher.respond(id, result);
+                },
+            );
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no document highlight")));
+        }
+    }
+
+    pub fn get_code_lens(&self, id: RequestId, buffer: &Buffer) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_code_lens)
+        {
+            let uri = client.get_uri(buffer);
+            if !client.supports_code_lens() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("code lens not supported")));
+                return;
+            }
+            client.request_code_lens(uri, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
+            });
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no code lens")));
+        }
+    }
+
+    /// Lenses are resolved by the server that was asked for them.
+    pub fn resolve_code_lens(&self, id: RequestId, buffer: &Buffer, lens: CodeLens) {
+        if let Some(client) =
+            self.client_for(&buffer.language_id, LspClient::supports_code_lens)
+        {
+            if !client.supports_code_lens_resolve() {
+                client
+                    .dispatcher
+                    .respond(id, Err(anyhow!("code lens resolve not supported")));
+                return;
+            }
+            client.request_code_lens_resolve(lens, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
+            });
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no code lens resolve")));
+        }
+    }
+
+    /// Runs a command on the server of the language that lists it.
+    pub fn execute_command(
+        &self,
+        id: RequestId,
+        buffer: &Buffer,
+        command: lsp_types::Command,
+    ) {
+        let client = self
+            .language_clients(&buffer.language_id)
+            .iter()
+            .find(|client| client.supports_execute_command(&command.command))
+            .or_else(|| self.language_clients(&buffer.language_id).first());
+        if let Some(client) = client {
+            if !client.supports_execute_command(&command.command) {
+                client.dispatcher.respond(
+                    id,
+                    Err(anyhow!("command {} not supported", command.command)),
+                );
+                return;
+            }
+            client.request_execute_command(command, move |lsp_client, result| {
+                lsp_client.dispatcher.respond(id, result);
+            });
+        } else {
+            self.dispatcher
+                .as_ref()
+                .unwrap()
+                .respond(id, Err(anyhow!("no execute command")));
+        }
+    }
+
+    pub fn update(
+        &self,
+        buffer: &Buffer,
+        content_changes: &[TextDocumentContentChangeEvent],
+        rev: u64,
+    ) {
+        for client in self.language_clients(&buffer.language_id) {
+            client.update(buffer, content_changes, rev);
+        }
+    }
+}
+
+impl LspClient {
+    pub fn new(
+        name: String,
+        settings: LspSettings,
+        root: Option<PathBuf>,
+        negotiate_encoding: bool,
+        diagnostics: Diagnostics,
+        dispatcher: Dispatcher,
+    ) -> Arc<LspClient> {
+        let lsp_client = Arc::new(LspClient {
+            dispatcher,
+            name,
+            settings,
+            root,
+            negotiate_encoding,
+            diagnostics,
+            state: Arc::new(Mutex::new(LspState {
+                next_id: 0,
+                writer: Box::new(io::sink()),
+                process: None,
+                generation: 0,
+                status: LspStatus::Starting,
+                crashes: 0,
+                started: Instant::now(),
+                pending: HashMap::new(),
+                server_capabilities: None,
+                raw_server_capabilities: None,
+                opened_documents: HashMap::new(),
+                is_initialized: false,
+                position_encoding: PositionEncoding::default(),
+                registrations: HashMap::new(),
+                semantic_tokens: HashMap::new(),
+                semantic_tokens_requests: HashMap::new(),
+                semantic_tokens_request_count: 0,
+                trace: TraceOption::Off,
+                traced_requests: HashMap::new(),
+            })),
+            log: Mutex::new(LspLog::default()),
+            created: Instant::now(),
+        });
+        LspClient::start(&lsp_client);
+        lsp_client
+    }
+
+    /// Spawns the server and the thread reading what it sends. The server
+    /// is initialized along with the first document opened on it.
+    fn start(client: &Arc<LspClient>) {
+        let root = client.root_path();
+        let mut process = match Command::new(&client.settings.command)
+            .args(&client.settings.args)
+            .envs(&client.settings.env)
+            .current_dir(&root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(process) => process,
+            Err(err) => {
+                client.append_log(
+                    LspLogKind::Messages,
+                    format!("can't start {}: {}", client.settings.command, err),
+                );
+                client.set_status(LspStatus::Crashed);
+                return;
+            }
+        };
+
+        let writer = Box::new(BufWriter::new(process.stdin.take().unwrap()));
+        let stdout = process.stdout.take().unwrap();
+        let stderr = process.stderr.take().unwrap();
+        let generation = {
+            let mut state = client.state.lock();
+            state.generation += 1;
+            state.writer = writer;
+            state.process = Some(process);
+            state.started = Instant::now();
+            state.generation
+        };
+        client.set_status(LspStatus::Starting);
+
+        let local_lsp_client = client.clone();
+        thread::spawn(move || {
+            let mut reader = Box::new(BufReader::new(stdout));
+            loop {
+                match read_message(&mut reader) {
+                    Ok(message_str) => {
+                        local_lsp_client.handle_message(message_str.as_ref());
+                    }
+                    Err(err) => {
+                        local_lsp_client.append_log(
+                            LspLogKind::Messages,
+                            format!("server output ended: {}", err),
+                        );
+                        LspClient::handle_exit(&local_lsp_client, generation);
+                        return;
+                    }
+                };
+            }
+        });
+
+        let local_lsp_client = client.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                match line {
+                    Ok(line) => {
+                        local_lsp_client.append_log(LspLogKind::Stderr, line)
+                    }
+                    Err(_) => return,
+                }
+            }
+        });
+    }
+
+    /// Called when the output of the server ends. Unless the server was
+    /// stopped or replaced, it crashed, and it's started again after a delay
+    /// that doubles with every crash in a row.
+    fn handle_exit(client: &Arc<LspClient>, generation: u64) {
+        let crashes = {
+            let mut state = client.state.lock();
+            if state.generation != generation {
+                return;
+            }
+            if state.started.elapsed() > CRASH_RESET {
+                state.crashes = 0;
+            }
+            state.crashes += 1;
+            state.crashes
+        };
+        client.reset();
+        client.set_status(LspStatus::Crashed);
+        if crashes > MAX_CRASHES {
+            client.append_log(
+                LspLogKind::Messages,
+                format!(
+                    "{} keeps crashing, not restarting it",
+                    client.settings.command
+                ),
+            );
+            return;
+        }
+
+        let delay = Duration::from_millis(500 * 2u64.pow(crashes - 1));
+        let client = client.clone();
+        thread::spawn(move || {
+            thread::sleep(delay);
+            if client.state.lock().generation == generation {
+                LspClient::start_and_reopen(&client);
+            }
+        });
+    }
+
+    /// Stops the server and starts it again with a clean crash count.
+    pub fn restart(client: &Arc<LspClient>) {
+        client.stop();
+        client.state.lock().crashes = 0;
+        LspClient::start_and_reopen(client);
+    }
+
+    /// Starts the server and opens on it the documents that were open on
+    /// the one before. The buffers aren't kept locked while it does, since
+    /// opening the first one waits for the server to initialize.
+    fn start_and_reopen(client: &Arc<LspClient>) {
+        let documents = std::mem::take(&mut client.state.lock().opened_documents);
+        LspClient::start(client);
+
+        let documents: Vec<(BufferId, Url, String, String, u64)> = {
+            let buffers = client.dispatcher.buffers.lock();
+            documents
+                .into_iter()
+                .filter_map(|(buffer_id, uri)| {
+                    let buffer = buffers.get(&buffer_id)?;
+                    Some((
+                        buffer_id,
+                        uri,
+                        buffer.language_id.clone(),
+                        buffer.get_document(),
+                        buffer.rev,
+                    ))
+                })
+                .collect()
+        };
+        for (buffer_id, uri, language_id, text, rev) in documents {
+            if client
+                .state
+                .lock()
                 .opened_documents
-                .insert(buffer_id.clone(), document_uri.clone());
+                .contains_key(&buffer_id)
+            {
+                continue;
+            }
+            client.send_did_open(&buffer_id, uri, &language_id, text, rev);
+        }
+    }
+
+    /// Asks the server to shut down and exit, and kills it if it's still
+    /// there after a second.
+    pub fn stop(&self) {
+        let is_initialized = {
+            let mut state = self.state.lock();
+            if state.process.is_none() {
+                state.generation += 1;
+                drop(state);
+                self.set_status(LspStatus::Stopped);
+                return;
+            }
+            state.generation += 1;
             state.is_initialized
         };
 
-        if !is_initialized {
-            let root_url =
-                Url::from_directory_path(self.dispatcher.workspace.lock().clone())
-                    .unwrap();
+        if is_initialized {
             let (sender, receiver) = channel();
-            self.send_initialize(Some(root_url), move |lsp_client, result| {
-                if let Ok(result) = result {
-                    {
-                        let init_result: InitializeResult =
-                            serde_json::from_value(result).unwrap();
-                        let mut state = lsp_client.state.lock();
-                        state.server_capabilities = Some(init_result.capabilities);
-                        state.is_initialized = true;
-                    }
-                    lsp_client.send_initialized();
-                }
-                sender.send(true);
-            });
+            self.send_request(
+                "shutdown",
+                Params::None(()),
+                Box::new(move |_: &LspClient, _: Result<Value>| {
+                    sender.send(());
+                }),
+            );
             receiver.recv_timeout(Duration::from_millis(1000));
+            self.send_notification("exit", Params::None(()));
         }
 
-        let text_document_did_open_params = DidOpenTextDocumentParams {
-            text_document: TextDocumentItem {
-                language_id: language_id.to_string(),
-                uri: document_uri,
-                version: 0,
-                text: document_text,
-            },
+        if let Some(mut process) = self.state.lock().process.take() {
+            for _ in 0..10 {
+                if let Ok(Some(_)) = process.try_wait() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            process.kill();
+            process.wait();
+        }
+        self.reset();
+        self.set_status(LspStatus::Stopped);
+    }
+
+    /// Forgets everything about the server that's gone, failing the
+    /// requests it never answered.
+    fn reset(&self) {
+        let pending: Vec<Callback> = {
+            let mut state = self.state.lock();
+            if let Some(mut process) = state.process.take() {
+                process.kill();
+                process.wait();
+            }
+            state.writer = Box::new(io::sink());
+            state.is_initialized = false;
+            state.server_capabilities = None;
+            state.raw_server_capabilities = None;
+            state.registrations.clear();
+            state.semantic_tokens.clear();
+            state.semantic_tokens_requests.clear();
+            state.traced_requests.clear();
+            state
+                .pending
+                .drain()
+                .map(|(_, callback)| callback)
+                .collect()
         };
-        let params = Params::from(
-            serde_json::to_value(text_document_did_open_params).unwrap(),
+        for callback in pending {
+            callback.call(self, Err(anyhow!("language server exited")));
+        }
+
+        // What the server found is gone with it.
+        let uris: Vec<Url> = {
+            let mut diagnostics = self.diagnostics.lock();
+            diagnostics
+                .iter_mut()
+                .filter_map(|(uri, servers)| {
+                    servers.remove(&self.name).map(|_| uri.clone())
+                })
+                .collect()
+        };
+        for uri in uris {
+            self.publish_diagnostics(uri, None);
+        }
+    }
+
+    /// Sends the editor the diagnostics of all the servers for `uri`.
+    fn publish_diagnostics(&self, uri: Url, version: Option<i32>) {
+        let diagnostics: Vec<Diagnostic> = self
+            .diagnostics
+            .lock()
+            .get(&uri)
+            .map(|servers| servers.values().flatten().cloned().collect())
+            .unwrap_or_default();
+        self.dispatcher.send_notification(
+            "publish_diagnostics",
+            json!({
+                "diagnostics": PublishDiagnosticsParams {
+                    uri,
+                    diagnostics,
+                    version,
+                },
+            }),
         );
-        self.send_notification("textDocument/didOpen", params);
     }
 
-    pub fn send_did_save(&self, uri: Url) {
-        let params = DidSaveTextDocumentParams {
-            text_document: TextDocumentIdentifier { uri },
-            text: None,
+    fn set_status(&self, status: LspStatus) {
+        self.state.lock().status = status;
+        self.dispatcher.send_notification(
+            "lsp_status",
+            json!({
+                "server": self.name,
+                "status": status,
+            }),
+        );
+    }
+
+    /// The diagnostics this server published for `uri`.
+    pub fn diagnostics_of(&self, uri: &Url) -> Vec<Diagnostic> {
+        self.diagnostics
+            .lock()
+            .get(uri)
+            .and_then(|servers| servers.get(&self.name))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn get_uri(&self, buffer: &Buffer) -> Url {
+        let exits = {
+            let state = self.state.lock();
+            state.opened_documents.contains_key(&buffer.id)
+        };
+        if !exits {
+            let document_uri = Url::from_file_path(&buffer.path).unwrap();
+            self.send_did_open(
+                &buffer.id,
+                document_uri,
+                &buffer.language_id,
+                buffer.get_document(),
+                buffer.rev,
+            );
+        }
+        self.state
+            .lock()
+            .opened_documents
+            .get(&buffer.id)
+            .unwrap()
+            .clone()
+    }
+
+    fn root_path(&self) -> PathBuf {
+        match self.root.as_ref() {
+            Some(root) => root.clone(),
+            None => self.dispatcher.workspace.lock().clone(),
+        }
+    }
+
+    fn append_log(&self, kind: LspLogKind, entry: String) {
+        let entry =
+            format!("[{:>10.3}] {}", self.created.elapsed().as_secs_f64(), entry);
+        self.log.lock().push(kind, entry);
+    }
+
+    /// Turns tracing on or off, telling the server too so that it sends
+    /// its own `$/logTrace` entries or stops.
+    pub fn set_trace(&self, trace: TraceOption) {
+        let is_initialized = {
+            let mut state = self.state.lock();
+            state.trace = trace;
+            state.traced_requests.clear();
+            state.is_initialized
+        };
+        if is_initialized {
+            self.send_notification(
+                "$/setTrace",
+                Params::from(json!({ "value": trace })),
+            );
+        }
+    }
+
+    fn is_tracing(&self) -> bool {
+        self.state.lock().trace != TraceOption::Off
+    }
+
+    /// Adds a message sent to or received from the server to the trace.
+    /// Responses to requests the trace saw going out say how long the
+    /// server took, and with verbose tracing the payload is shown too.
+    fn trace_message(&self, outgoing: bool, message: &Value) {
+        let trace = self.state.lock().trace;
+        if trace == TraceOption::Off {
+            return;
+        }
+
+        let arrow = if outgoing { "-->" } else { "<--" };
+        let method = message.get("method").and_then(|method| method.as_str());
+        let (entry, payload) = match (method, message.get("id")) {
+            (Some(method), Some(id)) => {
+                if let (true, Some(id)) = (outgoing, id.as_u64()) {
+                    self.state
+                        .lock()
+                        .traced_requests
+                        .insert(id, (method.to_string(), Instant::now()));
+                }
+                (
+                    format!("{} request {} ({})", arrow, method, id),
+                    message.get("params"),
+                )
+            }
+            (Some(method), None) => (
+                format!("{} notification {}", arrow, method),
+                message.get("params"),
+            ),
+            (None, id) => {
+                let id = id.cloned().unwrap_or(Value::Null);
+                let request = match (outgoing, id.as_u64()) {
+                    (false, Some(id)) => {
+                        self.state.lock().traced_requests.remove(&id)
+                    }
+                    _ => None,
+                };
+                let mut entry = match request {
+                    Some((method, sent)) => format!(
+                        "{} response {} ({}) in {}ms",
+                        arrow,
+                        method,
+                        id,
+                        sent.elapsed().as_millis()
+                    ),
+                    None => format!("{} response ({})", arrow, id),
+                };
+                match message.get("error") {
+                    Some(error) => {
+                        if let Some(error) =
+                            error.get("message").and_then(|m| m.as_str())
+                        {
+                            entry.push_str(&format!(" failed: {}", error));
+                        }
+                        (entry, Some(error))
+                    }
+                    None => (entry, message.get("result")),
+                }
+            }
+        };
+
+        let entry = match payload {
+            Some(payload) if trace == TraceOption::Verbose => format!(
+                "{}\n{}",
+                entry,
+                serde_json::to_string_pretty(payload).unwrap_or_default()
+            ),
+            _ => entry,
+        };
+        self.append_log(LspLogKind::Trace, entry);
+    }
+
+    pub fn handle_message(&self, message: &str) {
+        if self.is_tracing() {
+            if let Ok(value) = serde_json::from_str::<Value>(message) {
+                self.trace_message(false, &value);
+            }
+        }
+        match JsonRpc::parse(message) {
+            Ok(value @ JsonRpc::Request(_)) => {
+                self.handle_request(
+                    value.get_id().unwrap(),
+                    value.get_method().unwrap(),
+                    value.get_params().unwrap_or(Params::None(())),
+                );
+            }
+            Ok(value @ JsonRpc::Notification(_)) => {
+                self.handle_notification(
+                    value.get_method().unwrap(),
+                    value.get_params().unwrap(),
+                );
+            }
+            Ok(value @ JsonRpc::Success(_)) => {
+                if let Some(id) = value.get_id().as_ref().and_then(number_from_id) {
+                    let result = value.get_result().unwrap();
+                    self.handle_response(id, Ok(result.clone()));
+                }
+            }
+            Ok(value @ JsonRpc::Error(_)) => {
+                if let Some(id) = value.get_id().as_ref().and_then(number_from_id) {
+                    let error = value.get_error().unwrap();
+                    self.handle_response(id, Err(anyhow!("{}", error)));
+                }
+            }
+            Err(err) => self.append_log(
+                LspLogKind::Messages,
+                format!("invalid message from the server: {}\n{}", err, message),
+            ),
+        }
+    }
+
+    /// Answers a request from the server. The ones that need the editor,
+    /// applying an edit or asking the user, are passed on to it, and it
+    /// answers them through `LspCatalog::respond_server_request`.
+    pub fn handle_request(&self, id: Id, method: &str, params: Params) {
+        let params = to_value(params).unwrap_or(Value::Null);
+        match method {
+            "workspace/applyEdit" => {
+                match serde_json::from_value::<ApplyWorkspaceEditParams>(params) {
+                    Ok(params) => self.dispatcher.send_notification(
+                        "lsp_apply_edit",
+                        json!({
+                            "server": self.name,
+                            "id": id,
+                            "edit": params.edit,
+                        }),
+                    ),
+                    Err(_) => {
+                        self.send_response(id, Err(RpcError::invalid_params()))
+                    }
+                }
+            }
+            "window/showMessageRequest" => {
+                match serde_json::from_value::<ShowMessageRequestParams>(params) {
+                    Ok(params) => self.dispatcher.send_notification(
+                        "lsp_show_message_request",
+                        json!({
+                            "server": self.name,
+                            "id": id,
+                            "params": params,
+                        }),
+                    ),
+                    Err(_) => {
+                        self.send_response(id, Err(RpcError::invalid_params()))
+                    }
+                }
+            }
+            "workspace/configuration" => {
+                match serde_json::from_value::<ConfigurationParams>(params) {
+                    Ok(params) => {
+                        let items: Vec<Value> = params
+                            .items
+                            .iter()
+                            .map(|item| self.configuration(item.section.as_deref()))
+                            .collect();
+                        self.send_response(id, Ok(Value::Array(items)));
+                    }
+                    Err(_) => {
+                        self.send_response(id, Err(RpcError::invalid_params()))
+                    }
+                }
+            }
+            "workspace/workspaceFolders" => {
+                let workspace = self.dispatcher.workspace.lock().clone();
+                let folders = Url::from_directory_path(&workspace)
+                    .map(|uri| {
+                        vec![WorkspaceFolder {
+                            uri,
+                            name: workspace
+                                .file_name()
+                                .and_then(|name| name.to_str())
+                                .unwrap_or("")
+                                .to_string(),
+                        }]
+                    })
+                    .unwrap_or(Vec::new());
+                self.send_response(id, Ok(to_value(folders).unwrap()));
+            }
+            "client/registerCapability" => {
+                match serde_json::from_value::<RegistrationParams>(params) {
+                    Ok(params) => {
+                        let mut state = self.state.lock();
+                        for registration in params.registrations {
+                            state
+                                .registrations
+                                .insert(registration.id.clone(), registration);
+                        }
+                        drop(state);
+                        self.send_response(id, Ok(Value::Null));
+                    }
+                    Err(_) => {
+                        self.send_response(id, Err(RpcError::invalid_params()))
+                    }
+                }
+            }
+            "client/unregisterCapability" => {
+                match serde_json::from_value::<UnregistrationParams>(params) {
+                    Ok(params) => {
+                        let mut state = self.state.lock();
+                        for unregistration in params.unregisterations {
+                            state.registrations.remove(&unregistration.id);
+                        }
+                        drop(state);
+                        self.send_response(id, Ok(Value::Null));
+                    }
+                    Err(_) => {
+                        self.send_response(id, Err(RpcError::invalid_params()))
+                    }
+                }
+            }
+            "window/workDoneProgress/create" => {
+                self.send_response(id, Ok(Value::Null));
+            }
+            _ => self.send_response(id, Err(RpcError::method_not_found())),
+        }
+    }
+
+    pub fn send_response(&self, id: Id, result: Result<Value, RpcError>) {
+        let response = match result {
+            Ok(value) => JsonRpc::success(id, &value),
+            Err(error) => JsonRpc::error(id, error),
+        };
+        self.send_rpc(&to_value(&response).unwrap());
+    }
+
+    /// What `workspace/configuration` answers for `section`, taken from the
+    /// `settings` of the server, or else the options it was started with.
+    /// Those are usually the server's own section, which goes by the
+    /// language, the name in the settings or the command.
+    fn configuration(&self, section: Option<&str>) -> Value {
+        let options = match self
+            .settings
+            .settings
+            .as_ref()
+            .or(self.settings.initialization_options.as_ref())
+        {
+            Some(options) => options,
+            None => return Value::Null,
+        };
+        let command = Path::new(&self.settings.command)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("");
+        let own_sections: Vec<&str> =
+            self.name.split('.').chain(Some(command)).collect();
+        section_value(options, &own_sections, section)
+    }
+
+    pub fn handle_notification(&self, method: &str, params: Params) {
+        match method {
+            "textDocument/publishDiagnostics" => {
+                let params = to_value(params).unwrap_or(Value::Null);
+                if let Ok(mut params) =
+                    serde_json::from_value::<PublishDiagnosticsParams>(params)
+                {
+                    // Tells apart the servers in the list of problems.
+                    for diagnostic in params.diagnostics.iter_mut() {
+                        if diagnostic.source.is_none() {
+                            diagnostic.source = Some(self.name.clone());
+                        }
+                    }
+                    self.diagnostics
+                        .lock()
+                        .entry(params.uri.clone())
+                        .or_insert_with(BTreeMap::new)
+                        .insert(self.name.clone(), params.diagnostics);
+                    self.publish_diagnostics(params.uri, params.version);
+                }
+            }
+            "window/logMessage" => {
+                let params = to_value(params).unwrap_or(Value::Null);
+                if let Ok(params) =
+                    serde_json::from_value::<LogMessageParams>(params)
+                {
+                    let level = match params.typ {
+                        MessageType::Error => "error",
+                        MessageType::Warning => "warning",
+                        MessageType::Info => "info",
+                        MessageType::Log => "log",
+                    };
+                    self.append_log(
+                        LspLogKind::Messages,
+                        format!("[{}] {}", level, params.message),
+                    );
+                }
+            }
+            "$/logTrace" => {
+                let params = to_value(params).unwrap_or(Value::Null);
+                let mut entry = format!(
+                    "<-- log {}",
+                    params["message"].as_str().unwrap_or_default()
+                );
+                if let Some(verbose) = params["verbose"].as_str() {
+                    entry.push_str(&format!("\n{}", verbose));
+                }
+                self.append_log(LspLogKind::Trace, entry);
+            }
+            _ => {
+                let params = to_value(params).unwrap_or(Value::Null);
+                self.append_log(
+                    LspLogKind::Messages,
+                    format!("unhandled notification {}: {}", method, params),
+                );
+            }
+        }
+    }
+
+    pub fn handle_response(&self, id: u64, result: Result<Value>) {
+        let callback = self.state.lock().pending.remove(&id);
+        match callback {
+            Some(callback) => callback.call(self, result),
+            None => self.append_log(
+                LspLogKind::Messages,
+                format!("response to unknown request {}", id),
+            ),
+        }
+    }
+
+    pub fn write(&self, msg: &str) {
+        let mut state = self.state.lock();
+        if let Err(err) = state
+            .writer
+            .write_all(msg.as_bytes())
+            .and_then(|_| state.writer.flush())
+        {
+            self.append_log(
+                LspLogKind::Messages,
+                format!("error writing to {}: {}", self.settings.command, err),
+            );
+        }
+    }
+
+    fn send_rpc(&self, value: &Value) {
+        self.trace_message(true, value);
+        let rpc = match prepare_lsp_json(value) {
+            Ok(r) => r,
+            Err(err) => panic!("Encoding Error {:?}", err),
+        };
+
+        self.write(rpc.as_ref());
+    }
+
+    pub fn send_notification(&self, method: &str, params: Params) {
+        let notification = match params {
+            Params::None(_) => JsonRpc::notification(method),
+            params => JsonRpc::notification_with_params(method, params),
+        };
+        let res = to_value(&notification).unwrap();
+        self.send_rpc(&res);
+    }
+
+    pub fn send_request(&self, method: &str, params: Params, completion: Callback) {
+        let request = {
+            let mut state = self.state.lock();
+            if state.process.is_none() {
+                drop(state);
+                completion.call(self, Err(anyhow!("language server not running")));
+                return;
+            }
+            let next_id = state.next_id;
+            state.pending.insert(next_id, completion);
+            state.next_id += 1;
+
+            match params {
+                Params::None(_) => JsonRpc::request(Id::Num(next_id as i64), method),
+                params => JsonRpc::request_with_params(
+                    Id::Num(next_id as i64),
+                    method,
+                    params,
+                ),
+            }
+        };
+
+        self.send_rpc(&to_value(&request).unwrap());
+    }
+
+    /// Opens a document on the server with the `rev` of its buffer as the
+    /// version, which the versions of its changes go on from.
+    pub fn send_did_open(
+        &self,
+        buffer_id: &BufferId,
+        document_uri: Url,
+        language_id: &str,
+        document_text: String,
+        rev: u64,
+    ) {
+        let is_initialized = {
+            let mut state = self.state.lock();
+            state
+                .opened_documents
+                .insert(buffer_id.clone(), document_uri.clone());
+            state.is_initialized
+        };
+
+        if !is_initialized {
+            let root_url = Url::from_directory_path(self.root_path()).unwrap();
+            let (sender, receiver) = channel();
+            self.send_initialize(Some(root_url), move |lsp_client, result| {
+                if let Ok(result) = result {
+                    let documents: Vec<Url> = {
+                        let raw_capabilities = result.get("capabilities").cloned();
+                        // clangd answers with `offsetEncoding`, from before
+                        // the protocol had `positionEncoding`.
+                        let position_encoding: PositionEncoding = raw_capabilities
+                            .as_ref()
+                            .and_then(|c| c.get("positionEncoding"))
+                            .or_else(|| result.get("offsetEncoding"))
+                            .and_then(|e| serde_json::from_value(e.clone()).ok())
+                            .unwrap_or_default();
+                        let init_result: InitializeResult =
+                            serde_json::from_value(result).unwrap();
+                        let mut state = lsp_client.state.lock();
+                        state.server_capabilities = Some(init_result.capabilities);
+                        state.raw_server_capabilities = raw_capabilities;
+                        state.position_encoding = position_encoding;
+                        state.is_initialized = true;
+                        state.opened_documents.values().cloned().collect()
+                    };
+                    for uri in documents.iter() {
+                        lsp_client.send_position_encoding(uri);
+                    }
+                    lsp_client.send_initialized();
+                    lsp_client.set_status(LspStatus::Running);
+                }
+                sender.send(true);
+            });
+            receiver.recv_timeout(Duration::from_millis(1000));
+        } else {
+            self.send_position_encoding(&document_uri);
+        }
+
+        let text_document_did_open_params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                language_id: language_id.to_string(),
+                uri: document_uri,
+                version: rev as i32,
+                text: document_text,
+            },
+        };
+        let params = Params::from(
+            serde_json::to_value(text_document_did_open_params).unwrap(),
+        );
+        self.send_notification("textDocument/didOpen", params);
+    }
+
+    /// Tells the editor how the server counts the characters of positions
+    /// in the document at `uri`.
+    fn send_position_encoding(&self, uri: &Url) {
+        if let Ok(path) = uri.to_file_path() {
+            let encoding = self.state.lock().position_encoding;
+            self.dispatcher.send_notification(
+                "position_encoding",
+                json!({
+                    "path": path,
+                    "encoding": encoding,
+                }),
+            );
+        }
+    }
+
+    /// Forgets a document no editor shows anymore, telling the server
+    /// unless it never got it.
+    pub fn send_did_close(&self, buffer_id: &BufferId) {
+        let (uri, is_initialized) = {
+            let mut state = self.state.lock();
+            state.semantic_tokens.remove(buffer_id);
+            state.semantic_tokens_requests.remove(buffer_id);
+            (
+                state.opened_documents.remove(buffer_id),
+                state.is_initialized,
+            )
+        };
+        if let (Some(uri), true) = (uri, is_initialized) {
+            let params = DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri },
+            };
+            let params = Params::from(serde_json::to_value(params).unwrap());
+            self.send_notification("textDocument/didClose", params);
+        }
+    }
+
+    /// The watchers the server registered for files it wants to hear about
+    /// when they change on disk.
+    fn file_watchers(&self) -> Vec<FileSystemWatcher> {
+        let state = self.state.lock();
+        state
+            .registrations
+            .values()
+            .filter(|r| r.method == "workspace/didChangeWatchedFiles")
+            .filter_map(|r| r.register_options.clone())
+            .filter_map(|options| {
+                serde_json::from_value::<DidChangeWatchedFilesRegistrationOptions>(
+                    options,
+                )
+                .ok()
+            })
+            .flat_map(|options| options.watchers)
+            .collect()
+    }
+
+    /// Tells the server about the changes on disk that one of its watchers
+    /// matches.
+    pub fn send_did_change_watched_files(
+        &self,
+        changes: &[(&PathBuf, FileChangeType)],
+    ) {
+        let watchers = self.file_watchers();
+        if watchers.is_empty() {
+            return;
+        }
+        let root = self.root_path();
+        let changes: Vec<FileEvent> = changes
+            .iter()
+            .filter(|(path, typ)| {
+                let kind = match typ {
+                    FileChangeType::Created => WatchKind::Create,
+                    FileChangeType::Changed => WatchKind::Change,
+                    FileChangeType::Deleted => WatchKind::Delete,
+                };
+                watchers.iter().any(|watcher| {
+                    watcher.kind.unwrap_or(WatchKind::all()).contains(kind)
+                        && glob_matches(&watcher.glob_pattern, path, &root)
+                })
+            })
+            .filter_map(|(path, typ)| {
+                Url::from_file_path(path)
+                    .ok()
+                    .map(|uri| FileEvent::new(uri, *typ))
+            })
+            .collect();
+        if changes.is_empty() {
+            return;
+        }
+        let params = DidChangeWatchedFilesParams { changes };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_notification("workspace/didChangeWatchedFiles", params);
+    }
+
+    pub fn send_did_save(&self, uri: Url) {
+        let params = DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri },
+            text: None,
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_notification("textDocument/didSave", params);
+    }
+
+    pub fn send_initialized(&self) {
+        self.send_notification("initialized", Params::from(json!({})));
+    }
+
+    pub fn send_initialize<CB>(&self, root_uri: Option<Url>, on_init: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let client_capabilities = ClientCapabilities {
+            text_document: Some(TextDocumentClientCapabilities {
+                completion: Some(CompletionClientCapabilities {
+                    completion_item: Some(CompletionItemCapability {
+                        snippet_support: Some(true),
+                        commit_characters_support: Some(true),
+                        insert_replace_support: Some(true),
+                        documentation_format: Some(vec![
+                            MarkupKind::Markdown,
+                            MarkupKind::PlainText,
+                        ]),
+                        resolve_support: Some(
+                            CompletionItemCapabilityResolveSupport {
+                                properties: vec![
+                                    "additionalTextEdits".to_string(),
+                                    "documentation".to_string(),
+                                    "detail".to_string(),
+                                ],
+                            },
+                        ),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                }),
+                signature_help: Some(SignatureHelpClientCapabilities {
+                    signature_information: Some(SignatureInformationSettings {
+                        parameter_information: Some(ParameterInformationSettings {
+                            label_offset_support: Some(true),
+                        }),
+                        active_parameter_support: Some(true),
+                        documentation_format: Some(vec![
+                            MarkupKind::Markdown,
+                            MarkupKind::PlainText,
+                        ]),
+                    }),
+                    context_support: Some(true),
+                    ..Default::default()
+                }),
+                code_action: Some(CodeActionClientCapabilities {
+                    code_action_literal_support: Some(CodeActionLiteralSupport {
+                        code_action_kind: CodeActionKindLiteralSupport {
+                            value_set: vec![
+                                CodeActionKind::EMPTY.as_str().to_string(),
+                                CodeActionKind::QUICKFIX.as_str().to_string(),
+                                CodeActionKind::REFACTOR.as_str().to_string(),
+                                CodeActionKind::REFACTOR_EXTRACT
+                                    .as_str()
+                                    .to_string(),
+                                CodeActionKind::REFACTOR_INLINE.as_str().to_string(),
+                                CodeActionKind::REFACTOR_REWRITE
+                                    .as_str()
+                                    .to_string(),
+                                CodeActionKind::SOURCE.as_str().to_string(),
+                                CodeActionKind::SOURCE_ORGANIZE_IMPORTS
+                                    .as_str()
+                                    .to_string(),
+                            ],
+                        },
+                    }),
+                    data_support: Some(true),
+                    resolve_support: Some(CodeActionCapabilityResolveSupport {
+                        properties: vec!["edit".to_string()],
+                    }),
+                    ..Default::default()
+                }),
+                semantic_tokens: Some(SemanticTokensClientCapabilities {
+                    requests: SemanticTokensClientCapabilitiesRequests {
+                        range: Some(true),
+                        full: Some(SemanticTokensFullOptions::Delta {
+                            delta: Some(true),
+                        }),
+                    },
+                    token_modifiers: vec![
+                        SemanticTokenModifier::DECLARATION,
+                        SemanticTokenModifier::DEFINITION,
+                        SemanticTokenModifier::READONLY,
+                        SemanticTokenModifier::STATIC,
+                        SemanticTokenModifier::DEPRECATED,
+                        SemanticTokenModifier::ABSTRACT,
+                        SemanticTokenModifier::ASYNC,
+                        SemanticTokenModifier::MODIFICATION,
+                        SemanticTokenModifier::DOCUMENTATION,
+                        SemanticTokenModifier::DEFAULT_LIBRARY,
+                    ],
+                    formats: vec![TokenFormat::RELATIVE],
+                    ..Default::default()
+                }),
+                rename: Some(RenameClientCapabilities {
+                    prepare_support: Some(true),
+                    ..Default::default()
+                }),
+                code_lens: Some(CodeLensClientCapabilities {
+                    dynamic_registration: Some(false),
+                }),
+                type_definition: Some(GotoCapability {
+                    dynamic_registration: Some(false),
+                    link_support: Some(false),
+                }),
+                implementation: Some(GotoCapability {
+                    dynamic_registration: Some(false),
+                    link_support: Some(false),
+                }),
+                declaration: Some(GotoCapability {
+                    dynamic_registration: Some(false),
+                    link_support: Some(false),
+                }),
+                call_hierarchy: Some(CallHierarchyClientCapabilities {
+                    dynamic_registration: Some(false),
+                }),
+                range_formatting: Some(DocumentRangeFormattingClientCapabilities {
+                    dynamic_registration: Some(false),
+                }),
+                on_type_formatting: Some(
+                    DocumentOnTypeFormattingClientCapabilities {
+                        dynamic_registration: Some(false),
+                    },
+                ),
+                ..Default::default()
+            }),
+            window: Some(WindowClientCapabilities {
+                work_done_progress: Some(true),
+                show_message: Some(ShowMessageRequestClientCapabilities {
+                    message_action_item: Some(MessageActionItemCapabilities {
+                        additional_properties_support: Some(false),
+                    }),
+                }),
+                ..Default::default()
+            }),
+            workspace: Some(WorkspaceClientCapabilities {
+                apply_edit: Some(true),
+                configuration: Some(true),
+                workspace_folders: Some(true),
+                did_change_watched_files: Some(
+                    DidChangeWatchedFilesClientCapabilities {
+                        dynamic_registration: Some(true),
+                    },
+                ),
+                workspace_edit: Some(WorkspaceEditClientCapabilities {
+                    document_changes: Some(true),
+                    resource_operations: Some(vec![
+                        ResourceOperationKind::Create,
+                        ResourceOperationKind::Rename,
+                        ResourceOperationKind::Delete,
+                    ]),
+                    // the operations before a failing one stay applied
+                    failure_handling: Some(FailureHandlingKind::Abort),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let init_params = InitializeParams {
+            process_id: Some(u32::from(process::id())),
+            root_uri,
+            initialization_options: self.settings.initialization_options.clone(),
+            capabilities: client_capabilities,
+            trace: Some(self.state.lock().trace),
+            workspace_folders: None,
+            client_info: None,
+            root_path: None,
+            locale: None,
+        };
+
+        let mut params = serde_json::to_value(init_params).unwrap();
+        params["capabilities"]["textDocument"]["inlayHint"] = json!({
+            "resolveSupport": {
+                "properties": ["tooltip", "label.tooltip"],
+            },
+        });
+        params["capabilities"]["textDocument"]["inlineCompletion"] = json!({
+            "dynamicRegistration": false,
+        });
+        params["capabilities"]["textDocument"]["typeHierarchy"] = json!({
+            "dynamicRegistration": false,
+        });
+        if self.negotiate_encoding {
+            params["capabilities"]["general"]["positionEncodings"] =
+                json!(["utf-8", "utf-32", "utf-16"]);
+            params["capabilities"]["offsetEncoding"] =
+                params["capabilities"]["general"]["positionEncodings"].clone();
+        }
+        let params = Params::from(params);
+        self.send_request("initialize", params, Box::new(on_init));
+    }
+
+    pub fn request_document_symbols<CB>(&self, document_uri: Url, cb: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri: document_uri },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/documentSymbol", params, Box::new(cb));
+    }
+
+    pub fn request_workspace_symbols<CB>(&self, query: String, cb: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = WorkspaceSymbolParams {
+            query,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("workspace/symbol", params, Box::new(cb));
+    }
+
+    pub fn request_document_formatting<CB>(&self, document_uri: Url, cb: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier { uri: document_uri },
+            options: formatting_options(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/formatting", params, Box::new(cb));
+    }
+
+    pub fn request_document_range_formatting<CB>(
+        &self,
+        document_uri: Url,
+        range: Range,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = DocumentRangeFormattingParams {
+            text_document: TextDocumentIdentifier { uri: document_uri },
+            range,
+            options: formatting_options(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/rangeFormatting", params, Box::new(cb));
+    }
+
+    pub fn request_on_type_formatting<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        ch: String,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = DocumentOnTypeFormattingParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            ch,
+            options: formatting_options(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/onTypeFormatting", params, Box::new(cb));
+    }
+
+    /// Asks for the tokens of the whole document, as edits to the last ones
+    /// when the server can send those, and keeps them for the next time.
+    /// Only the latest request of a document is answered, the callbacks of
+    /// the ones before it get an error.
+    pub fn request_semantic_tokens<CB>(
+        &self,
+        document_uri: Url,
+        buffer_id: BufferId,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Vec<SemanticToken>>),
+    {
+        let supports_delta = self.supports_semantic_tokens_delta();
+        let (request, previous_result_id) = {
+            let mut state = self.state.lock();
+            state.semantic_tokens_request_count += 1;
+            let request = state.semantic_tokens_request_count;
+            state.semantic_tokens_requests.insert(buffer_id, request);
+            let previous_result_id = if supports_delta {
+                state
+                    .semantic_tokens
+                    .get(&buffer_id)
+                    .and_then(|tokens| tokens.result_id.clone())
+            } else {
+                None
+            };
+            (request, previous_result_id)
+        };
+        let text_document = TextDocumentIdentifier { uri: document_uri };
+        let callback = move |lsp_client: &LspClient, result: Result<Value>| {
+            let tokens =
+                lsp_client.receive_semantic_tokens(buffer_id, request, result);
+            cb(lsp_client, tokens);
+        };
+        match previous_result_id {
+            Some(previous_result_id) => {
+                let params = SemanticTokensDeltaParams {
+                    text_document,
+                    previous_result_id,
+                    work_done_progress_params: WorkDoneProgressParams::default(),
+                    partial_result_params: PartialResultParams::default(),
+                };
+                let params = Params::from(serde_json::to_value(params).unwrap());
+                self.send_request(
+                    "textDocument/semanticTokens/full/delta",
+                    params,
+                    Box::new(callback),
+                );
+            }
+            None => {
+                let params = SemanticTokensParams {
+                    text_document,
+                    work_done_progress_params: WorkDoneProgressParams::default(),
+                    partial_result_params: PartialResultParams::default(),
+                };
+                let params = Params::from(serde_json::to_value(params).unwrap());
+                self.send_request(
+                    "textDocument/semanticTokens/full",
+                    params,
+                    Box::new(callback),
+                );
+            }
+        }
+    }
+
+    /// Keeps the tokens of a full or delta response to the latest `request`
+    /// of the document as its latest. A stale response is dropped, and
+    /// anything else forgets them, so that full tokens are asked for next.
+    fn receive_semantic_tokens(
+        &self,
+        buffer_id: BufferId,
+        request: u64,
+        result: Result<Value>,
+    ) -> Result<Vec<SemanticToken>> {
+        let mut state = self.state.lock();
+        if state.semantic_tokens_requests.get(&buffer_id) != Some(&request) {
+            return Err(anyhow!("stale semantic tokens"));
+        }
+        state.semantic_tokens_requests.remove(&buffer_id);
+        let previous = state.semantic_tokens.remove(&buffer_id);
+        let tokens = match serde_json::from_value(result?)? {
+            SemanticTokensFullDeltaResult::Tokens(tokens) => tokens,
+            SemanticTokensFullDeltaResult::TokensDelta(delta) => {
+                let previous =
+                    previous.ok_or_else(|| anyhow!("no semantic tokens to edit"))?;
+                SemanticTokens {
+                    result_id: delta.result_id,
+                    data: apply_semantic_tokens_edits(&previous.data, delta.edits),
+                }
+            }
+            SemanticTokensFullDeltaResult::PartialTokensDelta { .. } => {
+                return Err(anyhow!("partial semantic tokens"));
+            }
+        };
+        let data = tokens.data.clone();
+        state.semantic_tokens.insert(buffer_id, tokens);
+        Ok(data)
+    }
+
+    pub fn request_semantic_tokens_range<CB>(
+        &self,
+        document_uri: Url,
+        range: Range,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = SemanticTokensRangeParams {
+            text_document: TextDocumentIdentifier { uri: document_uri },
+            range,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/semanticTokens/range", params, Box::new(cb));
+    }
+
+    /// The highlights of `tokens` in `buffer`, by the legend of the server.
+    fn format_semantic_tokens(
+        &self,
+        buffer: &Buffer,
+        tokens: &[SemanticToken],
+    ) -> Option<Vec<(usize, usize, String, Vec<String>)>> {
+        let state = self.state.lock();
+        let semantic_tokens_provider = state
+            .server_capabilities
+            .as_ref()?
+            .semantic_tokens_provider
+            .as_ref()?;
+        Some(format_semantic_tokens(
+            buffer,
+            &semantic_tokens_lengend(semantic_tokens_provider),
+            state.position_encoding,
+            tokens,
+        ))
+    }
+
+    pub fn request_code_actions<CB>(
+        &self,
+        document_uri: Url,
+        range: Range,
+        context: CodeActionContext,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: document_uri },
+            range,
+            context,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/codeAction", params, Box::new(cb));
+    }
+
+    pub fn request_code_action_resolve<CB>(&self, action: &CodeAction, cb: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = Params::from(serde_json::to_value(action).unwrap());
+        self.send_request("codeAction/resolve", params, Box::new(cb));
+    }
+
+    pub fn request_references<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = ReferenceParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: ReferenceContext {
+                include_declaration: false,
+            },
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/references", params, Box::new(cb));
+    }
+
+    pub fn request_definition<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/definition", params, Box::new(cb));
+    }
+
+    pub fn request_goto_location<CB>(
+        &self,
+        method: &str,
+        document_uri: Url,
+        position: Position,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = GotoDefinitionParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request(method, params, Box::new(cb));
+    }
+
+    pub fn request_completion<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        on_completion: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let completion_params = CompletionParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        };
+        let params = Params::from(serde_json::to_value(completion_params).unwrap());
+        self.send_request(
+            "textDocument/completion",
+            params,
+            Box::new(on_completion),
+        );
+    }
+
+    pub fn completion_resolve<CB>(
+        &self,
+        completion_item: &CompletionItem,
+        on_result: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = Params::from(serde_json::to_value(completion_item).unwrap());
+        self.send_request("completionItem/resolve", params, Box::new(on_result));
+    }
+
+    pub fn request_signature<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        context: Option<SignatureHelpContext>,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = SignatureHelpParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            context,
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/signatureHelp", params, Box::new(cb));
+    }
+
+    pub fn request_hover<CB>(&self, document_uri: Url, position: Position, cb: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/hover", params, Box::new(cb));
+    }
+
+    pub fn request_prepare_rename<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: document_uri },
+            position,
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/prepareRename", params, Box::new(cb));
+    }
+
+    pub fn request_rename<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        new_name: String,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            new_name,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request("textDocument/rename", params, Box::new(cb));
+    }
+
+    pub fn send_did_change(
+        &self,
+        buffer: &Buffer,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        version: u64,
+    ) {
+        let uri = self.get_uri(buffer);
+        let text_document_did_change_params = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri,
+                version: version as i32,
+            },
+            content_changes: changes,
+        };
+
+        let params = Params::from(
+            serde_json::to_value(text_document_did_change_params).unwrap(),
+        );
+        self.send_notification("textDocument/didChange", params);
+    }
+
+    pub fn request_inlay_hints<CB>(&self, document_uri: Url, range: Range, cb: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = json!({
+            "textDocument": TextDocumentIdentifier { uri: document_uri },
+            "range": range,
+        });
+        self.send_request(
+            "textDocument/inlayHint",
+            Params::from(params),
+            Box::new(cb),
+        );
+    }
+
+    /// `automatic` tells the server the request came from typing rather
+    /// than from being asked for.
+    pub fn request_inline_completion<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        automatic: bool,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = json!({
+            "textDocument": TextDocumentIdentifier { uri: document_uri },
+            "position": position,
+            "context": {
+                "triggerKind": if automatic { 2 } else { 1 },
+            },
+        });
+        self.send_request(
+            "textDocument/inlineCompletion",
+            Params::from(params),
+            Box::new(cb),
+        );
+    }
+
+    pub fn request_inlay_hint_resolve<CB>(&self, hint: Value, cb: CB)
+    where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        self.send_request("inlayHint/resolve", Params::from(hint), Box::new(cb));
+    }
+
+    pub fn request_document_highlights<CB>(
+        &self,
+        document_uri: Url,
+        position: Position,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = DocumentHighlightParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: document_uri },
+                position,
+            },
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
         };
         let params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_notification("textDocument/didSave", params);
+        self.send_request("textDocument/documentHighlight", params, Box::new(cb));
     }
 
-    pub fn send_initialized(&self) {
-        self.send_notification("initialized", Params::from(json!({})));
+    pub fn request_prepare_hierarchy<CB>(
+        &self,
+        method: &str,
+        document_uri: Url,
+        position: Position,
+        cb: CB,
+    ) where
+        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
+    {
+        let params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: document_uri },
+            position,
+        };
+        let params = Params::from(serde_json::to_value(params).unwrap());
+        self.send_request(method, params, Box::new(cb));
     }
 
-    pub fn send_initialize<CB>(&self, root_uri: Option<Url>, on_init: CB)
+    pub fn request_hierarchy_items<CB>(&self, method: &str, item: Value, cb: CB)
     where
         CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
     {
-        let client_capabilities = ClientCapabilities {
-            text_document: Some(TextDocumentClientCapabilities {
-                completion: Some(CompletionClientCapabilities {
-                    completion_item: Some(CompletionItemCapability {
-                        snippet_support: Some(true),
-                        resolve_support: Some(
-                            CompletionItemCapabilityResolveSupport {
-                                properties: vec!["additionalTextEdits".to_string()],
-                            },
-                        ),
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                }),
-                // signature_help: Some(SignatureHelpCapability {
-                //     signature_information: Some(SignatureInformationSettings {
-                //         parameter_information: Some(ParameterInformationSettings {
-                //             label_offset_support: Some(true),
-                //         }),
-                //         active_parameter_support: Some(true),
-                //         documentation_format: Some(vec![
-                //             MarkupKind::Markdown,
-                //             MarkupKind::PlainText,
-                //         ]),
-                //     }),
-                //     ..Default::default()
-                // }),
-                code_action: Some(CodeActionClientCapabilities {
-                    code_action_literal_support: Some(CodeActionLiteralSupport {
-                        code_action_kind: CodeActionKindLiteralSupport {
-                            value_set: vec![
-                                CodeActionKind::EMPTY.as_str().to_string(),
-                                CodeActionKind::QUICKFIX.as_str().to_string(),
-                                CodeActionKind::REFACTOR.as_str().to_string(),
-                                CodeActionKind::REFACTOR_EXTRACT
-                                    .as_str()
-                                    .to_string(),
-                                CodeActionKind::REFACTOR_INLINE.as_str().to_string(),
-                                CodeActionKind::REFACTOR_REWRITE
-                                    .as_str()
-                                    .to_string(),
-                                CodeActionKind::SOURCE.as_str().to_string(),
-                                CodeActionKind::SOURCE_ORGANIZE_IMPORTS
-                                    .as_str()
-                                    .to_string(),
-                            ],
-                        },
-                    }),
-                    ..Default::default()
-                }),
-                semantic_tokens: Some(SemanticTokensClientCapabilities {
-                    ..Default::default()
-                }),
-                ..Default::default()
-            }),
-            ..Default::default()
-        };
-
-        let init_params = InitializeParams {
-            process_id: Some(u32::from(process::id())),
-            root_uri,
-            initialization_options: self.options.clone(),
-            capabilities: client_capabilities,
-            trace: Some(TraceOption::Verbose),
-            workspace_folders: None,
-            client_info: None,
-            root_path: None,
-            locale: None,
-        };
-
-        let params = Params::from(serde_json::to_value(init_params).unwrap());
-        self.send_request("initialize", params, Box::new(on_init));
+        let params = json!({ "item": item });
+        self.send_request(method, Params::from(params), Box::new(cb));
     }
 
-    pub fn request_document_symbols<CB>(&self, document_uri: Url, cb: CB)
+    pub fn request_code_lens<CB>(&self, document_uri: Url, cb: CB)
     where
         CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
     {
-        let params = DocumentSymbolParams {
+        let params = CodeLensParams {
             text_document: TextDocumentIdentifier { uri: document_uri },
             work_done_progress_params: WorkDoneProgressParams::default(),
             partial_result_params: PartialResultParams::default(),
         };
         let params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_request("textDocument/documentSymbol", params, Box::new(cb));
+        self.send_request("textDocument/codeLens", params, Box::new(cb));
     }
 
-    pub fn request_document_formatting<CB>(&self, document_uri: Url, cb: CB)
+    pub fn request_code_lens_resolve<CB>(&self, lens: CodeLens, cb: CB)
     where
         CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
     {
-        let params = DocumentFormattingParams {
-            text_document: TextDocumentIdentifier { uri: document_uri },
-            options: FormattingOptions {
-                tab_size: 4,
-                insert_spaces: true,
-                ..Default::default()
-            },
-            work_done_progress_params: WorkDoneProgressParams::default(),
-        };
-        let params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_request("textDocument/formatting", params, Box::new(cb));
+        let params = Params::from(serde_json::to_value(lens).unwrap());
+        self.send_request("codeLens/resolve", params, Box::new(cb));
     }
 
-    pub fn request_semantic_tokens<CB>(&self, document_uri: Url, cb: CB)
+    pub fn request_execute_command<CB>(&self, command: lsp_types::Command, cb: CB)
     where
         CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
     {
-        let params = SemanticTokensParams {
-            text_document: TextDocumentIdentifier { uri: document_uri },
+        let params = ExecuteCommandParams {
+            command: command.command,
+            arguments: command.arguments.unwrap_or_default(),
             work_done_progress_params: WorkDoneProgressParams::default(),
-            partial_result_params: PartialResultParams::default(),
         };
         let params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_request("textDocument/semanticTokens/full", params, Box::new(cb));
+        self.send_request("workspace/executeCommand", params, Box::new(cb));
+    }
+
+    pub fn supports_completion(&self) -> bool {
+        let state = self.state.lock();
+        state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.completion_provider.as_ref())
+            .is_some()
+    }
+
+    pub fn supports_completion_resolve(&self) -> bool {
+        let state = self.state.lock();
+        state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.completion_provider.as_ref())
+            .and_then(|c| c.resolve_provider)
+            .unwrap_or(false)
+    }
+
+    pub fn supports_code_actions(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.code_action_provider.as_ref())
+        {
+            Some(CodeActionProviderCapability::Simple(supported)) => *supported,
+            Some(CodeActionProviderCapability::Options(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_code_action_resolve(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.code_action_provider.as_ref())
+        {
+            Some(CodeActionProviderCapability::Options(options)) => {
+                options.resolve_provider.unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn supports_hover(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.hover_provider.as_ref())
+        {
+            Some(HoverProviderCapability::Simple(supported)) => *supported,
+            Some(HoverProviderCapability::Options(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_definition(&self) -> bool {
+        let state = self.state.lock();
+        provides(
+            state
+                .server_capabilities
+                .as_ref()
+                .and_then(|c| c.definition_provider.as_ref()),
+        )
+    }
+
+    pub fn supports_references(&self) -> bool {
+        let state = self.state.lock();
+        provides(
+            state
+                .server_capabilities
+                .as_ref()
+                .and_then(|c| c.references_provider.as_ref()),
+        )
+    }
+
+    pub fn supports_document_symbols(&self) -> bool {
+        let state = self.state.lock();
+        provides(
+            state
+                .server_capabilities
+                .as_ref()
+                .and_then(|c| c.document_symbol_provider.as_ref()),
+        )
+    }
+
+    pub fn supports_workspace_symbols(&self) -> bool {
+        let state = self.state.lock();
+        provides(
+            state
+                .server_capabilities
+                .as_ref()
+                .and_then(|c| c.workspace_symbol_provider.as_ref()),
+        )
+    }
+
+    pub fn supports_formatting(&self) -> bool {
+        let state = self.state.lock();
+        provides(
+            state
+                .server_capabilities
+                .as_ref()
+                .and_then(|c| c.document_formatting_provider.as_ref()),
+        )
+    }
+
+    pub fn supports_on_type_formatting(&self) -> bool {
+        let state = self.state.lock();
+        state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.document_on_type_formatting_provider.as_ref())
+            .is_some()
+    }
+
+    pub fn supports_type_definition(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.type_definition_provider.as_ref())
+        {
+            Some(TypeDefinitionProviderCapability::Simple(supported)) => *supported,
+            Some(TypeDefinitionProviderCapability::Options(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_implementation(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.implementation_provider.as_ref())
+        {
+            Some(ImplementationProviderCapability::Simple(supported)) => *supported,
+            Some(ImplementationProviderCapability::Options(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_declaration(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.declaration_provider.as_ref())
+        {
+            Some(DeclarationCapability::Simple(supported)) => *supported,
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_signature_help(&self) -> bool {
+        let state = self.state.lock();
+        state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.signature_help_provider.as_ref())
+            .is_some()
+    }
+
+    /// Checks a typed character against the ones the server asked to be
+    /// triggered or retriggered on. A character that isn't one of them
+    /// only retriggers help that's already showing, as a content change,
+    /// and is dropped otherwise.
+    fn signature_help_context(
+        &self,
+        mut context: SignatureHelpContext,
+    ) -> Option<SignatureHelpContext> {
+        if context.trigger_kind != SignatureHelpTriggerKind::TriggerCharacter {
+            return Some(context);
+        }
+        let state = self.state.lock();
+        let options = state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.signature_help_provider.as_ref())?;
+        let c = context.trigger_character.as_ref()?;
+        let is_trigger = options
+            .trigger_characters
+            .as_ref()
+            .map(|chars| chars.contains(c))
+            .unwrap_or(false);
+        let is_retrigger = options
+            .retrigger_characters
+            .as_ref()
+            .map(|chars| chars.contains(c))
+            .unwrap_or(false);
+        if is_trigger || (context.is_retrigger && is_retrigger) {
+            Some(context)
+        } else if context.is_retrigger {
+            context.trigger_kind = SignatureHelpTriggerKind::ContentChange;
+            context.trigger_character = None;
+            Some(context)
+        } else {
+            None
+        }
+    }
+
+    pub fn supports_range_formatting(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.document_range_formatting_provider.as_ref())
+        {
+            Some(OneOf::Left(supported)) => *supported,
+            Some(OneOf::Right(_)) => true,
+            None => false,
+        }
+    }
+
+    fn is_on_type_formatting_trigger(&self, ch: &str) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.document_on_type_formatting_provider.as_ref())
+        {
+            Some(options) => {
+                options.first_trigger_character == ch
+                    || options
+                        .more_trigger_character
+                        .as_ref()
+                        .map(|chars| chars.iter().any(|c| c == ch))
+                        .unwrap_or(false)
+            }
+            None => false,
+        }
+    }
+
+    pub fn supports_call_hierarchy(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.call_hierarchy_provider.as_ref())
+        {
+            Some(CallHierarchyServerCapability::Simple(supported)) => *supported,
+            Some(CallHierarchyServerCapability::Options(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_type_hierarchy(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .raw_server_capabilities
+            .as_ref()
+            .and_then(|c| c.get("typeHierarchyProvider"))
+        {
+            Some(Value::Bool(supported)) => *supported,
+            Some(Value::Object(_)) => true,
+            _ => false,
+        }
+    }
+
+    pub fn supports_document_highlight(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.document_highlight_provider.as_ref())
+        {
+            Some(OneOf::Left(supported)) => *supported,
+            Some(OneOf::Right(_)) => true,
+            None => false,
+        }
+    }
+
+    pub fn supports_code_lens(&self) -> bool {
+        let state = self.state.lock();
+        state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.code_lens_provider.as_ref())
+            .is_some()
+    }
+
+    pub fn supports_code_lens_resolve(&self) -> bool {
+        let state = self.state.lock();
+        state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.code_lens_provider.as_ref())
+            .and_then(|p| p.resolve_provider)
+            .unwrap_or(false)
+    }
+
+    pub fn supports_execute_command(&self, command: &str) -> bool {
+        let state = self.state.lock();
+        state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.execute_command_provider.as_ref())
+            .map(|p| p.commands.iter().any(|c| c == command))
+            .unwrap_or(false)
+    }
+
+    fn semantic_tokens_options(&self) -> Option<SemanticTokensOptions> {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()?
+            .semantic_tokens_provider
+            .as_ref()?
+        {
+            SemanticTokensServerCapabilities::SemanticTokensOptions(options) => {
+                Some(options.clone())
+            }
+            SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(
+                options,
+            ) => Some(options.semantic_tokens_options.clone()),
+        }
     }
 
-    pub fn request_code_actions<CB>(&self, document_uri: Url, range: Range, cb: CB)
-    where
-        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
-    {
-        let params = CodeActionParams {
-            text_document: TextDocumentIdentifier { uri: document_uri },
-            range,
-            context: CodeActionContext::default(),
-            work_done_progress_params: WorkDoneProgressParams::default(),
-            partial_result_params: PartialResultParams::default(),
-        };
-        let params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_request("textDocument/codeAction", params, Box::new(cb));
+    pub fn supports_semantic_tokens(&self) -> bool {
+        self.semantic_tokens_options().is_some()
     }
 
-    pub fn request_references<CB>(
-        &self,
-        document_uri: Url,
-        position: Position,
-        cb: CB,
-    ) where
-        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
-    {
-        let params = ReferenceParams {
-            text_document_position: TextDocumentPositionParams {
-                text_document: TextDocumentIdentifier { uri: document_uri },
-                position,
-            },
-            work_done_progress_params: WorkDoneProgressParams::default(),
-            partial_result_params: PartialResultParams::default(),
-            context: ReferenceContext {
-                include_declaration: false,
-            },
-        };
-        let params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_request("textDocument/references", params, Box::new(cb));
+    pub fn supports_semantic_tokens_range(&self) -> bool {
+        self.semantic_tokens_options()
+            .and_then(|options| options.range)
+            .unwrap_or(false)
     }
 
-    pub fn request_definition<CB>(
-        &self,
-        document_uri: Url,
-        position: Position,
-        cb: CB,
-    ) where
-        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
-    {
-        let params = GotoDefinitionParams {
-            text_document_position_params: TextDocumentPositionParams {
-                text_document: TextDocumentIdentifier { uri: document_uri },
-                position,
-            },
-            work_done_progress_params: WorkDoneProgressParams::default(),
-            partial_result_params: PartialResultParams::default(),
-        };
-        let params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_request("textDocument/definition", params, Box::new(cb));
+    fn supports_semantic_tokens_delta(&self) -> bool {
+        match self
+            .semantic_tokens_options()
+            .and_then(|options| options.full)
+        {
+            Some(SemanticTokensFullOptions::Delta { delta }) => {
+                delta.unwrap_or(false)
+            }
+            _ => false,
+        }
     }
 
-    pub fn request_completion<CB>(
-        &self,
-        document_uri: Url,
-        position: Position,
-        on_completion: CB,
-    ) where
-        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
-    {
-        let completion_params = CompletionParams {
-            text_document_position: TextDocumentPositionParams {
-                text_document: TextDocumentIdentifier { uri: document_uri },
-                position,
-            },
-            work_done_progress_params: WorkDoneProgressParams::default(),
-            partial_result_params: PartialResultParams::default(),
-            context: None,
-        };
-        let params = Params::from(serde_json::to_value(completion_params).unwrap());
-        self.send_request(
-            "textDocument/completion",
-            params,
-            Box::new(on_completion),
-        );
+    pub fn supports_inlay_hints(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .raw_server_capabilities
+            .as_ref()
+            .and_then(|c| c.get("inlayHintProvider"))
+        {
+            Some(Value::Bool(supported)) => *supported,
+            Some(Value::Object(_)) => true,
+            _ => false,
+        }
     }
 
-    pub fn completion_resolve<CB>(
-        &self,
-        completion_item: &CompletionItem,
-        on_result: CB,
-    ) where
-        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
-    {
-        let params = Params::from(serde_json::to_value(completion_item).unwrap());
-        self.send_request("completionItem/resolve", params, Box::new(on_result));
+    pub fn supports_inline_completion(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .raw_server_capabilities
+            .as_ref()
+            .and_then(|c| c.get("inlineCompletionProvider"))
+        {
+            Some(Value::Bool(supported)) => *supported,
+            Some(Value::Object(_)) => true,
+            _ => false,
+        }
     }
 
-    pub fn request_signature<CB>(
-        &self,
-        document_uri: Url,
-        position: Position,
-        cb: CB,
-    ) where
-        CB: 'static + Send + FnOnce(&LspClient, Result<Value>),
-    {
-        let params = SignatureHelpParams {
-            text_document_position_params: TextDocumentPositionParams {
-                text_document: TextDocumentIdentifier { uri: document_uri },
-                position,
-            },
-            work_done_progress_params: WorkDoneProgressParams::default(),
-            context: None,
-        };
-        let params = Params::from(serde_json::to_value(params).unwrap());
-        self.send_request("textDocument/signatureHelp", params, Box::new(cb));
+    pub fn supports_inlay_hint_resolve(&self) -> bool {
+        let state = self.state.lock();
+        state
+            .raw_server_capabilities
+            .as_ref()
+            .and_then(|c| c.get("inlayHintProvider"))
+            .and_then(|p| p.get("resolveProvider"))
+            .and_then(|r| r.as_bool())
+            .unwrap_or(false)
     }
 
-    pub fn send_did_change(
-        &self,
-        buffer: &Buffer,
-        changes: Vec<TextDocumentContentChangeEvent>,
-        version: u64,
-    ) {
-        let uri = self.get_uri(buffer);
-        let text_document_did_change_params = DidChangeTextDocumentParams {
-            text_document: VersionedTextDocumentIdentifier {
-                uri,
-                version: version as i32,
-            },
-            content_changes: changes,
-        };
+    pub fn supports_rename(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.rename_provider.as_ref())
+        {
+            Some(OneOf::Left(supported)) => *supported,
+            Some(OneOf::Right(_)) => true,
+            None => false,
+        }
+    }
 
-        let params = Params::from(
-            serde_json::to_value(text_document_did_change_params).unwrap(),
-        );
-        self.send_notification("textDocument/didChange", params);
+    pub fn supports_prepare_rename(&self) -> bool {
+        let state = self.state.lock();
+        match state
+            .server_capabilities
+            .as_ref()
+            .and_then(|c| c.rename_provider.as_ref())
+        {
+            Some(OneOf::Right(options)) => options.prepare_provider.unwrap_or(false),
+            _ => false,
+        }
     }
 
     pub fn get_sync_kind(&self) -> Option<TextDocumentSyncKind> {
@@ -815,11 +3798,17 @@ impl LspClient {
     pub fn update(
         &self,
         buffer: &Buffer,
-        content_change: &TextDocumentContentChangeEvent,
+        content_changes: &[TextDocumentContentChangeEvent],
         rev: u64,
     ) {
+        // A document that was closed is opened again with all it has now,
+        // the change included.
+        if !self.state.lock().opened_documents.contains_key(&buffer.id) {
+            self.get_uri(buffer);
+            return;
+        }
         let sync_kind = self.get_sync_kind().unwrap_or(TextDocumentSyncKind::Full);
-        let changes = get_change_for_sync_kind(sync_kind, buffer, content_change);
+        let changes = get_change_for_sync_kind(sync_kind, buffer, content_changes);
         if let Some(changes) = changes {
             self.send_did_change(buffer, changes, rev);
         }
@@ -831,13 +3820,303 @@ pub enum LspHeader {
     ContentLength(usize),
 }
 
-fn number_from_id(id: &Id) -> u64 {
-    match *id {
-        Id::Num(n) => n as u64,
-        Id::Str(ref s) => {
-            u64::from_str_radix(s, 10).expect("failed to convert string id to u64")
+/// Whether a glob pattern a server registered matches `path`, either as a
+/// whole or relative to the root of the server.
+fn glob_matches(pattern: &str, path: &Path, root: &Path) -> bool {
+    let to_chars = |path: &Path| -> Vec<char> {
+        path.to_string_lossy().replace('\\', "/").chars().collect()
+    };
+    let full = to_chars(path);
+    let relative = path.strip_prefix(root).ok().map(to_chars);
+    expand_braces(pattern).iter().any(|pattern| {
+        let pattern: Vec<char> = pattern.chars().collect();
+        match_glob(&pattern, &full)
+            || relative
+                .as_ref()
+                .map(|relative| match_glob(&pattern, relative))
+                .unwrap_or(false)
+    })
+}
+
+/// Turns the `{a,b}` groups of a glob pattern into a pattern for each
+/// alternative.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let start = match pattern.find('{') {
+        Some(start) => start,
+        None => return vec![pattern.to_string()],
+    };
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut end = None;
+    for (i, c) in pattern[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(start + i);
+                    break;
+                }
+            }
+            ',' if depth == 1 => commas.push(start + i),
+            _ => {}
+        }
+    }
+    let end = match end {
+        Some(end) => end,
+        None => return vec![pattern.to_string()],
+    };
+    let mut alternatives = Vec::new();
+    let mut from = start + 1;
+    for to in commas.into_iter().chain(Some(end)) {
+        alternatives.push(&pattern[from..to]);
+        from = to + 1;
+    }
+    alternatives
+        .into_iter()
+        .flat_map(|alternative| {
+            expand_braces(&format!(
+                "{}{}{}",
+                &pattern[..start],
+                alternative,
+                &pattern[end + 1..]
+            ))
+        })
+        .collect()
+}
+
+/// Matches a glob pattern without braces, where `*` and `?` stay within a
+/// directory and `**` spans any number of them.
+fn match_glob(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            // `**/` matches no directory at all too.
+            if rest.first() == Some(&'/') && match_glob(&rest[1..], path) {
+                return true;
+            }
+            (0..=path.len()).any(|i| match_glob(rest, &path[i..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for i in 0..=path.len() {
+                if match_glob(rest, &path[i..]) {
+                    return true;
+                }
+                if i < path.len() && path[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => match path.first() {
+            Some(c) if *c != '/' => match_glob(&pattern[1..], &path[1..]),
+            _ => false,
+        },
+        Some('[') => {
+            let end = match pattern.iter().skip(1).position(|c| *c == ']') {
+                Some(i) => i + 1,
+                None => {
+                    return path.first() == Some(&'[')
+                        && match_glob(&pattern[1..], &path[1..])
+                }
+            };
+            let c = match path.first() {
+                Some(c) if *c != '/' => *c,
+                _ => return false,
+            };
+            let mut class = &pattern[1..end];
+            let negated = class.first() == Some(&'!');
+            if negated {
+                class = &class[1..];
+            }
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    matched |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            matched != negated && match_glob(&pattern[end + 1..], &path[1..])
+        }
+        Some(c) => path.first() == Some(c) && match_glob(&pattern[1..], &path[1..]),
+    }
+}
+
+/// Whether a capability that's either a flag or options is there.
+fn provides<T>(provider: Option<&OneOf<bool, T>>) -> bool {
+    match provider {
+        Some(OneOf::Left(supported)) => *supported,
+        Some(OneOf::Right(_)) => true,
+        None => false,
+    }
+}
+
+/// Puts the name of the server into the data of a code action, next to what
+/// the server keeps there. Commands have no data and are left as they are.
+fn wrap_code_action(server: &str, mut action: Value) -> Value {
+    let is_command = action
+        .get("command")
+        .map(|command| command.is_string())
+        .unwrap_or(false);
+    if action.is_object() && !is_command {
+        let data = action
+            .get_mut("data")
+            .map(|data| data.take())
+            .unwrap_or(Value::Null);
+        action["data"] = json!({
+            "server": server,
+            "data": data,
+        });
+    }
+    action
+}
+
+/// Resolves the fixes that came without their edits, one after the other,
+/// and calls `done` with all that have one. The ones that can't be resolved
+/// are left out.
+fn resolve_fixes(
+    mut pending: Vec<(Arc<LspClient>, CodeAction)>,
+    mut resolved: Vec<CodeAction>,
+    done: Box<dyn FnOnce(Vec<CodeAction>) + Send>,
+) {
+    let (client, fix) = match pending.pop() {
+        Some(next) => next,
+        None => {
+            resolved.reverse();
+            done(resolved);
+            return;
         }
-        _ => panic!("unexpected value for id: None"),
+    };
+    if fix.edit.is_some() || !client.supports_code_action_resolve() {
+        if fix.edit.is_some() {
+            resolved.push(fix);
+        }
+        resolve_fixes(pending, resolved, done);
+        return;
+    }
+    client.request_code_action_resolve(&fix, move |_, result| {
+        if let Some(fix) = result
+            .ok()
+            .and_then(|value| serde_json::from_value::<CodeAction>(value).ok())
+            .filter(|fix| fix.edit.is_some())
+        {
+            resolved.push(fix);
+        }
+        resolve_fixes(pending, resolved, done);
+    });
+}
+
+/// Splits data wrapped with the name of a server back into the name and
+/// what the server gave.
+fn unwrap_data(data: Option<Value>) -> (Option<String>, Option<Value>) {
+    match data {
+        Some(Value::Object(mut data)) => (
+            data.remove("server")
+                .and_then(|server| server.as_str().map(|s| s.to_string())),
+            data.remove("data").filter(|data| !data.is_null()),
+        ),
+        data => (None, data),
+    }
+}
+
+/// The value at the dotted `section` of `options`, null when it's not
+/// there. `options` are the section of the server, so a first segment
+/// that is one of `own_sections` and not a key of its own is skipped.
+fn section_value(
+    options: &Value,
+    own_sections: &[&str],
+    section: Option<&str>,
+) -> Value {
+    let section = match section {
+        Some(section) if section != "" => section,
+        _ => return options.clone(),
+    };
+    let mut keys: Vec<&str> = section.split('.').collect();
+    if options.get(keys[0]).is_none() && own_sections.contains(&keys[0]) {
+        keys.remove(0);
+    }
+    let mut value = options;
+    for key in keys {
+        match value.get(key) {
+            Some(v) => value = v,
+            None => return Value::Null,
+        }
+    }
+    value.clone()
+}
+
+/// Gathers what several servers answer to the same request.
+struct Gathering<D> {
+    remaining: usize,
+    /// The answers by the position of the server, and its name.
+    results: Vec<(usize, String, Result<Value>)>,
+    done: Option<D>,
+}
+
+/// Sends the same request to each of `clients` with `send`, and calls
+/// `done` with what they answered, in the order of the servers, once all
+/// of them have.
+fn gather<S, D>(clients: &[Arc<LspClient>], send: S, done: D)
+where
+    S: Fn(&LspClient, Box<dyn FnOnce(&LspClient, Result<Value>) + Send>),
+    D: 'static + Send + FnOnce(Vec<(String, Result<Value>)>),
+{
+    let gathering = Arc::new(Mutex::new(Gathering {
+        remaining: clients.len(),
+        results: Vec::new(),
+        done: Some(done),
+    }));
+    for (i, client) in clients.iter().enumerate() {
+        let gathering = gathering.clone();
+        send(
+            client,
+            Box::new(move |lsp_client: &LspClient, result: Result<Value>| {
+                let finished = {
+                    let mut gathering = gathering.lock();
+                    gathering.results.push((i, lsp_client.name.clone(), result));
+                    gathering.remaining -= 1;
+                    if gathering.remaining > 0 {
+                        return;
+                    }
+                    let mut results = std::mem::take(&mut gathering.results);
+                    results.sort_by_key(|(i, _, _)| *i);
+                    gathering.done.take().map(|done| (done, results))
+                };
+                if let Some((done, results)) = finished {
+                    done(
+                        results
+                            .into_iter()
+                            .map(|(_, name, result)| (name, result))
+                            .collect(),
+                    );
+                }
+            }),
+        );
+    }
+}
+
+/// The closest directory above `path` with one of `markers` in it.
+fn find_root(path: &Path, markers: &[String]) -> Option<PathBuf> {
+    if markers.len() == 0 {
+        return None;
+    }
+    path.ancestors()
+        .skip(1)
+        .find(|dir| markers.iter().any(|marker| dir.join(marker).exists()))
+        .map(|dir| dir.to_path_buf())
+}
+
+fn number_from_id(id: &Id) -> Option<u64> {
+    match *id {
+        Id::Num(n) => Some(n as u64),
+        Id::Str(ref s) => u64::from_str_radix(s, 10).ok(),
+        _ => None,
     }
 }
 
@@ -897,7 +4176,7 @@ pub fn read_message<T: BufRead>(reader: &mut T) -> Result<String> {
 pub fn get_change_for_sync_kind(
     sync_kind: TextDocumentSyncKind,
     buffer: &Buffer,
-    content_change: &TextDocumentContentChangeEvent,
+    content_changes: &[TextDocumentContentChangeEvent],
 ) -> Option<Vec<TextDocumentContentChangeEvent>> {
     match sync_kind {
         TextDocumentSyncKind::None => None,
@@ -910,36 +4189,101 @@ pub fn get_change_for_sync_kind(
                 };
             Some(vec![text_document_content_change_event])
         }
-        TextDocumentSyncKind::Incremental => Some(vec![content_change.clone()]),
+        TextDocumentSyncKind::Incremental => Some(content_changes.to_vec()),
+    }
+}
+
+/// The options sent with every formatting request.
+fn formatting_options() -> FormattingOptions {
+    FormattingOptions {
+        tab_size: 4,
+        insert_spaces: true,
+        ..Default::default()
     }
 }
 
+/// The offsets, type and modifiers of each token, with the names of the
+/// legend.
 fn format_semantic_tokens(
     buffer: &Buffer,
-    semantic_tokens_provider: &Option<SemanticTokensServerCapabilities>,
-    value: Value,
-) -> Option<Vec<(usize, usize, String)>> {
-    let semantic_tokens: SemanticTokens = serde_json::from_value(value).ok()?;
-    let semantic_tokens_provider = semantic_tokens_provider.as_ref()?;
-    let semantic_lengends = semantic_tokens_lengend(semantic_tokens_provider);
-
+    legend: &SemanticTokensLegend,
+    encoding: PositionEncoding,
+    tokens: &[SemanticToken],
+) -> Vec<(usize, usize, String, Vec<String>)> {
     let mut highlights = Vec::new();
     let mut line = 0;
-    let mut start = 0;
-    for semantic_token in &semantic_tokens.data {
+    let mut character = 0;
+    for semantic_token in tokens {
         if semantic_token.delta_line > 0 {
-            line += semantic_token.delta_line as usize;
-            start = buffer.offset_of_line(line);
+            line += semantic_token.delta_line;
+            character = 0;
         }
-        start += semantic_token.delta_start as usize;
-        let end = start + semantic_token.length as usize;
-        let kind = semantic_lengends.token_types[semantic_token.token_type as usize]
-            .as_str()
-            .to_string();
-        highlights.push((start, end, kind));
+        character += semantic_token.delta_start;
+        let kind = match legend.token_types.get(semantic_token.token_type as usize) {
+            Some(kind) => kind.as_str().to_string(),
+            None => continue,
+        };
+        let modifiers = legend
+            .token_modifiers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                *i < 32 && semantic_token.token_modifiers_bitset & (1 << i) != 0
+            })
+            .map(|(_, modifier)| modifier.as_str().to_string())
+            .collect();
+        let start =
+            buffer.offset_of_position(&Position { line, character }, encoding);
+        let end = buffer.offset_of_position(
+            &Position {
+                line,
+                character: character + semantic_token.length,
+            },
+            encoding,
+        );
+        highlights.push((start, end, kind, modifiers));
+    }
+    highlights
+}
+
+/// Applies the edits of a delta response to the tokens they were made
+/// against. The edits index the flat list of integers tokens are sent as.
+fn apply_semantic_tokens_edits(
+    tokens: &[SemanticToken],
+    mut edits: Vec<SemanticTokensEdit>,
+) -> Vec<SemanticToken> {
+    fn flatten(tokens: &[SemanticToken]) -> Vec<u32> {
+        tokens
+            .iter()
+            .flat_map(|token| {
+                vec![
+                    token.delta_line,
+                    token.delta_start,
+                    token.length,
+                    token.token_type,
+                    token.token_modifiers_bitset,
+                ]
+            })
+            .collect()
     }
 
-    Some(highlights)
+    let mut data = flatten(tokens);
+    edits.sort_by_key(|edit| edit.start);
+    for edit in edits.iter().rev() {
+        let start = (edit.start as usize).min(data.len());
+        let end = (start + edit.delete_count as usize).min(data.len());
+        let new = edit.data.as_ref().map(|d| flatten(d)).unwrap_or_default();
+        data.splice(start..end, new);
+    }
+    data.chunks_exact(5)
+        .map(|chunk| SemanticToken {
+            delta_line: chunk[0],
+            delta_start: chunk[1],
+            length: chunk[2],
+            token_type: chunk[3],
+            token_modifiers_bitset: chunk[4],
+        })
+        .collect()
 }
 
 fn semantic_tokens_lengend(
@@ -954,3 +4298,165 @@ fn semantic_tokens_lengend(
         ) => options.semantic_tokens_options.legend.clone(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(delta_line: u32) -> SemanticToken {
+        SemanticToken {
+            delta_line,
+            delta_start: 0,
+            length: 1,
+            token_type: 0,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    fn tokens_edit(
+        start: u32,
+        delete_count: u32,
+        data: Option<Vec<SemanticToken>>,
+    ) -> SemanticTokensEdit {
+        SemanticTokensEdit {
+            start,
+            delete_count,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_apply_semantic_tokens_edits() {
+        let tokens = vec![token(0), token(1), token(2), token(3)];
+
+        // the edits index the tokens they were made against, in any order
+        let edits = vec![
+            tokens_edit(15, 5, Some(vec![token(9)])),
+            tokens_edit(0, 5, None),
+            tokens_edit(20, 0, Some(vec![token(5)])),
+            tokens_edit(10, 0, Some(vec![token(7), token(8)])),
+        ];
+        assert_eq!(
+            apply_semantic_tokens_edits(&tokens, edits),
+            vec![token(1), token(7), token(8), token(2), token(9), token(5)]
+        );
+
+        assert_eq!(apply_semantic_tokens_edits(&tokens, Vec::new()), tokens);
+        assert_eq!(
+            apply_semantic_tokens_edits(&tokens, vec![tokens_edit(0, 20, None)]),
+            vec![]
+        );
+        // edits past the end stop there
+        assert_eq!(
+            apply_semantic_tokens_edits(
+                &tokens,
+                vec![tokens_edit(100, 5, Some(vec![token(4)]))]
+            ),
+            vec![token(0), token(1), token(2), token(3), token(4)]
+        );
+    }
+
+    #[test]
+    fn test_expand_braces() {
+        assert_eq!(expand_braces("*.rs"), vec!["*.rs"]);
+        assert_eq!(expand_braces("*.{ts,tsx}"), vec!["*.ts", "*.tsx"]);
+        assert_eq!(
+            expand_braces("{a,b}/*.{c,d}"),
+            vec!["a/*.c", "a/*.d", "b/*.c", "b/*.d"]
+        );
+        assert_eq!(
+            expand_braces("{a,{b,c}}.txt"),
+            vec!["a.txt", "b.txt", "c.txt"]
+        );
+        assert_eq!(expand_braces("{a,b"), vec!["{a,b"]);
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        let root = Path::new("/work");
+        let matches =
+            |pattern: &str, path: &str| glob_matches(pattern, Path::new(path), root);
+
+        // `**` spans any number of directories, none at all too
+        assert!(matches("**/*.rs", "/work/main.rs"));
+        assert!(matches("**/*.rs", "/work/src/a/b.rs"));
+        assert!(!matches("**/*.rs", "/work/src/a/b.rsx"));
+        assert!(matches("src/**/*.rs", "/work/src/b.rs"));
+        assert!(matches("src/**/*.rs", "/work/src/a/b/c.rs"));
+        assert!(!matches("src/**/*.rs", "/work/lib/b.rs"));
+
+        // `*` and `?` stay within a directory
+        assert!(matches("*.rs", "/work/main.rs"));
+        assert!(!matches("*.rs", "/work/src/main.rs"));
+        assert!(matches("file?.rs", "/work/file1.rs"));
+        assert!(!matches("file?.rs", "/work/file10.rs"));
+        assert!(!matches("file?rs", "/work/file/rs"));
+
+        // braces, nested too
+        assert!(matches("{Cargo.toml,Cargo.lock}", "/work/Cargo.lock"));
+        assert!(!matches("{Cargo.toml,Cargo.lock}", "/work/Cargo.json"));
+        assert!(matches("**/*.{ts,tsx}", "/work/web/app.tsx"));
+        assert!(matches("**/{a,{b,c}}.txt", "/work/x/c.txt"));
+        assert!(!matches("**/{a,{b,c}}.txt", "/work/x/d.txt"));
+
+        // character classes
+        assert!(matches("[abc].rs", "/work/b.rs"));
+        assert!(!matches("[abc].rs", "/work/d.rs"));
+        assert!(matches("[a-c]x", "/work/bx"));
+        assert!(!matches("[a-c]x", "/work/dx"));
+        assert!(matches("[!a-c]x", "/work/dx"));
+        assert!(!matches("[!a-c]x", "/work/ax"));
+        assert!(!matches("a[/]b", "/work/a/b"));
+
+        // patterns relative to the root, or absolute
+        assert!(matches("/work/src/*.rs", "/work/src/main.rs"));
+        assert!(!matches("/other/src/*.rs", "/work/src/main.rs"));
+        assert!(!matches("src/*.rs", "/other/src/main.rs"));
+    }
+
+    #[test]
+    fn test_section_value() {
+        let options = json!({
+            "cargo": { "features": ["a"] },
+            "checkOnSave": { "command": "clippy" },
+        });
+        let own = ["rust", "rust-analyzer"];
+
+        assert_eq!(section_value(&options, &own, None), options);
+        assert_eq!(section_value(&options, &own, Some("")), options);
+        assert_eq!(
+            section_value(&options, &own, Some("cargo")),
+            json!({ "features": ["a"] })
+        );
+        assert_eq!(
+            section_value(&options, &own, Some("rust-analyzer.cargo.features")),
+            json!(["a"])
+        );
+        assert_eq!(
+            section_value(&options, &own, Some("rust.checkOnSave.command")),
+            json!("clippy")
+        );
+        assert_eq!(section_value(&options, &own, Some("rust")), options);
+
+        // other sections aren't the server's to answer
+        assert_eq!(section_value(&options, &own, Some("editor")), Value::Null);
+        assert_eq!(
+            section_value(&options, &own, Some("foo.cargo")),
+            Value::Null
+        );
+        assert_eq!(
+            section_value(&options, &own, Some("cargo.missing")),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_section_value_own_key() {
+        // a key named like the server is looked up as a key
+        let options = json!({ "rust": { "edition": "2018" } });
+        assert_eq!(
+            section_value(&options, &["rust"], Some("rust.edition")),
+            json!("2018")
+        );
+    }
+}
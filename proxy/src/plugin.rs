@@ -1,5 +1,6 @@
 use anyhow::Result;
 use home::home_dir;
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_json::Value;
@@ -21,6 +22,9 @@ use xi_rpc::RpcPeer;
 use crate::buffer::BufferId;
 use crate::core_proxy::CoreProxy;
 use crate::dispatch::Dispatcher;
+use crate::inline_completion::{
+    parse_items, InlineCompletionItem, InlineCompletionParams,
+};
 
 pub type PluginName = String;
 
@@ -59,6 +63,11 @@ pub struct Plugin {
 pub struct PluginCatalog {
     id_counter: Counter,
     items: HashMap<PluginName, PluginDescription>,
+    /// The plugins whose processes are up, to send requests to.
+    running: HashMap<PluginId, (PluginName, RpcPeer)>,
+    /// The plugins that suggest inline completions, with the languages
+    /// they do it for, all of them when empty.
+    inline_completion_providers: Vec<(PluginId, Vec<String>)>,
 }
 
 impl PluginCatalog {
@@ -66,6 +75,8 @@ impl PluginCatalog {
         PluginCatalog {
             id_counter: Counter::default(),
             items: HashMap::new(),
+            running: HashMap::new(),
+            inline_completion_providers: Vec::new(),
         }
     }
 
@@ -100,6 +111,88 @@ impl PluginCatalog {
     pub fn next_plugin_id(&mut self) -> PluginId {
         PluginId(self.id_counter.next())
     }
+
+    fn plugin_exited(&mut self, id: &PluginId) {
+        self.running.remove(id);
+        self.inline_completion_providers
+            .retain(|(plugin_id, _)| plugin_id != id);
+    }
+
+    pub fn register_inline_completion_provider(
+        &mut self,
+        id: PluginId,
+        language_ids: Vec<String>,
+    ) {
+        self.inline_completion_providers
+            .retain(|(plugin_id, _)| plugin_id != &id);
+        self.inline_completion_providers.push((id, language_ids));
+    }
+
+    /// Asks the plugins that provide inline completions for the language
+    /// of `params`, and calls `done` with what they all suggested, in the
+    /// order they registered, and with the errors of the ones that failed.
+    pub fn get_inline_completion<D>(&self, params: &InlineCompletionParams, done: D)
+    where
+        D: 'static + Send + FnOnce(Vec<InlineCompletionItem>, Vec<String>),
+    {
+        let providers: Vec<(PluginName, RpcPeer)> = self
+            .inline_completion_providers
+            .iter()
+            .filter(|(_, language_ids)| {
+                language_ids.is_empty() || language_ids.contains(&params.language_id)
+            })
+            .filter_map(|(id, _)| self.running.get(id))
+            .map(|(name, peer)| (name.clone(), peer.box_clone()))
+            .collect();
+        if providers.is_empty() {
+            done(Vec::new(), Vec::new());
+            return;
+        }
+
+        let params = serde_json::to_value(params).unwrap();
+        let gathering =
+            Arc::new(Mutex::new((providers.len(), Vec::new(), Some(done))));
+        for (i, (name, peer)) in providers.into_iter().enumerate() {
+            let gathering = gathering.clone();
+            peer.send_rpc_request_async(
+                "inline_completion",
+                &params,
+                Box::new(move |result: Result<Value, xi_rpc::Error>| {
+                    let items = result
+                        .map(|value| parse_items(&name, value))
+                        .map_err(|err| {
+                            format!(
+                                "inline completion from {} failed: {:?}",
+                                name, err
+                            )
+                        });
+                    let finished = {
+                        let mut gathering = gathering.lock();
+                        let (remaining, results, done) = &mut *gathering;
+                        results.push((i, items));
+                        *remaining -= 1;
+                        if *remaining > 0 {
+                            return;
+                        }
+                        let mut results = std::mem::take(results);
+                        results.sort_by_key(|(i, _)| *i);
+                        done.take().map(|done| (done, results))
+                    };
+                    if let Some((done, results)) = finished {
+                        let mut items = Vec::new();
+                        let mut failures = Vec::new();
+                        for (_, result) in results {
+                            match result {
+                                Ok(result) => items.extend(result),
+                                Err(failure) => failures.push(failure),
+                            }
+                        }
+                        done(items, failures);
+                    }
+                }),
+            );
+        }
+    }
 }
 
 fn start_plugin_process(
@@ -140,13 +233,21 @@ fn start_plugin_process(
             name,
         };
         eprintln!("plugin main loop starting {:?}", &plugin_desc.exec_path);
+        dispatcher
+            .plugins
+            .lock()
+            .running
+            .insert(id.clone(), (plugin.name.clone(), plugin.peer.box_clone()));
         plugin.initialize();
-        let mut handler = PluginHandler { dispatcher };
+        let mut handler = PluginHandler {
+            dispatcher: dispatcher.clone(),
+        };
         if let Err(e) =
             looper.mainloop(|| BufReader::new(child_stdout), &mut handler)
         {
             eprintln!("plugin main loop failed {} {:?}", e, &plugin_desc.dir);
         }
+        dispatcher.plugins.lock().plugin_exited(&id);
         eprintln!("plugin main loop exit {:?}", plugin_desc.dir);
     });
 }
@@ -160,6 +261,10 @@ pub enum PluginNotification {
         language_id: String,
         options: Option<Value>,
     },
+    RegisterInlineCompletionProvider {
+        plugin_id: PluginId,
+        language_ids: Vec<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -190,6 +295,18 @@ impl Handler for PluginHandler {
                     options.clone(),
                 );
             }
+            PluginNotification::RegisterInlineCompletionProvider {
+                plugin_id,
+                language_ids,
+            } => {
+                self.dispatcher
+                    .plugins
+                    .lock()
+                    .register_inline_completion_provider(
+                        plugin_id.clone(),
+                        language_ids.clone(),
+                    );
+            }
         }
     }
 